@@ -0,0 +1,276 @@
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::error::EdgeError;
+use crate::types::{EdgeResult, EdgeToken, TokenType, TokenValidationStatus};
+
+#[derive(Clone, Debug, Deserialize)]
+struct JsonWebKeySet {
+    keys: Vec<JsonWebKey>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kty")]
+enum JsonWebKey {
+    #[serde(rename = "RSA")]
+    Rsa {
+        kid: Option<String>,
+        n: String,
+        e: String,
+    },
+    #[serde(rename = "EC")]
+    Ec {
+        kid: Option<String>,
+        crv: String,
+        x: String,
+        y: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// The claims we need to turn a signed token into a frontend [`EdgeToken`]
+#[derive(Debug, Deserialize)]
+struct FrontendTokenClaims {
+    exp: i64,
+    nbf: Option<i64>,
+    #[serde(alias = "env")]
+    environment: String,
+    projects: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+enum VerificationKey {
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+    Es256 { public_key: Vec<u8> },
+}
+
+impl VerificationKey {
+    fn alg(&self) -> &'static str {
+        match self {
+            VerificationKey::Rs256 { .. } => "RS256",
+            VerificationKey::Es256 { .. } => "ES256",
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            VerificationKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok(),
+            VerificationKey::Es256 { public_key } => {
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key)
+                    .verify(message, signature)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+/// Validates self-issued frontend tokens (JWTs) against a local JSON Web Key Set, so Edge can accept them
+/// without asking upstream. Supports RS256 and ES256 signatures.
+#[derive(Clone, Debug)]
+pub struct JwksValidator {
+    keys: Vec<(Option<String>, VerificationKey)>,
+}
+
+fn decode(part: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(part).ok()
+}
+
+impl JwksValidator {
+    pub fn from_file(path: &Path) -> EdgeResult<Self> {
+        let jwks = fs::read_to_string(path).map_err(|e| {
+            EdgeError::InvalidJwks(format!("Could not read {}: {e:?}", path.display()))
+        })?;
+        JwksValidator::from_json(&jwks)
+    }
+
+    pub fn from_json(jwks: &str) -> EdgeResult<Self> {
+        let key_set: JsonWebKeySet = serde_json::from_str(jwks)
+            .map_err(|e| EdgeError::InvalidJwks(format!("Could not parse key set: {e:?}")))?;
+        let keys = key_set
+            .keys
+            .into_iter()
+            .map(|key| match key {
+                JsonWebKey::Rsa { kid, n, e } => match (decode(&n), decode(&e)) {
+                    (Some(n), Some(e)) => Ok((kid, VerificationKey::Rs256 { n, e })),
+                    _ => Err(EdgeError::InvalidJwks(format!(
+                        "RSA key {kid:?} is not valid base64url"
+                    ))),
+                },
+                JsonWebKey::Ec { kid, crv, x, y } if crv == "P-256" => {
+                    match (decode(&x), decode(&y)) {
+                        (Some(x), Some(y)) => {
+                            let mut public_key = vec![0x04];
+                            public_key.extend(x);
+                            public_key.extend(y);
+                            Ok((kid, VerificationKey::Es256 { public_key }))
+                        }
+                        _ => Err(EdgeError::InvalidJwks(format!(
+                            "EC key {kid:?} is not valid base64url"
+                        ))),
+                    }
+                }
+                JsonWebKey::Ec { kid, crv, .. } => Err(EdgeError::InvalidJwks(format!(
+                    "EC key {kid:?} uses unsupported curve {crv}"
+                ))),
+            })
+            .collect::<EdgeResult<Vec<_>>>()?;
+        if keys.is_empty() {
+            return Err(EdgeError::InvalidJwks("Key set contains no keys".into()));
+        }
+        Ok(JwksValidator { keys })
+    }
+
+    /// Returns `None` if `token` isn't a JWT at all. Otherwise returns a frontend token that is
+    /// [`TokenValidationStatus::Validated`] if the signature and claims check out, and
+    /// [`TokenValidationStatus::Invalid`] if not.
+    pub fn validate(&self, token: &str) -> Option<EdgeToken> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let [raw_header, raw_claims, raw_signature] = parts[..] else {
+            return None;
+        };
+        let header: JwtHeader = decode(raw_header).and_then(|h| serde_json::from_slice(&h).ok())?;
+        let invalid = EdgeToken {
+            token: token.into(),
+            token_type: Some(TokenType::Invalid),
+            environment: None,
+            projects: vec![],
+            status: TokenValidationStatus::Invalid,
+        };
+
+        let message = format!("{raw_header}.{raw_claims}");
+        let signature_valid = decode(raw_signature).is_some_and(|signature| {
+            self.keys
+                .iter()
+                .filter(|(kid, key)| {
+                    key.alg() == header.alg && (header.kid.is_none() || kid == &header.kid)
+                })
+                .any(|(_, key)| key.verify(message.as_bytes(), &signature))
+        });
+        if !signature_valid {
+            debug!("JWT signature could not be verified by any key in the JWKS");
+            return Some(invalid);
+        }
+
+        let Some(claims) =
+            decode(raw_claims).and_then(|c| serde_json::from_slice::<FrontendTokenClaims>(&c).ok())
+        else {
+            debug!("JWT did not contain the claims needed for a frontend token");
+            return Some(invalid);
+        };
+        let now = Utc::now().timestamp();
+        if claims.exp <= now || claims.nbf.is_some_and(|nbf| nbf > now) {
+            debug!("JWT is expired or not yet valid");
+            return Some(invalid);
+        }
+
+        Some(EdgeToken {
+            token: token.into(),
+            token_type: Some(TokenType::Frontend),
+            environment: Some(claims.environment),
+            projects: claims.projects,
+            status: TokenValidationStatus::Validated,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use chrono::Utc;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    use crate::types::{TokenType, TokenValidationStatus};
+
+    use super::JwksValidator;
+
+    pub(crate) fn key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    pub(crate) fn jwks_for(key_pair: &EcdsaKeyPair) -> JwksValidator {
+        let public_key = key_pair.public_key().as_ref();
+        let jwks = json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "edge-test",
+                "x": URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&public_key[33..]),
+            }]
+        });
+        JwksValidator::from_json(&jwks.to_string()).unwrap()
+    }
+
+    pub(crate) fn sign(key_pair: &EcdsaKeyPair, expires_in_seconds: i64) -> String {
+        let header = json!({ "alg": "ES256", "typ": "JWT", "kid": "edge-test" });
+        let claims = json!({
+            "exp": Utc::now().timestamp() + expires_in_seconds,
+            "environment": "development",
+            "projects": ["default", "dx"],
+        });
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = key_pair
+            .sign(&SystemRandom::new(), message.as_bytes())
+            .unwrap();
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    #[test]
+    fn validly_signed_token_becomes_a_validated_frontend_token() {
+        let key_pair = key_pair();
+        let token = sign(&key_pair, 3600);
+        let validated = jwks_for(&key_pair).validate(&token).unwrap();
+        assert_eq!(validated.status, TokenValidationStatus::Validated);
+        assert_eq!(validated.token_type, Some(TokenType::Frontend));
+        assert_eq!(validated.environment, Some("development".into()));
+        assert_eq!(validated.projects, vec!["default", "dx"]);
+        assert_eq!(validated.token, token);
+    }
+
+    #[test]
+    fn expired_token_is_invalid() {
+        let key_pair = key_pair();
+        let token = sign(&key_pair, -60);
+        let validated = jwks_for(&key_pair).validate(&token).unwrap();
+        assert_eq!(validated.status, TokenValidationStatus::Invalid);
+        assert_eq!(validated.token_type, Some(TokenType::Invalid));
+    }
+
+    #[test]
+    fn token_signed_by_another_key_is_invalid() {
+        let token = sign(&key_pair(), 3600);
+        let validated = jwks_for(&key_pair()).validate(&token).unwrap();
+        assert_eq!(validated.status, TokenValidationStatus::Invalid);
+    }
+
+    #[test]
+    fn regular_unleash_tokens_are_not_handled() {
+        let jwks = jwks_for(&key_pair());
+        assert!(jwks
+            .validate("*:development.1d38eefdd7bf72676122b008dcf330f2f2aa2f3031438e1b7e8f0d1f")
+            .is_none());
+    }
+}
@@ -1 +1,2 @@
+pub mod jwks;
 pub mod token_validator;
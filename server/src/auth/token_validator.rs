@@ -100,12 +100,28 @@ impl TokenValidator {
         }
     }
 
-    pub async fn schedule_validation_of_known_tokens(&self, validation_interval_seconds: u64) {
+    pub async fn schedule_validation_of_known_tokens(
+        &self,
+        validation_interval_seconds: u64,
+        refresher: Option<Arc<FeatureRefresher>>,
+    ) {
         let sleep_duration = tokio::time::Duration::from_secs(validation_interval_seconds);
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(sleep_duration) => {
-                    let _ = self.revalidate_known_tokens().await;
+                    self.revalidate_and_forget_invalid_tokens(refresher.as_deref()).await;
+                }
+            }
+        }
+    }
+
+    /// Revalidates known tokens and stops refreshing features for the ones upstream no longer accepts,
+    /// the same way a 403 from upstream does when refreshing features
+    pub async fn revalidate_and_forget_invalid_tokens(&self, refresher: Option<&FeatureRefresher>) {
+        if let Ok(invalidated) = self.revalidate_known_tokens().await {
+            if let Some(refresher) = refresher {
+                for token in invalidated {
+                    refresher.forget_token(&token);
                 }
             }
         }
@@ -133,7 +149,8 @@ impl TokenValidator {
         }
     }
 
-    pub async fn revalidate_known_tokens(&self) -> EdgeResult<()> {
+    /// Returns the tokens that were valid before, but that upstream now considers invalid
+    pub async fn revalidate_known_tokens(&self) -> EdgeResult<Vec<EdgeToken>> {
         let tokens_to_validate: Vec<String> = self
             .token_cache
             .iter()
@@ -152,14 +169,17 @@ impl TokenValidator {
                 let invalid = tokens_to_validate
                     .into_iter()
                     .filter(|t| !valid_tokens.iter().any(|e| &e.token == t));
+                let mut invalidated = vec![];
                 for token in invalid {
-                    self.token_cache
-                        .entry(token)
-                        .and_modify(|t| t.status = TokenValidationStatus::Invalid);
+                    if let Some(mut t) = self.token_cache.get_mut(&token) {
+                        t.status = TokenValidationStatus::Invalid;
+                        invalidated.push(t.clone());
+                    }
                 }
+                return Ok(invalidated);
             }
         }
-        Ok(())
+        Ok(vec![])
     }
 }
 
@@ -173,10 +193,14 @@ mod tests {
     use actix_web::{dev::AppConfig, web, App, HttpResponse};
     use dashmap::DashMap;
     use serde::{Deserialize, Serialize};
+    use unleash_types::client_features::ClientFeatures;
+    use unleash_yggdrasil::EngineState;
 
     use crate::{
+        http::refresher::feature_refresher::FeatureRefresher,
         http::unleash_client::UnleashClient,
-        types::{EdgeToken, TokenType, TokenValidationStatus},
+        tokens::cache_key,
+        types::{EdgeToken, TokenRefresh, TokenType, TokenValidationStatus},
     };
 
     use super::TokenValidator;
@@ -330,6 +354,71 @@ mod tests {
             .all(|t| t.value().status == TokenValidationStatus::Invalid));
     }
 
+    #[tokio::test]
+    pub async fn revalidating_a_revoked_token_evicts_its_environment() {
+        let mut development_token =
+            EdgeToken::try_from("*:development.secret123".to_string()).expect("Bad Test Data");
+        development_token.status = TokenValidationStatus::Validated;
+        development_token.token_type = Some(TokenType::Client);
+        let mut revoked_token =
+            EdgeToken::try_from("*:production.123secret".to_string()).expect("Bad Test Data");
+        revoked_token.status = TokenValidationStatus::Validated;
+        revoked_token.token_type = Some(TokenType::Client);
+
+        let upstream_tokens = Arc::new(DashMap::default());
+        upstream_tokens.insert(development_token.token.clone(), development_token.clone());
+        upstream_tokens.insert(
+            revoked_token.token.clone(),
+            EdgeToken {
+                status: TokenValidationStatus::Invalid,
+                ..revoked_token.clone()
+            },
+        );
+        let srv = validation_server_with_valid_tokens(upstream_tokens).await;
+        let unleash_client = Arc::new(UnleashClient::new(srv.url("/").as_str(), None).unwrap());
+
+        let token_cache = Arc::new(DashMap::default());
+        let refresher = FeatureRefresher {
+            unleash_client: unleash_client.clone(),
+            ..Default::default()
+        };
+        for token in [&development_token, &revoked_token] {
+            token_cache.insert(token.token.clone(), token.clone());
+            refresher
+                .tokens_to_refresh
+                .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+            let key = cache_key(token);
+            refresher
+                .features_cache
+                .insert(key.clone(), ClientFeatures::default());
+            refresher.engine_cache.insert(key, EngineState::default());
+        }
+        let validator = TokenValidator {
+            unleash_client,
+            token_cache,
+            persistence: None,
+        };
+
+        validator
+            .revalidate_and_forget_invalid_tokens(Some(&refresher))
+            .await;
+
+        let revoked = validator.token_cache.get(&revoked_token.token).unwrap();
+        assert_eq!(revoked.status, TokenValidationStatus::Invalid);
+        let (revoked_key, development_key) =
+            (cache_key(&revoked_token), cache_key(&development_token));
+        assert!(!refresher
+            .tokens_to_refresh
+            .contains_key(&revoked_token.token));
+        assert!(refresher.features_cache.get(&revoked_key).is_none());
+        assert!(!refresher.engine_cache.contains_key(&revoked_key));
+        assert!(refresher
+            .tokens_to_refresh
+            .contains_key(&development_token.token));
+        assert!(refresher.features_cache.get(&development_key).is_some());
+        assert!(refresher.engine_cache.contains_key(&development_key));
+    }
+
     #[tokio::test]
     pub async fn still_valid_tokens_are_left_untouched() {
         let upstream_tokens: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
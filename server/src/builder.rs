@@ -1,10 +1,14 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use chrono::Duration;
 use dashmap::DashMap;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec, Opts};
 use reqwest::Url;
 use tracing::{debug, error, warn};
 use unleash_types::client_features::ClientFeatures;
@@ -12,21 +16,40 @@ use unleash_yggdrasil::EngineState;
 
 use crate::cli::RedisMode;
 use crate::feature_cache::FeatureCache;
-use crate::http::refresher::feature_refresher::{FeatureRefreshConfig, FeatureRefresherMode};
-use crate::http::unleash_client::{new_reqwest_client, ClientMetaInformation};
-use crate::offline::offline_hotload::{load_bootstrap, load_offline_engine_cache};
+use crate::filters::frontend_features;
+use crate::http::chaos::Chaos;
+use crate::http::circuit_breaker::CircuitBreaker;
+use crate::http::refresher::feature_refresher::{
+    compile_engine, FeatureRefreshConfig, FeatureRefresherMode,
+};
+use crate::http::unleash_client::{new_reqwest_client, ClientMetaInformation, HttpClientArgs};
+use crate::offline::offline_hotload::{
+    load_bootstrap, load_offline_engine_cache, load_tokens_file,
+};
 use crate::persistence::file::FilePersister;
 use crate::persistence::redis::RedisPersister;
 use crate::persistence::s3::S3Persister;
 use crate::persistence::EdgePersistence;
+use crate::tokens::cache_key;
 use crate::{
     auth::token_validator::TokenValidator,
     cli::{CliArgs, EdgeArgs, EdgeMode, OfflineArgs},
     error::EdgeError,
     http::{refresher::feature_refresher::FeatureRefresher, unleash_client::UnleashClient},
-    types::{EdgeResult, EdgeToken, TokenType},
+    types::{EdgeResult, EdgeToken, TokenType, TokenValidationStatus},
 };
 
+lazy_static! {
+    pub static ref STARTUP_TOKEN_ENVIRONMENT_MISMATCHES: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "startup_token_environment_mismatches_total",
+            "Startup tokens upstream validated for a different environment than the one they name"
+        ),
+        &["expected", "actual"]
+    )
+    .unwrap();
+}
+
 type CacheContainer = (
     Arc<DashMap<String, EdgeToken>>,
     Arc<FeatureCache>,
@@ -50,7 +73,13 @@ fn build_caches() -> CacheContainer {
     )
 }
 
-async fn hydrate_from_persistent_storage(cache: CacheContainer, storage: Arc<dyn EdgePersistence>) {
+async fn hydrate_from_persistent_storage(
+    cache: CacheContainer,
+    storage: Arc<dyn EdgePersistence>,
+    fail_on_compile_warnings: bool,
+    max_strategies_per_feature: Option<usize>,
+    hide_stale_features: bool,
+) -> EdgeResult<()> {
     let (token_cache, features_cache, engine_cache) = cache;
     let tokens = storage.load_tokens().await.unwrap_or_else(|error| {
         warn!("Failed to load tokens from cache {error:?}");
@@ -68,14 +97,14 @@ async fn hydrate_from_persistent_storage(cache: CacheContainer, storage: Arc<dyn
     for (key, features) in features {
         tracing::debug!("Hydrating features for {key:?}");
         features_cache.insert(key.clone(), features.clone());
-        let mut engine_state = EngineState::default();
-
-        let warnings = engine_state.take_state(features);
-        if let Some(warnings) = warnings {
-            warn!("Failed to hydrate features for {key:?}: {warnings:?}");
-        }
+        let engine_state = compile_engine(
+            &key,
+            frontend_features(features, max_strategies_per_feature, hide_stale_features),
+            fail_on_compile_warnings,
+        )?;
         engine_cache.insert(key.clone(), engine_state);
     }
+    Ok(())
 }
 
 pub(crate) fn build_offline_mode(
@@ -138,7 +167,10 @@ pub(crate) fn build_offline_mode(
     Ok((token_cache, features_cache, engine_cache))
 }
 
-fn build_offline(offline_args: OfflineArgs) -> EdgeResult<CacheContainer> {
+fn build_offline(mut offline_args: OfflineArgs) -> EdgeResult<CacheContainer> {
+    if let Some(tokens_file) = offline_args.tokens_file.as_ref() {
+        offline_args.tokens.extend(load_tokens_file(tokens_file)?);
+    }
     if offline_args.tokens.is_empty() && offline_args.client_tokens.is_empty() {
         return Err(EdgeError::NoTokens(
             "No tokens provided. Tokens must be specified when running in offline mode".into(),
@@ -156,6 +188,13 @@ fn build_offline(offline_args: OfflineArgs) -> EdgeResult<CacheContainer> {
             .map_err(|_| EdgeError::NoFeaturesFile)?;
 
         let client_features = load_bootstrap(&bootstrap)?;
+        if offline_args.fail_on_compile_warnings {
+            compile_engine(
+                &bootstrap.display().to_string(),
+                client_features.clone(),
+                true,
+            )?;
+        }
 
         build_offline_mode(
             client_features,
@@ -194,7 +233,8 @@ async fn get_data_source(args: &EdgeArgs) -> Option<Arc<dyn EdgePersistence>> {
                 "Could not build a redis persister from redis_args {:?}",
                 args.redis
             )
-        });
+        })
+        .with_pool_size(redis_args.redis_pool_size);
         return Some(Arc::new(redis_persister));
     }
 
@@ -218,18 +258,139 @@ async fn get_data_source(args: &EdgeArgs) -> Option<Arc<dyn EdgePersistence>> {
     None
 }
 
+/// The `--instance-id` if given. Otherwise a generated one, which is persisted to and read back from the backup folder,
+/// so upstream doesn't see a new Edge instance on every restart
+pub fn instance_id(args: &CliArgs) -> String {
+    if let Some(instance_id) = args.instance_id.clone() {
+        return instance_id;
+    }
+    let generated = format!("unleash-edge@{}", ulid::Ulid::new());
+    let backup_folder = match &args.mode {
+        EdgeMode::Edge(edge_args) => edge_args.backup_folder.as_ref(),
+        _ => None,
+    };
+    let Some(backup_folder) = backup_folder else {
+        return generated;
+    };
+    let path = FilePersister::new(backup_folder).instance_id_path();
+    match std::fs::read_to_string(&path) {
+        Ok(persisted) if !persisted.trim().is_empty() => persisted.trim().to_string(),
+        _ => {
+            if let Err(e) = std::fs::write(&path, &generated) {
+                warn!("Could not persist instance id to {path:?}, it will change on restart: {e}");
+            }
+            generated
+        }
+    }
+}
+
+/// The tokens passed with `--tokens` together with the ones in `--tokens-file`. An unreadable tokens file is logged and skipped
+pub fn startup_tokens(args: &EdgeArgs) -> Vec<String> {
+    let mut tokens = args.tokens.clone();
+    if let Some(tokens_file) = args.tokens_file.as_ref() {
+        match load_tokens_file(tokens_file) {
+            Ok(file_tokens) => tokens.extend(file_tokens),
+            Err(e) => warn!("{e}"),
+        }
+    }
+    tokens
+}
+
+/// The settings for the client Edge talks to upstream with
+pub fn http_client_args(
+    args: &EdgeArgs,
+    client_meta_information: ClientMetaInformation,
+) -> HttpClientArgs {
+    HttpClientArgs {
+        skip_ssl_verification: args.skip_ssl_verification,
+        client_identity: args.client_identity.clone(),
+        upstream_certificate_file: args.upstream_certificate_file.clone(),
+        upstream_certificate_pins: args.upstream_cert_pin.clone(),
+        upstream_proxy_url: args.upstream_proxy_url.clone(),
+        upstream_proxy_username: args.upstream_proxy_username.clone(),
+        upstream_proxy_password: args.upstream_proxy_password.clone(),
+        connect_timeout: Duration::seconds(args.upstream_request_timeout),
+        socket_timeout: Duration::seconds(args.upstream_socket_timeout),
+        pool_max_idle_per_host: args.upstream_pool_max_idle_per_host,
+        pool_idle_timeout: Duration::seconds(args.upstream_pool_idle_timeout_seconds),
+        user_agent_suffix: args.upstream_user_agent_suffix.clone(),
+        http2: args.upstream_http2,
+        http2_prior_knowledge: args.upstream_http2_prior_knowledge,
+        client_meta_information,
+    }
+}
+
+/// Trusts the startup tokens as client tokens and serves them the features in the snapshot, without asking upstream
+async fn seed_from_snapshot(
+    snapshot: &Path,
+    tokens: Vec<String>,
+    token_cache: &DashMap<String, EdgeToken>,
+    feature_refresher: &FeatureRefresher,
+) -> EdgeResult<()> {
+    let features = load_bootstrap(snapshot)?;
+    for token in tokens {
+        let mut token = EdgeToken::from_str(&token)?;
+        token.status = TokenValidationStatus::Validated;
+        token.token_type = Some(TokenType::Client);
+        token_cache.insert(token.token.clone(), token.clone());
+        feature_refresher
+            .seed_features(token, features.clone())
+            .await?;
+    }
+    Ok(())
+}
+
+/// A startup token that upstream validates for another environment than the one it names most likely points at an
+/// environment upstream doesn't have. Nothing would ever be fetched for that environment, so say so while starting
+fn check_startup_token_environments(
+    tokens: &[String],
+    token_cache: &DashMap<String, EdgeToken>,
+    strict_startup_tokens: bool,
+) -> EdgeResult<()> {
+    for token in tokens {
+        let Ok(requested) = EdgeToken::from_str(token) else {
+            continue;
+        };
+        let Some(validated) = token_cache.get(&requested.token) else {
+            continue;
+        };
+        if validated.status != TokenValidationStatus::Validated {
+            continue;
+        }
+        match (requested.environment, validated.environment.clone()) {
+            (Some(expected), Some(actual)) if expected != "*" && expected != actual => {
+                STARTUP_TOKEN_ENVIRONMENT_MISMATCHES
+                    .with_label_values(&[expected.as_str(), actual.as_str()])
+                    .inc();
+                warn!(
+                    "Upstream validated the startup token {:?} for environment {actual} instead of {expected}. Check that {expected} exists upstream",
+                    validated.value()
+                );
+                if strict_startup_tokens {
+                    return Err(EdgeError::StartupTokenEnvironmentMismatch(expected, actual));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 async fn build_edge(
     args: &EdgeArgs,
     client_meta_information: ClientMetaInformation,
 ) -> EdgeResult<EdgeInfo> {
-    if !args.strict {
+    // A snapshot is only served to the startup tokens, other tokens must not hydrate from upstream over it
+    let strict = args.strict || args.features_snapshot.is_some();
+    if !strict {
         if !args.dynamic {
             error!("You should explicitly opt into either strict or dynamic behavior. Edge has defaulted to dynamic to preserve legacy behavior, however we recommend using strict from now on. Not explicitly opting into a behavior will return an error on startup in a future release");
         }
         warn!("Dynamic behavior has been deprecated and we plan to remove it in a future release. If you have a use case for it, please reach out to us");
     }
 
-    if args.strict && args.tokens.is_empty() {
+    let tokens = startup_tokens(args);
+    if strict && tokens.is_empty() {
         return Err(EdgeError::NoTokens(
             "No tokens provided. Tokens must be specified when running with strict behavior".into(),
         ));
@@ -239,20 +400,69 @@ async fn build_edge(
 
     let persistence = get_data_source(args).await;
 
-    let http_client = new_reqwest_client(
-        args.skip_ssl_verification,
-        args.client_identity.clone(),
-        args.upstream_certificate_file.clone(),
-        Duration::seconds(args.upstream_request_timeout),
-        Duration::seconds(args.upstream_socket_timeout),
-        client_meta_information.clone(),
-    )?;
+    let http_client = new_reqwest_client(http_client_args(args, client_meta_information.clone()))?;
+
+    let secondary_unleash_client = args
+        .secondary_upstream_url
+        .as_ref()
+        .map(|secondary_url| {
+            Url::parse(secondary_url)
+                .map(|url| {
+                    UnleashClient::from_url(
+                        url,
+                        args.token_header.token_header.clone(),
+                        http_client.clone(),
+                    )
+                    .with_custom_client_headers(args.custom_client_headers.clone())
+                    .with_lenient_json_parsing(args.lenient_json_parsing)
+                    .with_max_feature_payload_bytes(args.max_feature_payload_bytes)
+                    .with_token_validation_timeout(
+                        args.token_validation_timeout_ms
+                            .map(std::time::Duration::from_millis),
+                    )
+                })
+                .map_err(|_| EdgeError::InvalidServerUrl(secondary_url.clone()))
+        })
+        .transpose()?;
 
     let unleash_client = Url::parse(&args.upstream_url.clone())
         .map(|url| {
             UnleashClient::from_url(url, args.token_header.token_header.clone(), http_client)
         })
         .map(|c| c.with_custom_client_headers(args.custom_client_headers.clone()))
+        .map(|c| c.with_lenient_json_parsing(args.lenient_json_parsing))
+        .map(|c| c.with_metrics_compression(args.compress_metrics))
+        .map(|c| c.with_max_feature_payload_bytes(args.max_feature_payload_bytes))
+        .map(|c| {
+            c.with_circuit_breaker(args.circuit_breaker_failure_threshold.map(|threshold| {
+                CircuitBreaker::new(
+                    threshold,
+                    std::time::Duration::from_secs(args.circuit_breaker_window_seconds),
+                    std::time::Duration::from_secs(args.circuit_breaker_open_seconds),
+                )
+            }))
+        })
+        .map(|c| {
+            c.with_token_validation_timeout(
+                args.token_validation_timeout_ms
+                    .map(std::time::Duration::from_millis),
+            )
+        })
+        .map(|c| {
+            c.with_secondary_upstream(
+                secondary_unleash_client,
+                Duration::seconds(args.secondary_upstream_retry_primary_seconds as i64),
+            )
+        })
+        .map(|c| {
+            c.with_chaos(args.unsafe_enable_chaos.then(|| {
+                warn!("Chaos is enabled, feature fetches from upstream will be delayed or fail on purpose");
+                Chaos::new(
+                    args.chaos_fail_rate.unwrap_or_default(),
+                    args.chaos_latency_ms.map(std::time::Duration::from_millis),
+                )
+            }))
+        })
         .map(Arc::new)
         .map_err(|_| EdgeError::InvalidServerUrl(args.upstream_url.clone()))?;
 
@@ -261,7 +471,7 @@ async fn build_edge(
         unleash_client: unleash_client.clone(),
         persistence: persistence.clone(),
     });
-    let refresher_mode = match (args.strict, args.streaming) {
+    let refresher_mode = match (strict, args.streaming) {
         (_, true) => FeatureRefresherMode::Streaming,
         (true, _) => FeatureRefresherMode::Strict,
         _ => FeatureRefresherMode::Dynamic,
@@ -272,6 +482,32 @@ async fn build_edge(
         client_meta_information,
         args.delta,
         args.delta_diff
+    )
+    .with_delta_diff_output(args.delta_diff_output.clone())
+    .with_normalized_feature_names(args.normalize_feature_names)
+    .with_token_scope_metrics(args.token_scope_metrics)
+    .with_token_simplification_log_level(args.token_simplification_log_level)
+    .with_environment_mismatch(args.upstream_environment_mismatch)
+    .with_reject_revision_regressions(args.reject_revision_regressions)
+    .with_ignore_empty_upstream(args.ignore_empty_upstream)
+    .with_scheduler_tick(args.scheduler_tick_ms.map(std::time::Duration::from_millis))
+    .with_skip_client_registration(
+        args.skip_client_registration || args.features_snapshot.is_some(),
+    )
+    .with_token_priorities(args.token_priority.clone())
+    .with_pinned_tokens(args.pinned_tokens.clone())
+    .with_max_strategies_per_feature(args.max_strategies_per_feature)
+    .with_hide_stale_features(args.hide_stale_features)
+    .with_fail_on_compile_warnings(args.fail_on_compile_warnings)
+    .with_required_environments(args.required_environments.clone())
+    .with_feature_count_drop_threshold(args.feature_count_drop_threshold)
+    .with_stale_threshold(
+        args.stale_threshold_seconds
+            .map(|seconds| Duration::seconds(seconds as i64)),
+    )
+    .with_token_eviction_grace(
+        args.token_eviction_grace_seconds
+            .map(|seconds| Duration::seconds(seconds as i64)),
     );
     let feature_refresher = Arc::new(FeatureRefresher::new(
         unleash_client,
@@ -280,7 +516,15 @@ async fn build_edge(
         persistence.clone(),
         feature_config,
     ));
-    let _ = token_validator.register_tokens(args.tokens.clone()).await;
+    match args.features_snapshot.as_ref() {
+        Some(snapshot) => {
+            seed_from_snapshot(snapshot, tokens, &token_cache, &feature_refresher).await?
+        }
+        None => {
+            let _ = token_validator.register_tokens(tokens.clone()).await;
+            check_startup_token_environments(&tokens, &token_cache, args.strict_startup_tokens)?;
+        }
+    }
 
     if let Some(persistence) = persistence.clone() {
         hydrate_from_persistent_storage(
@@ -290,11 +534,20 @@ async fn build_edge(
                 engine_cache.clone(),
             ),
             persistence,
+            args.fail_on_compile_warnings,
+            args.max_strategies_per_feature,
+            args.hide_stale_features,
         )
-        .await;
+        .await?;
     }
 
-    if args.strict && token_cache.is_empty() {
+    let delta_etags = if args.delta {
+        feature_refresher.restore_delta_from_persistence().await
+    } else {
+        HashMap::new()
+    };
+
+    if strict && token_cache.is_empty() {
         error!("You started Edge in strict mode, but Edge was not able to validate any of the tokens configured at startup");
         return Err(EdgeError::NoTokens("No valid tokens was provided on startup. At least one valid token must be specified at startup when running in Strict mode".into()));
     }
@@ -302,8 +555,9 @@ async fn build_edge(
         .iter()
         .filter(|candidate| candidate.value().token_type == Some(TokenType::Client))
     {
+        let etag = delta_etags.get(&cache_key(&validated_token)).cloned();
         feature_refresher
-            .register_token_for_refresh(validated_token.clone(), None)
+            .register_token_for_refresh(validated_token.clone(), etag)
             .await;
     }
     Ok((
@@ -315,6 +569,7 @@ async fn build_edge(
 }
 
 pub async fn build_caches_and_refreshers(args: CliArgs) -> EdgeResult<EdgeInfo> {
+    let instance_id = instance_id(&args);
     match args.mode {
         EdgeMode::Offline(offline_args) => {
             build_offline(offline_args).map(|cache| (cache, None, None, None))
@@ -324,7 +579,8 @@ pub async fn build_caches_and_refreshers(args: CliArgs) -> EdgeResult<EdgeInfo>
                 &edge_args,
                 ClientMetaInformation {
                     app_name: args.app_name,
-                    instance_id: args.instance_id,
+                    instance_id,
+                    client_spec_version: edge_args.client_spec_version.clone(),
                 },
             )
             .await
@@ -335,10 +591,32 @@ pub async fn build_caches_and_refreshers(args: CliArgs) -> EdgeResult<EdgeInfo>
 
 #[cfg(test)]
 mod tests {
+    use std::env::temp_dir;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use clap::Parser;
+    use dashmap::DashMap;
+    use test_case::test_case;
+    use unleash_types::client_features::{
+        ClientFeature, ClientFeatures, Constraint, Operator, Strategy,
+    };
+
     use crate::{
-        builder::{build_edge, build_offline},
-        cli::{EdgeArgs, OfflineArgs, TokenHeader},
+        builder::{build_edge, build_offline, instance_id, STARTUP_TOKEN_ENVIRONMENT_MISMATCHES},
+        cli::{
+            CliArgs, EdgeArgs, EdgeMode, EnvironmentMismatchBehavior, MetricsSinkType, OfflineArgs,
+            TokenHeader, TokenSimplificationLogLevel,
+        },
+        error::EdgeError,
+        feature_cache::FeatureCache,
+        filters::FeatureFilterSet,
         http::unleash_client::ClientMetaInformation,
+        offline::offline_hotload::load_bootstrap,
+        persistence::{file::FilePersister, EdgePersistence},
+        tests::upstream_server,
+        types::{EdgeToken, TokenType, TokenValidationStatus},
     };
 
     #[test]
@@ -349,6 +627,9 @@ mod tests {
             reload_interval: Default::default(),
             client_tokens: vec![],
             frontend_tokens: vec![],
+            tokens_file: None,
+            frontend_jwks_file: None,
+            fail_on_compile_warnings: false,
         };
 
         let result = build_offline(args);
@@ -363,15 +644,21 @@ mod tests {
     async fn should_fail_with_empty_tokens_when_strict() {
         let args = EdgeArgs {
             upstream_url: Default::default(),
+            secondary_upstream_url: None,
+            secondary_upstream_retry_primary_seconds: 300,
             backup_folder: None,
             metrics_interval_seconds: Default::default(),
             features_refresh_interval_seconds: Default::default(),
             strict: true,
             dynamic: false,
             tokens: vec![],
+            tokens_file: None,
+            features_snapshot: None,
+            strict_startup_tokens: false,
             redis: None,
             s3: None,
             client_identity: Default::default(),
+            client_identity_reload_interval_seconds: 60,
             skip_ssl_verification: false,
             upstream_request_timeout: Default::default(),
             upstream_socket_timeout: Default::default(),
@@ -389,6 +676,54 @@ mod tests {
             streaming: false,
             delta: false,
             delta_diff: false,
+            delta_diff_output: None,
+            token_priority: vec![],
+            pinned_tokens: vec![],
+            max_metrics_buffer: None,
+            metrics_bin_granularity: Default::default(),
+            metrics_sampling_rate: None,
+            metrics_sink: MetricsSinkType::Upstream,
+            metrics_sink_file: None,
+            metrics_sink_file_max_bytes: 10_485_760,
+            max_feature_payload_bytes: None,
+            circuit_breaker_failure_threshold: None,
+            circuit_breaker_window_seconds: 60,
+            circuit_breaker_open_seconds: 30,
+            token_validation_timeout_ms: None,
+            inline_segment_constraints_tokens: vec![],
+            empty_on_missing_environment: false,
+            hide_stale_features: false,
+            max_strategies_per_feature: None,
+            required_environments: vec![],
+            feature_count_drop_threshold: None,
+            fail_on_compile_warnings: false,
+            upstream_pool_max_idle_per_host: None,
+            upstream_pool_idle_timeout_seconds: 90,
+            upstream_user_agent_suffix: None,
+            upstream_http2: false,
+            upstream_http2_prior_knowledge: false,
+            stale_threshold_seconds: None,
+            token_eviction_grace_seconds: None,
+            lenient_json_parsing: false,
+            normalize_feature_names: false,
+            token_scope_metrics: false,
+            token_simplification_log_level: TokenSimplificationLogLevel::Info,
+            compress_metrics: None,
+            frontend_jwks_file: None,
+            upstream_environment_mismatch: EnvironmentMismatchBehavior::Reject,
+            reject_revision_regressions: false,
+            ignore_empty_upstream: false,
+            max_streams_per_token: None,
+            scheduler_tick_ms: None,
+            skip_client_registration: false,
+            client_spec_version: unleash_yggdrasil::SUPPORTED_SPEC_VERSION.into(),
+            chaos_fail_rate: None,
+            chaos_latency_ms: None,
+            unsafe_enable_chaos: false,
+            upstream_cert_pin: vec![],
+            upstream_proxy_url: None,
+            upstream_proxy_username: None,
+            upstream_proxy_password: None,
         };
 
         let result = build_edge(
@@ -396,6 +731,7 @@ mod tests {
             ClientMetaInformation {
                 app_name: "test-app".into(),
                 instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
             },
         )
         .await;
@@ -405,4 +741,458 @@ mod tests {
             "No tokens provided. Tokens must be specified when running with strict behavior"
         );
     }
+
+    #[tokio::test]
+    async fn engine_cache_is_warm_after_hydrating_from_persistence() {
+        let backup_folder =
+            temp_dir().join(format!("edge-builder-warm-engine-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&backup_folder).unwrap();
+        FilePersister::new(&backup_folder)
+            .save_features(vec![(
+                "development".into(),
+                ClientFeatures {
+                    version: 2,
+                    features: vec![ClientFeature {
+                        name: "some-feature".into(),
+                        enabled: true,
+                        ..ClientFeature::default()
+                    }],
+                    segments: None,
+                    query: None,
+                    meta: None,
+                },
+            )])
+            .await
+            .unwrap();
+        let args = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--dynamic",
+            "--backup-folder",
+            backup_folder.to_str().unwrap(),
+        ]);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+
+        let ((_, feature_cache, engine_cache), _, _, _) = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(feature_cache.get("development").is_some());
+        let engine = engine_cache.get("development").unwrap();
+        assert!(engine.is_enabled("some-feature", &Default::default(), &None));
+        std::fs::remove_dir_all(backup_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn features_are_served_from_a_snapshot_without_upstream() {
+        let args = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--strict",
+            "--tokens",
+            "*:default.snapshot",
+            "--features-snapshot",
+            "../examples/features.json",
+        ]);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+
+        let ((token_cache, _, engine_cache), _, feature_refresher, _) = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        let token = token_cache.get("*:default.snapshot").unwrap().clone();
+        assert_eq!(token.token_type, Some(TokenType::Client));
+        let features = feature_refresher
+            .unwrap()
+            .features_for_filter(token, &FeatureFilterSet::default())
+            .await
+            .unwrap();
+        let snapshot = load_bootstrap(Path::new("../examples/features.json")).unwrap();
+        assert_eq!(features.features.len(), snapshot.features.len());
+        assert!(engine_cache.contains_key("default"));
+    }
+
+    #[tokio::test]
+    async fn a_snapshot_is_only_served_to_the_startup_tokens() {
+        let args = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--tokens",
+            "*:default.snapshot",
+            "--features-snapshot",
+            "../examples/features.json",
+        ]);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+
+        let (_, _, feature_refresher, _) = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut other_token = EdgeToken::from_str("*:production.other").unwrap();
+        other_token.status = TokenValidationStatus::Validated;
+        other_token.token_type = Some(TokenType::Client);
+        let result = feature_refresher
+            .unwrap()
+            .features_for_filter(other_token, &FeatureFilterSet::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(EdgeError::InvalidTokenWithStrictBehavior)
+        ));
+    }
+
+    #[test]
+    fn a_snapshot_cannot_be_combined_with_persistence() {
+        let result = CliArgs::try_parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--features-snapshot",
+            "../examples/features.json",
+            "--backup-folder",
+            "/tmp",
+        ]);
+        assert!(result.is_err());
+    }
+
+    fn features_that_fail_to_compile() -> ClientFeatures {
+        ClientFeatures {
+            version: 2,
+            features: vec![ClientFeature {
+                name: "broken-feature".into(),
+                enabled: true,
+                strategies: Some(vec![Strategy {
+                    name: "default".into(),
+                    sort_order: None,
+                    segments: None,
+                    constraints: Some(vec![Constraint {
+                        context_name: "userId".into(),
+                        operator: Operator::NumEq,
+                        case_insensitive: false,
+                        inverted: false,
+                        values: None,
+                        value: Some("not a number".into()),
+                    }]),
+                    parameters: None,
+                    variants: None,
+                }]),
+                ..ClientFeature::default()
+            }],
+            segments: None,
+            query: None,
+            meta: None,
+        }
+    }
+
+    #[test_case(true; "fails with the flag")]
+    #[test_case(false; "starts without the flag")]
+    #[tokio::test]
+    async fn toggles_that_fail_to_compile_during_hydration(fail_on_compile_warnings: bool) {
+        let backup_folder = temp_dir().join(format!(
+            "edge-builder-compile-warnings-{}",
+            ulid::Ulid::new()
+        ));
+        std::fs::create_dir_all(&backup_folder).unwrap();
+        FilePersister::new(&backup_folder)
+            .save_features(vec![(
+                "development".into(),
+                features_that_fail_to_compile(),
+            )])
+            .await
+            .unwrap();
+        let mut cli = vec![
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--dynamic",
+            "--backup-folder",
+            backup_folder.to_str().unwrap(),
+        ];
+        if fail_on_compile_warnings {
+            cli.push("--fail-on-compile-warnings");
+        }
+        let args = CliArgs::parse_from(cli);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+
+        let result = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await;
+
+        if fail_on_compile_warnings {
+            assert!(matches!(result, Err(EdgeError::ClientHydrationFailed(_))));
+        } else {
+            let ((_, _, engine_cache), _, _, _) = result.unwrap();
+            let engine = engine_cache.get("development").unwrap();
+            assert!(!engine.is_enabled("broken-feature", &Default::default(), &None));
+        }
+        std::fs::remove_dir_all(backup_folder).unwrap();
+    }
+
+    #[test_case(true; "fails with the flag")]
+    #[test_case(false; "starts without the flag")]
+    fn bootstrap_toggles_that_fail_to_compile_in_offline_mode(fail_on_compile_warnings: bool) {
+        let bootstrap_file = temp_dir().join(format!(
+            "edge-builder-offline-compile-warnings-{}.json",
+            ulid::Ulid::new()
+        ));
+        std::fs::write(
+            &bootstrap_file,
+            serde_json::to_string(&features_that_fail_to_compile()).unwrap(),
+        )
+        .unwrap();
+        let args = OfflineArgs {
+            bootstrap_file: Some(bootstrap_file.clone()),
+            tokens: vec!["*:development.secret123".into()],
+            reload_interval: Default::default(),
+            client_tokens: vec![],
+            frontend_tokens: vec![],
+            tokens_file: None,
+            frontend_jwks_file: None,
+            fail_on_compile_warnings,
+        };
+
+        let result = build_offline(args);
+
+        if fail_on_compile_warnings {
+            assert!(matches!(result, Err(EdgeError::ClientHydrationFailed(_))));
+        } else {
+            let (_, _, engine_cache) = result.unwrap();
+            let engine = engine_cache.get("development").unwrap();
+            assert!(!engine.is_enabled("broken-feature", &Default::default(), &None));
+        }
+        std::fs::remove_file(bootstrap_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tokens_from_the_tokens_file_are_registered_at_startup() {
+        let upstream_tokens = Arc::new(DashMap::default());
+        let tokens = [
+            "*:development.file-token-one",
+            "*:production.file-token-two",
+        ];
+        for token in tokens {
+            let mut edge_token = EdgeToken::from_str(token).unwrap();
+            edge_token.status = TokenValidationStatus::Validated;
+            edge_token.token_type = Some(TokenType::Client);
+            upstream_tokens.insert(edge_token.token.clone(), edge_token);
+        }
+        let upstream = upstream_server(
+            upstream_tokens,
+            Arc::new(FeatureCache::default()),
+            Arc::new(DashMap::default()),
+        )
+        .await;
+        let tokens_file =
+            temp_dir().join(format!("edge-builder-tokens-file-{}", ulid::Ulid::new()));
+        std::fs::write(&tokens_file, format!("{}\n\n{}\n", tokens[0], tokens[1])).unwrap();
+        let args = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            upstream.url("/").as_str(),
+            "--strict",
+            "--tokens-file",
+            tokens_file.to_str().unwrap(),
+        ]);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+
+        let ((token_cache, _, _), _, _, _) = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await
+        .unwrap();
+
+        for token in tokens {
+            assert_eq!(
+                token_cache.get(token).unwrap().status,
+                TokenValidationStatus::Validated
+            );
+        }
+        std::fs::remove_file(tokens_file).unwrap();
+    }
+
+    #[test_case(false; "starts and counts the mismatch")]
+    #[test_case(true; "fails with strict startup tokens")]
+    #[tokio::test]
+    async fn startup_tokens_for_environments_upstream_does_not_know_are_reported(
+        strict_startup_tokens: bool,
+    ) {
+        let upstream_tokens = Arc::new(DashMap::default());
+        let known = "*:development.known-environment";
+        let unknown = "*:staging.unknown-environment";
+        for (token, environment) in [(known, "development"), (unknown, "default")] {
+            let mut edge_token = EdgeToken::from_str(token).unwrap();
+            edge_token.status = TokenValidationStatus::Validated;
+            edge_token.token_type = Some(TokenType::Client);
+            edge_token.environment = Some(environment.into());
+            upstream_tokens.insert(edge_token.token.clone(), edge_token);
+        }
+        let upstream = upstream_server(
+            upstream_tokens,
+            Arc::new(FeatureCache::default()),
+            Arc::new(DashMap::default()),
+        )
+        .await;
+        let upstream_url = upstream.url("/");
+        let tokens = format!("{known},{unknown}");
+        let mut cli = vec![
+            "edge",
+            "edge",
+            "--upstream-url",
+            upstream_url.as_str(),
+            "--strict",
+            "--tokens",
+            &tokens,
+        ];
+        if strict_startup_tokens {
+            cli.push("--strict-startup-tokens");
+        }
+        let args = CliArgs::parse_from(cli);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+        let mismatches = || {
+            STARTUP_TOKEN_ENVIRONMENT_MISMATCHES
+                .with_label_values(&["staging", "default"])
+                .get()
+        };
+        let mismatches_before = mismatches();
+
+        let result = build_edge(
+            &edge_args,
+            ClientMetaInformation {
+                app_name: "test-app".into(),
+                instance_id: "test-instance-id".into(),
+                ..ClientMetaInformation::test_config()
+            },
+        )
+        .await;
+
+        assert!(mismatches() > mismatches_before);
+        if strict_startup_tokens {
+            assert!(matches!(
+                result,
+                Err(EdgeError::StartupTokenEnvironmentMismatch(expected, actual))
+                    if expected == "staging" && actual == "default"
+            ));
+        } else {
+            let ((token_cache, _, _), _, _, _) = result.unwrap();
+            assert_eq!(
+                token_cache.get(known).unwrap().status,
+                TokenValidationStatus::Validated
+            );
+        }
+    }
+
+    #[test]
+    fn a_missing_tokens_file_falls_back_to_the_tokens_argument() {
+        let args = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--tokens",
+            "*:development.cli-token",
+            "--tokens-file",
+            "/does/not/exist",
+        ]);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+        assert_eq!(
+            super::startup_tokens(&edge_args),
+            vec!["*:development.cli-token".to_string()]
+        );
+    }
+
+    #[test]
+    fn generated_instance_id_is_reused_across_restarts_with_a_backup_folder() {
+        let backup_folder =
+            temp_dir().join(format!("edge-builder-instance-id-{}", ulid::Ulid::new()));
+        let args = || {
+            CliArgs::parse_from([
+                "edge",
+                "edge",
+                "--upstream-url",
+                "http://localhost:4242",
+                "--backup-folder",
+                backup_folder.to_str().unwrap(),
+            ])
+        };
+
+        let first = instance_id(&args());
+        let second = instance_id(&args());
+        assert!(first.starts_with("unleash-edge@"));
+        assert_eq!(first, second);
+
+        let explicit = CliArgs::parse_from([
+            "edge",
+            "--instance-id",
+            "my-edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--backup-folder",
+            backup_folder.to_str().unwrap(),
+        ]);
+        assert_eq!(instance_id(&explicit), "my-edge");
+        std::fs::remove_dir_all(backup_folder).unwrap();
+
+        let without_backup =
+            || CliArgs::parse_from(["edge", "edge", "--upstream-url", "http://localhost:4242"]);
+        assert_ne!(
+            instance_id(&without_backup()),
+            instance_id(&without_backup())
+        );
+    }
 }
@@ -2,20 +2,29 @@ use crate::cli::{EdgeArgs, EdgeMode};
 use crate::error::EdgeError;
 use crate::feature_cache::FeatureCache;
 use crate::filters::{
-    filter_client_features, name_match_filter, name_prefix_filter, project_filter, FeatureFilterSet,
+    filter_client_features, name_match_filter, name_prefix_filter, project_filter,
+    requested_feature_name, stale_filter, FeatureFilterSet, FilterKey,
 };
 use crate::http::broadcaster::Broadcaster;
+use crate::http::headers::{
+    EDGE_VERSION_HEADER, UNLEASH_CONNECTION_ID_HEADER, UNLEASH_EDGE_MISSING_ENVIRONMENT_HEADER,
+};
 use crate::http::refresher::feature_refresher::FeatureRefresher;
 use crate::metrics::client_metrics::MetricsCache;
 use crate::tokens::cache_key;
 use crate::types::{
-    self, BatchMetricsRequestBody, EdgeJsonResult, EdgeResult, EdgeToken, FeatureFilters,
+    self, BatchMetricsRequestBody, DeltaQuery, EdgeJsonResult, EdgeResult, EdgeToken,
+    FeatureFilterDebug, FeatureFilters,
 };
 use actix_web::web::{self, Data, Json, Query};
-use actix_web::Responder;
 use actix_web::{get, post, HttpRequest, HttpResponse};
+use actix_web::{CustomizeResponder, Responder};
 use dashmap::DashMap;
-use unleash_types::client_features::{ClientFeature, ClientFeatures};
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+use unleash_types::client_features::{
+    ClientFeature, ClientFeatures, ClientFeaturesDelta, Constraint, DeltaEvent, Operator, Strategy,
+};
 use unleash_types::client_metrics::{ClientApplication, ClientMetrics, ConnectVia};
 
 #[utoipa::path(
@@ -37,7 +46,7 @@ pub async fn get_features(
     token_cache: Data<DashMap<String, EdgeToken>>,
     filter_query: Query<FeatureFilters>,
     req: HttpRequest,
-) -> EdgeJsonResult<ClientFeatures> {
+) -> EdgeResult<impl Responder> {
     resolve_features(edge_token, features_cache, token_cache, filter_query, req).await
 }
 
@@ -48,15 +57,23 @@ pub async fn stream_features(
     token_cache: Data<DashMap<String, EdgeToken>>,
     edge_mode: Data<EdgeMode>,
     filter_query: Query<FeatureFilters>,
+    req: HttpRequest,
 ) -> EdgeResult<impl Responder> {
     match edge_mode.get_ref() {
         EdgeMode::Edge(EdgeArgs {
             streaming: true, ..
         }) => {
             let (validated_token, _filter_set, query) =
-                get_feature_filter(&edge_token, &token_cache, filter_query.clone())?;
+                get_feature_filter(&edge_token, &token_cache, filter_query.clone(), &req)?;
 
-            broadcaster.connect(validated_token, query).await
+            let connection_id = req
+                .headers()
+                .get(UNLEASH_CONNECTION_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from);
+            broadcaster
+                .connect(validated_token, query, connection_id)
+                .await
         }
         _ => Err(EdgeError::Forbidden(
             "This endpoint is only enabled in streaming mode".into(),
@@ -83,14 +100,69 @@ pub async fn post_features(
     token_cache: Data<DashMap<String, EdgeToken>>,
     filter_query: Query<FeatureFilters>,
     req: HttpRequest,
-) -> EdgeJsonResult<ClientFeatures> {
+) -> EdgeResult<impl Responder> {
     resolve_features(edge_token, features_cache, token_cache, filter_query, req).await
 }
 
+#[utoipa::path(
+    context_path = "/api/client",
+    params(FeatureFilters, DeltaQuery),
+    responses(
+        (status = 200, description = "Return the changes to feature toggles for this token since the given revision, or a hydration with all of them", body = ClientFeaturesDelta),
+        (status = 403, description = "Was not allowed to access features"),
+        (status = 400, description = "Invalid parameters used")
+    ),
+    security(
+        ("Authorization" = [])
+    )
+)]
+#[get("/delta")]
+pub async fn get_delta(
+    edge_token: EdgeToken,
+    features_cache: Data<FeatureCache>,
+    token_cache: Data<DashMap<String, EdgeToken>>,
+    filter_query: Query<FeatureFilters>,
+    delta_query: Query<DeltaQuery>,
+    req: HttpRequest,
+) -> EdgeJsonResult<ClientFeaturesDelta> {
+    let (validated_token, filter_set, _query) =
+        get_feature_filter(&edge_token, &token_cache, filter_query, &req)?;
+    let key = cache_key(&validated_token);
+    let refresher = req.app_data::<Data<FeatureRefresher>>();
+
+    let changes = delta_query
+        .since
+        .and_then(|revision| refresher?.delta_events_since(&key, revision, &filter_set));
+    if let Some(events) = changes {
+        return Ok(Json(ClientFeaturesDelta { events }));
+    }
+
+    let client_features =
+        filtered_features(&validated_token, &filter_set, &features_cache, &req).await?;
+    let event_id = refresher
+        .and_then(|refresher| refresher.latest_delta_revision(&key))
+        .or_else(|| {
+            client_features
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.revision_id)
+                .and_then(|revision_id| i32::try_from(revision_id).ok())
+        })
+        .unwrap_or_default();
+    Ok(Json(ClientFeaturesDelta {
+        events: vec![DeltaEvent::Hydration {
+            event_id,
+            features: client_features.features,
+            segments: client_features.segments.unwrap_or_default(),
+        }],
+    }))
+}
+
 fn get_feature_filter(
     edge_token: &EdgeToken,
     token_cache: &Data<DashMap<String, EdgeToken>>,
     filter_query: Query<FeatureFilters>,
+    req: &HttpRequest,
 ) -> EdgeResult<(
     EdgeToken,
     FeatureFilterSet,
@@ -101,21 +173,33 @@ fn get_feature_filter(
         .map(|e| e.value().clone())
         .ok_or(EdgeError::AuthorizationDenied)?;
 
-    let query_filters = filter_query.into_inner();
+    let name_prefix = filter_query
+        .into_inner()
+        .name_prefix
+        .map(|prefix| requested_feature_name(req, prefix));
     let query = unleash_types::client_features::Query {
         tags: None,
         projects: Some(validated_token.projects.clone()),
-        name_prefix: query_filters.name_prefix.clone(),
+        name_prefix: name_prefix.clone(),
         environment: validated_token.environment.clone(),
         inline_segment_constraints: Some(false),
     };
 
-    let filter_set = if let Some(name_prefix) = query_filters.name_prefix {
+    let hide_stale = hide_stale_features(req);
+    let filter_set = if let Some(name_prefix) = name_prefix.clone() {
         FeatureFilterSet::from(Box::new(name_prefix_filter(name_prefix)))
     } else {
         FeatureFilterSet::default()
     }
-    .with_filter(project_filter(&validated_token));
+    .with_filter(project_filter(&validated_token))
+    .with_key(
+        FilterKey::new(validated_token.projects.clone(), name_prefix).with_hide_stale(hide_stale),
+    );
+    let filter_set = if hide_stale {
+        filter_set.with_filter(stale_filter())
+    } else {
+        filter_set
+    };
 
     Ok((validated_token, filter_set, query))
 }
@@ -126,27 +210,207 @@ async fn resolve_features(
     token_cache: Data<DashMap<String, EdgeToken>>,
     filter_query: Query<FeatureFilters>,
     req: HttpRequest,
-) -> EdgeJsonResult<ClientFeatures> {
-    let (validated_token, filter_set, query) =
-        get_feature_filter(&edge_token, &token_cache, filter_query.clone())?;
+) -> EdgeResult<CustomizeResponder<Json<ClientFeatures>>> {
+    let (validated_token, filter_set, mut query) =
+        get_feature_filter(&edge_token, &token_cache, filter_query.clone(), &req)?;
+
+    let client_features =
+        filtered_features(&validated_token, &filter_set, &features_cache, &req).await;
+    let client_features = match client_features {
+        Err(EdgeError::InvalidTokenWithStrictBehavior | EdgeError::ClientCacheError)
+            if empty_on_missing_environment(&req)
+                && features_cache.get(&cache_key(&validated_token)).is_none() =>
+        {
+            return Ok(Json(ClientFeatures {
+                version: 2,
+                features: vec![],
+                segments: None,
+                query: Some(query),
+                meta: None,
+            })
+            .customize()
+            .insert_header((UNLEASH_EDGE_MISSING_ENVIRONMENT_HEADER, "true")));
+        }
+        result => result?,
+    };
+
+    let client_features = if hide_stale_features(&req) {
+        without_unused_segments(client_features)
+    } else {
+        client_features
+    };
+
+    let client_features = if wants_inlined_segments(&validated_token, &filter_query, &req) {
+        query.inline_segment_constraints = Some(true);
+        inline_segment_constraints(client_features)
+    } else {
+        client_features
+    };
+
+    Ok(Json(ClientFeatures {
+        query: Some(query),
+        ..client_features
+    })
+    .customize())
+}
 
-    let client_features = match req.app_data::<Data<FeatureRefresher>>() {
+async fn filtered_features(
+    validated_token: &EdgeToken,
+    filter_set: &FeatureFilterSet,
+    features_cache: &FeatureCache,
+    req: &HttpRequest,
+) -> EdgeResult<ClientFeatures> {
+    match req.app_data::<Data<FeatureRefresher>>() {
         Some(refresher) => {
             refresher
-                .features_for_filter(validated_token.clone(), &filter_set)
+                .features_for_filter(validated_token.clone(), filter_set)
                 .await
         }
         None => features_cache
-            .get(&cache_key(&validated_token))
-            .map(|client_features| filter_client_features(&client_features, &filter_set))
+            .get_filtered(&cache_key(validated_token), filter_set)
             .ok_or(EdgeError::ClientCacheError),
-    }?;
+    }
+}
 
-    Ok(Json(ClientFeatures {
-        query: Some(query),
+/// Shows the filter `/api/client/features` applies for the calling token, and which features pass it.
+/// Takes the same query parameters as `/api/client/features`
+#[get("/debug/filter")]
+pub async fn debug_feature_filter(
+    edge_token: EdgeToken,
+    features_cache: Data<FeatureCache>,
+    token_cache: Data<DashMap<String, EdgeToken>>,
+    filter_query: Query<FeatureFilters>,
+    req: HttpRequest,
+) -> EdgeJsonResult<FeatureFilterDebug> {
+    let (validated_token, filter_set, query) =
+        get_feature_filter(&edge_token, &token_cache, filter_query, &req)?;
+    let features = filtered_features(&validated_token, &filter_set, &features_cache, &req)
+        .await?
+        .features
+        .into_iter()
+        .map(|feature| feature.name)
+        .collect();
+    Ok(Json(FeatureFilterDebug { query, features }))
+}
+
+fn empty_on_missing_environment(req: &HttpRequest) -> bool {
+    req.app_data::<Data<EdgeMode>>()
+        .is_some_and(|mode| match mode.get_ref() {
+            EdgeMode::Edge(args) => args.empty_on_missing_environment,
+            _ => false,
+        })
+}
+
+fn hide_stale_features(req: &HttpRequest) -> bool {
+    req.app_data::<Data<EdgeMode>>()
+        .is_some_and(|mode| match mode.get_ref() {
+            EdgeMode::Edge(args) => args.hide_stale_features,
+            _ => false,
+        })
+}
+
+fn wants_inlined_segments(
+    token: &EdgeToken,
+    filter_query: &Query<FeatureFilters>,
+    req: &HttpRequest,
+) -> bool {
+    filter_query.inline_segment_constraints.unwrap_or(false)
+        || req
+            .app_data::<Data<EdgeMode>>()
+            .is_some_and(|mode| match mode.get_ref() {
+                EdgeMode::Edge(args) => args
+                    .inline_segment_constraints_tokens
+                    .contains(&token.token),
+                _ => false,
+            })
+}
+
+/// Drops the segments none of the features reference anymore, e.g. ones only stale features used
+fn without_unused_segments(client_features: ClientFeatures) -> ClientFeatures {
+    let referenced: HashSet<i32> = client_features
+        .features
+        .iter()
+        .flat_map(|feature| feature.strategies.iter().flatten())
+        .flat_map(|strategy| strategy.segments.iter().flatten())
+        .copied()
+        .collect();
+    ClientFeatures {
+        segments: client_features.segments.map(|segments| {
+            segments
+                .into_iter()
+                .filter(|segment| referenced.contains(&segment.id))
+                .collect()
+        }),
         ..client_features
-    }))
+    }
+}
+
+/// Replaces segment references in strategies with the constraints of those segments, like Unleash does when
+/// asked for inlined segment constraints. The segments themselves are left out of the response
+fn inline_segment_constraints(client_features: ClientFeatures) -> ClientFeatures {
+    let segments: HashMap<i32, Vec<Constraint>> = client_features
+        .segments
+        .iter()
+        .flatten()
+        .map(|segment| (segment.id, segment.constraints.clone()))
+        .collect();
+    let features = client_features
+        .features
+        .into_iter()
+        .map(|feature| ClientFeature {
+            strategies: feature.strategies.map(|strategies| {
+                strategies
+                    .into_iter()
+                    .map(|strategy| match strategy.segments {
+                        Some(segment_ids) => {
+                            let mut constraints = strategy.constraints.unwrap_or_default();
+                            for id in segment_ids.iter() {
+                                match segments.get(id) {
+                                    Some(segment_constraints) => {
+                                        constraints.extend(segment_constraints.iter().cloned())
+                                    }
+                                    None => {
+                                        warn!(
+                                            "Segment {id} is missing, disabling a strategy of {}",
+                                            feature.name
+                                        );
+                                        constraints.push(never_matching_constraint());
+                                    }
+                                }
+                            }
+                            Strategy {
+                                constraints: Some(constraints),
+                                segments: None,
+                                ..strategy
+                            }
+                        }
+                        None => strategy,
+                    })
+                    .collect()
+            }),
+            ..feature
+        })
+        .collect();
+    ClientFeatures {
+        features,
+        segments: None,
+        ..client_features
+    }
 }
+
+/// Stands in for the constraints of a segment that can't be found, so the strategy fails closed rather than
+/// matching a wider audience than the segment allows
+fn never_matching_constraint() -> Constraint {
+    Constraint {
+        context_name: "unleashEdgeMissingSegment".into(),
+        operator: Operator::In,
+        case_insensitive: false,
+        inverted: false,
+        values: Some(vec![]),
+        value: None,
+    }
+}
+
 #[utoipa::path(
     context_path = "/api/client",
     params(("feature_name" = String, Path,)),
@@ -173,6 +437,7 @@ pub async fn get_feature(
         .map(|e| e.value().clone())
         .ok_or(EdgeError::AuthorizationDenied)?;
 
+    let feature_name = requested_feature_name(&req, feature_name.into_inner());
     let filter_set = FeatureFilterSet::from(Box::new(name_match_filter(feature_name.clone())))
         .with_filter(project_filter(&validated_token));
 
@@ -188,7 +453,7 @@ pub async fn get_feature(
             .ok_or(EdgeError::ClientCacheError),
     }
     .map(|client_features| client_features.features.into_iter().next())?
-    .ok_or(EdgeError::FeatureNotFound(feature_name.into_inner()))
+    .ok_or(EdgeError::FeatureNotFound(feature_name))
     .map(Json)
 }
 
@@ -217,7 +482,7 @@ pub async fn register(
         metrics_cache,
     );
     Ok(HttpResponse::Accepted()
-        .append_header(("X-Edge-Version", types::EDGE_VERSION))
+        .append_header((EDGE_VERSION_HEADER, types::EDGE_VERSION))
         .finish())
 }
 
@@ -278,11 +543,13 @@ pub fn configure_client_api(cfg: &mut web::ServiceConfig) {
             crate::middleware::validate_token::validate_token,
         ))
         .service(get_features)
+        .service(get_delta)
         .service(get_feature)
         .service(register)
         .service(metrics)
         .service(post_bulk_metrics)
-        .service(stream_features);
+        .service(stream_features)
+        .service(debug_feature_filter);
 
     cfg.service(client_scope);
 }
@@ -300,7 +567,7 @@ pub fn configure_experimental_post_features(
 mod tests {
 
     use crate::metrics::client_metrics::{ApplicationKey, MetricsBatch, MetricsKey};
-    use crate::types::{TokenType, TokenValidationStatus};
+    use crate::types::{TokenRefresh, TokenType, TokenValidationStatus};
     use std::collections::HashMap;
     use std::path::PathBuf;
     use std::str::FromStr;
@@ -309,22 +576,24 @@ mod tests {
     use super::*;
 
     use crate::auth::token_validator::TokenValidator;
-    use crate::cli::{OfflineArgs, TokenHeader};
+    use crate::cli::{CliArgs, OfflineArgs, TokenHeader};
     use crate::http::unleash_client::{ClientMetaInformation, UnleashClient};
     use crate::middleware;
     use crate::tests::{features_from_disk, upstream_server};
     use actix_http::{Request, StatusCode};
+    use actix_middleware_etag::Etag;
     use actix_web::{
-        http::header::ContentType,
+        http::header::{self, ContentType},
         test,
         web::{self, Data},
         App, ResponseError,
     };
     use chrono::{DateTime, Duration, TimeZone, Utc};
+    use clap::Parser;
     use maplit::hashmap;
     use ulid::Ulid;
     use unleash_types::client_features::{
-        ClientFeature, Constraint, Operator, Strategy, StrategyVariant,
+        ClientFeature, Constraint, Context, Operator, Segment, Strategy, StrategyVariant,
     };
     use unleash_types::client_metrics::{
         ClientMetricsEnv, ConnectViaBuilder, MetricBucket, MetricsMetadata, ToggleStats,
@@ -455,6 +724,7 @@ mod tests {
                     .unwrap()
                     .with_timezone(&Utc),
                 environment: "development".into(),
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -766,6 +1036,396 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn missing_environment_is_an_error_unless_empty_on_missing_environment_is_set() {
+        let features_cache = Arc::new(FeatureCache::default());
+        features_cache.insert("production".into(), cached_client_features());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let feature_refresher = Arc::new(FeatureRefresher {
+            features_cache: features_cache.clone(),
+            ..FeatureRefresher::default()
+        });
+        let edge_mode = |extra_args: &[&str]| {
+            let args = ["edge", "edge", "--upstream-url", "http://localhost:4242"];
+            CliArgs::parse_from(args.iter().chain(extra_args)).mode
+        };
+        let request = || {
+            test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request()
+        };
+
+        for (extra_args, empty) in [
+            (vec![], false),
+            (vec!["--empty-on-missing-environment"], true),
+        ] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(Data::from(features_cache.clone()))
+                    .app_data(Data::from(token_cache.clone()))
+                    .app_data(Data::from(feature_refresher.clone()))
+                    .app_data(Data::new(edge_mode(&extra_args)))
+                    .service(web::scope("/api/client").service(get_features)),
+            )
+            .await;
+            let res = test::call_service(&app, request()).await;
+            if empty {
+                assert_eq!(res.status(), StatusCode::OK);
+                assert_eq!(
+                    res.headers()
+                        .get(UNLEASH_EDGE_MISSING_ENVIRONMENT_HEADER)
+                        .unwrap(),
+                    "true"
+                );
+                let features: ClientFeatures = test::read_body_json(res).await;
+                assert!(features.features.is_empty());
+            } else {
+                assert_eq!(res.status(), StatusCode::FORBIDDEN);
+                assert!(res
+                    .headers()
+                    .get(UNLEASH_EDGE_MISSING_ENVIRONMENT_HEADER)
+                    .is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_features_are_hidden_when_hide_stale_features_is_set() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let mut features = cached_client_features();
+        features
+            .features
+            .iter_mut()
+            .find(|f| f.name == "feature_three")
+            .unwrap()
+            .stale = Some(true);
+        features_cache.insert("development".into(), features);
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let edge_mode = |extra_args: &[&str]| {
+            let args = ["edge", "edge", "--upstream-url", "http://localhost:4242"];
+            CliArgs::parse_from(args.iter().chain(extra_args)).mode
+        };
+
+        for (extra_args, expected) in [
+            (
+                vec![],
+                vec!["feature_one", "feature_three", "feature_two_no_strats"],
+            ),
+            (
+                vec!["--hide-stale-features"],
+                vec!["feature_one", "feature_two_no_strats"],
+            ),
+        ] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(Data::from(features_cache.clone()))
+                    .app_data(Data::from(token_cache.clone()))
+                    .app_data(Data::new(edge_mode(&extra_args)))
+                    .service(web::scope("/api/client").service(get_features)),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request();
+            let features: ClientFeatures = test::call_and_read_body_json(&app, req).await;
+            let mut names: Vec<String> = features.features.into_iter().map(|f| f.name).collect();
+            names.sort();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn segments_only_stale_features_used_are_pruned_when_hiding_stale_features() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let segmented = |name: &str, stale: bool, segments: Vec<i32>| ClientFeature {
+            name: name.into(),
+            project: Some("default".into()),
+            stale: Some(stale),
+            strategies: Some(vec![Strategy {
+                name: "default".into(),
+                sort_order: None,
+                segments: Some(segments),
+                constraints: None,
+                parameters: None,
+                variants: None,
+            }]),
+            ..ClientFeature::default()
+        };
+        features_cache.insert(
+            "development".into(),
+            ClientFeatures {
+                version: 2,
+                features: vec![
+                    segmented("fresh", false, vec![1]),
+                    segmented("stale", true, vec![1, 2]),
+                ],
+                segments: Some(vec![
+                    Segment {
+                        id: 1,
+                        constraints: vec![],
+                    },
+                    Segment {
+                        id: 2,
+                        constraints: vec![],
+                    },
+                ]),
+                query: None,
+                meta: None,
+            },
+        );
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let edge_mode = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--hide-stale-features",
+        ])
+        .mode;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .app_data(Data::new(edge_mode))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/api/client/features")
+            .insert_header(("Authorization", token.token.clone()))
+            .to_request();
+
+        let features: ClientFeatures = test::call_and_read_body_json(&app, req).await;
+        let names: Vec<String> = features.features.into_iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["fresh"]);
+        let segment_ids: Vec<i32> = features.segments.unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(segment_ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn segment_constraints_are_inlined_when_requested() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+        let own_constraint = Constraint {
+            context_name: "userId".into(),
+            operator: Operator::In,
+            case_insensitive: false,
+            inverted: false,
+            values: Some(vec!["7".into()]),
+            value: None,
+        };
+        let segment_constraint = Constraint {
+            context_name: "environment".into(),
+            values: Some(vec!["beta".into()]),
+            ..own_constraint.clone()
+        };
+        features_cache.insert(
+            "development".into(),
+            ClientFeatures {
+                version: 2,
+                features: vec![ClientFeature {
+                    name: "segmented".into(),
+                    project: Some("default".into()),
+                    enabled: true,
+                    strategies: Some(vec![Strategy {
+                        name: "default".into(),
+                        sort_order: None,
+                        segments: Some(vec![1]),
+                        constraints: Some(vec![own_constraint.clone()]),
+                        parameters: None,
+                        variants: None,
+                    }]),
+                    ..ClientFeature::default()
+                }],
+                segments: Some(vec![Segment {
+                    id: 1,
+                    constraints: vec![segment_constraint.clone()],
+                }]),
+                query: None,
+                meta: None,
+            },
+        );
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let features_request = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request()
+        };
+
+        let referenced: ClientFeatures =
+            test::call_and_read_body_json(&app, features_request("/api/client/features")).await;
+        let strategy = &referenced.features[0].strategies.as_ref().unwrap()[0];
+        assert_eq!(strategy.segments, Some(vec![1]));
+        assert_eq!(strategy.constraints, Some(vec![own_constraint.clone()]));
+        assert_eq!(referenced.segments.unwrap().len(), 1);
+        assert_eq!(
+            referenced.query.unwrap().inline_segment_constraints,
+            Some(false)
+        );
+
+        let inlined: ClientFeatures = test::call_and_read_body_json(
+            &app,
+            features_request("/api/client/features?inlineSegmentConstraints=true"),
+        )
+        .await;
+        let strategy = &inlined.features[0].strategies.as_ref().unwrap()[0];
+        assert_eq!(strategy.segments, None);
+        assert_eq!(
+            strategy.constraints,
+            Some(vec![own_constraint, segment_constraint])
+        );
+        assert!(inlined.segments.is_none());
+        assert_eq!(
+            inlined.query.unwrap().inline_segment_constraints,
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn segment_constraints_are_always_inlined_for_configured_tokens() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let segment_constraint = Constraint {
+            context_name: "environment".into(),
+            operator: Operator::In,
+            case_insensitive: false,
+            inverted: false,
+            values: Some(vec!["beta".into()]),
+            value: None,
+        };
+        features_cache.insert(
+            "development".into(),
+            ClientFeatures {
+                version: 2,
+                features: vec![ClientFeature {
+                    name: "segmented".into(),
+                    project: Some("default".into()),
+                    enabled: true,
+                    strategies: Some(vec![Strategy {
+                        name: "default".into(),
+                        sort_order: None,
+                        segments: Some(vec![1]),
+                        constraints: None,
+                        parameters: None,
+                        variants: None,
+                    }]),
+                    ..ClientFeature::default()
+                }],
+                segments: Some(vec![Segment {
+                    id: 1,
+                    constraints: vec![segment_constraint.clone()],
+                }]),
+                query: None,
+                meta: None,
+            },
+        );
+        let mut inlined_token =
+            EdgeToken::try_from("*:development.inlined123".to_string()).unwrap();
+        inlined_token.token_type = Some(TokenType::Client);
+        inlined_token.status = TokenValidationStatus::Validated;
+        token_cache.insert(inlined_token.token.clone(), inlined_token.clone());
+        let mut other_token = EdgeToken::try_from("*:development.other123".to_string()).unwrap();
+        other_token.token_type = Some(TokenType::Client);
+        other_token.status = TokenValidationStatus::Validated;
+        token_cache.insert(other_token.token.clone(), other_token.clone());
+        let edge_mode = CliArgs::parse_from([
+            "edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--inline-segment-constraints-tokens",
+            inlined_token.token.as_str(),
+        ])
+        .mode;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .app_data(Data::new(edge_mode))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+        let features_request = |token: &EdgeToken| {
+            test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request()
+        };
+
+        let inlined: ClientFeatures =
+            test::call_and_read_body_json(&app, features_request(&inlined_token)).await;
+        let strategy = &inlined.features[0].strategies.as_ref().unwrap()[0];
+        assert_eq!(strategy.segments, None);
+        assert_eq!(strategy.constraints, Some(vec![segment_constraint]));
+        assert!(inlined.segments.is_none());
+
+        let referenced: ClientFeatures =
+            test::call_and_read_body_json(&app, features_request(&other_token)).await;
+        let strategy = &referenced.features[0].strategies.as_ref().unwrap()[0];
+        assert_eq!(strategy.segments, Some(vec![1]));
+        assert_eq!(referenced.segments.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn strategies_referencing_a_missing_segment_stay_disabled_when_inlined() {
+        let features = ClientFeatures {
+            version: 2,
+            features: vec![ClientFeature {
+                name: "segmented".into(),
+                project: Some("default".into()),
+                enabled: true,
+                strategies: Some(vec![Strategy {
+                    name: "default".into(),
+                    sort_order: None,
+                    segments: Some(vec![404]),
+                    constraints: None,
+                    parameters: None,
+                    variants: None,
+                }]),
+                ..ClientFeature::default()
+            }],
+            segments: Some(vec![]),
+            query: None,
+            meta: None,
+        };
+
+        let inlined = inline_segment_constraints(features);
+        let strategy = &inlined.features[0].strategies.as_ref().unwrap()[0];
+        assert_eq!(strategy.segments, None);
+        assert!(!strategy.constraints.as_ref().unwrap().is_empty());
+        let mut engine = EngineState::default();
+        engine.take_state(inlined);
+        assert_eq!(
+            engine.check_enabled("segmented", &Context::default(), &None),
+            Some(false)
+        );
+    }
+
     #[tokio::test]
     async fn client_features_endpoint_correctly_returns_cached_features() {
         let features_cache = Arc::new(FeatureCache::default());
@@ -790,7 +1450,9 @@ mod tests {
         token_cache.insert(token.token.clone(), token.clone());
         let req = make_features_request_with_token(token.clone()).await;
         let res: ClientFeatures = test::call_and_read_body_json(&app, req).await;
-        assert_eq!(res.features, client_features.features);
+        let mut expected_features = client_features.features.clone();
+        expected_features.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(res.features, expected_features);
         let mut production_token = EdgeToken::try_from(
             "*:production.03fa5f506428fe80ed5640c351c7232e38940814d2923b08f5c05fa7".to_string(),
         )
@@ -831,7 +1493,9 @@ mod tests {
         token_cache.insert(token.token.clone(), token.clone());
         let req = make_features_request_with_token(token.clone()).await;
         let res: ClientFeatures = test::call_and_read_body_json(&app, req).await;
-        assert_eq!(res.features, client_features.features);
+        let mut expected_features = client_features.features.clone();
+        expected_features.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(res.features, expected_features);
         let mut production_token = EdgeToken::try_from(
             "*:production.03fa5f506428fe80ed5640c351c7232e38940814d2923b08f5c05fa7".to_string(),
         )
@@ -962,6 +1626,48 @@ mod tests {
             .all(|f| token_a.projects.contains(&f.project.clone().unwrap())));
     }
 
+    #[tokio::test]
+    async fn repeated_requests_are_served_from_memoized_features_until_the_features_change() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+        let mut token =
+            EdgeToken::try_from("[]:production.puff_the_magic_dragon".to_string()).unwrap();
+        token.projects = vec!["dx".into(), "eg".into()];
+        token.status = TokenValidationStatus::Validated;
+        token.token_type = Some(TokenType::Client);
+        token_cache.insert(token.token.clone(), token.clone());
+        let mut example_features = features_from_disk("../examples/hostedexample.json");
+        features_cache.insert("production".into(), example_features.clone());
+
+        let req_1 = make_features_request_with_token(token.clone()).await;
+        let res_1: ClientFeatures = test::call_and_read_body_json(&app, req_1).await;
+        assert_eq!(features_cache.memoized_filters(), 1);
+        let req_2 = make_features_request_with_token(token.clone()).await;
+        let res_2: ClientFeatures = test::call_and_read_body_json(&app, req_2).await;
+        assert_eq!(features_cache.memoized_filters(), 1);
+        assert_eq!(res_1, res_2);
+
+        example_features
+            .features
+            .retain(|f| f.project != Some("dx".into()));
+        features_cache.insert("production".into(), example_features);
+        assert_eq!(features_cache.memoized_filters(), 0);
+        let req_3 = make_features_request_with_token(token.clone()).await;
+        let res_3: ClientFeatures = test::call_and_read_body_json(&app, req_3).await;
+        assert!(res_3.features.len() < res_1.features.len());
+        assert!(res_3
+            .features
+            .iter()
+            .all(|f| f.project == Some("eg".into())));
+    }
+
     #[tokio::test]
     async fn when_running_in_offline_mode_with_proxy_key_should_not_filter_features() {
         let features_cache = Arc::new(FeatureCache::default());
@@ -976,6 +1682,9 @@ mod tests {
                     client_tokens: vec![],
                     frontend_tokens: vec![],
                     reload_interval: 0,
+                    tokens_file: None,
+                    frontend_jwks_file: None,
+                    fail_on_compile_warnings: false,
                 })))
                 .service(web::scope("/api/client").service(get_features)),
         )
@@ -1025,6 +1734,7 @@ mod tests {
             client_meta_information: ClientMetaInformation::test_config(),
             delta: false,
             delta_diff: false,
+            ..Default::default()
         });
         let token_validator = Arc::new(TokenValidator {
             unleash_client: unleash_client.clone(),
@@ -1276,6 +1986,44 @@ mod tests {
         assert_eq!(result.query.unwrap().name_prefix.unwrap(), "embed");
     }
 
+    #[tokio::test]
+    pub async fn debug_filter_shows_query_and_features_for_project_token() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let features = features_from_disk("../examples/hostedexample.json");
+        let mut dx_token = EdgeToken::from_str("dx:development.secret123").unwrap();
+        dx_token.status = TokenValidationStatus::Validated;
+        dx_token.token_type = Some(TokenType::Client);
+        token_cache.insert(dx_token.token.clone(), dx_token.clone());
+        features_cache.insert(cache_key(&dx_token), features.clone());
+        let local_app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .service(web::scope("/api").configure(configure_client_api)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/api/client/debug/filter")
+            .insert_header(("Authorization", dx_token.token.clone()))
+            .to_request();
+
+        let result: FeatureFilterDebug = test::call_and_read_body_json(&local_app, request).await;
+        assert_eq!(result.query.projects, Some(vec!["dx".to_string()]));
+        assert_eq!(result.query.environment, Some("development".to_string()));
+        let mut expected: Vec<String> = features
+            .features
+            .iter()
+            .filter(|f| f.project.as_deref() == Some("dx"))
+            .map(|f| f.name.clone())
+            .collect();
+        let mut filtered = result.features;
+        expected.sort();
+        filtered.sort();
+        assert!(!filtered.is_empty());
+        assert_eq!(filtered, expected);
+    }
+
     #[tokio::test]
     pub async fn only_gets_correct_feature_by_name() {
         let features_cache = Arc::new(FeatureCache::default());
@@ -1394,4 +2142,247 @@ mod tests {
         let res = test::call_service(&app, request).await;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
     }
+
+    #[tokio::test]
+    async fn client_requests_are_authorized_with_the_client_token_header() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .app_data(Data::new(TokenHeader::from_str("X-Default-Token").unwrap()))
+                .app_data(Data::new(crate::tokens::EndpointTokenHeaders {
+                    client: Some("X-Client-Token".into()),
+                    frontend: Some("X-Frontend-Token".into()),
+                }))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+        features_cache.insert(
+            "production".into(),
+            features_from_disk("../examples/features.json"),
+        );
+        let mut production_token =
+            EdgeToken::try_from("*:production.someclientsecret".to_string()).unwrap();
+        production_token.token_type = Some(TokenType::Client);
+        production_token.status = TokenValidationStatus::Validated;
+        token_cache.insert(production_token.token.clone(), production_token.clone());
+
+        for (header, expected_status) in [
+            ("X-Client-Token", StatusCode::OK),
+            ("X-Frontend-Token", StatusCode::FORBIDDEN),
+            ("X-Default-Token", StatusCode::FORBIDDEN),
+        ] {
+            let request = test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header((header, production_token.token.clone()))
+                .to_request();
+            let res = test::call_service(&app, request).await;
+            assert_eq!(res.status(), expected_status, "{header}");
+        }
+    }
+
+    #[tokio::test]
+    async fn differently_ordered_features_produce_identical_responses_and_etags() {
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let segment = |id: i32| Segment {
+            id,
+            constraints: vec![],
+        };
+        let features = cached_client_features();
+        let mut reordered = features.clone();
+        reordered.features.reverse();
+        let ordered = ClientFeatures {
+            segments: Some(vec![segment(1), segment(2), segment(3)]),
+            ..features
+        };
+        let reordered = ClientFeatures {
+            segments: Some(vec![segment(3), segment(1), segment(2)]),
+            ..reordered
+        };
+
+        let mut responses = vec![];
+        for client_features in [ordered, reordered] {
+            let features_cache = Arc::new(FeatureCache::default());
+            features_cache.insert("development".into(), client_features);
+            let app = test::init_service(
+                App::new()
+                    .wrap(Etag)
+                    .app_data(Data::from(features_cache))
+                    .app_data(Data::from(token_cache.clone()))
+                    .service(web::scope("/api/client").service(get_features)),
+            )
+            .await;
+            let res =
+                test::call_service(&app, make_features_request_with_token(token.clone()).await)
+                    .await;
+            let etag = res.headers().get(header::ETAG).unwrap().clone();
+            responses.push((etag, test::read_body(res).await));
+        }
+
+        assert_eq!(responses[0].0, responses[1].0);
+        assert_eq!(responses[0].1, responses[1].1);
+        let features: ClientFeatures = serde_json::from_slice(&responses[0].1).unwrap();
+        let names: Vec<&str> = features.features.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["feature_one", "feature_three", "feature_two_no_strats"]
+        );
+        let segment_ids: Vec<i32> = features.segments.unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(segment_ids, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn non_ascii_etags_from_clients_are_treated_as_not_matching() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        features_cache.insert("development".into(), cached_client_features());
+        let app = test::init_service(
+            App::new()
+                .wrap(Etag)
+                .app_data(Data::from(features_cache))
+                .app_data(Data::from(token_cache))
+                .service(web::scope("/api/client").service(get_features)),
+        )
+        .await;
+
+        for name in [header::IF_NONE_MATCH, header::ETAG] {
+            let request = test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token.token.clone()))
+                .insert_header((
+                    name.clone(),
+                    header::HeaderValue::from_bytes("W/\"\u{e6}tag-\u{f8}\"".as_bytes()).unwrap(),
+                ))
+                .to_request();
+            let res = test::call_service(&app, request).await;
+            assert_eq!(res.status(), StatusCode::OK, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn delta_endpoint_returns_changes_since_the_revision_the_sdk_has() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::try_from("dx:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token_cache.insert(token.token.clone(), token.clone());
+        let feature_refresher = Arc::new(FeatureRefresher {
+            features_cache: features_cache.clone(),
+            ..FeatureRefresher::default()
+        });
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+        let feature = |event_id: i32, name: &str, project: &str| DeltaEvent::FeatureUpdated {
+            event_id,
+            feature: ClientFeature {
+                name: name.into(),
+                project: Some(project.into()),
+                ..ClientFeature::default()
+            },
+        };
+        let deltas = [
+            ClientFeaturesDelta {
+                events: vec![DeltaEvent::Hydration {
+                    event_id: 1,
+                    features: vec![
+                        ClientFeature {
+                            name: "moved".into(),
+                            project: Some("dx".into()),
+                            ..ClientFeature::default()
+                        },
+                        ClientFeature {
+                            name: "hidden-removed".into(),
+                            project: Some("eg".into()),
+                            ..ClientFeature::default()
+                        },
+                    ],
+                    segments: vec![],
+                }],
+            },
+            ClientFeaturesDelta {
+                events: vec![feature(2, "first", "dx")],
+            },
+            ClientFeaturesDelta {
+                events: vec![
+                    feature(3, "second", "dx"),
+                    feature(4, "hidden", "eg"),
+                    feature(5, "moved", "eg"),
+                    DeltaEvent::FeatureRemoved {
+                        event_id: 6,
+                        feature_name: "hidden-removed".into(),
+                    },
+                    DeltaEvent::FeatureRemoved {
+                        event_id: 7,
+                        feature_name: "first".into(),
+                    },
+                ],
+            },
+        ];
+        let key = cache_key(&token);
+        for delta in deltas.iter() {
+            feature_refresher
+                .delta_history
+                .entry(key.clone())
+                .or_default()
+                .record(&token.token, delta, features_cache.get(&key).as_deref());
+            features_cache.apply_delta(key.clone(), delta);
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .app_data(Data::from(feature_refresher.clone()))
+                .service(web::scope("/api/client").service(get_delta)),
+        )
+        .await;
+        let request = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request()
+        };
+
+        let delta: ClientFeaturesDelta =
+            test::call_and_read_body_json(&app, request("/api/client/delta?since=2")).await;
+        assert_eq!(
+            delta.events,
+            vec![
+                feature(3, "second", "dx"),
+                DeltaEvent::FeatureRemoved {
+                    event_id: 5,
+                    feature_name: "moved".into(),
+                },
+                DeltaEvent::FeatureRemoved {
+                    event_id: 7,
+                    feature_name: "first".into(),
+                },
+            ]
+        );
+
+        for uri in ["/api/client/delta", "/api/client/delta?since=0"] {
+            let delta: ClientFeaturesDelta =
+                test::call_and_read_body_json(&app, request(uri)).await;
+            let [DeltaEvent::Hydration {
+                event_id, features, ..
+            }] = delta.events.as_slice()
+            else {
+                panic!("Expected a single hydration for {uri}");
+            };
+            assert_eq!(*event_id, 7);
+            let names: Vec<&str> = features.iter().map(|f| f.name.as_str()).collect();
+            assert_eq!(names, vec!["second"]);
+        }
+    }
 }
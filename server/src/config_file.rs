@@ -0,0 +1,264 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{error::ErrorKind, parser::ValueSource, Arg, ArgMatches, Command};
+use serde_json::{Map, Value};
+
+/// Parses `args` like [`Command::try_get_matches_from`], filling in the arguments that weren't given on the command
+/// line or through environment variables from `--config-file`. Precedence is command line > environment > file > default.
+/// File values are passed to clap as if they were given on the command line, so they're validated the same way
+pub fn try_get_matches_from<I, T>(command: &Command, args: I) -> Result<ArgMatches, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    // Required arguments may only be set in the file, so this first pass can't fail on them
+    let lenient = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let subcommand = lenient.subcommand();
+    let config_file = lenient.get_one::<PathBuf>("config_file").or_else(|| {
+        subcommand.and_then(|(_, sub_matches)| sub_matches.get_one::<PathBuf>("config_file"))
+    });
+    let Some(config_file) = config_file else {
+        return command.clone().try_get_matches_from(args);
+    };
+    let sub_command = subcommand.and_then(|(name, _)| command.find_subcommand(name));
+    let sub_matches = subcommand.map(|(_, sub_matches)| sub_matches);
+
+    let mut top_level_args = vec![];
+    let mut sub_command_args = vec![];
+    for (key, value) in read_config_file(command, config_file)? {
+        let long = key.replace('_', "-");
+        let (arg, already_set) = if let Some(arg) = find_arg(command, &long) {
+            let mut sources = vec![lenient.value_source(arg.get_id().as_str())];
+            if arg.is_global_set() {
+                sources.extend(sub_matches.map(|m| m.value_source(arg.get_id().as_str())));
+            }
+            (arg, sources.into_iter().any(explicitly_set))
+        } else if let Some(arg) = sub_command.and_then(|sub_command| find_arg(sub_command, &long)) {
+            let source = sub_matches.and_then(|m| m.value_source(arg.get_id().as_str()));
+            (arg, explicitly_set(source))
+        } else {
+            return Err(command.clone().error(
+                ErrorKind::UnknownArgument,
+                format!("Unknown key {key} in config file {}", config_file.display()),
+            ));
+        };
+        if arg.get_id() == "config_file" {
+            return Err(command.clone().error(
+                ErrorKind::ArgumentConflict,
+                "config-file can't be set from within a config file",
+            ));
+        }
+        if already_set {
+            continue;
+        }
+        let values = to_cli_args(arg, &long, &value)
+            .map_err(|message| command.clone().error(ErrorKind::InvalidValue, message))?;
+        // Top level arguments that aren't global have to come before the subcommand
+        if sub_command.is_some_and(|sub_command| find_arg(sub_command, &long).is_some()) {
+            sub_command_args.extend(values);
+        } else {
+            top_level_args.extend(values);
+        }
+    }
+
+    let merged = args
+        .iter()
+        .take(1)
+        .cloned()
+        .chain(top_level_args)
+        .chain(args.iter().skip(1).cloned())
+        .chain(sub_command_args);
+    command.clone().try_get_matches_from(merged)
+}
+
+fn explicitly_set(source: Option<ValueSource>) -> bool {
+    matches!(
+        source,
+        Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+    )
+}
+
+fn find_arg<'a>(command: &'a Command, long: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(long))
+}
+
+/// Parses the file as YAML when it has a .yaml or .yml extension, and as TOML otherwise
+fn read_config_file(command: &Command, path: &Path) -> Result<Map<String, Value>, clap::Error> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        command.clone().error(
+            ErrorKind::Io,
+            format!("Could not read config file {}: {e}", path.display()),
+        )
+    })?;
+    let parsed = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str::<Value>(&contents).map_err(|e| e.to_string())
+        }
+        _ => toml::from_str::<Value>(&contents).map_err(|e| e.to_string()),
+    };
+    match parsed {
+        Ok(Value::Object(values)) => Ok(values),
+        Ok(Value::Null) => Ok(Map::new()),
+        Ok(_) => Err(command.clone().error(
+            ErrorKind::InvalidValue,
+            format!(
+                "Config file {} needs to map argument names to values",
+                path.display()
+            ),
+        )),
+        Err(e) => Err(command.clone().error(
+            ErrorKind::InvalidValue,
+            format!("Could not parse config file {}: {e}", path.display()),
+        )),
+    }
+}
+
+fn to_cli_args(arg: &Arg, long: &str, value: &Value) -> Result<Vec<OsString>, String> {
+    if !arg.get_action().takes_values() {
+        return match value {
+            Value::Bool(true) => Ok(vec![format!("--{long}").into()]),
+            Value::Bool(false) => Ok(vec![]),
+            _ => Err(format!("{long} is a flag and needs to be true or false")),
+        };
+    }
+    let values = match value {
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(value) => Ok(format!("--{long}={value}").into()),
+            Value::Number(value) => Ok(format!("--{long}={value}").into()),
+            Value::Bool(value) => Ok(format!("--{long}={value}").into()),
+            _ => Err(format!(
+                "{long} needs to be a string, number or boolean, or a list of them"
+            )),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::{error::ErrorKind, CommandFactory, FromArgMatches};
+
+    use super::try_get_matches_from;
+    use crate::cli::{CliArgs, EdgeMode};
+
+    fn config_file(extension: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("edge-config-{}.{extension}", ulid::Ulid::new()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn parse(args: &[&str]) -> Result<CliArgs, clap::Error> {
+        let matches = try_get_matches_from(&CliArgs::command(), args)?;
+        CliArgs::from_arg_matches(&matches)
+    }
+
+    #[test]
+    fn toml_values_fill_in_for_arguments_not_given_on_the_command_line() {
+        let path = config_file(
+            "toml",
+            r#"
+            port = 4000
+            upstream-url = "http://localhost:4242"
+            tokens = ["*:development.secret123", "*:production.secret123"]
+            features_refresh_interval_seconds = 30
+            strict = true
+            "#,
+        );
+        let args = parse(&[
+            "unleash-edge",
+            "--config-file",
+            path.to_str().unwrap(),
+            "edge",
+            "--features-refresh-interval-seconds",
+            "15",
+        ])
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(args.http.port, 4000);
+        let EdgeMode::Edge(edge_args) = args.mode else {
+            panic!("Expected edge mode");
+        };
+        assert_eq!(edge_args.upstream_url, "http://localhost:4242");
+        assert_eq!(
+            edge_args.tokens,
+            vec!["*:development.secret123", "*:production.secret123"]
+        );
+        assert_eq!(edge_args.features_refresh_interval_seconds, 15);
+        assert!(edge_args.strict);
+        assert_eq!(edge_args.metrics_interval_seconds, 60);
+    }
+
+    #[test]
+    fn environment_variables_take_precedence_over_yaml_values() {
+        let path = config_file(
+            "yaml",
+            "upstream-url: http://localhost:4242\nupstream-user-agent-suffix: from-file\n",
+        );
+        std::env::set_var("UPSTREAM_USER_AGENT_SUFFIX", "from-env");
+        let args = parse(&[
+            "unleash-edge",
+            "edge",
+            "--config-file",
+            path.to_str().unwrap(),
+        ]);
+        std::env::remove_var("UPSTREAM_USER_AGENT_SUFFIX");
+        std::fs::remove_file(path).unwrap();
+
+        let EdgeMode::Edge(edge_args) = args.unwrap().mode else {
+            panic!("Expected edge mode");
+        };
+        assert_eq!(edge_args.upstream_url, "http://localhost:4242");
+        assert_eq!(
+            edge_args.upstream_user_agent_suffix.as_deref(),
+            Some("from-env")
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let path = config_file(
+            "toml",
+            "upstream-url = \"http://localhost:4242\"\nupstream-urll = \"typo\"\n",
+        );
+        let result = parse(&[
+            "unleash-edge",
+            "--config-file",
+            path.to_str().unwrap(),
+            "edge",
+        ]);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn arguments_are_parsed_as_before_without_a_config_file() {
+        let args = parse(&[
+            "unleash-edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+        ]);
+        assert!(matches!(args.unwrap().mode, EdgeMode::Edge(_)));
+        assert_eq!(
+            parse(&["unleash-edge", "edge"]).unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+}
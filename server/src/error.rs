@@ -1,7 +1,13 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
-use actix_web::{http::StatusCode, HttpResponseBuilder, ResponseError};
+use actix_web::{
+    http::{
+        header::{HeaderValue, RETRY_AFTER},
+        StatusCode,
+    },
+    HttpResponseBuilder, ResponseError,
+};
 use actix_web_lab::sse::Event;
 use serde::Serialize;
 use serde_json::json;
@@ -10,6 +16,11 @@ use tracing::debug;
 
 use crate::types::{EdgeToken, Status, UnleashBadRequest};
 
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Streams that were dropped without closing only give back their slot once the broadcaster's 30 second heartbeat finds them
+const STREAM_LIMIT_RETRY_AFTER_SECS: u64 = 30;
+
 pub const TRUST_PROXY_PARSE_ERROR: &str =
     "needs to be a valid ip address (ipv4 or ipv6) or a valid cidr (ipv4 or ipv6)";
 
@@ -17,7 +28,12 @@ pub const TRUST_PROXY_PARSE_ERROR: &str =
 pub enum FeatureError {
     AccessDenied,
     NotFound,
+    PayloadTooLarge(usize),
     Retriable(reqwest::StatusCode),
+    /// The circuit breaker is open, so upstream wasn't contacted
+    CircuitOpen,
+    /// Upstream couldn't be reached, e.g. the connection was refused or timed out
+    Unreachable,
 }
 
 #[derive(Debug, Serialize)]
@@ -88,6 +104,7 @@ impl Display for CertificateError {
 
 #[derive(Debug)]
 pub enum EdgeError {
+    AllEndpointDisabled(String),
     AuthorizationDenied,
     AuthorizationPending,
     ClientBuildError(String),
@@ -98,6 +115,7 @@ pub enum EdgeError {
     ClientHydrationFailed(String),
     ClientRegisterError,
     ContextParseError,
+    ContextTooLarge(String),
     EdgeMetricsError,
     EdgeMetricsRequestError(reqwest::StatusCode, Option<UnleashBadRequest>),
     EdgeTokenError,
@@ -108,6 +126,7 @@ pub enum EdgeError {
     FrontendNotYetHydrated(FrontendHydrationMissing),
     HealthCheckError(String),
     InvalidBackupFile(String, String),
+    InvalidJwks(String),
     InvalidServerUrl(String),
     InvalidTokenWithStrictBehavior,
     JsonParseError(String),
@@ -115,12 +134,18 @@ pub enum EdgeError {
     NoTokenProvider,
     NoTokens(String),
     NotReady,
+    PayloadTooLarge(usize),
     PersistenceError(String),
     ReadyCheckError(String),
+    RefreshRateLimited(u64),
     SseError(String),
+    StartupTokenEnvironmentMismatch(String, String),
+    StreamLimitExceeded(usize),
     TlsError,
     TokenParseError(String),
     TokenValidationError(reqwest::StatusCode),
+    UpstreamEnvironmentMismatch(String, String),
+    UpstreamRevisionRegression(usize, usize),
 }
 
 impl Error for EdgeError {}
@@ -137,6 +162,9 @@ impl Display for EdgeError {
             EdgeError::NoTokenProvider => write!(f, "Could not get a TokenProvider"),
             EdgeError::NoTokens(msg) => write!(f, "{msg}"),
             EdgeError::TokenParseError(token) => write!(f, "Could not parse edge token: {token}"),
+            EdgeError::PayloadTooLarge(limit) => {
+                write!(f, "Request body exceeds the limit of {limit} bytes")
+            }
             EdgeError::PersistenceError(msg) => write!(f, "{msg}"),
             EdgeError::JsonParseError(msg) => write!(f, "{msg}"),
             EdgeError::ClientFeaturesFetchError(fe) => match fe {
@@ -152,8 +180,23 @@ impl Display for EdgeError {
                     f,
                     "Could not fetch features because upstream url was not found"
                 ),
+                FeatureError::PayloadTooLarge(limit) => write!(
+                    f,
+                    "Could not fetch features because the upstream response exceeded {limit} bytes"
+                ),
+                FeatureError::CircuitOpen => write!(
+                    f,
+                    "Did not fetch features because upstream is failing and the circuit breaker is open"
+                ),
+                FeatureError::Unreachable => write!(
+                    f,
+                    "Could not fetch features because upstream could not be reached. Will retry"
+                ),
             },
 
+            EdgeError::AllEndpointDisabled(environment) => {
+                write!(f, "The /all endpoints are disabled for the {environment} environment")
+            }
             EdgeError::FeatureNotFound(name) => {
                 write!(f, "Failed to find feature with name {name}")
             }
@@ -167,6 +210,15 @@ impl Display for EdgeError {
                 write!(f, "Failed to build cert {cert_error:?}")
             }
             EdgeError::ClientBuildError(e) => write!(f, "Failed to build client {e:?}"),
+            EdgeError::InvalidJwks(msg) => write!(f, "Failed to load JWKS: {msg}"),
+            EdgeError::UpstreamEnvironmentMismatch(expected, actual) => write!(
+                f,
+                "Upstream returned features for environment {actual} when {expected} was requested"
+            ),
+            EdgeError::UpstreamRevisionRegression(last_seen, incoming) => write!(
+                f,
+                "Upstream returned revision {incoming}, which is older than the already seen revision {last_seen}"
+            ),
             EdgeError::InvalidServerUrl(msg) => write!(f, "Failed to parse server url: [{msg}]"),
             EdgeError::EdgeTokenError => write!(f, "Edge token error"),
             EdgeError::EdgeTokenParseError => write!(f, "Failed to parse token response"),
@@ -183,6 +235,9 @@ impl Display for EdgeError {
             EdgeError::ContextParseError => {
                 write!(f, "Failed to parse query parameters to frontend api")
             }
+            EdgeError::ContextTooLarge(message) => {
+                write!(f, "Context too large: {message}")
+            }
             EdgeError::HealthCheckError(message) => {
                 write!(f, "{message}")
             }
@@ -212,7 +267,19 @@ impl Display for EdgeError {
                 write!(f, "Edge is not ready to serve requests")
             }
             EdgeError::InvalidTokenWithStrictBehavior => write!(f, "Edge is running with strict behavior and the token is not subsumed by any registered tokens"),
+            EdgeError::RefreshRateLimited(retry_after) => write!(
+                f,
+                "A refresh was forced too recently. Try again in {retry_after} seconds"
+            ),
             EdgeError::SseError(message) => write!(f, "{}", message),
+            EdgeError::StartupTokenEnvironmentMismatch(expected, actual) => write!(
+                f,
+                "Upstream validated a startup token for environment {expected} as a token for environment {actual}"
+            ),
+            EdgeError::StreamLimitExceeded(limit) => write!(
+                f,
+                "This token already has the maximum of {limit} streaming connections open"
+            ),
             EdgeError::Forbidden(reason) => write!(f, "{}", reason),
         }
     }
@@ -231,7 +298,11 @@ impl ResponseError for EdgeError {
             EdgeError::ClientBuildError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::ClientFeaturesParseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::ClientFeaturesFetchError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EdgeError::InvalidJwks(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EdgeError::UpstreamEnvironmentMismatch(_, _) => StatusCode::BAD_GATEWAY,
+            EdgeError::UpstreamRevisionRegression(_, _) => StatusCode::BAD_GATEWAY,
             EdgeError::InvalidServerUrl(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EdgeError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
             EdgeError::PersistenceError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::JsonParseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::EdgeTokenError => StatusCode::BAD_REQUEST,
@@ -239,11 +310,13 @@ impl ResponseError for EdgeError {
             EdgeError::TokenValidationError(_) => StatusCode::BAD_REQUEST,
             EdgeError::AuthorizationPending => StatusCode::UNAUTHORIZED,
             EdgeError::FeatureNotFound(_) => StatusCode::NOT_FOUND,
+            EdgeError::AllEndpointDisabled(_) => StatusCode::NOT_FOUND,
             EdgeError::EdgeMetricsError => StatusCode::BAD_REQUEST,
             EdgeError::ClientRegisterError => StatusCode::BAD_REQUEST,
             EdgeError::ClientCertificateError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::FrontendNotYetHydrated(_) => StatusCode::NETWORK_AUTHENTICATION_REQUIRED,
             EdgeError::ContextParseError => StatusCode::BAD_REQUEST,
+            EdgeError::ContextTooLarge(_) => StatusCode::BAD_REQUEST,
             EdgeError::EdgeMetricsRequestError(status_code, _) => {
                 StatusCode::from_u16(status_code.as_u16()).unwrap()
             }
@@ -254,45 +327,86 @@ impl ResponseError for EdgeError {
             EdgeError::FrontendExpectedToBeHydrated(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EdgeError::NotReady => StatusCode::SERVICE_UNAVAILABLE,
             EdgeError::InvalidTokenWithStrictBehavior => StatusCode::FORBIDDEN,
+            EdgeError::RefreshRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             EdgeError::SseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EdgeError::StartupTokenEnvironmentMismatch(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
+            EdgeError::StreamLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
             EdgeError::Forbidden(_) => StatusCode::FORBIDDEN,
         }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
         match self {
-            EdgeError::FrontendNotYetHydrated(hydration_info) => {
-                HttpResponseBuilder::new(self.status_code()).json(json!({
-                    "explanation": "Edge does not yet have data for this token. Please make a call against /api/client/features with a client token that has the same access as your token",
-                    "access": hydration_info
-                }))
-            },
+            EdgeError::FrontendNotYetHydrated(hydration_info) => self.problem(
+                "Edge does not yet have data for this token. Please make a call against /api/client/features with a client token that has the same access as your token".into(),
+                json!({ "access": hydration_info }),
+            ),
             EdgeError::TokenParseError(token) => {
                 debug!("Failed to parse token: {}", token);
-                HttpResponseBuilder::new(self.status_code()).json(json!({
-                    "explanation": format!("Edge could not parse token: {}", token),
-                }))
-            },
+                self.problem(format!("Edge could not parse token: {}", token), json!({}))
+            }
             EdgeError::TokenValidationError(status_code) => {
                 debug!("Failed to validate token upstream");
-                HttpResponseBuilder::new(self.status_code()).json(json!({
-                    "explanation": format!("Received a non 200 status code when trying to validate token upstream"),
-                    "status_code": status_code.as_str()
-                }))
+                self.problem(
+                    "Received a non 200 status code when trying to validate token upstream".into(),
+                    json!({ "status_code": status_code.as_str() }),
+                )
             }
+            // Readiness probes parse this body as an EdgeStatus, so it keeps its own shape
             EdgeError::NotReady => {
                 HttpResponseBuilder::new(self.status_code()).json(json!({
                     "error": "Edge is not ready to serve requests",
                     "status": Status::NotReady
                 }))
             }
-            _ => HttpResponseBuilder::new(self.status_code()).json(json!({
-                "error": self.to_string()
-            }))
+            EdgeError::RefreshRateLimited(_) | EdgeError::StreamLimitExceeded(_) => {
+                let mut response = self.problem(self.to_string(), json!({}));
+                if let Some(retry_after) = self.retry_after() {
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                }
+                response
+            }
+            _ => self.problem(self.to_string(), json!({})),
         }
     }
 }
 
+impl EdgeError {
+    /// Seconds a client should wait before retrying a request that was refused with 429
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            EdgeError::RefreshRateLimited(retry_after) => Some(*retry_after),
+            EdgeError::StreamLimitExceeded(_) => Some(STREAM_LIMIT_RETRY_AFTER_SECS),
+            _ => None,
+        }
+    }
+
+    /// An RFC 9457 problem details body. `extensions` must be a JSON object, its members are added next to the standard ones
+    fn problem(
+        &self,
+        detail: String,
+        extensions: serde_json::Value,
+    ) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        let status = self.status_code();
+        let mut body = json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": detail,
+        });
+        if let (Some(body), serde_json::Value::Object(extensions)) =
+            (body.as_object_mut(), extensions)
+        {
+            body.extend(extensions);
+        }
+        HttpResponseBuilder::new(status)
+            .content_type(PROBLEM_JSON_CONTENT_TYPE)
+            .json(body)
+    }
+}
+
 impl From<serde_json::Error> for EdgeError {
     fn from(value: serde_json::Error) -> Self {
         EdgeError::JsonParseError(value.to_string())
@@ -306,4 +420,68 @@ impl From<SendError<Event>> for EdgeError {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use actix_web::{
+        body::to_bytes,
+        http::header::{CONTENT_TYPE, RETRY_AFTER},
+        ResponseError,
+    };
+    use serde_json::{json, Value};
+
+    use super::{EdgeError, PROBLEM_JSON_CONTENT_TYPE};
+
+    async fn problem_of(error: EdgeError) -> Value {
+        let response = error.error_response();
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            PROBLEM_JSON_CONTENT_TYPE
+        );
+        serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn invalid_token_is_a_problem_json_response() {
+        let body = problem_of(EdgeError::InvalidTokenWithStrictBehavior).await;
+        assert_eq!(
+            body,
+            json!({
+                "type": "about:blank",
+                "title": "Forbidden",
+                "status": 403,
+                "detail": "Edge is running with strict behavior and the token is not subsumed by any registered tokens",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn token_validation_error_keeps_the_upstream_status_code_as_an_extension() {
+        let body = problem_of(EdgeError::TokenValidationError(
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+        ))
+        .await;
+        assert_eq!(
+            body,
+            json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "Received a non 200 status code when trying to validate token upstream",
+                "status_code": "500",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_refresh_tells_the_client_when_to_retry() {
+        let response = EdgeError::RefreshRateLimited(7).error_response();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "7");
+    }
+
+    #[tokio::test]
+    async fn exceeded_stream_limit_tells_the_client_when_to_retry() {
+        let response = EdgeError::StreamLimitExceeded(2).error_response();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "30");
+    }
+}
@@ -5,8 +5,12 @@ use unleash_types::{
     Deduplicate,
 };
 use unleash_types::client_features::ClientFeaturesDelta;
+use crate::filters::{filter_client_features, FeatureFilterSet, FilterKey};
 use crate::types::EdgeToken;
 
+/// Upper bound on the estimated size of memoized filter results, since name prefixes come from requests
+const MAX_MEMOIZED_FILTER_BYTES: usize = 32 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub enum UpdateType {
     Full(String),
@@ -17,6 +21,8 @@ pub enum UpdateType {
 #[derive(Debug, Clone)]
 pub struct FeatureCache {
     features: DashMap<String, ClientFeatures>,
+    filtered: DashMap<(String, FilterKey), (ClientFeatures, usize)>,
+    max_memoized_bytes: usize,
     update_sender: broadcast::Sender<UpdateType>,
 }
 
@@ -25,6 +31,8 @@ impl FeatureCache {
         let (tx, _rx) = tokio::sync::broadcast::channel::<UpdateType>(16);
         Self {
             features,
+            filtered: DashMap::default(),
+            max_memoized_bytes: MAX_MEMOIZED_FILTER_BYTES,
             update_sender: tx,
         }
     }
@@ -46,7 +54,60 @@ impl FeatureCache {
         v
     }
 
+    /// Features for the key, filtered by the filter set. Results for filter sets with a key are memoized until the
+    /// features for the key change
+    pub(crate) fn get_filtered(
+        &self,
+        key: &str,
+        filters: &FeatureFilterSet,
+    ) -> Option<ClientFeatures> {
+        self.with_filtered(key, filters, ClientFeatures::clone)
+    }
+
+    /// Names of the features for the key that pass the filter set, memoized the same way as [Self::get_filtered]
+    pub(crate) fn get_filtered_names(
+        &self,
+        key: &str,
+        filters: &FeatureFilterSet,
+    ) -> Option<Vec<String>> {
+        self.with_filtered(key, filters, |filtered| {
+            filtered.features.iter().map(|f| f.name.clone()).collect()
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn memoized_filters(&self) -> usize {
+        self.filtered.len()
+    }
+
+    fn with_filtered<T>(
+        &self,
+        key: &str,
+        filters: &FeatureFilterSet,
+        read: impl FnOnce(&ClientFeatures) -> T,
+    ) -> Option<T> {
+        // Holding on to the features while memoizing keeps an update from slipping in between filtering and memoizing
+        let features = self.features.get(key)?;
+        let Some(filter_key) = filters.key() else {
+            return Some(read(&filter_client_features(&features, filters)));
+        };
+        let memo_key = (key.to_string(), filter_key.clone());
+        if let Some(memoized) = self.filtered.get(&memo_key) {
+            return Some(read(&memoized.0));
+        }
+        let filtered = filter_client_features(&features, filters);
+        let size = estimated_size(&memo_key, &filtered);
+        let memoized_bytes: usize = self.filtered.iter().map(|memoized| memoized.1).sum();
+        let result = read(&filtered);
+        if memoized_bytes + size <= self.max_memoized_bytes {
+            self.filtered.insert(memo_key, (filtered, size));
+        }
+        Some(result)
+    }
+
     pub fn send_full_update(&self, cache_key: String) {
+        self.filtered
+            .retain(|(filtered_key, _), _| *filtered_key != cache_key);
         let _ = self.update_sender.send(UpdateType::Full(cache_key));
     }
 
@@ -92,6 +153,14 @@ impl Default for FeatureCache {
     }
 }
 
+/// Roughly what memoizing the features costs, counting the key too since its name prefix comes from a request
+fn estimated_size((key, filter_key): &(String, FilterKey), features: &ClientFeatures) -> usize {
+    let key_size = key.len()
+        + filter_key.projects.iter().map(String::len).sum::<usize>()
+        + filter_key.name_prefix.as_ref().map_or(0, String::len);
+    key_size + serde_json::to_vec(features).map_or(0, |json| json.len())
+}
+
 fn update_client_features(
     token: &EdgeToken,
     old: &ClientFeatures,
@@ -149,3 +218,97 @@ fn merge_segments_update(
         (None, None) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use unleash_types::client_features::{ClientFeature, ClientFeatures};
+
+    use crate::filters::{FeatureFilterSet, FilterKey};
+
+    use super::FeatureCache;
+
+    fn features(names: &[&str]) -> ClientFeatures {
+        ClientFeatures {
+            version: 2,
+            features: names
+                .iter()
+                .map(|name| ClientFeature {
+                    name: name.to_string(),
+                    ..ClientFeature::default()
+                })
+                .collect(),
+            segments: None,
+            query: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn filtered_features_are_memoized_until_the_features_change() {
+        let cache = FeatureCache::default();
+        cache.insert("development".into(), features(&["a-one", "a-two", "b-one"]));
+        let filter_calls = Arc::new(AtomicUsize::new(0));
+        let counted_calls = filter_calls.clone();
+        let filters = FeatureFilterSet::from(Box::new(move |feature: &ClientFeature| {
+            counted_calls.fetch_add(1, Ordering::SeqCst);
+            feature.name.starts_with("a-")
+        }))
+        .with_key(FilterKey::new(vec!["*".into()], Some("a-".into())));
+
+        let first = cache.get_filtered("development", &filters).unwrap();
+        assert_eq!(first.features.len(), 2);
+        assert_eq!(filter_calls.load(Ordering::SeqCst), 3);
+
+        let second = cache.get_filtered("development", &filters).unwrap();
+        assert_eq!(second.features.len(), 2);
+        assert_eq!(filter_calls.load(Ordering::SeqCst), 3);
+
+        cache.insert("development".into(), features(&["a-one", "b-one"]));
+        let updated = cache.get_filtered("development", &filters).unwrap();
+        assert_eq!(updated.features.len(), 1);
+        assert_eq!(filter_calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn memoized_features_are_capped_by_their_estimated_size() {
+        let all_features = features(&["a-one", "a-two", "b-one"]);
+        let cache = FeatureCache {
+            max_memoized_bytes: serde_json::to_vec(&all_features).unwrap().len() * 3 / 2,
+            ..FeatureCache::default()
+        };
+        cache.insert("development".into(), all_features);
+        let filters_for = |prefix: &str| {
+            let name_prefix = prefix.to_string();
+            FeatureFilterSet::from(Box::new(move |feature: &ClientFeature| {
+                feature.name.starts_with(&name_prefix)
+            }))
+            .with_key(FilterKey::new(vec!["*".into()], Some(prefix.into())))
+        };
+
+        assert_eq!(
+            cache
+                .get_filtered("development", &filters_for(""))
+                .unwrap()
+                .features
+                .len(),
+            3
+        );
+        assert_eq!(cache.memoized_filters(), 1);
+
+        let filtered = cache
+            .get_filtered("development", &filters_for("a-"))
+            .unwrap();
+        assert_eq!(filtered.features.len(), 2);
+        assert_eq!(cache.memoized_filters(), 1);
+
+        cache.insert("development".into(), features(&["a-one"]));
+        assert_eq!(cache.memoized_filters(), 0);
+        cache
+            .get_filtered("development", &filters_for("a-"))
+            .unwrap();
+        assert_eq!(cache.memoized_filters(), 1);
+    }
+}
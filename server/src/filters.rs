@@ -1,19 +1,60 @@
+use actix_web::web::Data;
+use actix_web::HttpRequest;
 use dashmap::mapref::one::Ref;
+use tracing::warn;
 use unleash_types::client_features::{ClientFeature, ClientFeatures};
 
+use crate::http::refresher::feature_refresher::FeatureRefresher;
 use crate::types::EdgeToken;
 
 pub type FeatureFilter = Box<dyn Fn(&ClientFeature) -> bool>;
 
+/// Describes what a filter set filters on, so features filtered by it can be memoized. Two filter sets with the same
+/// key must keep the same features
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FilterKey {
+    pub projects: Vec<String>,
+    pub name_prefix: Option<String>,
+    pub hide_stale: bool,
+    pub frontend: bool,
+}
+
+impl FilterKey {
+    pub fn new(mut projects: Vec<String>, name_prefix: Option<String>) -> Self {
+        projects.sort();
+        Self {
+            projects,
+            name_prefix,
+            hide_stale: false,
+            frontend: false,
+        }
+    }
+
+    pub fn with_hide_stale(self, hide_stale: bool) -> Self {
+        Self { hide_stale, ..self }
+    }
+
+    /// The frontend API counts features without a project as part of the default project, so its filters keep
+    /// different features than the client API's for the same projects
+    pub fn for_frontend(self) -> Self {
+        Self {
+            frontend: true,
+            ..self
+        }
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct FeatureFilterSet {
     filters: Vec<FeatureFilter>,
+    key: Option<FilterKey>,
 }
 
 impl FeatureFilterSet {
     pub fn from(filter: FeatureFilter) -> Self {
         Self {
             filters: vec![filter],
+            key: None,
         }
     }
 
@@ -22,6 +63,16 @@ impl FeatureFilterSet {
         self
     }
 
+    /// Lets the feature cache memoize the features this set filters out, see [crate::feature_cache::FeatureCache::get_filtered]
+    pub fn with_key(mut self, key: FilterKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn key(&self) -> Option<&FilterKey> {
+        self.key.as_ref()
+    }
+
     pub fn apply(&self, feature: &ClientFeature) -> bool {
         self.filters.iter().all(|filter| filter(feature))
     }
@@ -31,27 +82,48 @@ fn filter_features(
     feature_cache: &Ref<'_, String, ClientFeatures>,
     filters: &FeatureFilterSet,
 ) -> Vec<ClientFeature> {
-    feature_cache
+    let mut features = feature_cache
         .features
         .iter()
         .filter(|feature| filters.apply(feature))
         .cloned()
-        .collect::<Vec<ClientFeature>>()
+        .collect::<Vec<ClientFeature>>();
+    features.sort_by(|a, b| a.name.cmp(&b.name));
+    features
 }
 
+/// Features are sorted by name and segments by id, so the same content always serializes to the same bytes and ETag,
+/// however upstream happened to order it
 pub(crate) fn filter_client_features(
     feature_cache: &Ref<'_, String, ClientFeatures>,
     filters: &FeatureFilterSet,
 ) -> ClientFeatures {
+    let segments = feature_cache.segments.clone().map(|mut segments| {
+        segments.sort_by_key(|segment| segment.id);
+        segments
+    });
     ClientFeatures {
         features: filter_features(feature_cache, filters),
-        segments: feature_cache.segments.clone(),
+        segments,
         query: feature_cache.query.clone(),
         version: feature_cache.version,
         meta: feature_cache.meta.clone(),
     }
 }
 
+/// Trims and lowercases a feature name, used when Edge runs with `--normalize-feature-names`
+pub(crate) fn normalize_feature_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Names coming from requests have to be normalized the same way as the cached features before we can match on them
+pub(crate) fn requested_feature_name(req: &HttpRequest, name: String) -> String {
+    match req.app_data::<Data<FeatureRefresher>>() {
+        Some(refresher) if refresher.normalize_feature_names => normalize_feature_name(&name),
+        _ => name,
+    }
+}
+
 pub(crate) fn name_prefix_filter(name_prefix: String) -> FeatureFilter {
     Box::new(move |f| f.name.starts_with(&name_prefix))
 }
@@ -60,12 +132,67 @@ pub(crate) fn name_match_filter(name_prefix: String) -> FeatureFilter {
     Box::new(move |f| f.name.starts_with(&name_prefix))
 }
 
+/// Drops features marked as stale, used when Edge runs with `--hide-stale-features`
+pub(crate) fn stale_filter() -> FeatureFilter {
+    Box::new(|feature| !feature.stale.unwrap_or(false))
+}
+
+/// Drops features with more strategies than `max_strategies`, used when Edge runs with `--max-strategies-per-feature`
+pub(crate) fn max_strategies_filter(max_strategies: usize) -> FeatureFilter {
+    Box::new(move |feature| {
+        let strategies = feature.strategies.as_ref().map_or(0, Vec::len);
+        if strategies > max_strategies {
+            warn!(
+                "Feature {} has {strategies} strategies, more than the maximum of {max_strategies}. Leaving it out of frontend evaluations",
+                feature.name
+            );
+        }
+        strategies <= max_strategies
+    })
+}
+
+/// The features frontend evaluation engines are built from. Client features responses are not affected
+pub(crate) fn frontend_features(
+    features: ClientFeatures,
+    max_strategies: Option<usize>,
+    hide_stale: bool,
+) -> ClientFeatures {
+    if max_strategies.is_none() && !hide_stale {
+        return features;
+    }
+    let mut filters = FeatureFilterSet::default();
+    if let Some(max_strategies) = max_strategies {
+        filters = filters.with_filter(max_strategies_filter(max_strategies));
+    }
+    if hide_stale {
+        filters = filters.with_filter(stale_filter());
+    }
+    ClientFeatures {
+        features: features
+            .features
+            .into_iter()
+            .filter(|f| filters.apply(f))
+            .collect(),
+        ..features
+    }
+}
+
+/// Projects prefixed with `!` are excluded, e.g. `["!internal"]` or `["*", "!internal"]` keep every project except
+/// internal. An exclusion wins over the same project being included
 pub(crate) fn project_filter_from_projects(projects: Vec<String>) -> FeatureFilter {
+    let (excluded, included): (Vec<String>, Vec<String>) = projects
+        .into_iter()
+        .partition(|project| project.starts_with('!'));
+    let excluded: Vec<String> = excluded
+        .into_iter()
+        .map(|project| project[1..].to_string())
+        .collect();
     Box::new(move |feature| {
         if let Some(feature_project) = &feature.project {
-            projects.is_empty()
-                || projects.contains(&"*".to_string())
-                || projects.contains(feature_project)
+            (included.is_empty()
+                || included.contains(&"*".to_string())
+                || included.contains(feature_project))
+                && !excluded.contains(feature_project)
         } else {
             false
         }
@@ -76,10 +203,22 @@ pub(crate) fn project_filter(token: &EdgeToken) -> FeatureFilter {
     project_filter_from_projects(token.projects.clone())
 }
 
+/// Keeps the features a frontend token can see, the same way [crate::frontend_api::frontend_from_yggdrasil] checks
+/// evaluated toggles against the token's projects
+pub(crate) fn frontend_project_filter(token: &EdgeToken) -> FeatureFilterSet {
+    let projects = token.projects.clone();
+    FeatureFilterSet::from(Box::new(move |feature: &ClientFeature| {
+        let project = feature.project.as_deref().unwrap_or("default");
+        projects.iter().any(|p| p == project)
+    }))
+    .with_key(FilterKey::new(token.projects.clone(), None).for_frontend())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use dashmap::DashMap;
+    use test_case::test_case;
     use unleash_types::client_features::{ClientFeature, ClientFeatures};
 
     #[test]
@@ -249,4 +388,30 @@ mod tests {
         assert_eq!(filtered_features[0].name, "feature-one".to_string());
         assert_eq!(filtered_features[1].name, "feature-two".to_string());
     }
+
+    #[test_case(vec!["default"], vec!["feature-one"]; "include only")]
+    #[test_case(vec!["!internal"], vec!["feature-one", "feature-three"]; "exclude only")]
+    #[test_case(vec!["*", "!internal"], vec!["feature-one", "feature-three"]; "wildcard with exclusion")]
+    #[test_case(vec!["default", "internal", "!internal"], vec!["feature-one"]; "exclusion wins over inclusion")]
+    fn project_filter_supports_excluding_projects(projects: Vec<&str>, expected: Vec<&str>) {
+        let features = [
+            ("feature-one", "default"),
+            ("feature-two", "internal"),
+            ("feature-three", "other"),
+        ]
+        .map(|(name, project)| ClientFeature {
+            name: name.into(),
+            project: Some(project.into()),
+            ..ClientFeature::default()
+        });
+        let filter = project_filter_from_projects(projects.into_iter().map(String::from).collect());
+
+        let filtered: Vec<&str> = features
+            .iter()
+            .filter(|feature| filter(feature))
+            .map(|feature| feature.name.as_str())
+            .collect();
+
+        assert_eq!(filtered, expected);
+    }
 }
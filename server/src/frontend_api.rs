@@ -1,7 +1,8 @@
 use actix_http::body::MessageBody;
 use actix_http::HttpMessage;
 use actix_service::ServiceFactory;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::{
@@ -10,9 +11,11 @@ use actix_web::{
     HttpRequest, HttpResponse, Scope,
 };
 use dashmap::DashMap;
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, HistogramVec};
 use serde_qs::actix::QsQuery;
 use tracing::debug;
-use unleash_types::client_features::Context;
+use unleash_types::client_features::{ClientFeature, ClientFeatures, Context};
 use unleash_types::client_metrics::{ClientApplication, ConnectVia};
 use unleash_types::{
     client_metrics::ClientMetrics,
@@ -20,7 +23,13 @@ use unleash_types::{
 };
 use unleash_yggdrasil::{EngineState, ResolvedToggle};
 
-use crate::types::{ClientIp, IncomingContext, PostContext};
+use crate::feature_cache::FeatureCache;
+use crate::filters::{frontend_project_filter, requested_feature_name};
+use crate::single_flight::EvaluationCoalescer;
+use crate::types::{
+    ClientIp, EvaluationExplanation, EvaluationReason, IncomingContext, PostContext,
+    StrategyEvaluation,
+};
 use crate::{
     error::{EdgeError, FrontendHydrationMissing},
     metrics::client_metrics::MetricsCache,
@@ -28,6 +37,24 @@ use crate::{
     types::{EdgeJsonResult, EdgeResult, EdgeToken},
 };
 
+lazy_static! {
+    pub static ref FRONTEND_EVAL_DURATION: HistogramVec = register_histogram_vec!(
+        "frontend_eval_duration_milliseconds",
+        "Time spent evaluating frontend API requests in milliseconds, excluding request parsing and response serialization",
+        &["environment"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0]
+    )
+    .unwrap();
+}
+
+/// Only covers evaluation, so it doesn't overlap with the HTTP request duration metric, which already
+/// includes extracting, authorizing and serializing the request.
+fn observe_eval_duration(environment: Option<&str>, started: Instant) {
+    FRONTEND_EVAL_DURATION
+        .with_label_values(&[environment.unwrap_or_default()])
+        .observe(started.elapsed().as_secs_f64() * 1000.0);
+}
+
 ///
 /// Returns all evaluated toggles for the key used
 #[utoipa::path(
@@ -50,13 +77,19 @@ pub async fn get_proxy_all_features(
     context: QsQuery<IncomingContext>,
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
+    check_all_endpoint_access(&req, &edge_token)?;
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
     get_all_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         &context.into_inner().into(),
-        req.extensions().get::<ClientIp>(),
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
 #[utoipa::path(
@@ -78,13 +111,19 @@ pub async fn get_frontend_all_features(
     context: QsQuery<IncomingContext>,
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
+    check_all_endpoint_access(&req, &edge_token)?;
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
     get_all_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         &context.into_inner().into(),
-        req.extensions().get::<ClientIp>(),
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
 #[utoipa::path(
@@ -107,13 +146,19 @@ async fn post_proxy_all_features(
     context: Json<PostContext>,
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
+    check_all_endpoint_access(&req, &edge_token)?;
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
     post_all_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         context,
-        req.extensions().get::<ClientIp>(),
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
 #[utoipa::path(
@@ -132,7 +177,9 @@ async fn post_all_proxy_metrics(
     edge_token: EdgeToken,
     metrics: Json<ClientMetrics>,
     metrics_cache: Data<MetricsCache>,
+    req: HttpRequest,
 ) -> EdgeResult<HttpResponse> {
+    check_all_endpoint_access(&req, &edge_token)?;
     crate::metrics::client_metrics::register_client_metrics(
         edge_token,
         metrics.into_inner(),
@@ -158,7 +205,9 @@ async fn post_all_frontend_metrics(
     edge_token: EdgeToken,
     metrics: Json<ClientMetrics>,
     metrics_cache: Data<MetricsCache>,
+    req: HttpRequest,
 ) -> EdgeResult<HttpResponse> {
+    check_all_endpoint_access(&req, &edge_token)?;
     crate::metrics::client_metrics::register_client_metrics(
         edge_token,
         metrics.into_inner(),
@@ -188,23 +237,35 @@ async fn post_frontend_all_features(
     context: Json<PostContext>,
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
+    check_all_endpoint_access(&req, &edge_token)?;
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
     post_all_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         context,
-        req.extensions().get::<ClientIp>(),
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
-fn post_all_features(
+#[allow(clippy::too_many_arguments)]
+async fn post_all_features(
     edge_token: EdgeToken,
     engine_cache: Data<DashMap<String, EngineState>>,
+    features_cache: Option<&FeatureCache>,
     token_cache: Data<DashMap<String, EdgeToken>>,
     incoming_context: Json<PostContext>,
-    client_ip: Option<&ClientIp>,
+    client_ip: Option<ClientIp>,
+    coalescer: Option<&EvaluationCoalescer>,
+    context_limits: Option<&ContextLimits>,
 ) -> EdgeJsonResult<FrontendResult> {
+    let started = Instant::now();
     let context: Context = incoming_context.into_inner().into();
+    check_context_limits(context_limits, &context)?;
     let context_with_ip = if context.remote_address.is_none() {
         Context {
             remote_address: client_ip.map(|ip| ip.to_string()),
@@ -218,15 +279,28 @@ fn post_all_features(
         .map(|e| e.value().clone())
         .unwrap_or_else(|| edge_token.clone());
     let key = cache_key(&token);
-    let engine = engine_cache.get(&key).ok_or_else(|| {
-        EdgeError::FrontendNotYetHydrated(FrontendHydrationMissing::from(&edge_token))
-    })?;
-    let feature_results = engine.resolve_all(&context_with_ip, &None).ok_or_else(|| {
+    if !engine_cache.contains_key(&key) {
+        return Err(EdgeError::FrontendNotYetHydrated(
+            FrontendHydrationMissing::from(&edge_token),
+        ));
+    }
+    let feature_results = resolve_all(
+        &engine_cache,
+        features_cache,
+        &key,
+        &token,
+        &context_with_ip,
+        coalescer,
+    )
+    .await
+    .ok_or_else(|| {
         EdgeError::FrontendExpectedToBeHydrated(
             "Feature cache has not been hydrated yet, but it was expected to be. This can be due to a race condition from calling edge before it's ready. This error might auto resolve as soon as edge is able to fetch from upstream".into(),
         )
     })?;
-    Ok(Json(frontend_from_yggdrasil(feature_results, true, &token)))
+    let result = frontend_from_yggdrasil(feature_results, true, &token);
+    observe_eval_duration(token.environment.as_deref(), started);
+    Ok(Json(result))
 }
 
 #[utoipa::path(
@@ -249,13 +323,18 @@ async fn get_enabled_proxy(
     context: QsQuery<IncomingContext>,
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
     get_enabled_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         context.into_inner(),
-        req.extensions().get::<ClientIp>().cloned(),
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
 #[utoipa::path(
@@ -283,20 +362,30 @@ async fn get_enabled_frontend(
     get_enabled_features(
         edge_token,
         engine_cache,
+        features_cache(&req),
         token_cache,
         context.into_inner(),
         client_ip,
+        coalescer(&req),
+        context_limits(&req),
     )
+    .await
 }
 
-fn get_enabled_features(
+#[allow(clippy::too_many_arguments)]
+async fn get_enabled_features(
     edge_token: EdgeToken,
     engine_cache: Data<DashMap<String, EngineState>>,
+    features_cache: Option<&FeatureCache>,
     token_cache: Data<DashMap<String, EdgeToken>>,
     incoming_context: IncomingContext,
     client_ip: Option<ClientIp>,
+    coalescer: Option<&EvaluationCoalescer>,
+    context_limits: Option<&ContextLimits>,
 ) -> EdgeJsonResult<FrontendResult> {
+    let started = Instant::now();
     let context: Context = incoming_context.into();
+    check_context_limits(context_limits, &context)?;
     let context_with_ip = if context.remote_address.is_none() {
         Context {
             remote_address: client_ip.map(|ip| ip.to_string()),
@@ -310,19 +399,28 @@ fn get_enabled_features(
         .map(|e| e.value().clone())
         .unwrap_or_else(|| edge_token.clone());
     let key = cache_key(&token);
-    let engine = engine_cache.get(&key).ok_or_else(|| {
-        EdgeError::FrontendNotYetHydrated(FrontendHydrationMissing::from(&edge_token))
-    })?;
-    let feature_results = engine.resolve_all(&context_with_ip, &None).ok_or_else(|| {
+    if !engine_cache.contains_key(&key) {
+        return Err(EdgeError::FrontendNotYetHydrated(
+            FrontendHydrationMissing::from(&edge_token),
+        ));
+    }
+    let feature_results = resolve_all(
+        &engine_cache,
+        features_cache,
+        &key,
+        &token,
+        &context_with_ip,
+        coalescer,
+    )
+    .await
+    .ok_or_else(|| {
         EdgeError::FrontendExpectedToBeHydrated(
             "Feature cache has not been hydrated yet, but it was expected to be. This can be due to a race condition from calling edge before it's ready. This error might auto resolve as soon as edge is able to fetch from upstream".into(),
         )
     })?;
-    Ok(Json(frontend_from_yggdrasil(
-        feature_results,
-        false,
-        &token,
-    )))
+    let result = frontend_from_yggdrasil(feature_results, false, &token);
+    observe_eval_duration(token.environment.as_deref(), started);
+    Ok(Json(result))
 }
 
 #[utoipa::path(
@@ -346,7 +444,17 @@ async fn post_proxy_enabled_features(
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
     let client_ip = req.extensions().get::<ClientIp>().cloned();
-    post_enabled_features(edge_token, engine_cache, token_cache, context, client_ip).await
+    post_enabled_features(
+        edge_token,
+        engine_cache,
+        features_cache(&req),
+        token_cache,
+        context,
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
+    )
+    .await
 }
 
 #[utoipa::path(
@@ -370,7 +478,17 @@ async fn post_frontend_enabled_features(
     req: HttpRequest,
 ) -> EdgeJsonResult<FrontendResult> {
     let client_ip = req.extensions().get::<ClientIp>().cloned();
-    post_enabled_features(edge_token, engine_cache, token_cache, context, client_ip).await
+    post_enabled_features(
+        edge_token,
+        engine_cache,
+        features_cache(&req),
+        token_cache,
+        context,
+        client_ip,
+        coalescer(&req),
+        context_limits(&req),
+    )
+    .await
 }
 
 #[utoipa::path(
@@ -396,15 +514,19 @@ pub async fn post_frontend_evaluate_single_feature(
     token_cache: Data<DashMap<String, EdgeToken>>,
     req: HttpRequest,
 ) -> EdgeJsonResult<EvaluatedToggle> {
-    evaluate_feature(
+    let started = Instant::now();
+    let environment = edge_token.environment.clone();
+    let evaluated = evaluate_feature(
         edge_token,
-        feature_name.into_inner(),
+        requested_feature_name(&req, feature_name.into_inner()),
         &context.into_inner().into(),
         token_cache,
         engine_cache,
         req.extensions().get::<ClientIp>().cloned(),
-    )
-    .map(Json)
+        context_limits(&req),
+    )?;
+    observe_eval_duration(environment.as_deref(), started);
+    Ok(Json(evaluated))
 }
 
 #[utoipa::path(
@@ -432,15 +554,92 @@ pub async fn get_frontend_evaluate_single_feature(
     token_cache: Data<DashMap<String, EdgeToken>>,
     req: HttpRequest,
 ) -> EdgeJsonResult<EvaluatedToggle> {
-    evaluate_feature(
+    let started = Instant::now();
+    let environment = edge_token.environment.clone();
+    let evaluated = evaluate_feature(
         edge_token,
-        feature_name.into_inner(),
+        requested_feature_name(&req, feature_name.into_inner()),
         &context.into_inner().into(),
         token_cache,
         engine_cache,
         req.extensions().get::<ClientIp>().cloned(),
-    )
-    .map(Json)
+        context_limits(&req),
+    )?;
+    observe_eval_duration(environment.as_deref(), started);
+    Ok(Json(evaluated))
+}
+
+#[utoipa::path(
+context_path = "/api/frontend",
+responses(
+(status = 200, description = "Return, for each context in the request, the feature toggles for this token that evaluated to true. Results are in the same order as the contexts", body = Vec<FrontendResult>),
+(status = 403, description = "Was not allowed to access features"),
+(status = 400, description = "Invalid parameters used, or more contexts than allowed")
+),
+request_body = Vec<Context>,
+security(
+("Authorization" = [])
+)
+)]
+#[post("/bulk")]
+pub async fn post_frontend_bulk_evaluate(
+    edge_token: EdgeToken,
+    engine_cache: Data<DashMap<String, EngineState>>,
+    token_cache: Data<DashMap<String, EdgeToken>>,
+    contexts: Json<Vec<PostContext>>,
+    req: HttpRequest,
+) -> EdgeJsonResult<Vec<FrontendResult>> {
+    let contexts = contexts.into_inner();
+    let limits = context_limits(&req);
+    let max_bulk_contexts = limits.map_or(DEFAULT_MAX_BULK_CONTEXTS, |l| l.max_bulk_contexts);
+    if contexts.len() > max_bulk_contexts {
+        return Err(EdgeError::ContextTooLarge(format!(
+            "Got {} contexts, but at most {max_bulk_contexts} can be evaluated in one request",
+            contexts.len()
+        )));
+    }
+    let started = Instant::now();
+    let client_ip = req.extensions().get::<ClientIp>().cloned();
+    let token = token_cache
+        .get(&edge_token.token)
+        .map(|e| e.value().clone())
+        .unwrap_or_else(|| edge_token.clone());
+    let key = cache_key(&token);
+    if !engine_cache.contains_key(&key) {
+        return Err(EdgeError::FrontendNotYetHydrated(
+            FrontendHydrationMissing::from(&edge_token),
+        ));
+    }
+    let mut results = Vec::with_capacity(contexts.len());
+    for context in contexts {
+        let context: Context = context.into();
+        check_context_limits(limits, &context)?;
+        let context_with_ip = if context.remote_address.is_none() {
+            Context {
+                remote_address: client_ip.as_ref().map(|ip| ip.to_string()),
+                ..context
+            }
+        } else {
+            context
+        };
+        let feature_results = resolve_all(
+            &engine_cache,
+            features_cache(&req),
+            &key,
+            &token,
+            &context_with_ip,
+            coalescer(&req),
+        )
+        .await
+        .ok_or_else(|| {
+                EdgeError::FrontendExpectedToBeHydrated(
+                    "Feature cache has not been hydrated yet, but it was expected to be. This can be due to a race condition from calling edge before it's ready. This error might auto resolve as soon as edge is able to fetch from upstream".into(),
+                )
+            })?;
+        results.push(frontend_from_yggdrasil(feature_results, false, &token));
+    }
+    observe_eval_duration(token.environment.as_deref(), started);
+    Ok(Json(results))
 }
 
 pub fn evaluate_feature(
@@ -450,7 +649,9 @@ pub fn evaluate_feature(
     token_cache: Data<DashMap<String, EdgeToken>>,
     engine_cache: Data<DashMap<String, EngineState>>,
     client_ip: Option<ClientIp>,
+    context_limits: Option<&ContextLimits>,
 ) -> EdgeResult<EvaluatedToggle> {
+    check_context_limits(context_limits, incoming_context)?;
     let context: Context = incoming_context.clone();
     let context_with_ip = if context.remote_address.is_none() {
         Context {
@@ -460,11 +661,8 @@ pub fn evaluate_feature(
     } else {
         context
     };
-    let validated_token = token_cache
-        .get(&edge_token.token)
-        .ok_or(EdgeError::EdgeTokenError)?
-        .value()
-        .clone();
+    let validated_token =
+        tokens::known_token(&token_cache, &edge_token).ok_or(EdgeError::EdgeTokenError)?;
     engine_cache
         .get(&cache_key(&validated_token))
         .and_then(|engine| engine.resolve(&feature_name, &context_with_ip, &None))
@@ -491,14 +689,149 @@ pub fn evaluate_feature(
         .ok_or_else(|| EdgeError::FeatureNotFound(feature_name.clone()))
 }
 
+#[utoipa::path(
+context_path = "/api/frontend/debug",
+params(
+    ("feature_name" = String, Path, description = "Name of the feature"),
+),
+responses(
+(status = 200, description = "Explains which strategy decided the evaluation of the feature with name `name`", body = EvaluationExplanation),
+(status = 403, description = "Was not allowed to access features"),
+(status = 404, description = "Feature was not found"),
+(status = 400, description = "Invalid parameters used")
+),
+request_body = Context,
+security(
+("Authorization" = [])
+)
+)]
+#[post("/features/{feature_name}")]
+pub async fn post_frontend_explain_feature(
+    edge_token: EdgeToken,
+    feature_name: Path<String>,
+    context: Json<PostContext>,
+    engine_cache: Data<DashMap<String, EngineState>>,
+    token_cache: Data<DashMap<String, EdgeToken>>,
+    features_cache: Data<FeatureCache>,
+    req: HttpRequest,
+) -> EdgeJsonResult<EvaluationExplanation> {
+    let context = context.into_inner().into();
+    check_context_limits(context_limits(&req), &context)?;
+    explain_feature(
+        edge_token,
+        requested_feature_name(&req, feature_name.into_inner()),
+        &context,
+        token_cache,
+        engine_cache,
+        features_cache,
+        req.extensions().get::<ClientIp>().cloned(),
+    )
+    .map(Json)
+}
+
+/// Yggdrasil only exposes the combined result of a feature's strategies, so to find out which one matched
+/// each strategy is compiled and evaluated on its own against the same context.
+pub fn explain_feature(
+    edge_token: EdgeToken,
+    feature_name: String,
+    incoming_context: &Context,
+    token_cache: Data<DashMap<String, EdgeToken>>,
+    engine_cache: Data<DashMap<String, EngineState>>,
+    features_cache: Data<FeatureCache>,
+    client_ip: Option<ClientIp>,
+) -> EdgeResult<EvaluationExplanation> {
+    let context = if incoming_context.remote_address.is_none() {
+        Context {
+            remote_address: client_ip.map(|ip| ip.to_string()),
+            ..incoming_context.clone()
+        }
+    } else {
+        incoming_context.clone()
+    };
+    let evaluated = evaluate_feature(
+        edge_token.clone(),
+        feature_name.clone(),
+        &context,
+        token_cache.clone(),
+        engine_cache,
+        None,
+        None,
+    )?;
+    let validated_token =
+        tokens::known_token(&token_cache, &edge_token).ok_or(EdgeError::EdgeTokenError)?;
+    let features = features_cache
+        .get(&cache_key(&validated_token))
+        .ok_or_else(|| EdgeError::FeatureNotFound(feature_name.clone()))?;
+    let feature = features
+        .features
+        .iter()
+        .find(|f| f.name == feature_name)
+        .ok_or_else(|| EdgeError::FeatureNotFound(feature_name.clone()))?;
+
+    let strategies: Vec<StrategyEvaluation> = feature
+        .strategies
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(index, strategy)| {
+            let name = strategy.name.clone();
+            let mut engine = EngineState::default();
+            engine.take_state(ClientFeatures {
+                version: features.version,
+                features: vec![ClientFeature {
+                    enabled: true,
+                    strategies: Some(vec![strategy]),
+                    dependencies: None,
+                    ..feature.clone()
+                }],
+                segments: features.segments.clone(),
+                query: None,
+                meta: None,
+            });
+            StrategyEvaluation {
+                index,
+                name,
+                matched: engine.check_enabled(&feature_name, &context, &None) == Some(true),
+            }
+        })
+        .collect();
+    let matched_strategy = strategies.iter().find(|s| s.matched).cloned();
+    let reason = if !feature.enabled {
+        EvaluationReason::FeatureDisabled
+    } else if strategies.is_empty() {
+        EvaluationReason::NoStrategies
+    } else if matched_strategy.is_none() {
+        EvaluationReason::NoStrategyMatched
+    } else if evaluated.enabled {
+        EvaluationReason::StrategyMatched
+    } else {
+        EvaluationReason::DependenciesNotSatisfied
+    };
+    Ok(EvaluationExplanation {
+        name: feature_name,
+        enabled: evaluated.enabled,
+        variant: evaluated.variant,
+        reason,
+        matched_strategy,
+        strategies,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn post_enabled_features(
     edge_token: EdgeToken,
     engine_cache: Data<DashMap<String, EngineState>>,
+    features_cache: Option<&FeatureCache>,
     token_cache: Data<DashMap<String, EdgeToken>>,
     context: Json<PostContext>,
     client_ip: Option<ClientIp>,
+    coalescer: Option<&EvaluationCoalescer>,
+    context_limits: Option<&ContextLimits>,
 ) -> EdgeJsonResult<FrontendResult> {
+    let started = Instant::now();
     let context: Context = context.into_inner().into();
+    check_context_limits(context_limits, &context)?;
     let context_with_ip = if context.remote_address.is_none() {
         Context {
             remote_address: client_ip.map(|ip| ip.to_string()),
@@ -511,22 +844,30 @@ async fn post_enabled_features(
         .get(&edge_token.token)
         .map(|e| e.value().clone())
         .unwrap_or_else(|| edge_token.clone());
-    let engine = engine_cache
-        .get(&tokens::cache_key(&edge_token))
-        .ok_or_else(|| {
-            EdgeError::FrontendNotYetHydrated(FrontendHydrationMissing::from(&edge_token))
-        })?;
-    let feature_results = engine.resolve_all(&context_with_ip, &None).ok_or_else(|| {
+    let key = tokens::cache_key(&edge_token);
+    if !engine_cache.contains_key(&key) {
+        return Err(EdgeError::FrontendNotYetHydrated(
+            FrontendHydrationMissing::from(&edge_token),
+        ));
+    }
+    let feature_results = resolve_all(
+        &engine_cache,
+        features_cache,
+        &key,
+        &token,
+        &context_with_ip,
+        coalescer,
+    )
+    .await
+    .ok_or_else(|| {
         EdgeError::FrontendExpectedToBeHydrated(
             "Feature cache has not been hydrated yet, but it was expected to be. This can be due to a race condition from calling edge before it's ready. This error might auto resolve as soon as edge is able to fetch from upstream".into(),
         )
     })?;
 
-    Ok(Json(frontend_from_yggdrasil(
-        feature_results,
-        false,
-        &token,
-    )))
+    let result = frontend_from_yggdrasil(feature_results, false, &token);
+    observe_eval_duration(token.environment.as_deref(), started);
+    Ok(Json(result))
 }
 
 #[utoipa::path(
@@ -635,31 +976,20 @@ pub async fn post_frontend_register(
     Ok(HttpResponse::Accepted().finish())
 }
 
-fn configure_frontend_endpoints(cfg: &mut web::ServiceConfig, disable_all_endpoint: bool) {
-    if !disable_all_endpoint {
-        cfg.service(
-            scope_with_auth("/frontend")
-                .service(get_frontend_all_features)
-                .service(post_frontend_all_features)
-                .service(get_enabled_frontend)
-                .service(post_frontend_metrics)
-                .service(post_frontend_enabled_features)
-                .service(post_frontend_register)
-                .service(post_frontend_evaluate_single_feature)
-                .service(get_frontend_evaluate_single_feature)
-                .service(post_all_frontend_metrics),
-        );
-    } else {
-        cfg.service(
-            scope_with_auth("/frontend")
-                .service(get_enabled_frontend)
-                .service(post_frontend_metrics)
-                .service(post_frontend_enabled_features)
-                .service(post_frontend_register)
-                .service(post_frontend_evaluate_single_feature)
-                .service(get_frontend_evaluate_single_feature),
-        );
-    }
+fn configure_frontend_endpoints(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        scope_with_auth("/frontend")
+            .service(get_frontend_all_features)
+            .service(post_frontend_all_features)
+            .service(get_enabled_frontend)
+            .service(post_frontend_metrics)
+            .service(post_frontend_enabled_features)
+            .service(post_frontend_register)
+            .service(post_frontend_evaluate_single_feature)
+            .service(get_frontend_evaluate_single_feature)
+            .service(post_frontend_bulk_evaluate)
+            .service(post_all_frontend_metrics),
+    );
 }
 
 fn scope_with_auth(
@@ -685,32 +1015,221 @@ fn scope_with_auth(
         ))
 }
 
-fn configure_proxy_endpoints(cfg: &mut web::ServiceConfig, disable_all_endpoint: bool) {
-    if !disable_all_endpoint {
-        cfg.service(
-            scope_with_auth("/proxy")
-                .service(get_proxy_all_features)
-                .service(post_proxy_all_features)
-                .service(get_enabled_proxy)
-                .service(post_proxy_metrics)
-                .service(post_proxy_enabled_features)
-                .service(post_proxy_register)
-                .service(post_all_proxy_metrics),
-        );
+fn configure_proxy_endpoints(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        scope_with_auth("/proxy")
+            .service(get_proxy_all_features)
+            .service(post_proxy_all_features)
+            .service(get_enabled_proxy)
+            .service(post_proxy_metrics)
+            .service(post_proxy_enabled_features)
+            .service(post_proxy_register)
+            .service(post_all_proxy_metrics),
+    );
+}
+
+/// The `/all` endpoints are always routed, so whether they are available can be decided per request from the
+/// environment of the token, as configured by `all_endpoint_access`
+pub fn configure_frontend_api(
+    cfg: &mut web::ServiceConfig,
+    all_endpoint_access: AllEndpointAccess,
+) {
+    cfg.app_data(Data::new(all_endpoint_access));
+    configure_proxy_endpoints(cfg);
+    configure_frontend_endpoints(cfg);
+}
+
+/// Registers the strategy explanation endpoint when `enable_eval_debug` is set. Since `/frontend/debug` is nested
+/// under `/frontend`, this has to be configured before [`configure_frontend_api`] for the route to be reachable.
+pub fn configure_eval_debug(cfg: &mut web::ServiceConfig, enable_eval_debug: bool) {
+    if enable_eval_debug {
+        cfg.service(scope_with_auth("/frontend/debug").service(post_frontend_explain_feature));
+    }
+}
+
+fn features_cache(req: &HttpRequest) -> Option<&FeatureCache> {
+    req.app_data::<Data<FeatureCache>>()
+        .map(|features_cache| features_cache.get_ref())
+}
+
+fn coalescer(req: &HttpRequest) -> Option<&EvaluationCoalescer> {
+    req.app_data::<Data<EvaluationCoalescer>>()
+        .map(|coalescer| coalescer.get_ref())
+}
+
+pub const DEFAULT_MAX_BULK_CONTEXTS: usize = 100;
+
+/// Limits on the size of frontend evaluation contexts, set with `--max-context-properties` and `--max-context-bytes`,
+/// and on how many contexts a bulk evaluation may contain, set with `--max-bulk-contexts`. Every property can be
+/// matched against constraints, so without limits a single request can make evaluation arbitrarily expensive.
+#[derive(Clone, Debug)]
+pub struct ContextLimits {
+    pub max_properties: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub max_bulk_contexts: usize,
+}
+
+impl Default for ContextLimits {
+    fn default() -> Self {
+        Self {
+            max_properties: None,
+            max_bytes: None,
+            max_bulk_contexts: DEFAULT_MAX_BULK_CONTEXTS,
+        }
+    }
+}
+
+impl ContextLimits {
+    pub fn check(&self, context: &Context) -> EdgeResult<()> {
+        let property_count = context.properties.as_ref().map_or(0, HashMap::len);
+        if let Some(max_properties) = self.max_properties {
+            if property_count > max_properties {
+                return Err(EdgeError::ContextTooLarge(format!(
+                    "Context has {property_count} properties, but at most {max_properties} are allowed"
+                )));
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            let size = context_size(context);
+            if size > max_bytes {
+                return Err(EdgeError::ContextTooLarge(format!(
+                    "Context is {size} bytes, but at most {max_bytes} bytes are allowed"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The combined length of every field, property name and property value in `context`
+fn context_size(context: &Context) -> usize {
+    let fields: usize = [
+        &context.user_id,
+        &context.session_id,
+        &context.environment,
+        &context.app_name,
+        &context.current_time,
+        &context.remote_address,
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::len)
+    .sum();
+    let properties: usize = context
+        .properties
+        .iter()
+        .flatten()
+        .map(|(name, value)| name.len() + value.len())
+        .sum();
+    fields + properties
+}
+
+fn context_limits(req: &HttpRequest) -> Option<&ContextLimits> {
+    req.app_data::<Data<ContextLimits>>()
+        .map(|limits| limits.get_ref())
+}
+
+/// Which environments can use `/api/proxy/all` and `/api/frontend/all`, set with `--disable-all-endpoint`,
+/// `--all-endpoint-enabled-environments` and `--all-endpoint-disabled-environments`. Returning every toggle regardless
+/// of its state may be fine in a sandbox, but not in production, even when both are served by the same Edge.
+#[derive(Clone, Debug, Default)]
+pub struct AllEndpointAccess {
+    /// Applies to every environment that isn't listed in either of the environment lists
+    pub disabled: bool,
+    pub enabled_environments: HashSet<String>,
+    /// Takes precedence over `enabled_environments`, so an environment listed in both stays disabled
+    pub disabled_environments: HashSet<String>,
+}
+
+impl AllEndpointAccess {
+    pub fn is_enabled(&self, environment: Option<&str>) -> bool {
+        match environment {
+            Some(environment) if self.disabled_environments.contains(environment) => false,
+            Some(environment) if self.enabled_environments.contains(environment) => true,
+            _ => !self.disabled,
+        }
+    }
+}
+
+/// Resolves the environment from the token cache first, since offline tokens don't carry it in the token itself
+fn check_all_endpoint_access(req: &HttpRequest, edge_token: &EdgeToken) -> EdgeResult<()> {
+    let Some(access) = req.app_data::<Data<AllEndpointAccess>>() else {
+        return Ok(());
+    };
+    let environment = req
+        .app_data::<Data<DashMap<String, EdgeToken>>>()
+        .and_then(|token_cache| {
+            token_cache
+                .get(&edge_token.token)
+                .and_then(|token| token.environment.clone())
+        })
+        .or_else(|| edge_token.environment.clone());
+    if access.is_enabled(environment.as_deref()) {
+        Ok(())
     } else {
-        cfg.service(
-            scope_with_auth("/proxy")
-                .service(get_enabled_proxy)
-                .service(post_proxy_metrics)
-                .service(post_proxy_enabled_features)
-                .service(post_proxy_register),
-        );
+        Err(EdgeError::AllEndpointDisabled(
+            environment.unwrap_or_else(|| "unknown".into()),
+        ))
     }
 }
 
-pub fn configure_frontend_api(cfg: &mut web::ServiceConfig, disable_all_endpoint: bool) {
-    configure_proxy_endpoints(cfg, disable_all_endpoint);
-    configure_frontend_endpoints(cfg, disable_all_endpoint);
+fn check_context_limits(limits: Option<&ContextLimits>, context: &Context) -> EdgeResult<()> {
+    limits.map_or(Ok(()), |limits| limits.check(context))
+}
+
+/// Evaluates every feature in the engine cached under `key`. With `--coalesce-frontend-evaluations`, concurrent
+/// evaluations of the same engine and context share a single result.
+async fn resolve_all(
+    engine_cache: &DashMap<String, EngineState>,
+    features_cache: Option<&FeatureCache>,
+    key: &str,
+    token: &EdgeToken,
+    context: &Context,
+    coalescer: Option<&EvaluationCoalescer>,
+) -> Option<HashMap<String, ResolvedToggle>> {
+    let visible = visible_features(features_cache, key, token);
+    // Looked up only when evaluating, so requests waiting on a shared evaluation don't hold the engine
+    let evaluate = || async {
+        engine_cache.get(key).and_then(|engine| match &visible {
+            Some(names) => Some(
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        engine
+                            .resolve(name, context, &None)
+                            .map(|resolved| (name.clone(), resolved))
+                    })
+                    .collect(),
+            ),
+            None => engine.resolve_all(context, &None),
+        })
+    };
+    match (coalescer, serde_json::to_value(context)) {
+        (Some(coalescer), Ok(context_key)) => {
+            let scope = if visible.is_some() {
+                token.projects.join(",")
+            } else {
+                "*".into()
+            };
+            coalescer
+                .run(format!("{key}:{scope}:{context_key}"), evaluate)
+                .await
+        }
+        _ => evaluate().await,
+    }
+}
+
+/// Tokens scoped to some projects only get their own features evaluated, instead of evaluating every feature in the
+/// environment and throwing most of them away. Which features those are is memoized by the feature cache
+fn visible_features(
+    features_cache: Option<&FeatureCache>,
+    key: &str,
+    token: &EdgeToken,
+) -> Option<Vec<String>> {
+    if token.projects.is_empty() || token.projects.contains(&"*".to_string()) {
+        return None;
+    }
+    features_cache?.get_filtered_names(key, &frontend_project_filter(token))
 }
 
 pub fn frontend_from_yggdrasil(
@@ -741,13 +1260,19 @@ pub fn frontend_from_yggdrasil(
     FrontendResult { toggles }
 }
 
-pub fn get_all_features(
+#[allow(clippy::too_many_arguments)]
+pub async fn get_all_features(
     edge_token: EdgeToken,
     engine_cache: Data<DashMap<String, EngineState>>,
+    features_cache: Option<&FeatureCache>,
     token_cache: Data<DashMap<String, EdgeToken>>,
     context: &Context,
-    client_ip: Option<&ClientIp>,
+    client_ip: Option<ClientIp>,
+    coalescer: Option<&EvaluationCoalescer>,
+    context_limits: Option<&ContextLimits>,
 ) -> EdgeJsonResult<FrontendResult> {
+    let started = Instant::now();
+    check_context_limits(context_limits, context)?;
     let context_with_ip = if context.remote_address.is_none() {
         &Context {
             remote_address: client_ip.map(|ip| ip.to_string()),
@@ -761,15 +1286,28 @@ pub fn get_all_features(
         .map(|e| e.value().clone())
         .unwrap_or_else(|| edge_token.clone());
     let key = cache_key(&token);
-    let engine = engine_cache.get(&key).ok_or_else(|| {
-        EdgeError::FrontendNotYetHydrated(FrontendHydrationMissing::from(&edge_token))
-    })?;
-    let feature_results = engine.resolve_all(context_with_ip, &None).ok_or_else(|| {
+    if !engine_cache.contains_key(&key) {
+        return Err(EdgeError::FrontendNotYetHydrated(
+            FrontendHydrationMissing::from(&edge_token),
+        ));
+    }
+    let feature_results = resolve_all(
+        &engine_cache,
+        features_cache,
+        &key,
+        &token,
+        context_with_ip,
+        coalescer,
+    )
+    .await
+    .ok_or_else(|| {
         EdgeError::FrontendExpectedToBeHydrated(
             "Feature cache has not been hydrated yet, but it was expected to be. This can be due to a race condition from calling edge before it's ready. This error might auto resolve as soon as edge is able to fetch from upstream".into(),
         )
     })?;
-    Ok(Json(frontend_from_yggdrasil(feature_results, true, &token)))
+    let result = frontend_from_yggdrasil(feature_results, true, &token);
+    observe_eval_duration(token.environment.as_deref(), started);
+    Ok(Json(result))
 }
 
 #[cfg(test)]
@@ -796,11 +1334,17 @@ mod tests {
     };
     use unleash_yggdrasil::EngineState;
 
+    use super::AllEndpointAccess;
+    use crate::auth::jwks::tests::{jwks_for, key_pair, sign};
     use crate::cli::{EdgeMode, OfflineArgs, TrustProxy};
     use crate::metrics::client_metrics::MetricsCache;
     use crate::metrics::client_metrics::MetricsKey;
     use crate::middleware;
-    use crate::types::{EdgeToken, TokenType, TokenValidationStatus};
+    use crate::single_flight::EvaluationCoalescer;
+    use crate::types::{
+        EdgeToken, EvaluationExplanation, EvaluationReason, StrategyEvaluation, TokenType,
+        TokenValidationStatus,
+    };
     use crate::{builder::build_offline_mode, feature_cache::FeatureCache};
 
     async fn make_test_request() -> Request {
@@ -1192,6 +1736,7 @@ mod tests {
                 timestamp: DateTime::parse_from_rfc3339("1867-11-07T12:00:00Z")
                     .unwrap()
                     .with_timezone(&Utc),
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -1246,6 +1791,7 @@ mod tests {
                 timestamp: DateTime::parse_from_rfc3339("1867-11-07T12:00:00Z")
                     .unwrap()
                     .with_timezone(&Utc),
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -1274,6 +1820,9 @@ mod tests {
                     reload_interval: 0,
                     client_tokens: vec![],
                     frontend_tokens: vec![],
+                    tokens_file: None,
+                    frontend_jwks_file: None,
+                    fail_on_compile_warnings: false,
                 })))
                 .service(web::scope("/api/frontend").service(super::get_frontend_all_features)),
         )
@@ -1289,6 +1838,56 @@ mod tests {
         assert_eq!(result.toggles.len(), client_features.features.len());
     }
 
+    #[tokio::test]
+    async fn offline_mode_accepts_signed_frontend_tokens_validated_against_the_jwks() {
+        let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features,
+            vec![],
+            vec!["*:development.secret123".to_string()],
+            vec![],
+        )
+        .unwrap();
+        let key_pair = key_pair();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::new(jwks_for(&key_pair)))
+                .app_data(Data::new(EdgeMode::Offline(OfflineArgs {
+                    bootstrap_file: None,
+                    tokens: vec![],
+                    reload_interval: 0,
+                    client_tokens: vec!["*:development.secret123".into()],
+                    frontend_tokens: vec![],
+                    tokens_file: None,
+                    frontend_jwks_file: None,
+                    fail_on_compile_warnings: false,
+                })))
+                .wrap(middleware::as_async_middleware::as_async_middleware(
+                    middleware::validate_token::validate_token,
+                ))
+                .service(web::scope("/api/frontend").service(super::get_frontend_all_features)),
+        )
+        .await;
+        let request = |token: &str| {
+            test::TestRequest::get()
+                .uri("/api/frontend/all")
+                .insert_header(ContentType::json())
+                .insert_header(("Authorization", token))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, request(&sign(&key_pair, 3600))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let result: FrontendResult = test::read_body_json(res).await;
+        assert!(!result.toggles.is_empty());
+
+        let res = test::call_service(&app, request(&sign(&key_pair, -60))).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn frontend_api_filters_evaluated_toggles_to_tokens_access() {
         let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
@@ -1318,6 +1917,68 @@ mod tests {
         assert_eq!(result.toggles.len(), 16);
     }
 
+    #[tokio::test]
+    async fn project_scoped_tokens_only_evaluate_their_memoized_features() {
+        let features_cache = Arc::new(FeatureCache::default());
+        let engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let client_features = ClientFeatures {
+            version: 2,
+            features: vec![
+                ClientFeature {
+                    name: "in-dx".into(),
+                    project: Some("dx".into()),
+                    enabled: true,
+                    ..ClientFeature::default()
+                },
+                ClientFeature {
+                    name: "without-project".into(),
+                    project: None,
+                    enabled: true,
+                    ..ClientFeature::default()
+                },
+                ClientFeature {
+                    name: "in-eg".into(),
+                    project: Some("eg".into()),
+                    enabled: true,
+                    ..ClientFeature::default()
+                },
+            ],
+            segments: None,
+            query: None,
+            meta: None,
+        };
+        let mut engine = EngineState::default();
+        engine.take_state(client_features.clone());
+        engine_cache.insert("development".into(), engine);
+        features_cache.insert("development".into(), client_features);
+        let mut token = EdgeToken::try_from("[]:development.secret123".to_string()).unwrap();
+        token.projects = vec!["dx".into(), "default".into()];
+        token.status = TokenValidationStatus::Validated;
+        token.token_type = Some(TokenType::Frontend);
+        token_cache.insert(token.token.clone(), token.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(engine_cache))
+                .service(web::scope("/api/frontend").service(super::get_frontend_all_features)),
+        )
+        .await;
+
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .uri("/api/frontend/all")
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request();
+            let result: FrontendResult = test::call_and_read_body_json(&app, req).await;
+            let mut names: Vec<String> = result.toggles.into_iter().map(|t| t.name).collect();
+            names.sort();
+            assert_eq!(names, vec!["in-dx", "without-project"]);
+            assert_eq!(features_cache.memoized_filters(), 1);
+        }
+    }
+
     #[tokio::test]
     async fn frontend_token_without_matching_client_token_yields_511_when_trying_to_access_frontend_api(
     ) {
@@ -1332,9 +1993,9 @@ mod tests {
                 .wrap(middleware::as_async_middleware::as_async_middleware(
                     middleware::validate_token::validate_token,
                 ))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
 
@@ -1365,9 +2026,9 @@ mod tests {
                 .wrap(middleware::as_async_middleware::as_async_middleware(
                     middleware::validate_token::validate_token,
                 ))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
         let req = test::TestRequest::get()
@@ -1379,6 +2040,43 @@ mod tests {
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
     }
 
+    #[tokio::test]
+    async fn frontend_requests_are_authorized_with_the_frontend_token_header() {
+        let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let (token_cache, features_cache, engine_cache) = build_offline_mode(
+            client_features,
+            vec!["dx:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(features_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::new(crate::tokens::EndpointTokenHeaders {
+                    client: None,
+                    frontend: Some("X-Frontend-Token".into()),
+                }))
+                .service(web::scope("/api/frontend").service(super::get_frontend_all_features)),
+        )
+        .await;
+        let with_frontend_header = test::TestRequest::get()
+            .uri("/api/frontend/all")
+            .insert_header(("X-Frontend-Token", "dx:development.secret123"))
+            .to_request();
+        let with_default_header = test::TestRequest::get()
+            .uri("/api/frontend/all")
+            .insert_header(("Authorization", "dx:development.secret123"))
+            .to_request();
+
+        let res = test::call_service(&app, with_frontend_header).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(&app, with_default_header).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn using_a_string_for_properties_gives_400() {
         let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
@@ -1394,9 +2092,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
 
@@ -1410,6 +2108,39 @@ mod tests {
         assert_eq!(result.status(), 400);
     }
 
+    #[tokio::test]
+    async fn coalesced_evaluations_resolve_the_same_as_uncoalesced_ones() {
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features_with_constraint_requiring_user_id_of_seven(),
+            vec!["*:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let coalescer = Arc::new(EvaluationCoalescer::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::from(coalescer.clone()))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        for (user_id, expected_toggles) in [("7", 1), ("8", 0), ("7", 1)] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/frontend?userId={user_id}"))
+                .insert_header(("Authorization", "*:development.secret123"))
+                .to_request();
+            let result: FrontendResult = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(result.toggles.len(), expected_toggles);
+        }
+        assert_eq!(coalescer.in_flight(), 0);
+    }
+
     #[tokio::test]
     async fn can_get_single_feature() {
         let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
@@ -1425,9 +2156,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
 
@@ -1441,6 +2172,149 @@ mod tests {
         assert_eq!(result.status(), 200);
     }
 
+    #[tokio::test]
+    async fn contexts_over_the_configured_limits_are_rejected_with_400() {
+        let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features.clone(),
+            vec!["dx:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::new(super::ContextLimits {
+                    max_properties: Some(2),
+                    max_bytes: Some(32),
+                    ..Default::default()
+                }))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        for (uri, expected_status) in [
+            ("/api/frontend?properties[a]=1&properties[b]=2", 200),
+            (
+                "/api/frontend?properties[a]=1&properties[b]=2&properties[c]=3",
+                400,
+            ),
+            ("/api/frontend/features/batchMetrics?a=1&b=2&c=3", 400),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("Authorization", "dx:development.secret123"))
+                .to_request();
+            let result = test::call_service(&app, req).await;
+            assert_eq!(result.status(), expected_status, "{uri}");
+        }
+
+        for (user_id, expected_status) in [("u".repeat(32), 200), ("u".repeat(33), 400)] {
+            let req = test::TestRequest::post()
+                .uri("/api/frontend/all")
+                .insert_header(ContentType::json())
+                .insert_header(("Authorization", "dx:development.secret123"))
+                .set_json(json!({ "userId": user_id }))
+                .to_request();
+            let result = test::call_service(&app, req).await;
+            assert_eq!(result.status(), expected_status);
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_evaluation_returns_one_result_set_per_context_in_order() {
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features_with_constraint_requiring_user_id_of_seven(),
+            vec!["*:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::new(super::ContextLimits {
+                    max_bulk_contexts: 3,
+                    ..Default::default()
+                }))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/frontend/bulk")
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", "*:development.secret123"))
+            .set_json(json!([
+                { "userId": "7" },
+                { "userId": "8" },
+                { "context": { "userId": "7" } },
+            ]))
+            .to_request();
+        let results: Vec<FrontendResult> = test::call_and_read_body_json(&app, req).await;
+        let enabled: Vec<usize> = results.iter().map(|r| r.toggles.len()).collect();
+        assert_eq!(enabled, vec![1, 0, 1]);
+
+        let req = test::TestRequest::post()
+            .uri("/api/frontend/bulk")
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", "*:development.secret123"))
+            .set_json(json!([{}, {}, {}, {}]))
+            .to_request();
+        let result = test::call_service(&app, req).await;
+        assert_eq!(result.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn successful_evaluations_are_timed_per_environment() {
+        let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features.clone(),
+            vec!["dx:eval-timing.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        for (uri, expected_status) in [
+            ("/api/frontend/all", 200),
+            ("/api/frontend/features/batchMetrics", 200),
+            ("/api/frontend/features/does-not-exist", 404),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header(ContentType::json())
+                .insert_header(("Authorization", "dx:eval-timing.secret123"))
+                .to_request();
+            let result = test::call_service(&app, req).await;
+            assert_eq!(result.status(), expected_status);
+        }
+
+        let observed = super::FRONTEND_EVAL_DURATION
+            .with_label_values(&["eval-timing"])
+            .get_sample_count();
+        assert_eq!(observed, 2);
+    }
+
     #[tokio::test]
     async fn can_get_single_feature_with_top_level_properties() {
         let (token_cache, feature_cache, engine_cache) = build_offline_mode(
@@ -1455,9 +2329,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
 
@@ -1485,9 +2359,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
 
@@ -1521,9 +2395,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
         let req = test::TestRequest::get()
@@ -1565,9 +2439,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
         let req = test::TestRequest::post()
@@ -1606,9 +2480,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
         let req = test::TestRequest::post()
@@ -1641,9 +2515,15 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, true)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(
+                        cfg,
+                        AllEndpointAccess {
+                            disabled: true,
+                            ..Default::default()
+                        },
+                    )
+                })),
         )
         .await;
         let frontend_req = test::TestRequest::post()
@@ -1666,6 +2546,66 @@ mod tests {
         assert_eq!(result.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn all_endpoints_can_be_enabled_and_disabled_per_environment() {
+        let client_features_with_custom_context_field =
+            crate::tests::features_from_disk("../examples/ip_address_feature.json");
+        let production_key = "gard:production.secret123".to_string();
+        let sandbox_key = "gard:sandbox.secret123".to_string();
+        let development_key = "gard:development.secret123".to_string();
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features_with_custom_context_field.clone(),
+            vec![
+                production_key.clone(),
+                sandbox_key.clone(),
+                development_key.clone(),
+            ],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(
+                        cfg,
+                        AllEndpointAccess {
+                            disabled: true,
+                            enabled_environments: [
+                                "sandbox".to_string(),
+                                "development".to_string(),
+                            ]
+                            .into(),
+                            disabled_environments: ["development".to_string()].into(),
+                        },
+                    )
+                })),
+        )
+        .await;
+        let request = |uri: &str, key: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("Authorization", key))
+                .to_request()
+        };
+
+        for uri in ["/api/frontend/all", "/api/proxy/all"] {
+            let sandbox = test::call_service(&app, request(uri, &sandbox_key)).await;
+            assert_eq!(sandbox.status(), StatusCode::OK);
+            let production = test::call_service(&app, request(uri, &production_key)).await;
+            assert_eq!(production.status(), StatusCode::NOT_FOUND);
+            let development = test::call_service(&app, request(uri, &development_key)).await;
+            assert_eq!(development.status(), StatusCode::NOT_FOUND);
+        }
+
+        let enabled_frontend =
+            test::call_service(&app, request("/api/frontend", &production_key)).await;
+        assert_eq!(enabled_frontend.status(), StatusCode::OK);
+    }
+
     #[tokio::test]
     async fn can_handle_custom_context_fields_on_all_endpoint() {
         let client_features_with_custom_context_field =
@@ -1686,9 +2626,9 @@ mod tests {
                 .app_data(Data::from(token_cache))
                 .app_data(Data::from(feature_cache))
                 .app_data(Data::from(engine_cache))
-                .service(
-                    web::scope("/api").configure(|cfg| super::configure_frontend_api(cfg, false)),
-                ),
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
         )
         .await;
         let req = test::TestRequest::get()
@@ -1706,4 +2646,111 @@ mod tests {
         let feature_results: FrontendResult = test::call_and_read_body_json(&app, req).await;
         assert!(feature_results.toggles.iter().any(|f| f.enabled));
     }
+
+    fn client_features_with_user_id_and_gradual_rollout_strategies() -> ClientFeatures {
+        ClientFeatures {
+            version: 1,
+            features: vec![ClientFeature {
+                name: "rollout".into(),
+                enabled: true,
+                strategies: Some(vec![
+                    Strategy {
+                        name: "userWithId".into(),
+                        sort_order: None,
+                        segments: None,
+                        variants: None,
+                        constraints: None,
+                        parameters: Some(HashMap::from([("userIds".into(), "7".into())])),
+                    },
+                    Strategy {
+                        name: "gradualRolloutUserId".into(),
+                        sort_order: None,
+                        segments: None,
+                        variants: None,
+                        constraints: None,
+                        parameters: Some(HashMap::from([
+                            ("percentage".into(), "100".into()),
+                            ("groupId".into(), "rollout".into()),
+                        ])),
+                    },
+                ]),
+                ..ClientFeature::default()
+            }],
+            segments: None,
+            query: None,
+            meta: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn eval_debug_explains_which_strategy_matched() {
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features_with_user_id_and_gradual_rollout_strategies(),
+            vec!["*:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_eval_debug(cfg, true);
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/frontend/debug/features/rollout")
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", "*:development.secret123"))
+            .set_json(json!({ "userId": "3" }))
+            .to_request();
+        let explanation: EvaluationExplanation = test::call_and_read_body_json(&app, req).await;
+        assert!(explanation.enabled);
+        assert_eq!(explanation.reason, EvaluationReason::StrategyMatched);
+        assert_eq!(
+            explanation.matched_strategy,
+            Some(StrategyEvaluation {
+                index: 1,
+                name: "gradualRolloutUserId".into(),
+                matched: true,
+            })
+        );
+        assert!(!explanation.strategies[0].matched);
+    }
+
+    #[tokio::test]
+    async fn eval_debug_endpoint_is_not_available_unless_enabled() {
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features_with_user_id_and_gradual_rollout_strategies(),
+            vec!["*:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .service(web::scope("/api").configure(|cfg| {
+                    super::configure_eval_debug(cfg, false);
+                    super::configure_frontend_api(cfg, AllEndpointAccess::default())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/frontend/debug/features/rollout")
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", "*:development.secret123"))
+            .set_json(json!({ "userId": "3" }))
+            .to_request();
+        let result = test::call_service(&app, req).await;
+        assert_eq!(result.status(), StatusCode::NOT_FOUND);
+    }
 }
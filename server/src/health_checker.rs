@@ -3,11 +3,13 @@ use crate::error::EdgeError;
 use crate::tls::build_upstream_certificate;
 use reqwest::{ClientBuilder, Url};
 
-fn build_health_url(url: &Url) -> Url {
+fn build_health_url(url: &Url, base_path: &str) -> Url {
     let mut with_path = url.clone();
     with_path
         .path_segments_mut()
         .expect("Could not build health check url")
+        .pop_if_empty()
+        .extend(base_path.split('/').filter(|segment| !segment.is_empty()))
         .push("internal-backstage")
         .push("health");
     with_path
@@ -23,7 +25,7 @@ pub async fn check_health(health_check_args: HealthCheckArgs) -> Result<(), Edge
     };
     let base_url = Url::parse(&health_check_args.edge_url)
         .map_err(|p| EdgeError::HealthCheckError(format!("Invalid health check url: {p:?}")))?;
-    let health_check_url = build_health_url(&base_url);
+    let health_check_url = build_health_url(&base_url, &health_check_args.base_path);
     client
         .get(health_check_url)
         .send()
@@ -65,6 +67,29 @@ mod tests {
         let check_result = check_health(HealthCheckArgs {
             ca_certificate_file: None,
             edge_url: url,
+            base_path: "".into(),
+        })
+        .await;
+        assert!(check_result.is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn health_check_includes_the_base_path() {
+        let srv = test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(
+                    web::scope("/edge/under/ingress")
+                        .service(web::scope("/internal-backstage").service(health)),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let check_result = check_health(HealthCheckArgs {
+            ca_certificate_file: None,
+            edge_url: srv.url("/"),
+            base_path: "/edge/under/ingress".into(),
         })
         .await;
         assert!(check_result.is_ok());
@@ -75,6 +100,7 @@ mod tests {
         let check_result = check_health(HealthCheckArgs {
             ca_certificate_file: None,
             edge_url: "http://bogusurl".into(),
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
@@ -100,6 +126,7 @@ mod tests {
         let check_result = check_health(HealthCheckArgs {
             ca_certificate_file: None,
             edge_url: url,
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
@@ -110,6 +137,7 @@ mod tests {
         let check_result = check_health(HealthCheckArgs {
             ca_certificate_file: None,
             edge_url: ":\\///\\/".into(),
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
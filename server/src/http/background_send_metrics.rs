@@ -1,6 +1,7 @@
 use std::cmp::max;
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use chrono::Duration;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
@@ -8,10 +9,11 @@ use prometheus::{register_int_gauge, register_int_gauge_vec, IntGauge, IntGaugeV
 use reqwest::StatusCode;
 use tracing::{error, info, trace, warn};
 
-use crate::types::TokenRefresh;
+use crate::metrics::metrics_sink::MetricsSink;
+use crate::types::{EdgeResult, TokenRefresh};
 use crate::{
     error::EdgeError,
-    metrics::client_metrics::{size_of_batch, MetricsCache},
+    metrics::client_metrics::{size_of_batch, MetricsBatch, MetricsCache},
 };
 
 use super::refresher::feature_refresher::FeatureRefresher;
@@ -67,30 +69,46 @@ fn decide_where_to_post(
     }
 }
 
-pub async fn send_metrics_one_shot(
-    metrics_cache: Arc<MetricsCache>,
+/// Posts metrics to upstream, using the client bulk endpoint when we have a token for the batch's environment
+pub struct UpstreamMetricsSink {
     feature_refresher: Arc<FeatureRefresher>,
-) {
+}
+
+impl UpstreamMetricsSink {
+    pub fn new(feature_refresher: Arc<FeatureRefresher>) -> Self {
+        Self { feature_refresher }
+    }
+}
+
+#[async_trait]
+impl MetricsSink for UpstreamMetricsSink {
+    async fn send(&self, environment: &str, batch: MetricsBatch) -> EdgeResult<()> {
+        let (use_new_endpoint, token) = decide_where_to_post(
+            &environment.to_string(),
+            self.feature_refresher.tokens_to_refresh.clone(),
+        );
+        if use_new_endpoint {
+            self.feature_refresher
+                .unleash_client
+                .send_bulk_metrics_to_client_endpoint(batch, &token)
+                .await
+        } else {
+            self.feature_refresher
+                .unleash_client
+                .send_batch_metrics(batch)
+                .await
+        }
+    }
+}
+
+pub async fn send_metrics_one_shot(metrics_cache: Arc<MetricsCache>, sink: Arc<dyn MetricsSink>) {
     let envs = metrics_cache.get_metrics_by_environment();
     for (env, batch) in envs.iter() {
-        let (use_new_endpoint, token) =
-            decide_where_to_post(env, feature_refresher.tokens_to_refresh.clone());
         let batches = metrics_cache.get_appropriately_sized_env_batches(batch);
         trace!("Posting {} batches for {env}", batches.len());
         for batch in batches {
             if !batch.applications.is_empty() || !batch.metrics.is_empty() {
-                let result = if use_new_endpoint {
-                    feature_refresher
-                        .unleash_client
-                        .send_bulk_metrics_to_client_endpoint(batch.clone(), &token)
-                        .await
-                } else {
-                    feature_refresher
-                        .unleash_client
-                        .send_batch_metrics(batch.clone())
-                        .await
-                };
-                if let Err(edge_error) = result {
+                if let Err(edge_error) = sink.send(env, batch).await {
                     warn!("Shut down metrics flush failed with {edge_error:?}")
                 }
             }
@@ -100,7 +118,7 @@ pub async fn send_metrics_one_shot(
 
 pub async fn send_metrics_task(
     metrics_cache: Arc<MetricsCache>,
-    feature_refresher: Arc<FeatureRefresher>,
+    sink: Arc<dyn MetricsSink>,
     send_interval: i64,
 ) {
     let mut failures = 0;
@@ -109,23 +127,11 @@ pub async fn send_metrics_task(
         trace!("Looping metrics");
         let envs = metrics_cache.get_metrics_by_environment();
         for (env, batch) in envs.iter() {
-            let (use_new_endpoint, token) =
-                decide_where_to_post(env, feature_refresher.tokens_to_refresh.clone());
             let batches = metrics_cache.get_appropriately_sized_env_batches(batch);
             trace!("Posting {} batches for {env}", batches.len());
             for batch in batches {
                 if !batch.applications.is_empty() || !batch.metrics.is_empty() {
-                    let result = if use_new_endpoint {
-                        feature_refresher
-                            .unleash_client
-                            .send_bulk_metrics_to_client_endpoint(batch.clone(), &token)
-                            .await
-                    } else {
-                        feature_refresher
-                            .unleash_client
-                            .send_batch_metrics(batch.clone())
-                            .await
-                    };
+                    let result = sink.send(env, batch.clone()).await;
                     if let Err(edge_error) = result {
                         match edge_error {
                             EdgeError::EdgeMetricsRequestError(status_code, message) => {
@@ -202,11 +208,85 @@ fn new_interval(send_interval: i64, failures: i64) -> Duration {
 
 #[cfg(test)]
 mod tests {
-    use crate::http::background_send_metrics::new_interval;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use actix_http::HttpService;
+    use actix_http_test::test_server;
+    use actix_service::map_config;
+    use actix_web::{dev::AppConfig, web, App, HttpResponse};
+    use chrono::Utc;
+    use unleash_types::client_metrics::{ClientMetricsEnv, MetricsMetadata};
+
+    use crate::http::background_send_metrics::{
+        new_interval, send_metrics_task, UpstreamMetricsSink,
+    };
+    use crate::http::refresher::feature_refresher::FeatureRefresher;
+    use crate::http::unleash_client::UnleashClient;
+    use crate::metrics::client_metrics::MetricsCache;
+    use crate::types::{EdgeToken, TokenRefresh};
 
     #[tokio::test]
     pub async fn new_interval_does_not_overflow() {
         let metrics = new_interval(300, 10);
         assert!(metrics.num_seconds() < 3305);
     }
+
+    #[tokio::test]
+    pub async fn does_not_post_when_no_metrics_were_recorded() {
+        let posts = Arc::new(AtomicUsize::new(0));
+        let server_posts = posts.clone();
+        let srv = test_server(move || {
+            let posts = server_posts.clone();
+            HttpService::new(map_config(
+                App::new().default_service(web::to(move || {
+                    posts.fetch_add(1, Ordering::SeqCst);
+                    async { HttpResponse::Accepted().finish() }
+                })),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let feature_refresher = Arc::new(FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new(srv.url("/").as_str(), None).unwrap()),
+            ..FeatureRefresher::default()
+        });
+        let token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), TokenRefresh::new(token, None));
+        let metrics_cache = Arc::new(MetricsCache::default());
+        let run_for_two_intervals = || {
+            tokio::time::timeout(
+                std::time::Duration::from_millis(2500),
+                send_metrics_task(
+                    metrics_cache.clone(),
+                    Arc::new(UpstreamMetricsSink::new(feature_refresher.clone())),
+                    1,
+                ),
+            )
+        };
+
+        let _ = run_for_two_intervals().await;
+        assert_eq!(posts.load(Ordering::SeqCst), 0);
+
+        metrics_cache.sink_metrics(&[ClientMetricsEnv {
+            feature_name: "feature_one".into(),
+            app_name: "my_app".into(),
+            environment: "development".into(),
+            timestamp: Utc::now(),
+            yes: 1,
+            no: 0,
+            variants: Default::default(),
+            metadata: MetricsMetadata {
+                platform_name: None,
+                platform_version: None,
+                sdk_version: None,
+                yggdrasil_version: None,
+            },
+        }]);
+        let _ = run_for_two_intervals().await;
+        assert_eq!(posts.load(Ordering::SeqCst), 1);
+    }
 }
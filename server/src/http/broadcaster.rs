@@ -1,4 +1,4 @@
-use std::{hash::Hash, sync::Arc, time::Duration};
+use std::{collections::HashMap, hash::Hash, sync::Arc, time::Duration};
 
 use actix_web::{rt::time::interval, web::Json};
 use actix_web_lab::{
@@ -7,7 +7,7 @@ use actix_web_lab::{
 };
 use dashmap::DashMap;
 use futures::future;
-use prometheus::{register_int_gauge, IntGauge};
+use prometheus::{register_int_gauge_vec, IntGaugeVec, Opts};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, warn};
@@ -57,6 +57,8 @@ impl From<(&Query, &EdgeToken)> for StreamingQuery {
 #[derive(Clone, Debug)]
 struct ClientData {
     token: String,
+    /// The SDK's connection id, which stays the same when it reconnects
+    connection_id: Option<String>,
     sender: mpsc::Sender<sse::Event>,
 }
 
@@ -68,12 +70,21 @@ struct ClientGroup {
 pub struct Broadcaster {
     active_connections: DashMap<StreamingQuery, ClientGroup>,
     features_cache: Arc<FeatureCache>,
+    max_streams_per_token: Option<usize>,
+    /// The streams counted against `max_streams_per_token`. These don't keep the streams open, dropped streams free up their slot
+    streams_per_token: DashMap<String, Vec<mpsc::WeakSender<sse::Event>>>,
 }
 
+/// Clients that haven't taken a keep-alive off their stream within this long are considered dead and removed
+const DEAD_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
 lazy_static::lazy_static! {
-    pub static ref CONNECTED_STREAMING_CLIENTS: IntGauge = register_int_gauge!(
-        "connected_streaming_clients",
-        "Number of connected streaming clients",
+    pub static ref CONNECTED_STREAMING_CLIENTS: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "connected_streaming_clients",
+            "Number of connected streaming clients"
+        ),
+        &["environment"]
     )
     .unwrap();
 }
@@ -81,9 +92,19 @@ lazy_static::lazy_static! {
 impl Broadcaster {
     /// Constructs new broadcaster and spawns ping loop.
     pub fn new(features: Arc<FeatureCache>) -> Arc<Self> {
+        Broadcaster::new_with_max_streams_per_token(features, None)
+    }
+
+    /// Constructs new broadcaster that refuses more than `max_streams_per_token` open streams for any single token.
+    pub fn new_with_max_streams_per_token(
+        features: Arc<FeatureCache>,
+        max_streams_per_token: Option<usize>,
+    ) -> Arc<Self> {
         let broadcaster = Arc::new(Broadcaster {
             active_connections: DashMap::new(),
             features_cache: features.clone(),
+            max_streams_per_token,
+            streams_per_token: DashMap::new(),
         });
 
         Broadcaster::spawn_heartbeat(broadcaster.clone());
@@ -124,45 +145,83 @@ impl Broadcaster {
 
     /// Removes all non-responsive clients from broadcast list.
     async fn heartbeat(&self) {
-        let mut active_connections = 0i64;
         for mut group in self.active_connections.iter_mut() {
             let mut ok_clients = Vec::new();
 
-            for ClientData { token, sender } in &group.clients {
-                if sender
-                    .send(sse::Event::Comment("keep-alive".into()))
+            for client in &group.clients {
+                if client
+                    .sender
+                    .send_timeout(
+                        sse::Event::Comment("keep-alive".into()),
+                        DEAD_CONNECTION_TIMEOUT,
+                    )
                     .await
                     .is_ok()
                 {
-                    ok_clients.push(ClientData {
-                        token: token.clone(),
-                        sender: sender.clone(),
-                    });
+                    ok_clients.push(client.clone());
                 }
             }
 
-            active_connections += ok_clients.len() as i64;
             group.clients = ok_clients;
         }
-        CONNECTED_STREAMING_CLIENTS.set(active_connections)
+        self.streams_per_token.retain(|_, streams| {
+            streams.retain(is_open);
+            !streams.is_empty()
+        });
+        self.record_connected_clients();
+    }
+
+    /// Counts open streams per environment. Environments whose clients have all disconnected are kept with a count of 0
+    pub fn connected_clients_per_environment(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for group in self.active_connections.iter() {
+            let open = group
+                .clients
+                .iter()
+                .filter(|client| !client.sender.is_closed())
+                .count();
+            *counts.entry(group.key().environment.clone()).or_insert(0) += open;
+        }
+        counts
+    }
+
+    fn record_connected_clients(&self) {
+        for (environment, count) in self.connected_clients_per_environment() {
+            CONNECTED_STREAMING_CLIENTS
+                .with_label_values(&[&environment])
+                .set(count as i64);
+        }
     }
 
     pub async fn connect(
         &self,
         token: EdgeToken,
         query: Query,
+        connection_id: Option<String>,
     ) -> EdgeResult<Sse<InfallibleStream<ReceiverStream<sse::Event>>>> {
-        self.create_connection(StreamingQuery::from((&query, &token)), &token.token)
-            .await
-            .map(Sse::from_infallible_receiver)
+        self.create_connection(
+            StreamingQuery::from((&query, &token)),
+            &token.token,
+            connection_id.as_deref(),
+        )
+        .await
+        .map(Sse::from_infallible_receiver)
     }
 
+    /// A client reconnecting with the same connection id replaces its previous stream, which is closed,
+    /// instead of being counted twice
     async fn create_connection(
         &self,
         query: StreamingQuery,
         token: &str,
+        connection_id: Option<&str>,
     ) -> EdgeResult<mpsc::Receiver<sse::Event>> {
+        if let Some(connection_id) = connection_id {
+            self.remove_connection(&query, token, connection_id);
+        }
         let (tx, rx) = mpsc::channel(10);
+        // If connecting fails below, the stream is dropped on return, which releases the slot again
+        self.reserve_stream(token, &tx)?;
 
         let features = self.resolve_features(query.clone()).await?;
         tx.send(
@@ -177,19 +236,47 @@ impl Broadcaster {
             .and_modify(|group| {
                 group.clients.push(ClientData {
                     token: token.into(),
+                    connection_id: connection_id.map(Into::into),
                     sender: tx.clone(),
                 });
             })
             .or_insert(ClientGroup {
                 clients: vec![ClientData {
                     token: token.into(),
+                    connection_id: connection_id.map(Into::into),
                     sender: tx.clone(),
                 }],
             });
+        self.record_connected_clients();
 
         Ok(rx)
     }
 
+    /// Drops the streams `token` opened for `query` with `connection_id`, which ends them for the client.
+    /// Connection ids are picked by the SDK, so another token reusing one must not close these streams.
+    fn remove_connection(&self, query: &StreamingQuery, token: &str, connection_id: &str) {
+        if let Some(mut group) = self.active_connections.get_mut(query) {
+            group.clients.retain(|client| {
+                client.token != token || client.connection_id.as_deref() != Some(connection_id)
+            });
+        }
+    }
+
+    /// Takes one of the token's slots for `stream`. Counting the token's open streams and taking the slot both happen
+    /// under the token's entry lock, so concurrent connects can't both take the last slot.
+    fn reserve_stream(&self, token: &str, stream: &mpsc::Sender<sse::Event>) -> EdgeResult<()> {
+        let Some(limit) = self.max_streams_per_token else {
+            return Ok(());
+        };
+        let mut streams = self.streams_per_token.entry(token.into()).or_default();
+        streams.retain(is_open);
+        if streams.len() >= limit {
+            return Err(EdgeError::StreamLimitExceeded(limit));
+        }
+        streams.push(stream.downgrade());
+        Ok(())
+    }
+
     fn get_query_filters(query: &StreamingQuery) -> FeatureFilterSet {
         let filter_set = if let Some(name_prefix) = &query.name_prefix {
             FeatureFilterSet::from(Box::new(name_prefix_filter(name_prefix.clone())))
@@ -262,8 +349,16 @@ impl Broadcaster {
     }
 }
 
+/// Closed streams linger in the groups until the next heartbeat, so a stream only counts as open while we still hold it
+/// and the client hasn't gone away
+fn is_open(stream: &mpsc::WeakSender<sse::Event>) -> bool {
+    stream.upgrade().is_some_and(|stream| !stream.is_closed())
+}
+
 #[cfg(test)]
 mod test {
+    use actix_web::{http::StatusCode, ResponseError};
+    use tokio::sync::mpsc::error::TryRecvError;
     use tokio::time::timeout;
     use unleash_types::client_features::ClientFeature;
 
@@ -271,6 +366,8 @@ mod test {
 
     use super::*;
 
+    // Other events are ignored explicitly, which newer clippy versions want collapsed into the `if let`
+    #[allow(clippy::collapsible_match)]
     #[actix_web::test]
     async fn only_updates_clients_in_same_env() {
         let feature_cache = Arc::new(FeatureCache::default());
@@ -299,6 +396,7 @@ mod test {
                     projects: vec!["dx".to_string()],
                 },
                 "token",
+                None,
             )
             .await
             .expect("Failed to connect");
@@ -377,4 +475,225 @@ mod test {
 
         assert!(result.is_err());
     }
+
+    #[actix_web::test]
+    async fn rejects_streams_above_the_per_token_limit_without_affecting_other_tokens() {
+        let feature_cache = Arc::new(FeatureCache::default());
+        feature_cache.insert(
+            "development".into(),
+            ClientFeatures {
+                version: 0,
+                features: vec![],
+                query: None,
+                segments: None,
+                meta: None,
+            },
+        );
+        let broadcaster = Broadcaster::new_with_max_streams_per_token(feature_cache, Some(1));
+        let query = StreamingQuery {
+            name_prefix: None,
+            environment: "development".into(),
+            projects: vec!["dx".to_string()],
+        };
+
+        let first_stream = broadcaster
+            .create_connection(query.clone(), "busy-token", None)
+            .await
+            .expect("Failed to connect");
+        let rejected = broadcaster
+            .create_connection(query.clone(), "busy-token", None)
+            .await;
+        match rejected {
+            Err(e) => assert_eq!(e.status_code(), StatusCode::TOO_MANY_REQUESTS),
+            Ok(_) => panic!("Expected the second stream for the same token to be rejected"),
+        }
+        assert!(broadcaster
+            .create_connection(query.clone(), "other-token", None)
+            .await
+            .is_ok());
+
+        drop(first_stream);
+        assert!(broadcaster
+            .create_connection(query, "busy-token", None)
+            .await
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn counts_connected_clients_per_environment() {
+        let feature_cache = Arc::new(FeatureCache::default());
+        for env in ["streaming-count-a", "streaming-count-b"] {
+            feature_cache.insert(
+                env.into(),
+                ClientFeatures {
+                    version: 0,
+                    features: vec![],
+                    query: None,
+                    segments: None,
+                    meta: None,
+                },
+            );
+        }
+        let broadcaster = Broadcaster::new(feature_cache);
+        let query = |environment: &str| StreamingQuery {
+            name_prefix: None,
+            environment: environment.into(),
+            projects: vec!["dx".to_string()],
+        };
+
+        let first_a = broadcaster
+            .create_connection(query("streaming-count-a"), "token-a", None)
+            .await
+            .expect("Failed to connect");
+        let _second_a = broadcaster
+            .create_connection(query("streaming-count-a"), "token-a", None)
+            .await
+            .expect("Failed to connect");
+        let only_b = broadcaster
+            .create_connection(query("streaming-count-b"), "token-b", None)
+            .await
+            .expect("Failed to connect");
+
+        let counts = broadcaster.connected_clients_per_environment();
+        assert_eq!(counts.get("streaming-count-a"), Some(&2));
+        assert_eq!(counts.get("streaming-count-b"), Some(&1));
+
+        drop(first_a);
+        drop(only_b);
+        broadcaster.heartbeat().await;
+
+        let counts = broadcaster.connected_clients_per_environment();
+        assert_eq!(counts.get("streaming-count-a"), Some(&1));
+        assert_eq!(counts.get("streaming-count-b"), Some(&0));
+        assert_eq!(
+            CONNECTED_STREAMING_CLIENTS
+                .with_label_values(&["streaming-count-a"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            CONNECTED_STREAMING_CLIENTS
+                .with_label_values(&["streaming-count-b"])
+                .get(),
+            0
+        );
+    }
+
+    #[actix_web::test]
+    async fn reconnecting_with_the_same_connection_id_replaces_the_previous_stream() {
+        let feature_cache = Arc::new(FeatureCache::default());
+        feature_cache.insert(
+            "streaming-reconnect".into(),
+            ClientFeatures {
+                version: 0,
+                features: vec![],
+                query: None,
+                segments: None,
+                meta: None,
+            },
+        );
+        let broadcaster = Broadcaster::new(feature_cache);
+        let query = StreamingQuery {
+            name_prefix: None,
+            environment: "streaming-reconnect".into(),
+            projects: vec!["dx".to_string()],
+        };
+
+        let mut first = broadcaster
+            .create_connection(query.clone(), "token", Some("flaky-client"))
+            .await
+            .expect("Failed to connect");
+        let _reconnected = broadcaster
+            .create_connection(query.clone(), "token", Some("flaky-client"))
+            .await
+            .expect("Failed to connect");
+
+        let counts = broadcaster.connected_clients_per_environment();
+        assert_eq!(counts.get("streaming-reconnect"), Some(&1));
+        assert_eq!(
+            CONNECTED_STREAMING_CLIENTS
+                .with_label_values(&["streaming-reconnect"])
+                .get(),
+            1
+        );
+        // The replaced stream gets its connected event and is then closed
+        assert!(first.recv().await.is_some());
+        assert!(first.recv().await.is_none());
+
+        let _other = broadcaster
+            .create_connection(query, "token", Some("another-client"))
+            .await
+            .expect("Failed to connect");
+        let counts = broadcaster.connected_clients_per_environment();
+        assert_eq!(counts.get("streaming-reconnect"), Some(&2));
+    }
+
+    #[actix_web::test]
+    async fn reusing_a_connection_id_does_not_close_streams_of_other_tokens() {
+        let feature_cache = Arc::new(FeatureCache::default());
+        feature_cache.insert(
+            "streaming-connection-scope".into(),
+            ClientFeatures {
+                version: 0,
+                features: vec![],
+                query: None,
+                segments: None,
+                meta: None,
+            },
+        );
+        let broadcaster = Broadcaster::new(feature_cache);
+        let query = |projects: &[&str]| StreamingQuery {
+            name_prefix: None,
+            environment: "streaming-connection-scope".into(),
+            projects: projects.iter().map(|p| p.to_string()).collect(),
+        };
+
+        let mut victim = broadcaster
+            .create_connection(query(&["dx"]), "victim-token", Some("shared-id"))
+            .await
+            .expect("Failed to connect");
+        let _other_token = broadcaster
+            .create_connection(query(&["dx"]), "other-token", Some("shared-id"))
+            .await
+            .expect("Failed to connect");
+        let _other_query = broadcaster
+            .create_connection(query(&["eg"]), "victim-token", Some("shared-id"))
+            .await
+            .expect("Failed to connect");
+
+        let counts = broadcaster.connected_clients_per_environment();
+        assert_eq!(counts.get("streaming-connection-scope"), Some(&3));
+        assert!(victim.recv().await.is_some());
+        assert!(matches!(victim.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[actix_web::test]
+    async fn failed_connects_do_not_use_up_a_stream_slot() {
+        let feature_cache = Arc::new(FeatureCache::default());
+        feature_cache.insert(
+            "streaming-failed-connect".into(),
+            ClientFeatures {
+                version: 0,
+                features: vec![],
+                query: None,
+                segments: None,
+                meta: None,
+            },
+        );
+        let broadcaster = Broadcaster::new_with_max_streams_per_token(feature_cache, Some(1));
+        let query = |environment: &str| StreamingQuery {
+            name_prefix: None,
+            environment: environment.into(),
+            projects: vec!["dx".to_string()],
+        };
+
+        assert!(broadcaster
+            .create_connection(query("unknown-environment"), "token", None)
+            .await
+            .is_err());
+        assert!(broadcaster
+            .create_connection(query("streaming-failed-connect"), "token", None)
+            .await
+            .is_ok());
+    }
 }
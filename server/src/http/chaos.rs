@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reqwest::StatusCode;
+
+use crate::error::{EdgeError, FeatureError};
+use crate::types::EdgeResult;
+
+/// Degrades feature fetches from upstream on purpose, so SDK fallback behavior can be tested against a struggling Edge.
+/// Only built when `--unsafe-enable-chaos` is set
+#[derive(Debug)]
+pub struct Chaos {
+    fail_rate: f64,
+    latency: Option<Duration>,
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    pub fn new(fail_rate: f64, latency: Option<Duration>) -> Self {
+        Self::with_rng(fail_rate, latency, StdRng::from_os_rng())
+    }
+
+    pub fn seeded(fail_rate: f64, latency: Option<Duration>, seed: u64) -> Self {
+        Self::with_rng(fail_rate, latency, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(fail_rate: f64, latency: Option<Duration>, rng: StdRng) -> Self {
+        Self {
+            fail_rate: fail_rate.clamp(0.0, 1.0),
+            latency,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Waits for the configured latency, then fails a `fail_rate` fraction of calls like an unavailable upstream would
+    pub async fn inject(&self) -> EdgeResult<()> {
+        if let Some(latency) = self.latency {
+            tokio::time::sleep(latency).await;
+        }
+        let fail = self.rng.lock().unwrap().random_bool(self.fail_rate);
+        if fail {
+            Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::Retriable(StatusCode::SERVICE_UNAVAILABLE),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Chaos;
+
+    #[tokio::test]
+    async fn fails_about_the_configured_fraction_of_calls() {
+        let chaos = Chaos::seeded(0.1, None, 42);
+        let mut failures = 0;
+        for _ in 0..10_000 {
+            if chaos.inject().await.is_err() {
+                failures += 1;
+            }
+        }
+        assert!((900..=1100).contains(&failures), "{failures} calls failed");
+    }
+
+    #[tokio::test]
+    async fn delays_every_call_by_the_configured_latency() {
+        let chaos = Chaos::seeded(0.0, Some(Duration::from_millis(50)), 42);
+        let started = Instant::now();
+        assert!(chaos.inject().await.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge, IntGauge};
+use tracing::{info, warn};
+
+lazy_static! {
+    pub static ref UPSTREAM_CIRCUIT_BREAKER_STATE: IntGauge = register_int_gauge!(
+        "upstream_circuit_breaker_state",
+        "State of the circuit breaker for upstream feature fetches. 0 is closed, 1 is open and 2 is half open"
+    )
+    .unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go to upstream as usual
+    Closed,
+    /// Upstream is considered down, requests fail immediately
+    Open,
+    /// A single probe request is let through to see whether upstream has recovered
+    HalfOpen,
+}
+
+impl CircuitState {
+    fn gauge_value(&self) -> i64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    first_failure_at: Option<Instant>,
+    opened_at: Option<Instant>,
+    /// When the current probe was let through. Probes whose future is dropped never report back, so another is
+    /// let through once this is `open_duration` ago
+    probe_started_at: Option<Instant>,
+}
+
+/// Shared between all tokens' feature fetches, so a hard-down upstream isn't hammered with requests that are bound
+/// to fail. Opens after `failure_threshold` consecutive failures within `window`, and lets a probe through once
+/// it has been open for `open_duration`. The probe succeeding closes it again, failing reopens it
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    window: Duration,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, window: Duration, open_duration: Duration) -> Self {
+        UPSTREAM_CIRCUIT_BREAKER_STATE.set(CircuitState::Closed.gauge_value());
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            window,
+            open_duration,
+            state: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                first_failure_at: None,
+                opened_at: None,
+                probe_started_at: None,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap().state
+    }
+
+    /// Whether a request may be sent. Moves an open breaker to half open once `open_duration` has passed,
+    /// letting only the caller that made that transition through until the probe's outcome is recorded, or until
+    /// the probe has gone `open_duration` without reporting back
+    pub fn try_acquire(&self) -> bool {
+        let mut breaker = self.state.lock().unwrap();
+        match breaker.state {
+            CircuitState::Closed => true,
+            CircuitState::HalfOpen => {
+                let probe_abandoned = breaker.probe_started_at.map_or(true, |started_at| {
+                    started_at.elapsed() >= self.open_duration
+                });
+                if probe_abandoned {
+                    info!("Upstream probe never reported back, letting another one through");
+                    breaker.probe_started_at = Some(Instant::now());
+                }
+                probe_abandoned
+            }
+            CircuitState::Open => {
+                let waited_long_enough = breaker
+                    .opened_at
+                    .map_or(true, |opened_at| opened_at.elapsed() >= self.open_duration);
+                if waited_long_enough {
+                    info!("Probing whether upstream has recovered");
+                    breaker.probe_started_at = Some(Instant::now());
+                    Self::transition(&mut breaker, CircuitState::HalfOpen);
+                }
+                waited_long_enough
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut breaker = self.state.lock().unwrap();
+        if breaker.state != CircuitState::Closed {
+            info!("Upstream recovered, closing the circuit breaker");
+        }
+        breaker.consecutive_failures = 0;
+        breaker.first_failure_at = None;
+        breaker.opened_at = None;
+        breaker.probe_started_at = None;
+        Self::transition(&mut breaker, CircuitState::Closed);
+    }
+
+    pub fn record_failure(&self) {
+        let mut breaker = self.state.lock().unwrap();
+        let now = Instant::now();
+        match breaker.state {
+            CircuitState::HalfOpen => {
+                warn!("Upstream is still failing, reopening the circuit breaker");
+                breaker.opened_at = Some(now);
+                breaker.probe_started_at = None;
+                Self::transition(&mut breaker, CircuitState::Open);
+            }
+            CircuitState::Open => {}
+            CircuitState::Closed => {
+                let within_window = breaker
+                    .first_failure_at
+                    .is_some_and(|first| now.duration_since(first) <= self.window);
+                if within_window {
+                    breaker.consecutive_failures += 1;
+                } else {
+                    breaker.consecutive_failures = 1;
+                    breaker.first_failure_at = Some(now);
+                }
+                if breaker.consecutive_failures >= self.failure_threshold {
+                    warn!(
+                        "Upstream failed {} times in a row, pausing feature fetches for {:?}",
+                        breaker.consecutive_failures, self.open_duration
+                    );
+                    breaker.opened_at = Some(now);
+                    Self::transition(&mut breaker, CircuitState::Open);
+                }
+            }
+        }
+    }
+
+    fn transition(breaker: &mut BreakerState, state: CircuitState) {
+        breaker.state = state;
+        UPSTREAM_CIRCUIT_BREAKER_STATE.set(state.gauge_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CircuitBreaker, CircuitState};
+
+    #[test]
+    fn failures_outside_the_window_start_a_new_count() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO, Duration::from_secs(30));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(5));
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn only_one_probe_is_let_through_while_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60), Duration::from_millis(50));
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.try_acquire());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn another_probe_is_let_through_when_the_first_never_reports_back() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60), Duration::from_millis(50));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.try_acquire());
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::http::unleash_client::{new_reqwest_client, HttpClientArgs, UnleashClient};
+
+/// Rebuilds the client used for upstream when the client certificate files change, so short-lived certificates
+/// can be rotated without restarting Edge
+pub struct ClientIdentityWatcher {
+    unleash_client: Arc<UnleashClient>,
+    http_client_args: HttpClientArgs,
+    /// Contents of the identity files the current client was built from
+    loaded: Option<Vec<Vec<u8>>>,
+}
+
+impl ClientIdentityWatcher {
+    /// None when no client identity is configured, as there is nothing to watch
+    pub fn new(
+        unleash_client: Arc<UnleashClient>,
+        http_client_args: HttpClientArgs,
+    ) -> Option<Self> {
+        http_client_args.client_identity.as_ref()?;
+        let mut watcher = Self {
+            unleash_client,
+            http_client_args,
+            loaded: None,
+        };
+        watcher.loaded = watcher.read_identity_files();
+        Some(watcher)
+    }
+
+    fn identity_files(&self) -> Vec<PathBuf> {
+        self.http_client_args
+            .client_identity
+            .iter()
+            .flat_map(|identity| {
+                [
+                    identity.pkcs12_identity_file.clone(),
+                    identity.pkcs8_client_certificate_file.clone(),
+                    identity.pkcs8_client_key_file.clone(),
+                ]
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn read_identity_files(&self) -> Option<Vec<Vec<u8>>> {
+        self.identity_files()
+            .iter()
+            .map(|path| std::fs::read(path).ok())
+            .collect()
+    }
+
+    /// Rebuilds the client if the identity files changed since the last successful build. A client that fails to
+    /// build, e.g. because only the certificate has been replaced so far and not yet its key, is retried on the
+    /// next check while the previous client stays in use
+    pub fn reload_if_changed(&mut self) -> bool {
+        let current = self.read_identity_files();
+        if current.is_none() || current == self.loaded {
+            return false;
+        }
+        match new_reqwest_client(self.http_client_args.clone()) {
+            Ok(client) => {
+                self.unleash_client.replace_backing_client(client);
+                self.loaded = current;
+                info!("Client certificate changed, rebuilt the client used for upstream");
+                true
+            }
+            Err(e) => {
+                warn!("Client certificate changed, but could not build a client from it. Keeping the previous client: {e:?}");
+                false
+            }
+        }
+    }
+
+    pub async fn watch(mut self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            self.reload_if_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::sync::Arc;
+
+    use ulid::Ulid;
+
+    use super::ClientIdentityWatcher;
+    use crate::cli::ClientIdentity;
+    use crate::http::unleash_client::{HttpClientArgs, UnleashClient};
+
+    #[test]
+    pub fn changing_the_certificate_file_rebuilds_the_client() {
+        let dir = temp_dir().join(format!("client-identity-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("client.pem");
+        let key = dir.join("client.key");
+        std::fs::copy("./testdata/pkcs12/snakeoil.pem", &cert).unwrap();
+        std::fs::copy("./testdata/pkcs8/snakeoil.key", &key).unwrap();
+        let unleash_client = Arc::new(UnleashClient::new("http://localhost:4242", None).unwrap());
+        let mut watcher = ClientIdentityWatcher::new(
+            unleash_client,
+            HttpClientArgs {
+                client_identity: Some(ClientIdentity {
+                    pkcs8_client_certificate_file: Some(cert.clone()),
+                    pkcs8_client_key_file: Some(key.clone()),
+                    pkcs12_identity_file: None,
+                    pkcs12_passphrase: None,
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(!watcher.reload_if_changed());
+
+        std::fs::write(&cert, "not a certificate").unwrap();
+        assert!(!watcher.reload_if_changed());
+
+        let rotated = std::fs::read_to_string("./testdata/pkcs12/snakeoil.pem").unwrap();
+        std::fs::write(&cert, format!("{rotated}\n")).unwrap();
+        assert!(watcher.reload_if_changed());
+        assert!(!watcher.reload_if_changed());
+    }
+
+    #[test]
+    pub fn nothing_is_watched_without_a_client_identity() {
+        let unleash_client = Arc::new(UnleashClient::new("http://localhost:4242", None).unwrap());
+        assert!(ClientIdentityWatcher::new(unleash_client, HttpClientArgs::default()).is_none());
+    }
+}
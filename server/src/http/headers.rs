@@ -1,3 +1,8 @@
 pub(crate) const UNLEASH_APPNAME_HEADER: &str = "UNLEASH-APPNAME";
 pub(crate) const UNLEASH_INSTANCE_ID_HEADER: &str = "UNLEASH-INSTANCEID";
 pub(crate) const UNLEASH_CLIENT_SPEC_HEADER: &str = "Unleash-Client-Spec";
+pub(crate) const UNLEASH_CONNECTION_ID_HEADER: &str = "UNLEASH-CONNECTION-ID";
+pub(crate) const EDGE_VERSION_HEADER: &str = "X-Edge-Version";
+pub(crate) const UNLEASH_EDGE_STALE_HEADER: &str = "Unleash-Edge-Stale";
+pub(crate) const UNLEASH_EDGE_AGE_HEADER: &str = "Unleash-Edge-Age";
+pub(crate) const UNLEASH_EDGE_MISSING_ENVIRONMENT_HEADER: &str = "Unleash-Edge-Missing-Environment";
@@ -1,6 +1,9 @@
 #[cfg(not(tarpaulin_include))]
 pub mod background_send_metrics;
 pub mod broadcaster;
+pub mod chaos;
+pub mod circuit_breaker;
+pub mod client_identity_watcher;
 pub(crate) mod headers;
 pub mod unleash_client;
 pub mod refresher;
@@ -1,21 +1,306 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
 use actix_web::http::header::EntityTag;
 use reqwest::StatusCode;
 use tracing::{debug, info, warn};
-use unleash_types::client_features::{ClientFeaturesDelta};
+use unleash_types::client_features::{
+    ClientFeature, ClientFeatures, ClientFeaturesDelta, DeltaEvent,
+};
 use unleash_yggdrasil::EngineState;
 
 use crate::error::{EdgeError, FeatureError};
+use crate::filters::{frontend_features, FeatureFilterSet};
 use crate::types::{ClientFeaturesDeltaResponse, ClientFeaturesRequest, EdgeToken, TokenRefresh};
-use crate::http::refresher::feature_refresher::FeatureRefresher;
+use crate::http::refresher::feature_refresher::{
+    normalize_client_features_delta, observe_cache_update_duration, FeatureRefresher,
+};
 use crate::tokens::cache_key;
 
+/// How many delta events are kept per cache key for `/api/client/delta`. Older revisions get a full hydration
+const MAX_DELTA_HISTORY: usize = 500;
+
+/// The delta events received for a cache key since its last hydration, capped at [MAX_DELTA_HISTORY]. Several tokens
+/// can feed the same cache key, each with its own delta stream, so events are kept ordered by event id and the
+/// revision only moves up to the point every one of those tokens has caught up with
+#[derive(Debug, Default)]
+pub struct DeltaHistory {
+    /// Revision the kept events follow on from, changes from older revisions can't be replayed
+    base_revision: i32,
+    events: VecDeque<DeltaEvent>,
+    /// The version of the feature each kept update or removal replaced, by event id
+    replaced: HashMap<i32, ClientFeature>,
+    /// The last event id each token feeding this history has seen
+    token_revisions: HashMap<String, i32>,
+}
+
+impl DeltaHistory {
+    /// A history for features restored at `event_id`, with no events to replay yet
+    pub fn resumed_from(event_id: i32) -> Self {
+        DeltaHistory {
+            base_revision: event_id,
+            ..Default::default()
+        }
+    }
+
+    /// Records the `delta` `token` received. `current` is the state of the features before `delta` gets applied
+    pub fn record(
+        &mut self,
+        token: &str,
+        delta: &ClientFeaturesDelta,
+        current: Option<&ClientFeatures>,
+    ) {
+        let mut latest: HashMap<&str, &ClientFeature> = current
+            .map(|features| {
+                features
+                    .features
+                    .iter()
+                    .map(|f| (f.name.as_str(), f))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for event in delta.events.iter() {
+            let event_id = delta_event_id(event);
+            match event {
+                DeltaEvent::Hydration { features, .. } => {
+                    self.token_revisions.insert(token.into(), event_id);
+                    // Whatever changed for this token's features up to the hydration was never seen as events
+                    self.base_revision = self.base_revision.max(event_id);
+                    let base_revision = self.base_revision;
+                    self.events
+                        .retain(|kept| delta_event_id(kept) > base_revision);
+                    self.replaced.retain(|id, _| *id > base_revision);
+                    latest = features.iter().map(|f| (f.name.as_str(), f)).collect();
+                    continue;
+                }
+                DeltaEvent::FeatureUpdated { feature, .. } => {
+                    if let Some(previous) = latest.insert(feature.name.as_str(), feature) {
+                        self.replaced.insert(event_id, previous.clone());
+                    }
+                }
+                DeltaEvent::FeatureRemoved { feature_name, .. } => {
+                    if let Some(previous) = latest.remove(feature_name.as_str()) {
+                        self.replaced.insert(event_id, previous.clone());
+                    }
+                }
+                _ => {}
+            }
+            let token_revision = self.token_revisions.entry(token.into()).or_insert(event_id);
+            *token_revision = (*token_revision).max(event_id);
+            self.insert_in_order(event);
+        }
+        while self.events.len() > MAX_DELTA_HISTORY {
+            if let Some(dropped) = self.events.pop_front() {
+                self.base_revision = delta_event_id(&dropped);
+                self.replaced.remove(&self.base_revision);
+            }
+        }
+    }
+
+    /// Tokens overlapping in projects receive the same events, those are only kept once
+    fn insert_in_order(&mut self, event: &DeltaEvent) {
+        let event_id = delta_event_id(event);
+        if event_id <= self.base_revision {
+            return;
+        }
+        let position = self
+            .events
+            .partition_point(|kept| delta_event_id(kept) <= event_id);
+        let already_kept = self
+            .events
+            .range(..position)
+            .rev()
+            .take_while(|kept| delta_event_id(kept) == event_id)
+            .any(|kept| kept == event);
+        if !already_kept {
+            self.events.insert(position, event.clone());
+        }
+    }
+
+    /// Stops waiting for `token` to catch up before moving the revision on
+    pub fn forget_token(&mut self, token: &str) {
+        self.token_revisions.remove(token);
+    }
+
+    /// The newest revision every token feeding this history has seen. Events past it are held back, since a token
+    /// lagging behind may still report older events
+    pub fn latest_revision(&self) -> i32 {
+        self.token_revisions
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(self.base_revision)
+    }
+
+    /// None when events after `revision` have already been dropped, or `revision` is unknown
+    pub fn events_since(&self, revision: i32) -> Option<Vec<DeltaEvent>> {
+        let latest_revision = self.latest_revision();
+        if revision < self.base_revision || revision > latest_revision {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .filter(|event| (revision + 1..=latest_revision).contains(&delta_event_id(event)))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// The events after `revision` that touch features `filter_set` lets through. An update that takes a feature
+    /// out of the filter is sent as a removal, but only if the version it replaced was let through
+    pub(crate) fn filtered_events_since(
+        &self,
+        revision: i32,
+        filter_set: &FeatureFilterSet,
+    ) -> Option<Vec<DeltaEvent>> {
+        let was_visible = |event_id: i32| {
+            self.replaced
+                .get(&event_id)
+                .is_some_and(|previous| filter_set.apply(previous))
+        };
+        let events = self.events_since(revision)?;
+        Some(
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    DeltaEvent::FeatureUpdated { event_id, feature }
+                        if !filter_set.apply(&feature) =>
+                    {
+                        was_visible(event_id).then_some(DeltaEvent::FeatureRemoved {
+                            event_id,
+                            feature_name: feature.name,
+                        })
+                    }
+                    DeltaEvent::FeatureRemoved { event_id, .. } if !was_visible(event_id) => None,
+                    event => Some(event),
+                })
+                .collect(),
+        )
+    }
+}
+
 impl FeatureRefresher {
+    /// Changes to the features for `key` after `revision` that `filter_set` lets through, if they're all still known
+    pub(crate) fn delta_events_since(
+        &self,
+        key: &str,
+        revision: i32,
+        filter_set: &FeatureFilterSet,
+    ) -> Option<Vec<DeltaEvent>> {
+        self.delta_history
+            .get(key)
+            .and_then(|history| history.filtered_events_since(revision, filter_set))
+    }
+
+    pub fn latest_delta_revision(&self, key: &str) -> Option<i32> {
+        self.delta_history
+            .get(key)
+            .map(|history| history.latest_revision())
+    }
+
+    /// Collapses the current feature cache into a single hydration event per cache key, tagged with the
+    /// lowest event id any token for that key has seen. Restoring this lets us resume from that event
+    /// instead of asking upstream for a full hydration.
+    pub fn delta_cache_snapshot(&self) -> Vec<(String, ClientFeaturesDelta)> {
+        let mut event_ids: HashMap<String, i32> = HashMap::new();
+        for refresh in self.tokens_to_refresh.iter() {
+            if let Some(event_id) = refresh
+                .etag
+                .as_ref()
+                .and_then(|etag| etag.tag().parse::<i32>().ok())
+            {
+                event_ids
+                    .entry(cache_key(&refresh.token))
+                    .and_modify(|id| *id = (*id).min(event_id))
+                    .or_insert(event_id);
+            }
+        }
+        event_ids
+            .into_iter()
+            .filter_map(|(key, event_id)| {
+                self.features_cache.get(&key).map(|features| {
+                    let delta = ClientFeaturesDelta {
+                        events: vec![DeltaEvent::Hydration {
+                            event_id,
+                            features: features.features.clone(),
+                            segments: features.segments.clone().unwrap_or_default(),
+                        }],
+                    };
+                    (key, delta)
+                })
+            })
+            .collect()
+    }
+
+    /// Loads persisted delta state into the feature and engine caches.
+    /// Returns the etag to resume from for each restored cache key
+    pub async fn restore_delta_from_persistence(&self) -> HashMap<String, EntityTag> {
+        let mut etags = HashMap::new();
+        if let Some(persistence) = self.persistence.clone() {
+            let persisted = persistence.load_delta().await.unwrap_or_else(|error| {
+                warn!("Failed to load delta state from cache {error:?}");
+                Default::default()
+            });
+            for (key, delta) in persisted {
+                debug!("Restoring delta state for {key:?}");
+                self.features_cache.apply_delta(key.clone(), &delta);
+                if !self.rebuild_frontend_engine(&key) {
+                    let mut engine_state = EngineState::default();
+                    if let Some(warnings) = engine_state.apply_delta(&delta) {
+                        warn!("Failed to restore delta state for {key:?}: {warnings:?}");
+                    }
+                    self.engine_cache.insert(key.clone(), engine_state);
+                }
+                if let Some(event_id) = delta.events.iter().map(delta_event_id).max() {
+                    self.delta_history
+                        .insert(key.clone(), DeltaHistory::resumed_from(event_id));
+                    etags.insert(key, EntityTag::new_strong(event_id.to_string()));
+                }
+            }
+        }
+        etags
+    }
+
+    /// Engines built without oversized or stale features can't take deltas, since a delta may bring a left out feature
+    /// back. With `--max-strategies-per-feature` or `--hide-stale-features`, the engine is rebuilt from the updated
+    /// features cache instead
+    fn rebuild_frontend_engine(&self, key: &str) -> bool {
+        if self.max_strategies_per_feature.is_none() && !self.hide_stale_features {
+            return false;
+        }
+        let Some(features) = self
+            .features_cache
+            .get(key)
+            .map(|features| features.clone())
+        else {
+            return false;
+        };
+        let mut engine_state = EngineState::default();
+        if let Some(warnings) = engine_state.take_state(frontend_features(
+            features,
+            self.max_strategies_per_feature,
+            self.hide_stale_features,
+        )) {
+            warn!(
+                "The following toggle failed to compile and will be defaulted to off: {warnings:?}"
+            );
+        }
+        self.engine_cache.insert(key.to_string(), engine_state);
+        true
+    }
+
     async fn handle_client_features_delta_updated(
         &self,
         refresh_token: &EdgeToken,
         delta: ClientFeaturesDelta,
         etag: Option<EntityTag>,
     ) {
+        let delta = if self.normalize_feature_names {
+            normalize_client_features_delta(delta)
+        } else {
+            delta
+        };
         let updated_len = delta.events.len();
 
         debug!(
@@ -23,12 +308,23 @@ impl FeatureRefresher {
         );
 
         let key = cache_key(refresh_token);
+        let environment = refresh_token.environment.as_deref().unwrap_or_default();
+        let started = Instant::now();
+        self.delta_history.entry(key.clone()).or_default().record(
+            &refresh_token.token,
+            &delta,
+            self.features_cache.get(&key).as_deref(),
+        );
         self.features_cache.apply_delta(key.clone(), &delta);
         self.update_last_refresh(
             refresh_token,
             etag,
             self.features_cache.get(&key).unwrap().features.len(),
         );
+        if self.rebuild_frontend_engine(&key) {
+            observe_cache_update_duration(environment, started);
+            return;
+        }
         self.engine_cache
             .entry(key.clone())
             .and_modify(|engine| {
@@ -43,6 +339,7 @@ impl FeatureRefresher {
                 };
                 new_state
             });
+        observe_cache_update_duration(environment, started);
     }
 
     pub async fn refresh_single_delta(&self, refresh: TokenRefresh) {
@@ -85,21 +382,23 @@ impl FeatureRefresher {
                                 }
                             },
                             FeatureError::AccessDenied => {
-                                info!("Token used to fetch features was Forbidden, will remove from list of refresh tasks");
-                                self.tokens_to_refresh.remove(&refresh.token.token);
-                                if !self.tokens_to_refresh.iter().any(|e| {
-                                    e.value().token.environment == refresh.token.environment
-                                }) {
-                                    let cache_key = cache_key(&refresh.token);
-                                    // No tokens left that access the environment of our current refresh. Deleting client features and engine cache
-                                    self.features_cache.remove(&cache_key);
-                                    self.engine_cache.remove(&cache_key);
-                                }
+                                self.deny_token(&refresh.token);
                             }
                             FeatureError::NotFound => {
                                 info!("Had a bad URL when trying to fetch features. Increasing waiting period for the token before trying again");
                                 self.backoff(&refresh.token);
                             }
+                            FeatureError::PayloadTooLarge(limit) => {
+                                warn!("Upstream features response exceeded {limit} bytes and was discarded. Increasing waiting period for the token before trying again");
+                                self.backoff(&refresh.token);
+                            }
+                            FeatureError::CircuitOpen => {
+                                debug!("Upstream circuit breaker is open, skipping refresh until it closes");
+                            }
+                            FeatureError::Unreachable => {
+                                info!("Could not reach upstream. Increasing waiting period for the token before trying again");
+                                self.backoff(&refresh.token);
+                            }
                         }
                     }
                     EdgeError::ClientCacheError => {
@@ -112,6 +411,16 @@ impl FeatureRefresher {
     }
 }
 
+fn delta_event_id(event: &DeltaEvent) -> i32 {
+    match event {
+        DeltaEvent::FeatureUpdated { event_id, .. }
+        | DeltaEvent::FeatureRemoved { event_id, .. }
+        | DeltaEvent::SegmentUpdated { event_id, .. }
+        | DeltaEvent::SegmentRemoved { event_id, .. }
+        | DeltaEvent::Hydration { event_id, .. } => *event_id,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use actix_http::header::IF_NONE_MATCH;
@@ -123,10 +432,14 @@ mod tests {
     use actix_web::{web, App, HttpRequest, HttpResponse};
     use chrono::Duration;
     use dashmap::DashMap;
+    use std::env::temp_dir;
     use std::sync::Arc;
+    use super::{DeltaHistory, MAX_DELTA_HISTORY};
     use crate::feature_cache::FeatureCache;
     use crate::http::refresher::feature_refresher::FeatureRefresher;
     use crate::http::unleash_client::{ClientMetaInformation, UnleashClient};
+    use crate::persistence::file::FilePersister;
+    use crate::persistence::EdgePersistence;
     use crate::types::EdgeToken;
     use unleash_types::client_features::{ClientFeature, ClientFeatures, ClientFeaturesDelta, Constraint, DeltaEvent, Operator, Segment};
     use unleash_yggdrasil::EngineState;
@@ -151,6 +464,7 @@ mod tests {
             delta: true,
             delta_diff : false,
             client_meta_information: ClientMetaInformation::test_config(),
+            ..Default::default()
         });
         let mut delta_features = ClientFeatures::create_from_delta(&revision(1));
         let token =
@@ -181,6 +495,65 @@ mod tests {
         assert_eq!(refreshed_features, delta_features);
     }
 
+    #[actix_web::test]
+    async fn delta_state_is_restored_from_persistence() {
+        let srv = test_features_server().await;
+        let unleash_client = Arc::new(UnleashClient::new(srv.url("/").as_str(), None).unwrap());
+        let backup_folder =
+            temp_dir().join(format!("delta-state-persistence-{}", ulid::Ulid::new()));
+        let persistence: Arc<dyn EdgePersistence> = Arc::new(FilePersister::new(&backup_folder));
+        let token =
+            EdgeToken::try_from("*:development.abcdefghijklmnopqrstuvwxyz".to_string()).unwrap();
+
+        let feature_refresher = FeatureRefresher {
+            unleash_client: unleash_client.clone(),
+            persistence: Some(persistence.clone()),
+            delta: true,
+            ..Default::default()
+        };
+        feature_refresher
+            .register_token_for_refresh(token.clone(), None)
+            .await;
+        feature_refresher.refresh_features().await;
+        persistence
+            .save_delta(feature_refresher.delta_cache_snapshot())
+            .await
+            .unwrap();
+
+        let features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let restarted_refresher = FeatureRefresher {
+            unleash_client: unleash_client.clone(),
+            features_cache: features_cache.clone(),
+            persistence: Some(persistence.clone()),
+            delta: true,
+            ..Default::default()
+        };
+        let etags = restarted_refresher.restore_delta_from_persistence().await;
+        let mut expected = ClientFeatures::create_from_delta(&revision(1));
+        assert_eq!(
+            etags.get(&cache_key(&token)),
+            Some(&EntityTag::new_strong("1".to_string()))
+        );
+        assert_eq!(
+            features_cache.get(&cache_key(&token)).unwrap().value().clone(),
+            expected
+        );
+        assert!(restarted_refresher
+            .engine_cache
+            .contains_key(&cache_key(&token)));
+
+        restarted_refresher
+            .register_token_for_refresh(token.clone(), etags.get(&cache_key(&token)).cloned())
+            .await;
+        restarted_refresher.refresh_features().await;
+        expected.apply_delta(&revision(2));
+        assert_eq!(
+            features_cache.get(&cache_key(&token)).unwrap().value().clone(),
+            expected
+        );
+        std::fs::remove_dir_all(backup_folder).unwrap();
+    }
+
     fn cache_key(token: &EdgeToken) -> String {
         token
             .environment
@@ -277,4 +650,143 @@ mod tests {
         })
             .await
     }
+
+    fn updated(event_id: i32, name: &str) -> DeltaEvent {
+        DeltaEvent::FeatureUpdated {
+            event_id,
+            feature: ClientFeature {
+                name: name.into(),
+                ..ClientFeature::default()
+            },
+        }
+    }
+
+    #[test]
+    fn delta_history_replays_events_after_a_known_revision() {
+        let mut history = DeltaHistory::default();
+        history.record(
+            "token",
+            &ClientFeaturesDelta {
+                events: vec![DeltaEvent::Hydration {
+                    event_id: 1,
+                    features: vec![],
+                    segments: vec![],
+                }],
+            },
+            None,
+        );
+        history.record(
+            "token",
+            &ClientFeaturesDelta {
+                events: vec![updated(2, "first")],
+            },
+            None,
+        );
+        history.record(
+            "token",
+            &ClientFeaturesDelta {
+                events: vec![updated(3, "second")],
+            },
+            None,
+        );
+
+        assert_eq!(history.latest_revision(), 3);
+        assert_eq!(
+            history.events_since(1).unwrap(),
+            vec![updated(2, "first"), updated(3, "second")]
+        );
+        assert_eq!(history.events_since(2).unwrap(), vec![updated(3, "second")]);
+        assert!(history.events_since(3).unwrap().is_empty());
+        assert!(history.events_since(0).is_none());
+        assert!(history.events_since(4).is_none());
+    }
+
+    #[test]
+    fn delta_history_forgets_revisions_older_than_what_it_keeps() {
+        let mut history = DeltaHistory::default();
+        history.record(
+            "token",
+            &ClientFeaturesDelta {
+                events: (1..=MAX_DELTA_HISTORY as i32 + 10)
+                    .map(|event_id| updated(event_id, "toggle"))
+                    .collect(),
+            },
+            None,
+        );
+
+        assert!(history.events_since(5).is_none());
+        assert_eq!(history.events_since(10).unwrap().len(), MAX_DELTA_HISTORY);
+    }
+
+    #[test]
+    fn delta_history_from_several_tokens_only_replays_what_every_token_has_caught_up_with() {
+        let mut history = DeltaHistory::default();
+        for token in ["dx-token", "eg-token"] {
+            history.record(
+                token,
+                &ClientFeaturesDelta {
+                    events: vec![DeltaEvent::Hydration {
+                        event_id: 5,
+                        features: vec![],
+                        segments: vec![],
+                    }],
+                },
+                None,
+            );
+        }
+        history.record(
+            "dx-token",
+            &ClientFeaturesDelta {
+                events: vec![updated(6, "dx-first"), updated(10, "dx-second")],
+            },
+            None,
+        );
+
+        assert_eq!(history.latest_revision(), 5);
+        assert!(history.events_since(5).unwrap().is_empty());
+        assert!(history.events_since(10).is_none());
+
+        history.record(
+            "eg-token",
+            &ClientFeaturesDelta {
+                events: vec![updated(8, "eg-first"), updated(10, "dx-second")],
+            },
+            None,
+        );
+
+        assert_eq!(history.latest_revision(), 10);
+        assert_eq!(
+            history.events_since(5).unwrap(),
+            vec![
+                updated(6, "dx-first"),
+                updated(8, "eg-first"),
+                updated(10, "dx-second")
+            ]
+        );
+        assert_eq!(
+            history.events_since(7).unwrap(),
+            vec![updated(8, "eg-first"), updated(10, "dx-second")]
+        );
+
+        history.record(
+            "eg-token",
+            &ClientFeaturesDelta {
+                events: vec![DeltaEvent::Hydration {
+                    event_id: 9,
+                    features: vec![],
+                    segments: vec![],
+                }],
+            },
+            None,
+        );
+        assert_eq!(history.latest_revision(), 9);
+        assert!(history.events_since(8).is_none());
+        assert!(history.events_since(9).unwrap().is_empty());
+
+        history.forget_token("eg-token");
+        assert_eq!(
+            history.events_since(9).unwrap(),
+            vec![updated(10, "dx-second")]
+        );
+    }
 }
@@ -1,32 +1,58 @@
-use std::collections::HashSet;
-use std::{sync::Arc, time::Duration};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use actix_web::http::header::EntityTag;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use eventsource_client::Client;
 use futures::TryStreamExt;
 use json_structural_diff::JsonDiff;
+use lazy_static::lazy_static;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+};
 use reqwest::StatusCode;
-use tracing::{debug, info, warn};
-use unleash_types::client_features::{ClientFeatures, DeltaEvent};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, error, info, warn};
+use unleash_types::client_features::{
+    ClientFeature, ClientFeatures, ClientFeaturesDelta, DeltaEvent,
+};
 use unleash_types::client_metrics::{ClientApplication, MetricsMetadata};
 use unleash_yggdrasil::EngineState;
 
+use crate::cli::{EnvironmentMismatchBehavior, TokenPriority, TokenSimplificationLogLevel};
 use crate::error::{EdgeError, FeatureError};
 use crate::feature_cache::FeatureCache;
-use crate::filters::{filter_client_features, FeatureFilterSet};
+use crate::filters::{frontend_features, normalize_feature_name, project_filter, FeatureFilterSet};
 use crate::http::headers::{
     UNLEASH_APPNAME_HEADER, UNLEASH_CLIENT_SPEC_HEADER, UNLEASH_INSTANCE_ID_HEADER,
 };
 use crate::types::{build, ClientFeaturesDeltaResponse, EdgeResult, TokenType, TokenValidationStatus};
 use crate::{
     persistence::EdgePersistence,
-    tokens::{cache_key, simplify},
+    tokens::{anonymize_token, cache_key, simplify},
     types::{ClientFeaturesRequest, ClientFeaturesResponse, EdgeToken, TokenRefresh},
 };
 
+use crate::http::refresher::delta_refresher::DeltaHistory;
 use crate::http::unleash_client::{ClientMetaInformation, UnleashClient};
+use crate::metrics::upstream_latency::UPSTREAM_FEATURE_FETCH_LATENCY;
+
+/// Covers both writing the features and rebuilding the engine, since reads wait on either
+pub(crate) fn observe_cache_update_duration(environment: &str, started: Instant) {
+    FEATURE_CACHE_UPDATE_DURATION
+        .with_label_values(&[environment])
+        .observe(started.elapsed().as_secs_f64() * 1000.0);
+}
 
 fn frontend_token_is_covered_by_tokens(
     frontend_token: &EdgeToken,
@@ -39,6 +65,189 @@ fn frontend_token_is_covered_by_tokens(
     })
 }
 
+lazy_static! {
+    pub static ref REGISTERED_TOKENS_PER_ENVIRONMENT: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "registered_tokens_per_environment",
+            "Number of tokens registered for refresh per environment"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref REGISTERED_TOKENS_BY_SCOPE: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "registered_tokens_by_scope",
+            "Number of tokens registered for refresh with wildcard or scoped project access"
+        ),
+        &["scope"]
+    )
+    .unwrap();
+    pub static ref REGISTERED_TOKEN_PROJECTS: IntGauge = register_int_gauge!(
+        "registered_token_distinct_projects",
+        "Number of distinct projects explicitly granted by tokens registered for refresh"
+    )
+    .unwrap();
+    pub static ref UPSTREAM_ENVIRONMENT_MISMATCHES: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "upstream_environment_mismatches",
+            "Feature updates where upstream returned a different environment than the token's"
+        ),
+        &["expected", "actual"]
+    )
+    .unwrap();
+    pub static ref REVISION_REGRESSIONS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "revision_regression_total",
+            "Feature updates where upstream returned an older revision than Edge had already seen"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref FEATURE_COUNT_DROPS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "feature_count_drop_total",
+            "Refreshes where the feature count for a token dropped by more than --feature-count-drop-threshold percent"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref EMPTY_UPSTREAM_RESPONSES_IGNORED: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "empty_upstream_responses_ignored_total",
+            "Feature updates without any features that were ignored to keep serving the previously cached features"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref ENGINE_REBUILDS_SKIPPED: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "engine_rebuilds_skipped_total",
+            "Feature updates with the same content as the last one for the token, which didn't rebuild the engine"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref STRICT_MODE_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "strict_mode_rejections_total",
+            "Requests refused because strict mode only serves tokens within the scope of the startup tokens"
+        ),
+        &["environment"]
+    )
+    .unwrap();
+    pub static ref FEATURE_CACHE_UPDATE_DURATION: HistogramVec = register_histogram_vec!(
+        "feature_cache_update_duration_milliseconds",
+        "Time spent writing upstream updates into the features cache and rebuilding the engine in milliseconds. Reads of the environment wait on this",
+        &["environment"],
+        vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0]
+    )
+    .unwrap();
+    pub static ref TOKENS_SUBSUMED: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "tokens_subsumed",
+            "Tokens dropped from refresh because a registered token with broader project access covers them"
+        ),
+        &["environment", "removed_projects", "surviving_projects"]
+    )
+    .unwrap();
+    pub static ref CACHED_FEATURE_COUNT: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "cached_feature_count",
+            "Number of features in the last update received for a token's environment and projects"
+        ),
+        &["environment", "projects"]
+    )
+    .unwrap();
+    pub static ref SECONDS_SINCE_LAST_UPDATE: SecondsSinceLastUpdate =
+        SecondsSinceLastUpdate::default();
+}
+
+/// Reports how long ago each environment was last confirmed up to date with upstream, either by receiving
+/// features or by upstream answering that nothing changed. The age is computed at scrape time, so it keeps
+/// growing while refreshes for an environment fail.
+#[derive(Clone, Debug)]
+pub struct SecondsSinceLastUpdate {
+    template: IntGaugeVec,
+    last_updates: Arc<DashMap<String, DateTime<Utc>>>,
+}
+
+impl SecondsSinceLastUpdate {
+    fn gauge() -> IntGaugeVec {
+        IntGaugeVec::new(
+            Opts::new(
+                "seconds_since_last_update",
+                "Seconds since features for the environment were last confirmed up to date with upstream",
+            ),
+            &["environment"],
+        )
+        .unwrap()
+    }
+
+    pub fn record(&self, environment: &str, at: DateTime<Utc>) {
+        self.last_updates.insert(environment.into(), at);
+    }
+
+    pub fn seconds_since(&self, environment: &str, now: DateTime<Utc>) -> Option<i64> {
+        self.last_updates
+            .get(environment)
+            .map(|last_update| (now - *last_update).num_seconds())
+    }
+}
+
+impl Default for SecondsSinceLastUpdate {
+    fn default() -> Self {
+        Self {
+            template: Self::gauge(),
+            last_updates: Arc::new(DashMap::default()),
+        }
+    }
+}
+
+impl Collector for SecondsSinceLastUpdate {
+    fn desc(&self) -> Vec<&Desc> {
+        self.template.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = Utc::now();
+        let gauge = Self::gauge();
+        for last_update in self.last_updates.iter() {
+            gauge
+                .with_label_values(&[last_update.key().as_str()])
+                .set((now - *last_update.value()).num_seconds());
+        }
+        gauge.collect()
+    }
+}
+
+/// Upper bound for how long the background refresh task sleeps when no `--scheduler-tick-ms` is configured.
+const DEFAULT_SCHEDULER_TICK: Duration = Duration::from_secs(5);
+/// Lower bound for the sleep, so tokens due within a few milliseconds of each other are refreshed in one pass.
+const MIN_SCHEDULER_TICK: Duration = Duration::from_millis(50);
+
+/// Published every time a token is hydrated for the first time, successfully or not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HydrationProgress {
+    /// The anonymized token that was hydrated
+    pub token: String,
+    pub succeeded: bool,
+    pub feature_count: usize,
+    /// How many of the currently registered tokens have been hydrated so far
+    pub hydrated: usize,
+    pub total: usize,
+}
+
+/// A token is hydrated once we've received features for it, or upstream confirmed our cached ones are current.
+/// Failed attempts also set `last_check`, but leave a non-zero failure count behind.
+fn is_hydrated(refresh: &TokenRefresh) -> bool {
+    refresh.last_refreshed.is_some() || (refresh.last_check.is_some() && refresh.failure_count == 0)
+}
+
+fn hydration_progress_channel() -> broadcast::Sender<HydrationProgress> {
+    let (tx, _rx) = broadcast::channel::<HydrationProgress>(128);
+    tx
+}
+
 #[derive(Clone)]
 pub struct FeatureRefresher {
     pub unleash_client: Arc<UnleashClient>,
@@ -52,6 +261,41 @@ pub struct FeatureRefresher {
     pub client_meta_information: ClientMetaInformation,
     pub delta: bool,
     pub delta_diff: bool,
+    pub delta_diff_output: Option<PathBuf>,
+    pub normalize_feature_names: bool,
+    pub token_scope_metrics: bool,
+    pub token_simplification_log_level: TokenSimplificationLogLevel,
+    pub environment_mismatch: EnvironmentMismatchBehavior,
+    pub hydration_progress: broadcast::Sender<HydrationProgress>,
+    pub scheduler_tick: Option<Duration>,
+    pub skip_client_registration: bool,
+    pub token_priorities: Vec<TokenPriority>,
+    /// Tokens `simplify` keeps even when another registered token subsumes them
+    pub pinned_tokens: Vec<String>,
+    /// Features with more strategies are left out of the engines frontend evaluations run against
+    pub max_strategies_per_feature: Option<usize>,
+    /// Features marked as stale are left out of the engines frontend evaluations run against
+    pub hide_stale_features: bool,
+    /// Rejects feature updates with toggles that fail to compile, instead of defaulting those toggles to off
+    pub fail_on_compile_warnings: bool,
+    /// Environments that need to be hydrated before Edge reports itself as ready
+    pub required_environments: Vec<String>,
+    /// Percentage a token's feature count can drop by between two refreshes before it's reported
+    pub feature_count_drop_threshold: Option<u8>,
+    pub stale_threshold: Option<chrono::Duration>,
+    /// How long upstream has to keep denying a token before it's forgotten and its environment's caches are evicted
+    pub token_eviction_grace: Option<chrono::Duration>,
+    pub reject_revision_regressions: bool,
+    /// Keep the cached features when upstream responds without any for a token that had features before
+    pub ignore_empty_upstream: bool,
+    /// Highest revision id seen from upstream per cache key, used to spot upstream going back in time
+    pub last_revision_ids: Arc<DashMap<String, usize>>,
+    /// Hash of the last features applied per token, ignoring metadata, used to skip rebuilding unchanged engines
+    pub last_applied_hashes: Arc<DashMap<String, String>>,
+    /// Recent delta events per cache key, so SDKs can ask for only what changed since a revision they have
+    pub delta_history: Arc<DashMap<String, DeltaHistory>>,
+    /// Wakes the background refresh task after a new token is hydrated, so it can reschedule its sleep
+    pub refresh_wakeup: Arc<Notify>,
 }
 
 impl Default for FeatureRefresher {
@@ -68,6 +312,29 @@ impl Default for FeatureRefresher {
             client_meta_information: Default::default(),
             delta: false,
             delta_diff: false,
+            delta_diff_output: None,
+            normalize_feature_names: false,
+            token_scope_metrics: false,
+            token_simplification_log_level: TokenSimplificationLogLevel::Info,
+            environment_mismatch: EnvironmentMismatchBehavior::Reject,
+            hydration_progress: hydration_progress_channel(),
+            scheduler_tick: None,
+            skip_client_registration: false,
+            token_priorities: vec![],
+            pinned_tokens: vec![],
+            max_strategies_per_feature: None,
+            hide_stale_features: false,
+            fail_on_compile_warnings: false,
+            required_environments: vec![],
+            feature_count_drop_threshold: None,
+            stale_threshold: None,
+            token_eviction_grace: None,
+            reject_revision_regressions: false,
+            ignore_empty_upstream: false,
+            last_revision_ids: Arc::new(DashMap::default()),
+            last_applied_hashes: Arc::new(DashMap::default()),
+            delta_history: Arc::new(DashMap::default()),
+            refresh_wakeup: Arc::new(Notify::new()),
         }
     }
 }
@@ -94,6 +361,97 @@ fn client_application_from_token_and_name(
     }
 }
 
+fn normalize_feature(feature: ClientFeature) -> ClientFeature {
+    ClientFeature {
+        name: normalize_feature_name(&feature.name),
+        dependencies: feature.dependencies.map(|dependencies| {
+            dependencies
+                .into_iter()
+                .map(|mut dependency| {
+                    dependency.feature = normalize_feature_name(&dependency.feature);
+                    dependency
+                })
+                .collect()
+        }),
+        ..feature
+    }
+}
+
+/// Normalizes feature names and the names of their dependencies, so the engine can still resolve dependencies.
+/// If two features normalize to the same name, only the first one is kept.
+pub(crate) fn normalize_client_features(features: ClientFeatures) -> ClientFeatures {
+    let mut seen = HashSet::new();
+    let normalized = features
+        .features
+        .into_iter()
+        .map(normalize_feature)
+        .filter(|feature| {
+            let first = seen.insert(feature.name.clone());
+            if !first {
+                warn!(
+                    "Multiple features normalize to {}, ignoring all but the first",
+                    feature.name
+                );
+            }
+            first
+        })
+        .collect();
+    ClientFeatures {
+        features: normalized,
+        ..features
+    }
+}
+
+/// Compiles `features` into an engine. Toggles that fail to compile are defaulted to off, unless Edge runs with
+/// `--fail-on-compile-warnings`, which rejects the features instead
+pub(crate) fn compile_engine(
+    environment: &str,
+    features: ClientFeatures,
+    fail_on_compile_warnings: bool,
+) -> EdgeResult<EngineState> {
+    let mut engine = EngineState::default();
+    if let Some(warnings) = engine.take_state(features) {
+        if fail_on_compile_warnings {
+            return Err(EdgeError::ClientHydrationFailed(format!(
+                "Failed to compile features for {environment:?}: {warnings:?}"
+            )));
+        }
+        warn!("The following toggle failed to compile and will be defaulted to off: {warnings:?}");
+    }
+    Ok(engine)
+}
+
+pub(crate) fn normalize_client_features_delta(delta: ClientFeaturesDelta) -> ClientFeaturesDelta {
+    let events = delta
+        .events
+        .into_iter()
+        .map(|event| match event {
+            DeltaEvent::FeatureUpdated { event_id, feature } => DeltaEvent::FeatureUpdated {
+                event_id,
+                feature: normalize_feature(feature),
+            },
+            DeltaEvent::FeatureRemoved {
+                event_id,
+                feature_name,
+            } => DeltaEvent::FeatureRemoved {
+                event_id,
+                feature_name: normalize_feature_name(&feature_name),
+            },
+            DeltaEvent::Hydration {
+                event_id,
+                features,
+                segments,
+            } => DeltaEvent::Hydration {
+                event_id,
+                features: features.into_iter().map(normalize_feature).collect(),
+                segments,
+            },
+            other => other,
+        })
+        .collect();
+    ClientFeaturesDelta { events }
+}
+
 #[derive(Eq, PartialEq)]
 pub enum FeatureRefresherMode {
     Dynamic,
@@ -107,6 +465,24 @@ pub struct FeatureRefreshConfig {
     client_meta_information: ClientMetaInformation,
     delta: bool,
     delta_diff: bool,
+    delta_diff_output: Option<PathBuf>,
+    normalize_feature_names: bool,
+    token_scope_metrics: bool,
+    token_simplification_log_level: TokenSimplificationLogLevel,
+    environment_mismatch: EnvironmentMismatchBehavior,
+    scheduler_tick: Option<Duration>,
+    skip_client_registration: bool,
+    token_priorities: Vec<TokenPriority>,
+    pinned_tokens: Vec<String>,
+    max_strategies_per_feature: Option<usize>,
+    hide_stale_features: bool,
+    fail_on_compile_warnings: bool,
+    required_environments: Vec<String>,
+    feature_count_drop_threshold: Option<u8>,
+    stale_threshold: Option<chrono::Duration>,
+    token_eviction_grace: Option<chrono::Duration>,
+    reject_revision_regressions: bool,
+    ignore_empty_upstream: bool,
 }
 
 impl FeatureRefreshConfig {
@@ -122,11 +498,214 @@ impl FeatureRefreshConfig {
             mode,
             client_meta_information,
             delta,
-            delta_diff
+            delta_diff,
+            delta_diff_output: None,
+            normalize_feature_names: false,
+            token_scope_metrics: false,
+            token_simplification_log_level: TokenSimplificationLogLevel::Info,
+            environment_mismatch: EnvironmentMismatchBehavior::Reject,
+            scheduler_tick: None,
+            skip_client_registration: false,
+            token_priorities: vec![],
+            pinned_tokens: vec![],
+            max_strategies_per_feature: None,
+            hide_stale_features: false,
+            fail_on_compile_warnings: false,
+            required_environments: vec![],
+            feature_count_drop_threshold: None,
+            stale_threshold: None,
+            token_eviction_grace: None,
+            reject_revision_regressions: false,
+            ignore_empty_upstream: false,
+        }
+    }
+
+    pub fn with_delta_diff_output(self, delta_diff_output: Option<PathBuf>) -> Self {
+        Self {
+            delta_diff_output,
+            ..self
+        }
+    }
+
+    pub fn with_normalized_feature_names(self, normalize_feature_names: bool) -> Self {
+        Self {
+            normalize_feature_names,
+            ..self
+        }
+    }
+
+    pub fn with_token_scope_metrics(self, token_scope_metrics: bool) -> Self {
+        Self {
+            token_scope_metrics,
+            ..self
+        }
+    }
+
+    pub fn with_token_simplification_log_level(
+        self,
+        token_simplification_log_level: TokenSimplificationLogLevel,
+    ) -> Self {
+        Self {
+            token_simplification_log_level,
+            ..self
+        }
+    }
+
+    pub fn with_environment_mismatch(
+        self,
+        environment_mismatch: EnvironmentMismatchBehavior,
+    ) -> Self {
+        Self {
+            environment_mismatch,
+            ..self
+        }
+    }
+
+    pub fn with_scheduler_tick(self, scheduler_tick: Option<Duration>) -> Self {
+        Self {
+            scheduler_tick,
+            ..self
+        }
+    }
+
+    pub fn with_skip_client_registration(self, skip_client_registration: bool) -> Self {
+        Self {
+            skip_client_registration,
+            ..self
+        }
+    }
+
+    pub fn with_token_priorities(self, token_priorities: Vec<TokenPriority>) -> Self {
+        Self {
+            token_priorities,
+            ..self
+        }
+    }
+
+    pub fn with_pinned_tokens(self, pinned_tokens: Vec<String>) -> Self {
+        Self {
+            pinned_tokens,
+            ..self
+        }
+    }
+
+    pub fn with_max_strategies_per_feature(
+        self,
+        max_strategies_per_feature: Option<usize>,
+    ) -> Self {
+        Self {
+            max_strategies_per_feature,
+            ..self
+        }
+    }
+
+    pub fn with_hide_stale_features(self, hide_stale_features: bool) -> Self {
+        Self {
+            hide_stale_features,
+            ..self
+        }
+    }
+
+    pub fn with_fail_on_compile_warnings(self, fail_on_compile_warnings: bool) -> Self {
+        Self {
+            fail_on_compile_warnings,
+            ..self
+        }
+    }
+
+    pub fn with_required_environments(self, required_environments: Vec<String>) -> Self {
+        Self {
+            required_environments,
+            ..self
+        }
+    }
+
+    pub fn with_feature_count_drop_threshold(
+        self,
+        feature_count_drop_threshold: Option<u8>,
+    ) -> Self {
+        Self {
+            feature_count_drop_threshold,
+            ..self
+        }
+    }
+
+    pub fn with_stale_threshold(self, stale_threshold: Option<chrono::Duration>) -> Self {
+        Self {
+            stale_threshold,
+            ..self
+        }
+    }
+
+    pub fn with_token_eviction_grace(self, token_eviction_grace: Option<chrono::Duration>) -> Self {
+        Self {
+            token_eviction_grace,
+            ..self
+        }
+    }
+
+    pub fn with_reject_revision_regressions(self, reject_revision_regressions: bool) -> Self {
+        Self {
+            reject_revision_regressions,
+            ..self
+        }
+    }
+
+    pub fn with_ignore_empty_upstream(self, ignore_empty_upstream: bool) -> Self {
+        Self {
+            ignore_empty_upstream,
+            ..self
         }
     }
 }
 
+/// One line of `--delta-diff-output`, describing where the delta payload disagreed with the full payload
+#[derive(Debug, Serialize)]
+struct DeltaDiffRecord {
+    timestamp: DateTime<Utc>,
+    environment: String,
+    client_revision_id: Option<usize>,
+    delta_revision_id: Option<i32>,
+    differing_features: Vec<String>,
+}
+
+/// Names of features that are missing from one of the payloads or that differ between them, sorted by name.
+/// Features are compared by their JSON, since [`ClientFeature`]'s equality only compares names.
+fn differing_feature_names(full: &[ClientFeature], delta: &[ClientFeature]) -> Vec<String> {
+    let as_json = |features: &[ClientFeature]| -> HashMap<String, serde_json::Value> {
+        features
+            .iter()
+            .map(|feature| {
+                (
+                    feature.name.clone(),
+                    serde_json::to_value(feature).unwrap_or_default(),
+                )
+            })
+            .collect()
+    };
+    let full = as_json(full);
+    let delta = as_json(delta);
+    full.keys()
+        .chain(delta.keys())
+        .filter(|name| full.get(*name) != delta.get(*name))
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+async fn append_delta_diff(output: &Path, record: &DeltaDiffRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output)
+        .await?;
+    file.write_all(&line).await?;
+    file.flush().await
+}
+
 impl FeatureRefresher {
     pub fn new(
         unleash_client: Arc<UnleashClient>,
@@ -147,6 +726,29 @@ impl FeatureRefresher {
             client_meta_information: config.client_meta_information,
             delta: config.delta,
             delta_diff: config.delta_diff,
+            delta_diff_output: config.delta_diff_output,
+            normalize_feature_names: config.normalize_feature_names,
+            token_scope_metrics: config.token_scope_metrics,
+            token_simplification_log_level: config.token_simplification_log_level,
+            environment_mismatch: config.environment_mismatch,
+            hydration_progress: hydration_progress_channel(),
+            scheduler_tick: config.scheduler_tick,
+            skip_client_registration: config.skip_client_registration,
+            token_priorities: config.token_priorities,
+            pinned_tokens: config.pinned_tokens,
+            max_strategies_per_feature: config.max_strategies_per_feature,
+            hide_stale_features: config.hide_stale_features,
+            fail_on_compile_warnings: config.fail_on_compile_warnings,
+            required_environments: config.required_environments,
+            feature_count_drop_threshold: config.feature_count_drop_threshold,
+            stale_threshold: config.stale_threshold,
+            token_eviction_grace: config.token_eviction_grace,
+            reject_revision_regressions: config.reject_revision_regressions,
+            ignore_empty_upstream: config.ignore_empty_upstream,
+            last_revision_ids: Arc::new(DashMap::default()),
+            last_applied_hashes: Arc::new(DashMap::default()),
+            delta_history: Arc::new(DashMap::default()),
+            refresh_wakeup: Arc::new(Notify::new()),
         }
     }
 
@@ -157,8 +759,46 @@ impl FeatureRefresher {
         }
     }
 
+    /// How long ago upstream last confirmed the features for the token's environment, if that's longer
+    /// than `--stale-threshold-seconds`. Upstream answering 304 counts as a confirmation, but a check that failed
+    /// doesn't, so failing tokens only count from when they last received features
+    pub fn staleness(&self, token: &EdgeToken) -> Option<chrono::Duration> {
+        let threshold = self.stale_threshold?;
+        let last_confirmed = self
+            .tokens_to_refresh
+            .iter()
+            .filter(|refresh| refresh.value().token.environment == token.environment)
+            .filter_map(|refresh| {
+                let refresh = refresh.value();
+                if refresh.failure_count == 0 {
+                    refresh.last_check.or(refresh.last_refreshed)
+                } else {
+                    refresh.last_refreshed
+                }
+            })
+            .max()?;
+        let age = Utc::now() - last_confirmed;
+        (age > threshold).then_some(age)
+    }
+
+    /// The `--required-environments` no token has fetched features for yet
+    pub fn unhydrated_required_environments(&self) -> Vec<String> {
+        self.required_environments
+            .iter()
+            .filter(|environment| {
+                !self.tokens_to_refresh.iter().any(|refresh| {
+                    refresh.last_refreshed.is_some()
+                        && refresh.token.environment.as_ref() == Some(*environment)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Refreshes that are due, highest priority first
     pub(crate) fn get_tokens_due_for_refresh(&self) -> Vec<TokenRefresh> {
-        self.tokens_to_refresh
+        let mut due: Vec<TokenRefresh> = self
+            .tokens_to_refresh
             .iter()
             .map(|e| e.value().clone())
             .filter(|token| {
@@ -167,7 +807,44 @@ impl FeatureRefresher {
                     .map(|refresh| Utc::now() > refresh)
                     .unwrap_or(true)
             })
-            .collect()
+            .collect();
+        due.sort_by_key(|refresh| std::cmp::Reverse(refresh.priority));
+        due
+    }
+
+    /// The priority of the first `--token-priority` matching `token`, or 0 if none match
+    fn priority_of(&self, token: &EdgeToken) -> i32 {
+        self.token_priorities
+            .iter()
+            .find(|priority| priority.matches(token))
+            .map_or(0, |priority| priority.priority)
+    }
+
+    /// The longest the background task sleeps between checks. Unless configured, this is the refresh interval,
+    /// capped at [`DEFAULT_SCHEDULER_TICK`].
+    fn max_scheduler_tick(&self) -> Duration {
+        self.scheduler_tick
+            .unwrap_or_else(|| {
+                self.refresh_interval
+                    .to_std()
+                    .unwrap_or(DEFAULT_SCHEDULER_TICK)
+                    .min(DEFAULT_SCHEDULER_TICK)
+            })
+            .max(MIN_SCHEDULER_TICK)
+    }
+
+    /// How long to sleep until the earliest registered token is due for refresh. Tokens that are already due
+    /// were attempted on the last pass without being rescheduled (e.g. upstream was unreachable), so they're
+    /// retried on the regular tick rather than immediately.
+    pub(crate) fn time_until_next_refresh(&self) -> Duration {
+        let now = Utc::now();
+        let max_tick = self.max_scheduler_tick();
+        self.tokens_to_refresh
+            .iter()
+            .filter_map(|e| e.next_refresh.and_then(|next| (next - now).to_std().ok()))
+            .min()
+            .unwrap_or(max_tick)
+            .clamp(MIN_SCHEDULER_TICK, max_tick)
     }
 
     pub(crate) fn get_tokens_never_refreshed(&self) -> Vec<TokenRefresh> {
@@ -178,6 +855,84 @@ impl FeatureRefresher {
             .collect()
     }
 
+    pub fn subscribe_to_hydration_progress(&self) -> broadcast::Receiver<HydrationProgress> {
+        self.hydration_progress.subscribe()
+    }
+
+    /// Logs a line for every token hydration until the refresher is dropped. Subscribe before starting the
+    /// refresh task to see the startup tokens being hydrated.
+    pub async fn log_hydration_progress(mut progress: broadcast::Receiver<HydrationProgress>) {
+        loop {
+            match progress.recv().await {
+                Ok(p) if p.succeeded => info!(
+                    "Hydrated {}/{} tokens. {} had {} features",
+                    p.hydrated, p.total, p.token, p.feature_count
+                ),
+                Ok(p) => warn!(
+                    "Failed to hydrate {}. Hydrated {}/{} tokens",
+                    p.token, p.hydrated, p.total
+                ),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Skipped {skipped} hydration progress events")
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn publish_hydration_progress(&self, token: &EdgeToken) {
+        // The token might have been removed during hydration, e.g. if upstream told us it was forbidden
+        let refresh = self.tokens_to_refresh.get(&token.token).map(|r| r.clone());
+        let succeeded = refresh.as_ref().is_some_and(is_hydrated);
+        let _ = self.hydration_progress.send(HydrationProgress {
+            token: anonymize_token(token).token,
+            succeeded,
+            feature_count: refresh.and_then(|r| r.last_feature_count).unwrap_or(0),
+            hydrated: self.hydrated_token_count(),
+            total: self.tokens_to_refresh.len(),
+        });
+    }
+
+    fn hydrated_token_count(&self) -> usize {
+        self.tokens_to_refresh
+            .iter()
+            .filter(|t| is_hydrated(t.value()))
+            .count()
+    }
+
+    /// Summarizes the registered tokens into the token scope gauges, without exposing the tokens themselves
+    pub fn update_token_scope_metrics(&self) {
+        let mut per_environment: HashMap<String, i64> = HashMap::new();
+        let mut projects = HashSet::new();
+        let mut wildcard = 0;
+        let mut scoped = 0;
+        for refresh in self.tokens_to_refresh.iter() {
+            let token = &refresh.token;
+            *per_environment
+                .entry(token.environment.clone().unwrap_or_default())
+                .or_default() += 1;
+            if token.projects.iter().any(|p| p == "*") {
+                wildcard += 1;
+            } else {
+                scoped += 1;
+                projects.extend(token.projects.iter().cloned());
+            }
+        }
+        REGISTERED_TOKENS_PER_ENVIRONMENT.reset();
+        for (environment, count) in per_environment {
+            REGISTERED_TOKENS_PER_ENVIRONMENT
+                .with_label_values(&[environment.as_str()])
+                .set(count);
+        }
+        REGISTERED_TOKENS_BY_SCOPE
+            .with_label_values(&["wildcard"])
+            .set(wildcard);
+        REGISTERED_TOKENS_BY_SCOPE
+            .with_label_values(&["scoped"])
+            .set(scoped);
+        REGISTERED_TOKEN_PROJECTS.set(projects.len() as i64);
+    }
+
     pub(crate) fn token_is_subsumed(&self, token: &EdgeToken) -> bool {
         self.tokens_to_refresh
             .iter()
@@ -198,6 +953,7 @@ impl FeatureRefresher {
     pub(crate) async fn register_and_hydrate_token(&self, token: &EdgeToken) {
         self.register_token_for_refresh(token.clone(), None).await;
         self.hydrate_new_tokens().await;
+        self.refresh_wakeup.notify_one();
     }
 
     pub(crate) async fn create_client_token_for_fe_token(
@@ -230,6 +986,9 @@ impl FeatureRefresher {
             _ => {
                 if self.strict {
                     debug!("Strict behavior: Token is not subsumed by any registered tokens. Returning error");
+                    STRICT_MODE_REJECTIONS
+                        .with_label_values(&[token.environment.as_deref().unwrap_or("unknown")])
+                        .inc();
                     Err(EdgeError::InvalidTokenWithStrictBehavior)
                 } else {
                     debug!(
@@ -252,37 +1011,104 @@ impl FeatureRefresher {
         token: &EdgeToken,
         filters: &FeatureFilterSet,
     ) -> Option<ClientFeatures> {
-        self.features_cache
-            .get(&cache_key(token))
-            .map(|client_features| filter_client_features(&client_features, filters))
+        self.features_cache.get_filtered(&cache_key(token), filters)
+    }
+
+    /// Serves `features` to `token` as if upstream had returned them, for running from a saved snapshot
+    pub async fn seed_features(
+        &self,
+        token: EdgeToken,
+        features: ClientFeatures,
+    ) -> EdgeResult<()> {
+        self.register_token_for_refresh(token.clone(), None).await;
+        self.handle_client_features_updated(&token, features, None)
+            .await
     }
 
     ///
-    /// Registers a token for refresh, the token will be discarded if it can be subsumed by another previously registered token
+    /// Registers a token for refresh, the token will be discarded if it can be subsumed by another previously registered token,
+    /// unless it's one of the pinned tokens
     pub async fn register_token_for_refresh(&self, token: EdgeToken, etag: Option<EntityTag>) {
         if !self.tokens_to_refresh.contains_key(&token.token) {
-            self.unleash_client
-                .register_as_client(
-                    token.token.clone(),
-                    client_application_from_token_and_name(
-                        token.clone(),
-                        self.refresh_interval.num_seconds(),
-                        self.client_meta_information.clone(),
-                    ),
-                )
-                .await
-                .unwrap_or_default();
+            if !self.skip_client_registration {
+                self.unleash_client
+                    .register_as_client(
+                        token.token.clone(),
+                        client_application_from_token_and_name(
+                            token.clone(),
+                            self.refresh_interval.num_seconds(),
+                            self.client_meta_information.clone(),
+                        ),
+                    )
+                    .await
+                    .unwrap_or_default();
+            }
             let mut registered_tokens: Vec<TokenRefresh> =
                 self.tokens_to_refresh.iter().map(|t| t.clone()).collect();
-            registered_tokens.push(TokenRefresh::new(token.clone(), etag));
+            registered_tokens.push(TokenRefresh {
+                priority: self.priority_of(&token),
+                pinned: self.pinned_tokens.contains(&token.token),
+                ..TokenRefresh::new(token.clone(), etag)
+            });
             let minimum = simplify(&registered_tokens);
+            self.report_simplification(&registered_tokens, &minimum);
             let mut keys = HashSet::new();
             for refreshes in minimum {
                 keys.insert(refreshes.token.token.clone());
                 self.tokens_to_refresh
                     .insert(refreshes.token.token.clone(), refreshes.clone());
             }
-            self.tokens_to_refresh.retain(|key, _| keys.contains(key));
+            self.tokens_to_refresh.retain(|key, refresh| {
+                let keep = keys.contains(key);
+                if !keep {
+                    if let Some(mut history) =
+                        self.delta_history.get_mut(&cache_key(&refresh.token))
+                    {
+                        history.forget_token(key);
+                    }
+                }
+                keep
+            });
+            if self.token_scope_metrics {
+                self.update_token_scope_metrics();
+            }
+        }
+    }
+
+    /// Logs, and counts when token scope metrics are enabled, every token `simplify` dropped because a surviving
+    /// token subsumes it
+    fn report_simplification(&self, registered: &[TokenRefresh], minimum: &[TokenRefresh]) {
+        let survivors: HashSet<&str> = minimum.iter().map(|r| r.token.token.as_str()).collect();
+        for removed in registered
+            .iter()
+            .filter(|r| !survivors.contains(r.token.token.as_str()))
+        {
+            let Some(survivor) = minimum.iter().find(|r| r.token.subsumes(&removed.token)) else {
+                continue;
+            };
+            let removed_token = anonymize_token(&removed.token).token;
+            let surviving_token = anonymize_token(&survivor.token).token;
+            match self.token_simplification_log_level {
+                TokenSimplificationLogLevel::Off => {}
+                TokenSimplificationLogLevel::Debug => debug!(
+                    "Token {removed_token} is subsumed by {surviving_token} and will no longer be refreshed on its own"
+                ),
+                TokenSimplificationLogLevel::Info => info!(
+                    "Token {removed_token} is subsumed by {surviving_token} and will no longer be refreshed on its own"
+                ),
+                TokenSimplificationLogLevel::Warn => warn!(
+                    "Token {removed_token} is subsumed by {surviving_token} and will no longer be refreshed on its own"
+                ),
+            }
+            if self.token_scope_metrics {
+                TOKENS_SUBSUMED
+                    .with_label_values(&[
+                        removed.token.environment.as_deref().unwrap_or_default(),
+                        &removed.token.projects.join(","),
+                        &survivor.token.projects.join(","),
+                    ])
+                    .inc();
+            }
         }
     }
 
@@ -309,7 +1135,7 @@ impl FeatureRefresher {
                 )?
                 .header(
                     UNLEASH_CLIENT_SPEC_HEADER,
-                    unleash_yggdrasil::SUPPORTED_SPEC_VERSION,
+                    &client_meta_information.client_spec_version,
                 )?;
 
             for (key, value) in custom_headers.clone() {
@@ -346,7 +1172,11 @@ impl FeatureRefresher {
                                     );
 
                                     match serde_json::from_str(&event.data) {
-                                        Ok(features) => { refresher.handle_client_features_updated(&token, features, None).await; }
+                                        Ok(features) => {
+                                            if let Err(e) = refresher.handle_client_features_updated(&token, features, None).await {
+                                                warn!("Rejected features update: {e}");
+                                            }
+                                        }
                                         Err(e) => { warn!("Could not parse features response to internal representation: {e:?}");
                                         }
                                     }
@@ -360,7 +1190,11 @@ impl FeatureRefresher {
                                     );
 
                                     match serde_json::from_str(&event.data) {
-                                        Ok(features) => { refresher.handle_client_features_updated(&token, features, None).await; }
+                                        Ok(features) => {
+                                            if let Err(e) = refresher.handle_client_features_updated(&token, features, None).await {
+                                                warn!("Rejected features update: {e}");
+                                            }
+                                        }
                                         Err(e) => { warn!("Could not parse features response to internal representation: {e:?}");
                                         }
                                     }
@@ -408,19 +1242,43 @@ impl FeatureRefresher {
             .await;
 
         let key = cache_key(&refresh.token);
-        if let Some(client_features) = self.features_cache.get(&key).as_ref() {
+        // A copy, since holding the cache's read guard across the awaits below blocks anyone writing to the cache
+        let cached_features = self.features_cache.get(&key).map(|f| f.value().clone());
+        if let Some(client_features) = cached_features.as_ref() {
             if let Ok(ClientFeaturesDeltaResponse::Updated(delta_features, _etag)) = delta_result {
                 let c_features = &client_features.features;
-                let d_features = delta_features
+                let hydration = delta_features
                     .events
                     .iter()
                     .find_map(|event| {
-                        if let DeltaEvent::Hydration { features, .. } = event {
-                            Some(features)
+                        if let DeltaEvent::Hydration { event_id, features, .. } = event {
+                            Some((*event_id, features))
                         } else {
                             None
                         }
                     });
+                let d_features = hydration.map(|(_, features)| features);
+
+                if let Some(output) = &self.delta_diff_output {
+                    let differing_features =
+                        differing_feature_names(c_features, d_features.map_or(&[], Vec::as_slice));
+                    if !differing_features.is_empty() {
+                        let record = DeltaDiffRecord {
+                            timestamp: Utc::now(),
+                            environment: key.clone(),
+                            client_revision_id: client_features
+                                .meta
+                                .as_ref()
+                                .and_then(|meta| meta.revision_id),
+                            delta_revision_id: hydration.map(|(event_id, _)| event_id),
+                            differing_features,
+                        };
+                        if let Err(e) = append_delta_diff(output, &record).await {
+                            warn!("Could not write delta diff to {}: {e:?}", output.display());
+                        }
+                    }
+                    return;
+                }
 
                 let delta_json = serde_json::to_value(d_features).unwrap();
                 let client_json = serde_json::to_value(c_features).unwrap();
@@ -449,10 +1307,10 @@ impl FeatureRefresher {
         } else {
             loop {
                 tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                        self.refresh_features().await;
-                    }
+                    _ = tokio::time::sleep(self.time_until_next_refresh()) => {}
+                    _ = self.refresh_wakeup.notified() => {}
                 }
+                self.refresh_features().await;
             }
         }
     }
@@ -460,22 +1318,29 @@ impl FeatureRefresher {
     pub async fn hydrate_new_tokens(&self) {
         let hydrations = self.get_tokens_never_refreshed();
         for hydration in hydrations {
-            if self.delta {
-                self.refresh_single_delta(hydration).await;
-            } else {
-                self.refresh_single(hydration).await;
-            }
+            self.refresh_and_report(hydration).await;
         }
     }
     pub async fn refresh_features(&self) {
         let refreshes = self.get_tokens_due_for_refresh();
         for refresh in refreshes {
-            if self.delta {
-                self.refresh_single_delta(refresh).await;
-            } else {
-                self.refresh_single(refresh).await;
-            }
+            self.refresh_and_report(refresh).await;
+        }
+        if self.token_scope_metrics {
+            self.update_token_scope_metrics();
+        }
+    }
 
+    pub(crate) async fn refresh_and_report(&self, refresh: TokenRefresh) {
+        let is_hydration = !is_hydrated(&refresh);
+        let token = refresh.token.clone();
+        if self.delta {
+            self.refresh_single_delta(refresh).await;
+        } else {
+            self.refresh_single(refresh).await;
+        }
+        if is_hydration {
+            self.publish_hydration_progress(&token);
         }
     }
 
@@ -484,55 +1349,176 @@ impl FeatureRefresher {
         refresh_token: &EdgeToken,
         features: ClientFeatures,
         etag: Option<EntityTag>,
-    ) {
+    ) -> EdgeResult<()> {
         debug!("Got updated client features. Updating features with {etag:?}");
+        self.check_upstream_environment(refresh_token, &features)?;
+        let mut features = if self.normalize_feature_names {
+            normalize_client_features(features)
+        } else {
+            features
+        };
         let key = cache_key(refresh_token);
+        // Tokens without an environment are cached under their secret, so metrics and logs use the environment
+        let environment = refresh_token.environment.as_deref().unwrap_or_default();
+        if self.ignore_empty_upstream
+            && features.features.is_empty()
+            && self.has_cached_features_for(&key, refresh_token)
+        {
+            warn!(
+                "Upstream returned no features for {environment}, keeping the previously cached features"
+            );
+            EMPTY_UPSTREAM_RESPONSES_IGNORED
+                .with_label_values(&[environment])
+                .inc();
+            self.update_last_check(refresh_token);
+            return Ok(());
+        }
+        self.check_revision(&key, environment, &features)?;
+        if self.fail_on_compile_warnings {
+            // Checked up front, so rejected features never reach the caches
+            compile_engine(environment, features.clone(), true)?;
+        }
         self.update_last_refresh(refresh_token, etag, features.features.len());
+        let started = Instant::now();
+        let meta = features.meta.take();
+        let content_hash = features.xx3_hash().ok();
+        features.meta = meta;
+        let unchanged = content_hash.is_some()
+            && self.engine_cache.contains_key(&key)
+            && self
+                .last_applied_hashes
+                .get(&refresh_token.token)
+                .is_some_and(|last| Some(last.value()) == content_hash.as_ref());
+        if let Some(content_hash) = content_hash {
+            self.last_applied_hashes
+                .insert(refresh_token.token.clone(), content_hash);
+        }
         self.features_cache
             .modify(key.clone(), refresh_token, features.clone());
-        self.engine_cache
-                        .entry(key.clone())
-                        .and_modify(|engine| {
-                            if let Some(f) = self.features_cache.get(&key) {
-                                let mut new_state = EngineState::default();
-                                let warnings = new_state.take_state(f.clone());
-                                if let Some(warnings) = warnings {
-                                    warn!("The following toggle failed to compile and will be defaulted to off: {warnings:?}");
-                                };
-                                *engine = new_state;
-
-                            }
-                        })
-                        .or_insert_with(|| {
-                            let mut new_state = EngineState::default();
+        if unchanged {
+            debug!("Features for {environment} are unchanged, keeping the current engine");
+            ENGINE_REBUILDS_SKIPPED
+                .with_label_values(&[environment])
+                .inc();
+            observe_cache_update_duration(environment, started);
+            return Ok(());
+        }
+        if let Some(features) = self.features_cache.get(&key).map(|f| f.clone()) {
+            let engine = compile_engine(
+                environment,
+                frontend_features(
+                    features,
+                    self.max_strategies_per_feature,
+                    self.hide_stale_features,
+                ),
+                false,
+            )?;
+            self.engine_cache.insert(key.clone(), engine);
+        }
+        observe_cache_update_duration(environment, started);
+        Ok(())
+    }
 
-                            let warnings = new_state.take_state(features);
-                            if let Some(warnings) = warnings {
-                                warn!("The following toggle failed to compile and will be defaulted to off: {warnings:?}");
-                            };
-                            new_state
-                        });
+    /// Whether the features cached for `key` include any `token` has access to
+    fn has_cached_features_for(&self, key: &str, token: &EdgeToken) -> bool {
+        let filter = project_filter(token);
+        self.features_cache
+            .get(key)
+            .is_some_and(|cached| cached.features.iter().any(&filter))
     }
-    pub async fn refresh_single(&self, refresh: TokenRefresh) {
-        let features_result = self
-            .unleash_client
-            .get_client_features(ClientFeaturesRequest {
-                api_key: refresh.token.token.clone(),
-                etag: refresh.etag.clone(),
-            })
-            .await;
 
-        match features_result {
-            Ok(feature_response) => match feature_response {
+    /// Counts upstream serving an older revision than we've already seen (e.g. an inconsistent upstream cache or a
+    /// failover), and rejects the update when configured to, so older state doesn't silently replace newer
+    fn check_revision(
+        &self,
+        key: &str,
+        environment: &str,
+        features: &ClientFeatures,
+    ) -> EdgeResult<()> {
+        let Some(incoming) = features.meta.as_ref().and_then(|meta| meta.revision_id) else {
+            return Ok(());
+        };
+        let mut last_seen = self
+            .last_revision_ids
+            .entry(key.to_string())
+            .or_insert(incoming);
+        if incoming < *last_seen {
+            REVISION_REGRESSIONS.with_label_values(&[environment]).inc();
+            if self.reject_revision_regressions {
+                return Err(EdgeError::UpstreamRevisionRegression(*last_seen, incoming));
+            }
+            warn!(
+                "Upstream returned revision {incoming} for {environment}, older than the already seen revision {}. Applying it anyway",
+                *last_seen
+            );
+        }
+        *last_seen = incoming;
+        Ok(())
+    }
+
+    /// Guards against a misrouted upstream handing us features for another environment, which we'd
+    /// otherwise cache under this token's environment
+    fn check_upstream_environment(
+        &self,
+        refresh_token: &EdgeToken,
+        features: &ClientFeatures,
+    ) -> EdgeResult<()> {
+        let upstream_environment = features.query.as_ref().and_then(|q| q.environment.clone());
+        match (&refresh_token.environment, upstream_environment) {
+            (Some(expected), Some(actual)) if expected != "*" && expected != &actual => {
+                UPSTREAM_ENVIRONMENT_MISMATCHES
+                    .with_label_values(&[expected.as_str(), actual.as_str()])
+                    .inc();
+                match self.environment_mismatch {
+                    EnvironmentMismatchBehavior::Reject => Err(
+                        EdgeError::UpstreamEnvironmentMismatch(expected.clone(), actual),
+                    ),
+                    EnvironmentMismatchBehavior::Accept => {
+                        warn!("Upstream returned features for {actual} when refreshing a token for {expected}. Accepting them anyway");
+                        Ok(())
+                    }
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn refresh_single(&self, refresh: TokenRefresh) {
+        let started = Instant::now();
+        let features_result = self
+            .unleash_client
+            .get_client_features(ClientFeaturesRequest {
+                api_key: refresh.token.token.clone(),
+                etag: refresh.etag.clone(),
+            })
+            .await;
+        if upstream_answered(&features_result) {
+            UPSTREAM_FEATURE_FETCH_LATENCY.record(
+                refresh.token.environment.as_deref().unwrap_or_default(),
+                started.elapsed(),
+            );
+        }
+
+        match features_result {
+            Ok(feature_response) => match feature_response {
                 ClientFeaturesResponse::NoUpdate(tag) => {
                     debug!("No update needed. Will update last check time with {tag}");
                     self.update_last_check(&refresh.token.clone());
                 }
                 ClientFeaturesResponse::Updated(features, etag) => {
-                    self.handle_client_features_updated(&refresh.token, features, etag)
-                        .await;
-                    if self.delta_diff {
-                        self.compare_delta_cache(&refresh).await;
+                    match self
+                        .handle_client_features_updated(&refresh.token, features, etag)
+                        .await
+                    {
+                        Ok(()) => {
+                            if self.delta_diff {
+                                self.compare_delta_cache(&refresh).await;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Rejected features update: {e}");
+                            self.backoff(&refresh.token);
+                        }
                     }
                 }
             },
@@ -557,21 +1543,23 @@ impl FeatureRefresher {
                                 }
                             },
                             FeatureError::AccessDenied => {
-                                info!("Token used to fetch features was Forbidden, will remove from list of refresh tasks");
-                                self.tokens_to_refresh.remove(&refresh.token.token);
-                                if !self.tokens_to_refresh.iter().any(|e| {
-                                    e.value().token.environment == refresh.token.environment
-                                }) {
-                                    let cache_key = cache_key(&refresh.token);
-                                    // No tokens left that access the environment of our current refresh. Deleting client features and engine cache
-                                    self.features_cache.remove(&cache_key);
-                                    self.engine_cache.remove(&cache_key);
-                                }
+                                self.deny_token(&refresh.token);
                             }
                             FeatureError::NotFound => {
                                 info!("Had a bad URL when trying to fetch features. Increasing waiting period for the token before trying again");
                                 self.backoff(&refresh.token);
                             }
+                            FeatureError::PayloadTooLarge(limit) => {
+                                warn!("Upstream features response exceeded {limit} bytes and was discarded. Increasing waiting period for the token before trying again");
+                                self.backoff(&refresh.token);
+                            }
+                            FeatureError::CircuitOpen => {
+                                debug!("Upstream circuit breaker is open, skipping refresh until it closes");
+                            }
+                            FeatureError::Unreachable => {
+                                info!("Could not reach upstream. Increasing waiting period for the token before trying again");
+                                self.backoff(&refresh.token);
+                            }
                         }
                     }
                     EdgeError::ClientCacheError => {
@@ -582,6 +1570,52 @@ impl FeatureRefresher {
             }
         }
     }
+    /// Upstream denied `token`. While it has been denied for less than the eviction grace period, the token is only
+    /// backed off, so a brief denial doesn't blank the environment's features. After that it's forgotten
+    pub fn deny_token(&self, token: &EdgeToken) {
+        let first_denied_at = self
+            .tokens_to_refresh
+            .get(&token.token)
+            .and_then(|refresh| refresh.first_denied_at);
+        let within_grace = match (self.token_eviction_grace, first_denied_at) {
+            (Some(grace), Some(first_denied_at)) => Utc::now() - first_denied_at < grace,
+            (Some(grace), None) => grace > chrono::Duration::zero(),
+            (None, _) => false,
+        };
+        if within_grace {
+            info!("Token used to fetch features was Forbidden, keeping it and its cached features until the eviction grace period has passed");
+            self.tokens_to_refresh
+                .alter(&token.token, |_k, old_refresh| {
+                    old_refresh.denied(&self.refresh_interval)
+                });
+        } else {
+            info!("Token used to fetch features was Forbidden, will remove from list of refresh tasks");
+            self.forget_token(token);
+        }
+    }
+
+    /// Stops refreshing features for `token`. If no other registered token covers its environment, the
+    /// environment's features and engine are evicted as well
+    pub fn forget_token(&self, token: &EdgeToken) {
+        self.last_applied_hashes.remove(&token.token);
+        if self.tokens_to_refresh.remove(&token.token).is_none() {
+            return;
+        }
+        if !self
+            .tokens_to_refresh
+            .iter()
+            .any(|e| e.value().token.environment == token.environment)
+        {
+            let cache_key = cache_key(token);
+            // No tokens left that access the environment of the token. Deleting client features and engine cache
+            self.features_cache.remove(&cache_key);
+            self.engine_cache.remove(&cache_key);
+            self.delta_history.remove(&cache_key);
+        } else if let Some(mut history) = self.delta_history.get_mut(&cache_key(token)) {
+            history.forget_token(&token.token);
+        }
+    }
+
     pub fn backoff(&self, token: &EdgeToken) {
         self.tokens_to_refresh
             .alter(&token.token, |_k, old_refresh| {
@@ -589,6 +1623,8 @@ impl FeatureRefresher {
             });
     }
     pub fn update_last_check(&self, token: &EdgeToken) {
+        SECONDS_SINCE_LAST_UPDATE
+            .record(token.environment.as_deref().unwrap_or_default(), Utc::now());
         self.tokens_to_refresh
             .alter(&token.token, |_k, old_refresh| {
                 old_refresh.successful_check(&self.refresh_interval)
@@ -601,16 +1637,60 @@ impl FeatureRefresher {
         etag: Option<EntityTag>,
         feature_count: usize,
     ) {
+        let environment = token.environment.as_deref().unwrap_or_default();
+        CACHED_FEATURE_COUNT
+            .with_label_values(&[environment, &token.projects.join(",")])
+            .set(feature_count as i64);
+        SECONDS_SINCE_LAST_UPDATE.record(environment, Utc::now());
+        let previous_feature_count = self
+            .tokens_to_refresh
+            .get(&token.token)
+            .and_then(|refresh| refresh.last_feature_count);
+        self.check_feature_count_drop(environment, previous_feature_count, feature_count);
         self.tokens_to_refresh
             .alter(&token.token, |_k, old_refresh| {
                 old_refresh.successful_refresh(&self.refresh_interval, etag, feature_count)
             });
     }
+
+    /// A sudden drop in features usually means flags were deleted or archived upstream by mistake
+    fn check_feature_count_drop(&self, environment: &str, previous: Option<usize>, current: usize) {
+        let (Some(threshold), Some(previous)) = (self.feature_count_drop_threshold, previous)
+        else {
+            return;
+        };
+        if previous == 0 || current >= previous {
+            return;
+        }
+        let dropped_percent = (previous - current) as f64 * 100.0 / previous as f64;
+        if dropped_percent > threshold as f64 {
+            error!(
+                "Feature count for {environment} dropped from {previous} to {current} ({dropped_percent:.0}%), more than the threshold of {threshold}%. Check whether features were deleted or archived upstream by mistake"
+            );
+            FEATURE_COUNT_DROPS.with_label_values(&[environment]).inc();
+        }
+    }
+}
+
+/// Whether upstream responded to a feature fetch. Fetches the circuit breaker short-circuited or that never
+/// reached upstream take no time, so counting them would hide an outage in the latency quantiles
+fn upstream_answered<T>(result: &EdgeResult<T>) -> bool {
+    matches!(
+        result,
+        Ok(_)
+            | Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::AccessDenied
+                    | FeatureError::NotFound
+                    | FeatureError::PayloadTooLarge(_)
+                    | FeatureError::Retriable(_)
+            ))
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::Arc;
 
     use actix_http::HttpService;
@@ -618,16 +1698,26 @@ mod tests {
     use actix_service::map_config;
     use actix_web::dev::AppConfig;
     use actix_web::http::header::EntityTag;
-    use actix_web::{web, App};
+    use actix_web::{web, App, HttpResponse};
     use chrono::{Duration, Utc};
     use dashmap::DashMap;
+    use prometheus::core::Collector;
     use reqwest::Url;
-    use unleash_types::client_features::ClientFeature;
+    use test_case::test_case;
+    use tracing_test::traced_test;
+    use unleash_types::client_features::{
+        ClientFeature, ClientFeatures, ClientFeaturesDelta, Constraint, Context, DeltaEvent, Meta,
+        Operator, Query, Strategy,
+    };
     use unleash_yggdrasil::EngineState;
 
+    use crate::cli::EnvironmentMismatchBehavior;
+    use crate::error::EdgeError;
     use crate::feature_cache::{update_projects_from_feature_update, FeatureCache};
-    use crate::filters::{project_filter, FeatureFilterSet};
-    use crate::http::unleash_client::{new_reqwest_client, ClientMetaInformation};
+    use crate::filters::{
+        name_prefix_filter, normalize_feature_name, project_filter, FeatureFilterSet,
+    };
+    use crate::http::unleash_client::{new_reqwest_client, ClientMetaInformation, HttpClientArgs};
     use crate::tests::features_from_disk;
     use crate::tokens::cache_key;
     use crate::types::TokenValidationStatus::Validated;
@@ -649,14 +1739,10 @@ mod tests {
     }
 
     fn create_test_client() -> UnleashClient {
-        let http_client = new_reqwest_client(
-            false,
-            None,
-            None,
-            Duration::seconds(5),
-            Duration::seconds(5),
-            ClientMetaInformation::test_config(),
-        )
+        let http_client = new_reqwest_client(HttpClientArgs {
+            client_meta_information: ClientMetaInformation::test_config(),
+            ..Default::default()
+        })
         .expect("Failed to create client");
 
         UnleashClient::from_url(
@@ -797,6 +1883,90 @@ mod tests {
             .contains_key("*:development.abcdefghijklmnopqrstuvwxyz"))
     }
 
+    #[tokio::test]
+    pub async fn pinned_project_token_survives_registering_a_wildcard() {
+        let pinned = "projecta:development.abcdefghijklmnopqrstuvwxyz";
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            pinned_tokens: vec![pinned.into()],
+            ..Default::default()
+        };
+        let project_a_token = EdgeToken::try_from(pinned.to_string()).unwrap();
+        let project_b_token =
+            EdgeToken::try_from("projectb:development.abcdefghijklmnopqrstuvwxyz".to_string())
+                .unwrap();
+        let wildcard_token =
+            EdgeToken::try_from("*:development.abcdefghijklmnopqrstuvwxyz".to_string()).unwrap();
+
+        feature_refresher
+            .register_token_for_refresh(project_a_token, None)
+            .await;
+        feature_refresher
+            .register_token_for_refresh(project_b_token, None)
+            .await;
+        feature_refresher
+            .register_token_for_refresh(wildcard_token, None)
+            .await;
+
+        let mut refreshed: Vec<String> = feature_refresher
+            .tokens_to_refresh
+            .iter()
+            .map(|r| r.key().clone())
+            .collect();
+        refreshed.sort();
+        assert_eq!(
+            refreshed,
+            vec![
+                "*:development.abcdefghijklmnopqrstuvwxyz".to_string(),
+                pinned.to_string()
+            ]
+        );
+        assert!(
+            feature_refresher
+                .tokens_to_refresh
+                .get(pinned)
+                .unwrap()
+                .pinned
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    pub async fn tokens_simplified_away_are_logged_redacted_and_counted() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            token_scope_metrics: true,
+            ..Default::default()
+        };
+        let mut project_token =
+            EdgeToken::try_from("simplified:development.abcdefghijklmnopqrstuvwxyz".to_string())
+                .unwrap();
+        project_token.token_type = Some(TokenType::Client);
+        let mut wildcard_token =
+            EdgeToken::try_from("*:development.zyxwvutsrqponmlkjihgfedcba".to_string()).unwrap();
+        wildcard_token.token_type = Some(TokenType::Client);
+        let subsumed = || {
+            super::TOKENS_SUBSUMED
+                .with_label_values(&["development", "simplified", "*"])
+                .get()
+        };
+        let subsumed_before = subsumed();
+
+        feature_refresher
+            .register_token_for_refresh(project_token, None)
+            .await;
+        assert!(!logs_contain("is subsumed by"));
+        feature_refresher
+            .register_token_for_refresh(wildcard_token, None)
+            .await;
+
+        assert!(logs_contain(
+            "Token simplified:development.abcdef****uvwxyz is subsumed by *:development.zyxwvu****fedcba"
+        ));
+        assert!(!logs_contain("abcdefghijklmnopqrstuvwxyz"));
+        assert_eq!(subsumed(), subsumed_before + 1);
+    }
+
     #[tokio::test]
     pub async fn registering_tokens_with_multiple_projects_overwrites_single_tokens() {
         let unleash_client = create_test_client();
@@ -931,6 +2101,9 @@ mod tests {
             last_check: None,
             failure_count: 0,
             last_feature_count: None,
+            priority: 0,
+            first_denied_at: None,
+            pinned: false,
         };
         let etag_and_last_refreshed_token =
             EdgeToken::try_from("projectb:development.etag_and_last_refreshed_token".to_string())
@@ -943,6 +2116,9 @@ mod tests {
             last_check: Some(Utc::now()),
             failure_count: 0,
             last_feature_count: None,
+            priority: 0,
+            first_denied_at: None,
+            pinned: false,
         };
         let etag_but_old_token =
             EdgeToken::try_from("projectb:development.etag_but_old_token".to_string()).unwrap();
@@ -956,6 +2132,9 @@ mod tests {
             last_check: Some(ten_seconds_ago),
             failure_count: 0,
             last_feature_count: None,
+            priority: 0,
+            first_denied_at: None,
+            pinned: false,
         };
         feature_refresher.tokens_to_refresh.insert(
             etag_but_last_refreshed_ten_seconds_ago.token.token.clone(),
@@ -996,6 +2175,329 @@ mod tests {
         })
         .await
     }
+    async fn count_registration(registrations: web::Data<AtomicUsize>) -> HttpResponse {
+        registrations.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Accepted().finish()
+    }
+
+    async fn registration_counting_server(registrations: Arc<AtomicUsize>) -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new()
+                    .app_data(web::Data::from(registrations.clone()))
+                    .route("/api/client/register", web::post().to(count_registration)),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    pub async fn skipping_client_registration_still_registers_token_for_refresh() {
+        let registrations = Arc::new(AtomicUsize::new(0));
+        let server = registration_counting_server(registrations.clone()).await;
+        let unleash_client = Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap());
+        let token =
+            EdgeToken::try_from("*:development.abcdefghijklmnopqrstuvwxyz".to_string()).unwrap();
+
+        let skipping = FeatureRefresher {
+            unleash_client: unleash_client.clone(),
+            skip_client_registration: true,
+            ..Default::default()
+        };
+        skipping
+            .register_token_for_refresh(token.clone(), None)
+            .await;
+        assert!(skipping.tokens_to_refresh.contains_key(&token.token));
+        assert_eq!(registrations.load(Ordering::SeqCst), 0);
+
+        let registering = FeatureRefresher {
+            unleash_client,
+            ..Default::default()
+        };
+        registering.register_token_for_refresh(token, None).await;
+        assert_eq!(registrations.load(Ordering::SeqCst), 1);
+    }
+
+    async fn denying_server(denying: Arc<AtomicBool>) -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new().app_data(web::Data::from(denying.clone())).route(
+                    "/api/client/features",
+                    web::get().to(|denying: web::Data<AtomicBool>| async move {
+                        if denying.load(Ordering::SeqCst) {
+                            HttpResponse::Forbidden().finish()
+                        } else {
+                            HttpResponse::Ok().json(ClientFeatures {
+                                version: 2,
+                                features: vec![named_feature("recovered", true)],
+                                segments: None,
+                                query: None,
+                                meta: None,
+                            })
+                        }
+                    }),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    pub async fn token_recovering_within_eviction_grace_keeps_caches() {
+        let denying = Arc::new(AtomicBool::new(true));
+        let server = denying_server(denying.clone()).await;
+        let unleash_client = Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap());
+        let feature_refresher = FeatureRefresher {
+            unleash_client,
+            token_eviction_grace: Some(Duration::seconds(60)),
+            ..Default::default()
+        };
+        let mut token =
+            EdgeToken::try_from("*:development.abcdefghijklmnopqrstuvwxyz".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        let key = cache_key(&token);
+        let cached = ClientFeatures {
+            version: 2,
+            features: vec![named_feature("cached", true)],
+            segments: None,
+            query: None,
+            meta: None,
+        };
+        feature_refresher.features_cache.insert(key.clone(), cached);
+        feature_refresher
+            .engine_cache
+            .insert(key.clone(), EngineState::default());
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+
+        let refresh = feature_refresher.get_tokens_due_for_refresh()[0].clone();
+        feature_refresher.refresh_single(refresh).await;
+        assert!(feature_refresher.features_cache.get(&key).is_some());
+        assert!(feature_refresher.engine_cache.contains_key(&key));
+        let denied = feature_refresher
+            .tokens_to_refresh
+            .get(&token.token)
+            .unwrap()
+            .clone();
+        assert!(denied.first_denied_at.is_some());
+
+        denying.store(false, Ordering::SeqCst);
+        feature_refresher.refresh_single(denied).await;
+        let recovered = feature_refresher
+            .tokens_to_refresh
+            .get(&token.token)
+            .unwrap();
+        assert!(recovered.first_denied_at.is_none());
+        assert_eq!(
+            feature_refresher.features_cache.get(&key).unwrap().features[0].name,
+            "recovered"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn token_denied_past_eviction_grace_evicts_caches() {
+        let server = denying_server(Arc::new(AtomicBool::new(true))).await;
+        let unleash_client = Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap());
+        let feature_refresher = FeatureRefresher {
+            unleash_client,
+            token_eviction_grace: Some(Duration::seconds(60)),
+            ..Default::default()
+        };
+        let mut token =
+            EdgeToken::try_from("*:development.abcdefghijklmnopqrstuvwxyz".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        let key = cache_key(&token);
+        feature_refresher
+            .features_cache
+            .insert(key.clone(), ClientFeatures::default());
+        feature_refresher
+            .engine_cache
+            .insert(key.clone(), EngineState::default());
+        let refresh = TokenRefresh {
+            first_denied_at: Some(Utc::now() - Duration::seconds(61)),
+            ..TokenRefresh::new(token.clone(), None)
+        };
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), refresh.clone());
+
+        feature_refresher.refresh_single(refresh).await;
+        assert!(!feature_refresher
+            .tokens_to_refresh
+            .contains_key(&token.token));
+        assert!(feature_refresher.features_cache.get(&key).is_none());
+        assert!(!feature_refresher.engine_cache.contains_key(&key));
+    }
+
+    #[tokio::test]
+    pub async fn only_fetches_upstream_answered_are_counted_in_the_latency_quantiles() {
+        let server = denying_server(Arc::new(AtomicBool::new(true))).await;
+        let answering = FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap()),
+            ..Default::default()
+        };
+        let unreachable = FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new("http://localhost:1", None).unwrap()),
+            ..Default::default()
+        };
+        let refresh =
+            |token: &str| TokenRefresh::new(EdgeToken::try_from(token.to_string()).unwrap(), None);
+
+        unreachable
+            .refresh_single(refresh("*:latencyunreachable.secret123"))
+            .await;
+        answering
+            .refresh_single(refresh("*:latencydenied.secret123"))
+            .await;
+
+        assert!(super::UPSTREAM_FEATURE_FETCH_LATENCY
+            .quantile("latencyunreachable", 0.5)
+            .is_none());
+        assert!(super::UPSTREAM_FEATURE_FETCH_LATENCY
+            .quantile("latencydenied", 0.5)
+            .is_some());
+    }
+
+    fn named_feature(name: &str, enabled: bool) -> ClientFeature {
+        ClientFeature {
+            name: name.into(),
+            enabled,
+            ..ClientFeature::default()
+        }
+    }
+
+    async fn diverging_payloads_server() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new()
+                    .route(
+                        "/api/client/features",
+                        web::get().to(|| async {
+                            HttpResponse::Ok().json(ClientFeatures {
+                                version: 2,
+                                features: vec![
+                                    named_feature("flipped", true),
+                                    named_feature("same", true),
+                                ],
+                                segments: None,
+                                query: None,
+                                meta: Some(Meta {
+                                    etag: None,
+                                    revision_id: Some(7),
+                                    query_hash: None,
+                                }),
+                            })
+                        }),
+                    )
+                    .route(
+                        "/api/client/delta",
+                        web::get().to(|| async {
+                            HttpResponse::Ok().json(ClientFeaturesDelta {
+                                events: vec![DeltaEvent::Hydration {
+                                    event_id: 8,
+                                    features: vec![
+                                        named_feature("flipped", false),
+                                        named_feature("same", true),
+                                        named_feature("delta-only", true),
+                                    ],
+                                    segments: vec![],
+                                }],
+                            })
+                        }),
+                    ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    pub async fn delta_diffs_are_written_to_the_output_file_instead_of_logged() {
+        let server = diverging_payloads_server().await;
+        let output = std::env::temp_dir().join(format!("delta-diff-{}.jsonl", ulid::Ulid::new()));
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap()),
+            delta_diff: true,
+            delta_diff_output: Some(output.clone()),
+            ..Default::default()
+        };
+        let token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+
+        feature_refresher
+            .refresh_single(TokenRefresh::new(token, None))
+            .await;
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["environment"], "development");
+        assert_eq!(lines[0]["client_revision_id"], 7);
+        assert_eq!(lines[0]["delta_revision_id"], 8);
+        assert_eq!(
+            lines[0]["differing_features"],
+            serde_json::json!(["delta-only", "flipped"])
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn features_cache_can_be_written_while_a_delta_diff_is_being_appended() {
+        let server = diverging_payloads_server().await;
+        // Opening a fifo for writing blocks until someone reads it, which keeps the append pending
+        let output = std::env::temp_dir().join(format!("delta-diff-{}.fifo", ulid::Ulid::new()));
+        assert!(std::process::Command::new("mkfifo")
+            .arg(&output)
+            .status()
+            .unwrap()
+            .success());
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap()),
+            delta_diff_output: Some(output.clone()),
+            ..Default::default()
+        };
+        let token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        feature_refresher
+            .refresh_single(TokenRefresh::new(token.clone(), None))
+            .await;
+
+        let refresher = feature_refresher.clone();
+        let refresh = TokenRefresh::new(token.clone(), None);
+        let comparison = tokio::spawn(async move { refresher.compare_delta_cache(&refresh).await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!comparison.is_finished());
+
+        let features_cache = feature_refresher.features_cache.clone();
+        let (written_tx, written_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            features_cache.insert(cache_key(&token), ClientFeatures::default());
+            written_tx.send(()).unwrap();
+        });
+        let written = written_rx.recv_timeout(std::time::Duration::from_secs(2));
+
+        let fifo = output.clone();
+        let diff = tokio::task::spawn_blocking(move || std::fs::read_to_string(fifo))
+            .await
+            .unwrap()
+            .unwrap();
+        comparison.await.unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert!(
+            written.is_ok(),
+            "writing to the cache blocked on the diff append"
+        );
+        assert!(diff.contains("flipped"));
+    }
+
     #[tokio::test]
     pub async fn getting_403_when_refreshing_features_will_remove_token() {
         let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
@@ -1031,21 +2533,149 @@ mod tests {
     }
 
     #[tokio::test]
-    pub async fn getting_404_removes_tokens_from_token_to_refresh_but_not_its_features() {
+    pub async fn tokens_only_known_to_the_secondary_upstream_are_hydrated_from_it() {
         let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
-        token.status = Validated;
         token.token_type = Some(TokenType::Client);
-        let token_cache = DashMap::default();
-        token_cache.insert(token.token.clone(), token.clone());
-        let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
-        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
-        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(token_cache);
-        let example_features = features_from_disk("../examples/features.json");
-        let cache_key = cache_key(&token);
-        let mut engine_state = EngineState::default();
-        let warnings = engine_state.take_state(example_features.clone());
-        upstream_features_cache.insert(cache_key.clone(), example_features.clone());
-        upstream_engine_cache.insert(cache_key.clone(), engine_state);
+        token.status = Validated;
+        let primary_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        primary_token_cache.insert(
+            token.token.clone(),
+            EdgeToken {
+                token_type: Some(TokenType::Invalid),
+                status: TokenValidationStatus::Invalid,
+                ..token.clone()
+            },
+        );
+        let primary = crate::tests::upstream_server(
+            primary_token_cache,
+            Arc::new(FeatureCache::default()),
+            Arc::new(DashMap::default()),
+        )
+        .await;
+        let secondary_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        secondary_token_cache.insert(token.token.clone(), token.clone());
+        let secondary_features_cache = Arc::new(FeatureCache::default());
+        secondary_features_cache.insert(
+            cache_key(&token),
+            features_from_disk("../examples/features.json"),
+        );
+        let secondary = crate::tests::upstream_server(
+            secondary_token_cache,
+            secondary_features_cache,
+            Arc::new(DashMap::default()),
+        )
+        .await;
+
+        let unleash_client = Arc::new(
+            UnleashClient::new(primary.url("/").as_str(), None)
+                .unwrap()
+                .with_secondary_upstream(
+                    Some(UnleashClient::new(secondary.url("/").as_str(), None).unwrap()),
+                    Duration::minutes(5),
+                ),
+        );
+        let token_validator = crate::auth::token_validator::TokenValidator {
+            unleash_client: unleash_client.clone(),
+            token_cache: Arc::new(DashMap::default()),
+            persistence: None,
+        };
+        let feature_refresher = FeatureRefresher {
+            unleash_client,
+            ..Default::default()
+        };
+
+        let validated = token_validator
+            .register_token(token.token.clone())
+            .await
+            .unwrap();
+        assert_eq!(validated.status, Validated);
+        feature_refresher
+            .register_and_hydrate_token(&validated)
+            .await;
+
+        let cached = feature_refresher
+            .features_cache
+            .get(&cache_key(&token))
+            .unwrap();
+        assert!(!cached.features.is_empty());
+        assert!(feature_refresher
+            .engine_cache
+            .contains_key(&cache_key(&token)));
+    }
+
+    #[tokio::test]
+    pub async fn hydrating_tokens_publishes_progress_for_each_token() {
+        let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut dx_token = EdgeToken::try_from("dx:development.secret123".to_string()).unwrap();
+        dx_token.token_type = Some(TokenType::Client);
+        dx_token.status = Validated;
+        upstream_token_cache.insert(dx_token.token.clone(), dx_token.clone());
+        upstream_features_cache.insert(
+            cache_key(&dx_token),
+            features_from_disk("../examples/hostedexample.json"),
+        );
+        let server = client_api_test_server(
+            upstream_token_cache,
+            upstream_features_cache,
+            upstream_engine_cache,
+        )
+        .await;
+        let unleash_client = UnleashClient::new(server.url("/").as_str(), None).unwrap();
+        let feature_refresher = FeatureRefresher::with_client(Arc::new(unleash_client));
+        let mut progress = feature_refresher.subscribe_to_hydration_progress();
+        let mut unknown_token = EdgeToken::try_from("*:production.secret123".to_string()).unwrap();
+        unknown_token.token_type = Some(TokenType::Client);
+        unknown_token.status = Validated;
+        feature_refresher
+            .register_token_for_refresh(dx_token.clone(), None)
+            .await;
+        feature_refresher
+            .register_token_for_refresh(unknown_token.clone(), None)
+            .await;
+
+        feature_refresher.hydrate_new_tokens().await;
+
+        let mut events = vec![];
+        while let Ok(event) = progress.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 2);
+        let dx_progress = events
+            .iter()
+            .find(|p| p.token.starts_with("dx:development"))
+            .expect("No progress for the dx token");
+        assert!(dx_progress.succeeded);
+        assert_eq!(dx_progress.feature_count, 16);
+        assert_eq!(dx_progress.hydrated, 1);
+        let unknown_progress = events
+            .iter()
+            .find(|p| p.token.starts_with("*:production"))
+            .expect("No progress for the forbidden token");
+        assert!(!unknown_progress.succeeded);
+        assert_eq!(unknown_progress.feature_count, 0);
+        // Subsequent refreshes of already hydrated tokens are not reported
+        feature_refresher.refresh_features().await;
+        assert!(progress.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    pub async fn getting_404_removes_tokens_from_token_to_refresh_but_not_its_features() {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.status = Validated;
+        token.token_type = Some(TokenType::Client);
+        let token_cache = DashMap::default();
+        token_cache.insert(token.token.clone(), token.clone());
+        let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(token_cache);
+        let example_features = features_from_disk("../examples/features.json");
+        let cache_key = cache_key(&token);
+        let mut engine_state = EngineState::default();
+        let warnings = engine_state.take_state(example_features.clone());
+        upstream_features_cache.insert(cache_key.clone(), example_features.clone());
+        upstream_engine_cache.insert(cache_key.clone(), engine_state);
         let mut server = client_api_test_server(
             upstream_token_cache,
             upstream_features_cache,
@@ -1118,11 +2748,15 @@ mod tests {
         feature_refresher.refresh_features().await;
         assert!(!feature_refresher.features_cache.is_empty());
         assert!(!feature_refresher.engine_cache.is_empty());
+        assert!(feature_refresher
+            .last_applied_hashes
+            .contains_key(&valid_token.token));
         token_cache_to_modify.remove(&valid_token.token);
         feature_refresher.refresh_features().await;
         assert!(feature_refresher.tokens_to_refresh.is_empty());
         assert!(feature_refresher.features_cache.is_empty());
         assert!(feature_refresher.engine_cache.is_empty());
+        assert!(feature_refresher.last_applied_hashes.is_empty());
         assert!(warnings.is_none());
     }
 
@@ -1233,6 +2867,65 @@ mod tests {
         assert!(warnings.is_none());
     }
 
+    #[tokio::test]
+    pub async fn project_tokens_in_the_same_environment_share_a_single_cache_entry() {
+        let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut dx_token = EdgeToken::try_from("dx:development.secret123".to_string()).unwrap();
+        dx_token.token_type = Some(TokenType::Client);
+        dx_token.status = Validated;
+        upstream_token_cache.insert(dx_token.token.clone(), dx_token.clone());
+        let mut eg_token = EdgeToken::try_from("eg:development.secret123".to_string()).unwrap();
+        eg_token.token_type = Some(TokenType::Client);
+        eg_token.status = Validated;
+        upstream_token_cache.insert(eg_token.token.clone(), eg_token.clone());
+        let example_features = features_from_disk("../examples/hostedexample.json");
+        upstream_features_cache.insert(cache_key(&dx_token), example_features.clone());
+        let server = client_api_test_server(
+            upstream_token_cache,
+            upstream_features_cache,
+            upstream_engine_cache,
+        )
+        .await;
+        let unleash_client = UnleashClient::new(server.url("/").as_str(), None).unwrap();
+        let mut feature_refresher = FeatureRefresher::with_client(Arc::new(unleash_client));
+        feature_refresher.strict = false;
+        feature_refresher.refresh_interval = Duration::seconds(0);
+        feature_refresher
+            .register_and_hydrate_token(&dx_token)
+            .await;
+        feature_refresher
+            .register_and_hydrate_token(&eg_token)
+            .await;
+
+        assert_eq!(feature_refresher.tokens_to_refresh.len(), 2);
+        assert_eq!(feature_refresher.features_cache.len(), 1);
+        assert_eq!(feature_refresher.engine_cache.len(), 1);
+        let shared = feature_refresher
+            .features_cache
+            .get(&cache_key(&dx_token))
+            .expect("No shared entry for the development environment");
+        assert_eq!(shared.features.len(), 23);
+
+        let dx_features = feature_refresher
+            .features_for_filter(
+                dx_token.clone(),
+                &FeatureFilterSet::from(project_filter(&dx_token)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(dx_features.features.len(), 16);
+        let eg_features = feature_refresher
+            .features_for_filter(
+                eg_token.clone(),
+                &FeatureFilterSet::from(project_filter(&eg_token)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(eg_features.features.len(), 7);
+    }
+
     #[tokio::test]
     pub async fn should_get_data_for_multi_project_token_even_if_we_have_data_for_one_of_the_projects_when_dynamic(
     ) {
@@ -1340,6 +3033,9 @@ mod tests {
             last_check: None,
             failure_count: 0,
             last_feature_count: None,
+            priority: 0,
+            first_denied_at: None,
+            pinned: false,
         };
 
         current_tokens.insert(wildcard_token.token, token_refresh);
@@ -1400,6 +3096,59 @@ mod tests {
         assert!(warnings.is_none());
     }
 
+    #[tokio::test]
+    async fn empty_upstream_responses_are_ignored_when_configured() {
+        let upstream_features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let mut token = EdgeToken::from_str("*:ignoreempty.devsecret").unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        upstream_token_cache.insert(token.token.clone(), token.clone());
+        let cache_key = cache_key(&token);
+        upstream_features_cache.insert(
+            cache_key.clone(),
+            features_from_disk("../examples/hostedexample.json"),
+        );
+        let server = client_api_test_server(
+            upstream_token_cache,
+            upstream_features_cache.clone(),
+            upstream_engine_cache,
+        )
+        .await;
+        let features_cache: Arc<FeatureCache> = Arc::new(FeatureCache::default());
+        let unleash_client = UnleashClient::new(server.url("/").as_str(), None).unwrap();
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(unleash_client),
+            features_cache: features_cache.clone(),
+            refresh_interval: Duration::seconds(0),
+            ignore_empty_upstream: true,
+            ..Default::default()
+        };
+        let ignored = || {
+            super::EMPTY_UPSTREAM_RESPONSES_IGNORED
+                .with_label_values(&[&cache_key])
+                .get()
+        };
+
+        feature_refresher.register_and_hydrate_token(&token).await;
+        let hydrated_count = features_cache.get(&cache_key).unwrap().features.len();
+        assert!(hydrated_count > 0);
+
+        upstream_features_cache.insert(
+            cache_key.clone(),
+            features_from_disk("../examples/empty-features.json"),
+        );
+        feature_refresher.refresh_features().await;
+
+        assert_eq!(
+            features_cache.get(&cache_key).unwrap().features.len(),
+            hydrated_count
+        );
+        assert_eq!(ignored(), 1);
+        assert!(feature_refresher.engine_cache.contains_key(&cache_key));
+    }
+
     #[test]
     pub fn an_update_with_one_feature_removed_from_one_project_removes_the_feature_from_the_feature_list(
     ) {
@@ -1657,4 +3406,757 @@ mod tests {
         );
         assert_eq!(updated.len(), 0);
     }
+
+    #[tokio::test]
+    pub async fn normalized_feature_names_are_used_for_both_filtering_and_evaluation() {
+        let features: ClientFeatures = serde_json::from_str(
+            r#"{
+                "version": 2,
+                "features": [
+                    {
+                        "name": " Parent.Feature ",
+                        "enabled": true,
+                        "strategies": [{ "name": "default", "parameters": {} }]
+                    },
+                    {
+                        "name": "Child.Feature",
+                        "enabled": true,
+                        "strategies": [{ "name": "default", "parameters": {} }],
+                        "dependencies": [{ "feature": "Parent.Feature", "enabled": true }]
+                    },
+                    {
+                        "name": "PARENT.FEATURE",
+                        "enabled": false,
+                        "strategies": []
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            normalize_feature_names: true,
+            ..Default::default()
+        };
+        feature_refresher
+            .register_token_for_refresh(token.clone(), None)
+            .await;
+
+        feature_refresher
+            .handle_client_features_updated(&token, features, None)
+            .await
+            .unwrap();
+
+        let cached = feature_refresher
+            .features_cache
+            .get(&cache_key(&token))
+            .unwrap()
+            .clone();
+        let mut names: Vec<String> = cached.features.iter().map(|f| f.name.clone()).collect();
+        names.sort();
+        assert_eq!(names, vec!["child.feature", "parent.feature"]);
+
+        let filtered = feature_refresher
+            .features_for_filter(
+                token.clone(),
+                &FeatureFilterSet::from(name_prefix_filter(normalize_feature_name(" Child"))),
+            )
+            .await
+            .unwrap();
+        assert_eq!(filtered.features.len(), 1);
+        assert_eq!(filtered.features[0].name, "child.feature");
+
+        let engine = feature_refresher
+            .engine_cache
+            .get(&cache_key(&token))
+            .unwrap();
+        assert!(engine.is_enabled("parent.feature", &Context::default(), &None));
+        assert!(engine.is_enabled("child.feature", &Context::default(), &None));
+    }
+
+    #[tokio::test]
+    pub async fn token_scope_metrics_summarize_registered_tokens() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        for token in [
+            "*:development.secret123",
+            "dx:production.secret123",
+            "eg:production.secret123",
+            "[]:staging.secret123",
+        ] {
+            let mut token = EdgeToken::try_from(token.to_string()).unwrap();
+            token.token_type = Some(TokenType::Client);
+            token.status = Validated;
+            if token.projects.is_empty() {
+                token.projects = vec!["dx".into(), "ops".into()];
+            }
+            feature_refresher
+                .register_token_for_refresh(token, None)
+                .await;
+        }
+
+        feature_refresher.update_token_scope_metrics();
+
+        let per_environment = |env: &str| {
+            super::REGISTERED_TOKENS_PER_ENVIRONMENT
+                .with_label_values(&[env])
+                .get()
+        };
+        assert_eq!(per_environment("development"), 1);
+        assert_eq!(per_environment("production"), 2);
+        assert_eq!(per_environment("staging"), 1);
+        let by_scope = |scope: &str| {
+            super::REGISTERED_TOKENS_BY_SCOPE
+                .with_label_values(&[scope])
+                .get()
+        };
+        assert_eq!(by_scope("wildcard"), 1);
+        assert_eq!(by_scope("scoped"), 3);
+        assert_eq!(super::REGISTERED_TOKEN_PROJECTS.get(), 3);
+    }
+
+    fn features_for_environment(environment: &str) -> ClientFeatures {
+        ClientFeatures {
+            version: 2,
+            features: vec![ClientFeature {
+                name: "some-feature".into(),
+                enabled: true,
+                ..Default::default()
+            }],
+            segments: None,
+            query: Some(Query {
+                tags: None,
+                projects: Some(vec!["*".into()]),
+                name_prefix: None,
+                environment: Some(environment.into()),
+                inline_segment_constraints: Some(false),
+            }),
+            meta: None,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn features_for_another_environment_than_the_token_are_rejected() {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        let mismatches = super::UPSTREAM_ENVIRONMENT_MISMATCHES
+            .with_label_values(&["development", "production"])
+            .get();
+
+        let result = feature_refresher
+            .handle_client_features_updated(&token, features_for_environment("production"), None)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(EdgeError::UpstreamEnvironmentMismatch(expected, actual))
+                if expected == "development" && actual == "production"
+        ));
+        assert!(feature_refresher.features_cache.is_empty());
+        assert!(feature_refresher.engine_cache.is_empty());
+        assert!(
+            super::UPSTREAM_ENVIRONMENT_MISMATCHES
+                .with_label_values(&["development", "production"])
+                .get()
+                > mismatches
+        );
+
+        feature_refresher
+            .handle_client_features_updated(&token, features_for_environment("development"), None)
+            .await
+            .unwrap();
+        assert_eq!(feature_refresher.features_cache.len(), 1);
+    }
+
+    fn features_at_revision(feature_name: &str, revision_id: usize) -> ClientFeatures {
+        ClientFeatures {
+            features: vec![named_feature(feature_name, true)],
+            meta: Some(Meta {
+                etag: None,
+                revision_id: Some(revision_id),
+                query_hash: None,
+            }),
+            ..features_for_environment("development")
+        }
+    }
+
+    #[test_case(None, true; "without a cap")]
+    #[test_case(Some(2), false; "with a cap")]
+    #[tokio::test]
+    pub async fn features_with_too_many_strategies_are_left_out_of_frontend_engines(
+        max_strategies_per_feature: Option<usize>,
+        expect_oversized: bool,
+    ) {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            max_strategies_per_feature,
+            ..Default::default()
+        };
+        let strategy = || Strategy {
+            name: "default".into(),
+            sort_order: None,
+            segments: None,
+            constraints: None,
+            parameters: None,
+            variants: None,
+        };
+        let features = ClientFeatures {
+            features: vec![
+                ClientFeature {
+                    strategies: Some(vec![strategy(), strategy(), strategy()]),
+                    ..named_feature("oversized", true)
+                },
+                ClientFeature {
+                    strategies: Some(vec![strategy()]),
+                    ..named_feature("small", true)
+                },
+            ],
+            ..features_for_environment("development")
+        };
+
+        feature_refresher
+            .handle_client_features_updated(&token, features, None)
+            .await
+            .unwrap();
+        // The first update builds a new engine, this one updates the existing engine
+        let oversized_again = ClientFeatures {
+            features: vec![ClientFeature {
+                strategies: Some(vec![strategy(), strategy(), strategy()]),
+                ..named_feature("oversized", true)
+            }],
+            ..features_for_environment("development")
+        };
+        feature_refresher
+            .handle_client_features_updated(&token, oversized_again, None)
+            .await
+            .unwrap();
+
+        let cached = feature_refresher.features_cache.get("development").unwrap();
+        assert!(cached.features.iter().any(|f| f.name == "oversized"));
+        let evaluated = feature_refresher
+            .engine_cache
+            .get("development")
+            .unwrap()
+            .resolve_all(&Context::default(), &None)
+            .unwrap();
+        assert_eq!(evaluated.contains_key("oversized"), expect_oversized);
+    }
+
+    #[test_case(false, true; "when showing stale features")]
+    #[test_case(true, false; "when hiding stale features")]
+    #[tokio::test]
+    pub async fn stale_features_are_left_out_of_frontend_engines_when_hidden(
+        hide_stale_features: bool,
+        expect_stale: bool,
+    ) {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            hide_stale_features,
+            ..Default::default()
+        };
+        let stale_feature = || ClientFeature {
+            stale: Some(true),
+            ..named_feature("stale", true)
+        };
+        let features = ClientFeatures {
+            features: vec![stale_feature(), named_feature("fresh", true)],
+            ..features_for_environment("development")
+        };
+
+        feature_refresher
+            .handle_client_features_updated(&token, features, None)
+            .await
+            .unwrap();
+        // The first update builds a new engine, this one updates the existing engine
+        let stale_again = ClientFeatures {
+            features: vec![stale_feature()],
+            ..features_for_environment("development")
+        };
+        feature_refresher
+            .handle_client_features_updated(&token, stale_again, None)
+            .await
+            .unwrap();
+
+        let cached = feature_refresher.features_cache.get("development").unwrap();
+        assert!(cached.features.iter().any(|f| f.name == "stale"));
+        let evaluated = feature_refresher
+            .engine_cache
+            .get("development")
+            .unwrap()
+            .resolve_all(&Context::default(), &None)
+            .unwrap();
+        assert_eq!(evaluated.contains_key("stale"), expect_stale);
+    }
+
+    #[test_case(true; "rejected with the flag")]
+    #[test_case(false; "defaulted to off without the flag")]
+    #[tokio::test]
+    pub async fn upstream_features_that_fail_to_compile(fail_on_compile_warnings: bool) {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            fail_on_compile_warnings,
+            ..Default::default()
+        };
+        feature_refresher
+            .handle_client_features_updated(&token, features_for_environment("development"), None)
+            .await
+            .unwrap();
+        let broken = ClientFeatures {
+            features: vec![ClientFeature {
+                strategies: Some(vec![Strategy {
+                    name: "default".into(),
+                    sort_order: None,
+                    segments: None,
+                    constraints: Some(vec![Constraint {
+                        context_name: "userId".into(),
+                        operator: Operator::NumEq,
+                        case_insensitive: false,
+                        inverted: false,
+                        values: None,
+                        value: Some("not a number".into()),
+                    }]),
+                    parameters: None,
+                    variants: None,
+                }]),
+                ..named_feature("broken", true)
+            }],
+            ..features_for_environment("development")
+        };
+
+        let result = feature_refresher
+            .handle_client_features_updated(&token, broken, None)
+            .await;
+
+        let cached = feature_refresher.features_cache.get("development").unwrap();
+        let engine = feature_refresher.engine_cache.get("development").unwrap();
+        if fail_on_compile_warnings {
+            assert!(matches!(result, Err(EdgeError::ClientHydrationFailed(_))));
+            assert!(cached.features.iter().all(|f| f.name != "broken"));
+        } else {
+            result.unwrap();
+            assert!(cached.features.iter().any(|f| f.name == "broken"));
+        }
+        assert!(!engine.is_enabled("broken", &Context::default(), &None));
+    }
+
+    #[test_case(true; "rejecting regressions")]
+    #[test_case(false; "applying regressions")]
+    #[tokio::test]
+    pub async fn older_revisions_after_newer_ones_are_counted(reject_revision_regressions: bool) {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            reject_revision_regressions,
+            ..Default::default()
+        };
+        let regressions = || {
+            super::REVISION_REGRESSIONS
+                .with_label_values(&["development"])
+                .get()
+        };
+
+        feature_refresher
+            .handle_client_features_updated(&token, features_at_revision("newer", 10), None)
+            .await
+            .unwrap();
+        let regressions_before = regressions();
+        let result = feature_refresher
+            .handle_client_features_updated(&token, features_at_revision("older", 5), None)
+            .await;
+
+        assert!(regressions() > regressions_before);
+        let cached = feature_refresher.features_cache.get("development").unwrap();
+        let last_seen = *feature_refresher
+            .last_revision_ids
+            .get("development")
+            .unwrap();
+        if reject_revision_regressions {
+            assert!(matches!(
+                result,
+                Err(EdgeError::UpstreamRevisionRegression(10, 5))
+            ));
+            assert_eq!(cached.features[0].name, "newer");
+            assert_eq!(last_seen, 10);
+        } else {
+            assert!(result.is_ok());
+            assert_eq!(cached.features[0].name, "older");
+            assert_eq!(last_seen, 5);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn feature_cache_update_duration_is_observed_for_updates() {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        let observed = || {
+            super::FEATURE_CACHE_UPDATE_DURATION
+                .with_label_values(&["development"])
+                .get_sample_count()
+        };
+        let observed_before = observed();
+
+        feature_refresher
+            .handle_client_features_updated(&token, features_at_revision("first", 1), None)
+            .await
+            .unwrap();
+        feature_refresher
+            .handle_client_features_updated(&token, features_at_revision("second", 2), None)
+            .await
+            .unwrap();
+
+        // Other tests update development concurrently, so this can only be a lower bound
+        assert!(observed() >= observed_before + 2);
+    }
+
+    #[test]
+    pub fn large_feature_count_drops_between_refreshes_are_counted() {
+        let mut token = EdgeToken::try_from("*:featurecountdrop.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            feature_count_drop_threshold: Some(50),
+            ..Default::default()
+        };
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+        let drops = || {
+            super::FEATURE_COUNT_DROPS
+                .with_label_values(&["featurecountdrop"])
+                .get()
+        };
+
+        feature_refresher.update_last_refresh(&token, None, 100);
+        feature_refresher.update_last_refresh(&token, None, 60);
+        assert_eq!(drops(), 0);
+        feature_refresher.update_last_refresh(&token, None, 2);
+        assert_eq!(drops(), 1);
+        feature_refresher.update_last_refresh(&token, None, 80);
+        assert_eq!(drops(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn strict_mode_rejections_are_counted_per_environment() {
+        let mut startup_token =
+            EdgeToken::try_from("dx:strictrejections.secret123".to_string()).unwrap();
+        startup_token.token_type = Some(TokenType::Client);
+        startup_token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        feature_refresher
+            .register_token_for_refresh(startup_token, None)
+            .await;
+        let rejections = || {
+            super::STRICT_MODE_REJECTIONS
+                .with_label_values(&["strictrejections"])
+                .get()
+        };
+        let before = rejections();
+
+        let mut out_of_scope =
+            EdgeToken::try_from("eg:strictrejections.secret123".to_string()).unwrap();
+        out_of_scope.token_type = Some(TokenType::Client);
+        out_of_scope.status = Validated;
+        let result = feature_refresher
+            .features_for_filter(out_of_scope, &FeatureFilterSet::default())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(EdgeError::InvalidTokenWithStrictBehavior)
+        ));
+        assert_eq!(rejections(), before + 1);
+    }
+
+    #[tokio::test]
+    pub async fn identical_feature_updates_only_rebuild_the_engine_once() {
+        let mut token = EdgeToken::try_from("*:enginerebuilds.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        let skipped = || {
+            super::ENGINE_REBUILDS_SKIPPED
+                .with_label_values(&["enginerebuilds"])
+                .get()
+        };
+        let with_revision = |features: ClientFeatures, revision_id: usize| ClientFeatures {
+            meta: Some(Meta {
+                etag: None,
+                revision_id: Some(revision_id),
+                query_hash: None,
+            }),
+            ..features
+        };
+
+        feature_refresher
+            .handle_client_features_updated(
+                &token,
+                with_revision(features_for_environment("enginerebuilds"), 1),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(skipped(), 0);
+        feature_refresher
+            .handle_client_features_updated(
+                &token,
+                with_revision(features_for_environment("enginerebuilds"), 2),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(skipped(), 1);
+
+        let changed = ClientFeatures {
+            features: vec![named_feature("another-feature", true)],
+            ..features_for_environment("enginerebuilds")
+        };
+        feature_refresher
+            .handle_client_features_updated(&token, with_revision(changed, 3), None)
+            .await
+            .unwrap();
+        assert_eq!(skipped(), 1);
+        let engine = feature_refresher
+            .engine_cache
+            .get("enginerebuilds")
+            .unwrap();
+        assert!(engine
+            .resolve_all(&Context::default(), &None)
+            .unwrap()
+            .contains_key("another-feature"));
+    }
+
+    #[tokio::test]
+    pub async fn features_for_another_environment_are_cached_when_mismatches_are_accepted() {
+        let mut token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            environment_mismatch: EnvironmentMismatchBehavior::Accept,
+            ..Default::default()
+        };
+
+        feature_refresher
+            .handle_client_features_updated(&token, features_for_environment("production"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(feature_refresher.features_cache.len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn feature_updates_set_feature_count_and_staleness_gauges() {
+        let mut token = EdgeToken::try_from("dx:gauges.secret123".to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = Validated;
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        assert_eq!(
+            super::SECONDS_SINCE_LAST_UPDATE.seconds_since("gauges", Utc::now()),
+            None
+        );
+
+        feature_refresher
+            .handle_client_features_updated(&token, features_for_environment("gauges"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            super::CACHED_FEATURE_COUNT
+                .with_label_values(&["gauges", "dx"])
+                .get(),
+            1
+        );
+        let an_hour_from_now = Utc::now() + Duration::hours(1);
+        let age = super::SECONDS_SINCE_LAST_UPDATE.seconds_since("gauges", an_hour_from_now);
+        assert!(age.is_some_and(|seconds| (3599..=3600).contains(&seconds)));
+        let scraped = super::SECONDS_SINCE_LAST_UPDATE.collect();
+        assert_eq!(scraped[0].get_name(), "seconds_since_last_update");
+        assert!(scraped[0].get_metric().iter().any(|metric| metric
+            .get_label()
+            .iter()
+            .any(|label| label.get_value() == "gauges")));
+    }
+
+    #[test]
+    pub fn staleness_counts_successful_checks_but_not_failed_ones() {
+        let token = EdgeToken::try_from("*:staleness.secret123".to_string()).unwrap();
+        let feature_refresher = FeatureRefresher {
+            stale_threshold: Some(Duration::seconds(60)),
+            ..Default::default()
+        };
+        let refresh_with = |failure_count: u32| TokenRefresh {
+            last_refreshed: Some(Utc::now() - Duration::minutes(10)),
+            last_check: Some(Utc::now()),
+            failure_count,
+            ..TokenRefresh::new(token.clone(), None)
+        };
+
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), refresh_with(0));
+        assert!(feature_refresher.staleness(&token).is_none());
+
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), refresh_with(2));
+        let age = feature_refresher.staleness(&token).unwrap();
+        assert!((600..610).contains(&age.num_seconds()));
+    }
+
+    #[tokio::test]
+    pub async fn due_tokens_are_refreshed_in_priority_order() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            skip_client_registration: true,
+            token_priorities: vec![
+                crate::cli::string_to_token_priority("*:production=10").unwrap(),
+                crate::cli::string_to_token_priority("dx:*=5").unwrap(),
+            ],
+            ..Default::default()
+        };
+        for token in [
+            "*:development.secret123",
+            "*:production.secret123",
+            "dx:staging.secret123",
+        ] {
+            feature_refresher
+                .register_token_for_refresh(EdgeToken::try_from(token.to_string()).unwrap(), None)
+                .await;
+        }
+
+        let due: Vec<(String, i32)> = feature_refresher
+            .get_tokens_due_for_refresh()
+            .into_iter()
+            .map(|refresh| (refresh.token.token, refresh.priority))
+            .collect();
+        assert_eq!(
+            due,
+            vec![
+                ("*:production.secret123".to_string(), 10),
+                ("dx:staging.secret123".to_string(), 5),
+                ("*:development.secret123".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn scheduler_sleeps_until_the_earliest_token_is_due() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            refresh_interval: Duration::seconds(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            feature_refresher.time_until_next_refresh(),
+            super::DEFAULT_SCHEDULER_TICK
+        );
+
+        let mut soon = TokenRefresh::new(
+            EdgeToken::try_from("*:development.secret123".to_string()).unwrap(),
+            None,
+        );
+        soon.next_refresh = Some(Utc::now() + Duration::seconds(2));
+        let mut later = TokenRefresh::new(
+            EdgeToken::try_from("*:production.secret123".to_string()).unwrap(),
+            None,
+        );
+        later.next_refresh = Some(Utc::now() + Duration::seconds(4));
+        feature_refresher
+            .tokens_to_refresh
+            .insert(soon.token.token.clone(), soon);
+        feature_refresher
+            .tokens_to_refresh
+            .insert(later.token.token.clone(), later);
+
+        let sleep = feature_refresher.time_until_next_refresh();
+        assert!(sleep > std::time::Duration::from_secs(1));
+        assert!(sleep <= std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    pub fn scheduler_tick_caps_the_sleep_and_overdue_tokens_are_retried_on_the_tick() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            refresh_interval: Duration::seconds(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            feature_refresher.time_until_next_refresh(),
+            std::time::Duration::from_secs(1)
+        );
+
+        let feature_refresher = FeatureRefresher {
+            scheduler_tick: Some(std::time::Duration::from_millis(200)),
+            ..feature_refresher
+        };
+        assert_eq!(
+            feature_refresher.time_until_next_refresh(),
+            std::time::Duration::from_millis(200)
+        );
+
+        let mut overdue = TokenRefresh::new(
+            EdgeToken::try_from("*:development.secret123".to_string()).unwrap(),
+            None,
+        );
+        overdue.next_refresh = Some(Utc::now() - Duration::seconds(30));
+        feature_refresher
+            .tokens_to_refresh
+            .insert(overdue.token.token.clone(), overdue);
+        assert_eq!(
+            feature_refresher.time_until_next_refresh(),
+            std::time::Duration::from_millis(200)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn hydrating_a_new_token_wakes_the_scheduler() {
+        let feature_refresher = FeatureRefresher {
+            unleash_client: Arc::new(create_test_client()),
+            ..Default::default()
+        };
+        let token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        feature_refresher.register_and_hydrate_token(&token).await;
+
+        tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            feature_refresher.refresh_wakeup.notified(),
+        )
+        .await
+        .expect("Hydrating a token should wake the scheduler");
+    }
 }
@@ -1,30 +1,44 @@
 use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
 use actix_web::http::header::EntityTag;
 use chrono::Duration;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
 use lazy_static::lazy_static;
-use prometheus::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec, Opts};
+use opentelemetry::propagation::Injector;
+use opentelemetry::{global, Context};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_gauge_vec, HistogramVec, IntCounter,
+    IntGaugeVec, Opts,
+};
 use reqwest::header::{HeaderMap, HeaderName};
 use reqwest::{header, Client};
-use reqwest::{ClientBuilder, Identity, RequestBuilder, StatusCode, Url};
+use reqwest::{ClientBuilder, Identity, NoProxy, Proxy, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing::error;
 use tracing::{info, trace, warn};
 use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
 use unleash_types::client_metrics::ClientApplication;
 
-use crate::cli::ClientIdentity;
+use crate::cli::{CertificatePin, ClientIdentity, MetricsCompression};
 use crate::error::EdgeError::EdgeMetricsRequestError;
 use crate::error::{CertificateError, FeatureError};
+use crate::http::chaos::Chaos;
+use crate::http::circuit_breaker::CircuitBreaker;
 use crate::http::headers::{
-    UNLEASH_APPNAME_HEADER, UNLEASH_CLIENT_SPEC_HEADER, UNLEASH_INSTANCE_ID_HEADER,
+    EDGE_VERSION_HEADER, UNLEASH_APPNAME_HEADER, UNLEASH_CLIENT_SPEC_HEADER,
+    UNLEASH_INSTANCE_ID_HEADER,
 };
 use crate::metrics::client_metrics::MetricsBatch;
-use crate::tls::build_upstream_certificate;
+use crate::tls::{build_upstream_certificate, pinned_upstream_client_config};
 use crate::types::{
     ClientFeaturesDeltaResponse, ClientFeaturesResponse, EdgeResult, EdgeToken,
     TokenValidationStatus, ValidateTokensRequest,
@@ -79,12 +93,30 @@ lazy_static! {
         &["server", "version"]
     )
     .unwrap();
+    pub static ref UPSTREAM_MALFORMED_ETAGS: IntCounter = register_int_counter!(
+        "upstream_malformed_etags_total",
+        "Number of upstream features responses with an ETag header Edge could not parse"
+    )
+    .unwrap();
+    pub static ref UPSTREAM_CLOCK_SKEW_SECONDS: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "upstream_clock_skew_seconds",
+            "How many seconds upstream's clock is ahead of Edge's, going by the Date header on feature responses. Negative when upstream is behind"
+        ),
+        &["upstream"]
+    )
+    .unwrap();
 }
 
+/// Client metrics are bucketed by hour with Edge's clock, so skew beyond this gets logged
+const CLOCK_SKEW_WARN_THRESHOLD_SECONDS: i64 = 60;
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClientMetaInformation {
     pub app_name: String,
     pub instance_id: String,
+    /// Sent upstream as the `Unleash-Client-Spec` header
+    pub client_spec_version: String,
 }
 
 impl Default for ClientMetaInformation {
@@ -92,6 +124,7 @@ impl Default for ClientMetaInformation {
         Self {
             app_name: "unleash-edge".into(),
             instance_id: format!("unleash-edge@{}", ulid::Ulid::new().to_string()),
+            client_spec_version: unleash_yggdrasil::SUPPORTED_SPEC_VERSION.into(),
         }
     }
 }
@@ -101,16 +134,138 @@ impl ClientMetaInformation {
         Self {
             app_name: "test-app-name".into(),
             instance_id: "test-instance-id".into(),
+            client_spec_version: unleash_yggdrasil::SUPPORTED_SPEC_VERSION.into(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamKind {
+    Unleash,
+    Edge,
+    /// Edge is running without an upstream, e.g. in offline mode
+    None,
+}
+
+impl UpstreamKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamKind::Unleash => "unleash",
+            UpstreamKind::Edge => "edge",
+            UpstreamKind::None => "none",
+        }
+    }
+}
+
+/// What we know about the upstream we're connected to. The kind and version are detected when registering
+/// with upstream, since Edge announces its version on the register endpoint and Unleash doesn't
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamInfo {
+    pub kind: Option<UpstreamKind>,
+    pub version: Option<String>,
+    pub last_successful_contact: Option<DateTime<Utc>>,
+    /// How many seconds upstream's clock was ahead of Edge's on the last feature fetch
+    pub clock_skew_seconds: Option<i64>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct UnleashClient {
     pub urls: UnleashUrls,
-    backing_client: Client,
+    /// Shared between clones, so a client rebuilt after a client certificate rotation is picked up everywhere
+    backing_client: Arc<RwLock<Client>>,
     custom_headers: HashMap<String, String>,
     token_header: String,
+    lenient_json_parsing: bool,
+    metrics_compression: Option<MetricsCompression>,
+    max_feature_payload_bytes: Option<usize>,
+    token_validation_timeout: Option<std::time::Duration>,
+    upstream_info: Arc<RwLock<UpstreamInfo>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    secondary_upstream: Option<Arc<SecondaryUpstream>>,
+    chaos: Option<Arc<Chaos>>,
+}
+
+/// Writes W3C trace context headers like `traceparent`, so upstream traces link to the span Edge made the request in
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_str(key), value.parse()) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// The OpenTelemetry context of the `tracing` span Edge is currently in. Spans only carry one when the enterprise
+/// feature bridges them to OpenTelemetry, see [crate::prom_metrics::trace_context_layer]
+#[cfg(feature = "enterprise")]
+fn current_trace_context() -> Context {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    let context = tracing::Span::current().context();
+    if context.has_active_span() {
+        context
+    } else {
+        Context::current()
+    }
+}
+
+#[cfg(not(feature = "enterprise"))]
+fn current_trace_context() -> Context {
+    Context::current()
+}
+
+/// A second Unleash instance consulted for tokens the primary doesn't know, e.g. while migrating between instances
+#[derive(Debug)]
+struct SecondaryUpstream {
+    client: UnleashClient,
+    /// Tokens the secondary validated or served features for, with when the primary should be tried for them again.
+    /// Until then, these skip the primary when fetching features
+    tokens: DashMap<String, DateTime<Utc>>,
+    retry_primary_after: Duration,
+}
+
+impl SecondaryUpstream {
+    fn pin(&self, token: String) {
+        self.tokens
+            .insert(token, Utc::now() + self.retry_primary_after);
+    }
+
+    fn is_pinned(&self, token: &str) -> bool {
+        self.tokens
+            .remove_if(token, |_, until| *until <= Utc::now());
+        self.tokens.contains_key(token)
+    }
+}
+
+impl MetricsCompression {
+    fn content_encoding(&self) -> &'static str {
+        match self {
+            MetricsCompression::Gzip => "gzip",
+            MetricsCompression::Deflate => "deflate",
+        }
+    }
+}
+
+fn compress_json<T: Serialize>(
+    body: &T,
+    compression: MetricsCompression,
+) -> std::io::Result<Vec<u8>> {
+    let json = serde_json::to_vec(body)?;
+    match compression {
+        MetricsCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json)?;
+            encoder.finish()
+        }
+        MetricsCompression::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json)?;
+            encoder.finish()
+        }
+    }
 }
 
 fn load_pkcs12(id: &ClientIdentity) -> EdgeResult<Identity> {
@@ -161,46 +316,216 @@ fn build_identity(tls: Option<ClientIdentity>) -> EdgeResult<ClientBuilder> {
     )
 }
 
-pub fn new_reqwest_client(
-    skip_ssl_verification: bool,
-    client_identity: Option<ClientIdentity>,
-    upstream_certificate_file: Option<PathBuf>,
-    connect_timeout: Duration,
-    socket_timeout: Duration,
-    client_meta_information: ClientMetaInformation,
-) -> EdgeResult<Client> {
-    build_identity(client_identity)
-        .and_then(|builder| {
-            build_upstream_certificate(upstream_certificate_file).map(|cert| match cert {
-                Some(c) => builder.add_root_certificate(c),
-                None => builder,
+/// Everything needed to build the HTTP client used to talk to upstream
+#[derive(Clone, Debug)]
+pub struct HttpClientArgs {
+    pub skip_ssl_verification: bool,
+    pub client_identity: Option<ClientIdentity>,
+    pub upstream_certificate_file: Option<PathBuf>,
+    pub upstream_certificate_pins: Vec<CertificatePin>,
+    pub upstream_proxy_url: Option<String>,
+    pub upstream_proxy_username: Option<String>,
+    pub upstream_proxy_password: Option<String>,
+    pub connect_timeout: Duration,
+    pub socket_timeout: Duration,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Duration,
+    pub user_agent_suffix: Option<String>,
+    /// Offer HTTP/2 to upstream when negotiating TLS. Without it, Edge talks HTTP/1.1 to upstream
+    pub http2: bool,
+    /// Speak HTTP/2 from the first byte, without negotiating. Requires an upstream that accepts it
+    pub http2_prior_knowledge: bool,
+    pub client_meta_information: ClientMetaInformation,
+}
+
+impl Default for HttpClientArgs {
+    fn default() -> Self {
+        Self {
+            skip_ssl_verification: false,
+            client_identity: None,
+            upstream_certificate_file: None,
+            upstream_certificate_pins: vec![],
+            upstream_proxy_url: None,
+            upstream_proxy_username: None,
+            upstream_proxy_password: None,
+            connect_timeout: Duration::seconds(5),
+            socket_timeout: Duration::seconds(5),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: Duration::seconds(90),
+            user_agent_suffix: None,
+            http2: false,
+            http2_prior_knowledge: false,
+            client_meta_information: ClientMetaInformation::default(),
+        }
+    }
+}
+
+fn build_tls(args: &HttpClientArgs) -> EdgeResult<ClientBuilder> {
+    if args.upstream_certificate_pins.is_empty() {
+        build_identity(args.client_identity.clone())
+            .and_then(|builder| {
+                build_upstream_certificate(args.upstream_certificate_file.clone()).map(|cert| {
+                    match cert {
+                        Some(c) => builder.add_root_certificate(c),
+                        None => builder,
+                    }
+                })
             })
+            .map(|builder| builder.danger_accept_invalid_certs(args.skip_ssl_verification))
+    } else {
+        pinned_upstream_client_config(
+            args.upstream_certificate_pins.clone(),
+            args.skip_ssl_verification,
+            args.upstream_certificate_file.clone(),
+            args.client_identity.clone(),
+        )
+        .map(|mut config| {
+            if args.http2 {
+                config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            }
+            ClientBuilder::new().use_preconfigured_tls(config)
         })
-        .and_then(|client| {
-            let mut header_map = HeaderMap::new();
-            header_map.insert(
-                UNLEASH_APPNAME_HEADER,
-                header::HeaderValue::from_str(&client_meta_information.app_name)
-                    .expect("Could not add app name as a header"),
-            );
-            header_map.insert(
-                UNLEASH_INSTANCE_ID_HEADER,
-                header::HeaderValue::from_str(&client_meta_information.instance_id).unwrap(),
-            );
-            header_map.insert(
-                UNLEASH_CLIENT_SPEC_HEADER,
-                header::HeaderValue::from_static(unleash_yggdrasil::SUPPORTED_SPEC_VERSION),
-            );
+    }
+}
+
+/// Builds the proxy upstream requests go through. Hosts matched by NO_PROXY are still reached directly.
+/// An https proxy's certificate is verified with the same trust roots as upstream, so a proxy with a
+/// self-signed certificate can be trusted by passing its CA as the upstream certificate file.
+fn build_proxy(args: &HttpClientArgs) -> EdgeResult<Option<Proxy>> {
+    let Some(proxy_url) = &args.upstream_proxy_url else {
+        return Ok(None);
+    };
+    let invalid_proxy =
+        |reason: String| EdgeError::ClientBuildError(format!("Invalid upstream proxy: {reason}"));
+    let scheme = Url::parse(proxy_url)
+        .map_err(|e| invalid_proxy(format!("{e:?}")))?
+        .scheme()
+        .to_string();
+    if !matches!(scheme.as_str(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(invalid_proxy(format!(
+            "{scheme} is not supported, use http, https, socks5 or socks5h"
+        )));
+    }
+    let proxy = Proxy::all(proxy_url).map_err(|e| invalid_proxy(format!("{e:?}")))?;
+    let proxy = match &args.upstream_proxy_username {
+        Some(username) => proxy.basic_auth(
+            username,
+            args.upstream_proxy_password.as_deref().unwrap_or_default(),
+        ),
+        None => proxy,
+    };
+    Ok(Some(proxy.no_proxy(NoProxy::from_env())))
+}
+
+pub fn new_reqwest_client(args: HttpClientArgs) -> EdgeResult<Client> {
+    let proxy = build_proxy(&args)?;
+    build_tls(&args).and_then(|client| {
+        let client_meta_information = args.client_meta_information;
+        let mut header_map = HeaderMap::new();
+        header_map.insert(
+            UNLEASH_APPNAME_HEADER,
+            header::HeaderValue::from_str(&client_meta_information.app_name)
+                .expect("Could not add app name as a header"),
+        );
+        header_map.insert(
+            UNLEASH_INSTANCE_ID_HEADER,
+            header::HeaderValue::from_str(&client_meta_information.instance_id).unwrap(),
+        );
+        let client_spec_version = header::HeaderValue::from_str(
+            &client_meta_information.client_spec_version,
+        )
+        .map_err(|e| EdgeError::ClientBuildError(format!("Invalid client spec version: {e:?}")))?;
+        header_map.insert(UNLEASH_CLIENT_SPEC_HEADER, client_spec_version);
 
+        let client = match proxy {
+            Some(proxy) => client.proxy(proxy),
+            None => client,
+        };
+        let client = match args.pool_max_idle_per_host {
+            Some(max_idle) => client.pool_max_idle_per_host(max_idle),
+            None => client,
+        };
+        let client = if args.http2_prior_knowledge {
+            client.http2_prior_knowledge()
+        } else if args.http2 {
             client
-                .user_agent(format!("unleash-edge-{}", crate::types::build::PKG_VERSION))
-                .default_headers(header_map)
-                .danger_accept_invalid_certs(skip_ssl_verification)
-                .timeout(socket_timeout.to_std().unwrap())
-                .connect_timeout(connect_timeout.to_std().unwrap())
-                .build()
-                .map_err(|e| EdgeError::ClientBuildError(format!("{e:?}")))
-        })
+        } else {
+            client.http1_only()
+        };
+        let user_agent = format!("unleash-edge-{}", crate::types::build::PKG_VERSION);
+        let user_agent = match args.user_agent_suffix {
+            Some(suffix) => format!("{user_agent} {suffix}"),
+            None => user_agent,
+        };
+        client
+            .pool_idle_timeout(args.pool_idle_timeout.to_std().ok())
+            .user_agent(user_agent)
+            .default_headers(header_map)
+            .timeout(args.socket_timeout.to_std().unwrap())
+            .connect_timeout(args.connect_timeout.to_std().unwrap())
+            .build()
+            .map_err(|e| EdgeError::ClientBuildError(format!("{e:?}")))
+    })
+}
+
+/// How many seconds upstream's clock is ahead of `now`, going by the Date header of an upstream response
+fn upstream_clock_skew(headers: &HeaderMap, now: DateTime<Utc>) -> Option<i64> {
+    let date = headers.get(header::DATE)?.to_str().ok()?;
+    let upstream_now = DateTime::parse_from_rfc2822(date).ok()?;
+    Some(
+        upstream_now
+            .with_timezone(&Utc)
+            .signed_duration_since(now)
+            .num_seconds(),
+    )
+}
+
+fn clock_skew_warning(upstream: &str, skew: i64) -> String {
+    let direction = if skew > 0 { "ahead of" } else { "behind" };
+    format!(
+        "The clock of upstream {upstream} is {} seconds {direction} Edge's, metrics may end up in the wrong hourly bucket",
+        skew.abs()
+    )
+}
+
+/// A malformed ETag (e.g. mangled by a proxy) is logged and ignored, so the response is still used as a fresh update
+fn response_etag(response: &reqwest::Response) -> Option<EntityTag> {
+    let etag = response.headers().get(header::ETAG)?;
+    let parsed = etag
+        .to_str()
+        .ok()
+        .and_then(|etag| EntityTag::from_str(etag).ok());
+    if parsed.is_none() {
+        UPSTREAM_MALFORMED_ETAGS.inc();
+        warn!("Ignoring malformed ETag {etag:?} from upstream");
+    }
+    parsed
+}
+
+/// Parses an upstream response body. When lenient, the first complete JSON value is used and any
+/// trailing data (e.g. a duplicated body appended by a misbehaving proxy) is discarded.
+fn parse_upstream_json<T: DeserializeOwned>(
+    body: &[u8],
+    lenient: bool,
+) -> Result<T, serde_json::Error> {
+    if !lenient {
+        return serde_json::from_slice(body);
+    }
+    let mut values = serde_json::Deserializer::from_slice(body).into_iter::<T>();
+    match values.next() {
+        Some(value) => {
+            let value = value?;
+            let trailing = &body[values.byte_offset()..];
+            if trailing.iter().any(|b| !b.is_ascii_whitespace()) {
+                warn!(
+                    "Discarded {} bytes of trailing data after JSON in upstream response",
+                    trailing.len()
+                );
+            }
+            Ok(value)
+        }
+        None => serde_json::from_slice(body),
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -212,9 +537,17 @@ impl UnleashClient {
     pub fn from_url(server_url: Url, token_header: String, backing_client: Client) -> Self {
         Self {
             urls: UnleashUrls::from_base_url(server_url),
-            backing_client,
+            backing_client: Arc::new(RwLock::new(backing_client)),
             custom_headers: Default::default(),
             token_header,
+            lenient_json_parsing: false,
+            metrics_compression: None,
+            max_feature_payload_bytes: None,
+            token_validation_timeout: None,
+            upstream_info: Default::default(),
+            circuit_breaker: None,
+            secondary_upstream: None,
+            chaos: None,
         }
     }
 
@@ -224,20 +557,27 @@ impl UnleashClient {
         let instance_id = instance_id_opt.unwrap_or_else(|| Ulid::new().to_string());
         Ok(Self {
             urls: UnleashUrls::from_str(server_url)?,
-            backing_client: new_reqwest_client(
-                false,
-                None,
-                None,
-                Duration::seconds(5),
-                Duration::seconds(5),
-                ClientMetaInformation {
-                    instance_id,
-                    app_name: "test-client".into(),
-                },
-            )
-            .unwrap(),
+            backing_client: Arc::new(RwLock::new(
+                new_reqwest_client(HttpClientArgs {
+                    client_meta_information: ClientMetaInformation {
+                        instance_id,
+                        app_name: "test-client".into(),
+                        ..ClientMetaInformation::test_config()
+                    },
+                    ..Default::default()
+                })
+                .unwrap(),
+            )),
             custom_headers: Default::default(),
             token_header: "Authorization".to_string(),
+            lenient_json_parsing: false,
+            metrics_compression: None,
+            max_feature_payload_bytes: None,
+            token_validation_timeout: None,
+            upstream_info: Default::default(),
+            circuit_breaker: None,
+            secondary_upstream: None,
+            chaos: None,
         })
     }
 
@@ -246,23 +586,30 @@ impl UnleashClient {
 
         Ok(Self {
             urls: UnleashUrls::from_str(server_url)?,
-            backing_client: new_reqwest_client(
-                true,
-                None,
-                None,
-                Duration::seconds(5),
-                Duration::seconds(5),
-                ClientMetaInformation::test_config(),
-            )
-            .unwrap(),
+            backing_client: Arc::new(RwLock::new(
+                new_reqwest_client(HttpClientArgs {
+                    skip_ssl_verification: true,
+                    client_meta_information: ClientMetaInformation::test_config(),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )),
             custom_headers: Default::default(),
             token_header: "Authorization".to_string(),
+            lenient_json_parsing: false,
+            metrics_compression: None,
+            max_feature_payload_bytes: None,
+            token_validation_timeout: None,
+            upstream_info: Default::default(),
+            circuit_breaker: None,
+            secondary_upstream: None,
+            chaos: None,
         })
     }
 
     fn client_features_req(&self, req: ClientFeaturesRequest) -> RequestBuilder {
         let client_req = self
-            .backing_client
+            .backing_client()
             .get(self.urls.client_features_url.to_string())
             .headers(self.header_map(Some(req.api_key)));
         if let Some(tag) = req.etag {
@@ -274,7 +621,7 @@ impl UnleashClient {
 
     fn client_features_delta_req(&self, req: ClientFeaturesRequest) -> RequestBuilder {
         let client_req = self
-            .backing_client
+            .backing_client()
             .get(self.urls.client_features_delta_url.to_string())
             .headers(self.header_map(Some(req.api_key)));
         if let Some(tag) = req.etag {
@@ -294,6 +641,12 @@ impl UnleashClient {
             let key = HeaderName::from_str(header_name.as_str()).unwrap();
             header_map.insert(key, header_value.parse().unwrap());
         }
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(
+                &current_trace_context(),
+                &mut HeaderInjector(&mut header_map),
+            )
+        });
         header_map
     }
 
@@ -304,12 +657,204 @@ impl UnleashClient {
         }
     }
 
+    pub fn with_lenient_json_parsing(self, lenient_json_parsing: bool) -> Self {
+        Self {
+            lenient_json_parsing,
+            ..self
+        }
+    }
+
+    pub fn with_metrics_compression(self, metrics_compression: Option<MetricsCompression>) -> Self {
+        Self {
+            metrics_compression,
+            ..self
+        }
+    }
+
+    pub fn with_circuit_breaker(self, circuit_breaker: Option<CircuitBreaker>) -> Self {
+        Self {
+            circuit_breaker: circuit_breaker.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// Tokens the primary upstream denies or doesn't know are validated against and fetched from `secondary`.
+    /// Their features are fetched from the primary again once `retry_primary_after` has passed
+    pub fn with_secondary_upstream(
+        self,
+        secondary: Option<UnleashClient>,
+        retry_primary_after: Duration,
+    ) -> Self {
+        Self {
+            secondary_upstream: secondary.map(|client| {
+                Arc::new(SecondaryUpstream {
+                    client,
+                    tokens: DashMap::new(),
+                    retry_primary_after,
+                })
+            }),
+            ..self
+        }
+    }
+
+    /// Makes feature fetches slow or fail on purpose, see [Chaos]
+    pub fn with_chaos(self, chaos: Option<Chaos>) -> Self {
+        Self {
+            chaos: chaos.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// False while the circuit breaker is open, in which case feature fetches fail without contacting upstream
+    fn circuit_allows_request(&self) -> bool {
+        self.circuit_breaker
+            .as_ref()
+            .map_or(true, |breaker| breaker.try_acquire())
+    }
+
+    /// Only connection failures and 5xx responses count against upstream, other errors are specific to a token
+    fn record_circuit_outcome(&self, upstream_failed: bool) {
+        if let Some(breaker) = self.circuit_breaker.as_ref() {
+            if upstream_failed {
+                breaker.record_failure();
+            } else {
+                breaker.record_success();
+            }
+        }
+    }
+
+    pub fn with_max_feature_payload_bytes(self, max_feature_payload_bytes: Option<usize>) -> Self {
+        Self {
+            max_feature_payload_bytes,
+            ..self
+        }
+    }
+
+    /// Overrides the socket timeout for token validation requests only, so new tokens fail fast when upstream is slow
+    pub fn with_token_validation_timeout(
+        self,
+        token_validation_timeout: Option<std::time::Duration>,
+    ) -> Self {
+        Self {
+            token_validation_timeout,
+            ..self
+        }
+    }
+
+    /// Reads a features response body, giving up as soon as it grows past `--max-feature-payload-bytes`
+    async fn read_features_body(&self, mut response: reqwest::Response) -> EdgeResult<Vec<u8>> {
+        let read_error = |e: reqwest::Error| {
+            warn!("Could not read features response body");
+            EdgeError::ClientFeaturesParseError(e.to_string())
+        };
+        let Some(limit) = self.max_feature_payload_bytes else {
+            return response
+                .bytes()
+                .await
+                .map(|body| body.to_vec())
+                .map_err(read_error);
+        };
+        let too_large = || {
+            CLIENT_FEATURE_FETCH_FAILURES
+                .with_label_values(&["payload_too_large"])
+                .inc();
+            warn!("Upstream features response exceeded the limit of {limit} bytes");
+            EdgeError::ClientFeaturesFetchError(FeatureError::PayloadTooLarge(limit))
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            return Err(too_large());
+        }
+        let mut body = vec![];
+        while let Some(chunk) = response.chunk().await.map_err(read_error)? {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    fn backing_client(&self) -> Client {
+        self.backing_client
+            .read()
+            .map(|client| client.clone())
+            .unwrap_or_else(|e| e.into_inner().clone())
+    }
+
+    /// Swaps the client used for upstream, and for the secondary upstream if there is one, e.g. after the client
+    /// certificate was rotated. Requests already in flight finish on the previous client
+    pub fn replace_backing_client(&self, client: Client) {
+        if let Some(secondary) = &self.secondary_upstream {
+            secondary.client.replace_backing_client(client.clone());
+        }
+        *self
+            .backing_client
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = client;
+    }
+
+    pub fn upstream_info(&self) -> UpstreamInfo {
+        self.upstream_info
+            .read()
+            .map(|info| info.clone())
+            .unwrap_or_default()
+    }
+
+    fn record_upstream_contact(&self, headers: &HeaderMap) {
+        let now = Utc::now();
+        let clock_skew = upstream_clock_skew(headers, now);
+        if let Some(skew) = clock_skew {
+            // A secondary upstream has its own client, so this only looks at this upstream's previous skew
+            let previously_skewed = self
+                .upstream_info()
+                .clock_skew_seconds
+                .is_some_and(|skew| skew.abs() > CLOCK_SKEW_WARN_THRESHOLD_SECONDS);
+            let upstream = self.urls.base_url.origin().ascii_serialization();
+            if skew.abs() > CLOCK_SKEW_WARN_THRESHOLD_SECONDS && !previously_skewed {
+                warn!("{}", clock_skew_warning(&upstream, skew));
+            }
+            UPSTREAM_CLOCK_SKEW_SECONDS
+                .with_label_values(&[&upstream])
+                .set(skew);
+        }
+        if let Ok(mut info) = self.upstream_info.write() {
+            info.last_successful_contact = Some(now);
+            if clock_skew.is_some() {
+                info.clock_skew_seconds = clock_skew;
+            }
+        }
+    }
+
+    fn detect_upstream(&self, headers: &HeaderMap) {
+        let (kind, version) = match headers.get(EDGE_VERSION_HEADER) {
+            Some(version) => (
+                UpstreamKind::Edge,
+                version.to_str().ok().map(|version| version.to_string()),
+            ),
+            None => (UpstreamKind::Unleash, None),
+        };
+        if let Ok(mut info) = self.upstream_info.write() {
+            if info.kind != Some(kind) || info.version != version {
+                UPSTREAM_VERSION.reset();
+                UPSTREAM_VERSION
+                    .with_label_values(&[kind.as_str(), version.as_deref().unwrap_or("unknown")])
+                    .set(1);
+            }
+            info.kind = Some(kind);
+            info.version = version;
+            info.last_successful_contact = Some(Utc::now());
+        }
+    }
+
     pub async fn register_as_client(
         &self,
         api_key: String,
         application: ClientApplication,
     ) -> EdgeResult<()> {
-        self.backing_client
+        self.backing_client()
             .post(self.urls.client_register_app_url.to_string())
             .headers(self.header_map(Some(api_key)))
             .json(&application)
@@ -320,7 +865,9 @@ impl UnleashClient {
                 EdgeError::ClientRegisterError
             })
             .map(|r| {
-                if !r.status().is_success() {
+                if r.status().is_success() {
+                    self.detect_upstream(r.headers());
+                } else {
                     CLIENT_REGISTER_FAILURES
                         .with_label_values(&[r.status().as_str()])
                         .inc();
@@ -336,6 +883,45 @@ impl UnleashClient {
         &self,
         request: ClientFeaturesRequest,
     ) -> EdgeResult<ClientFeaturesResponse> {
+        if let Some(chaos) = self.chaos.as_ref() {
+            chaos.inject().await?;
+        }
+        let Some(secondary) = self.secondary_upstream.as_ref() else {
+            return self.fetch_client_features(request).await;
+        };
+        if secondary.is_pinned(&request.api_key) {
+            return secondary.client.fetch_client_features(request).await;
+        }
+        match self.fetch_client_features(request.clone()).await {
+            Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::AccessDenied | FeatureError::NotFound,
+            )) => {
+                let result = secondary
+                    .client
+                    .fetch_client_features(request.clone())
+                    .await;
+                if result.is_ok() {
+                    info!(
+                        "Primary upstream didn't serve features for a token, fetching them from the secondary upstream for the next {} seconds",
+                        secondary.retry_primary_after.num_seconds()
+                    );
+                    secondary.pin(request.api_key);
+                }
+                result
+            }
+            result => result,
+        }
+    }
+
+    async fn fetch_client_features(
+        &self,
+        request: ClientFeaturesRequest,
+    ) -> EdgeResult<ClientFeaturesResponse> {
+        if !self.circuit_allows_request() {
+            return Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::CircuitOpen,
+            ));
+        }
         let start_time = Utc::now();
         let response = self
             .client_features_req(request.clone())
@@ -343,11 +929,13 @@ impl UnleashClient {
             .await
             .map_err(|e| {
                 warn!("Failed to fetch. Due to [{e:?}] - Will retry");
+                self.record_circuit_outcome(true);
                 match e.status() {
                     Some(s) => EdgeError::ClientFeaturesFetchError(FeatureError::Retriable(s)),
-                    None => EdgeError::ClientFeaturesFetchError(FeatureError::NotFound),
+                    None => EdgeError::ClientFeaturesFetchError(FeatureError::Unreachable),
                 }
             })?;
+        self.record_circuit_outcome(response.status().is_server_error());
         let stop_time = Utc::now();
         CLIENT_FEATURE_FETCH
             .with_label_values(&[&response.status().as_u16().to_string()])
@@ -356,20 +944,21 @@ impl UnleashClient {
                     .signed_duration_since(start_time)
                     .num_milliseconds() as f64,
             );
+        if response.status() == StatusCode::NOT_MODIFIED || response.status().is_success() {
+            self.record_upstream_contact(response.headers());
+        }
         if response.status() == StatusCode::NOT_MODIFIED {
             Ok(ClientFeaturesResponse::NoUpdate(
                 request.etag.expect("Got NOT_MODIFIED without an ETag"),
             ))
         } else if response.status().is_success() {
-            let etag = response
-                .headers()
-                .get("ETag")
-                .or_else(|| response.headers().get("etag"))
-                .and_then(|etag| EntityTag::from_str(etag.to_str().unwrap()).ok());
-            let features = response.json::<ClientFeatures>().await.map_err(|e| {
-                warn!("Could not parse features response to internal representation");
-                EdgeError::ClientFeaturesParseError(e.to_string())
-            })?;
+            let etag = response_etag(&response);
+            let body = self.read_features_body(response).await?;
+            let features = parse_upstream_json::<ClientFeatures>(&body, self.lenient_json_parsing)
+                .map_err(|e| {
+                    warn!("Could not parse features response to internal representation");
+                    EdgeError::ClientFeaturesParseError(e.to_string())
+                })?;
             Ok(ClientFeaturesResponse::Updated(features, etag))
         } else if response.status() == StatusCode::FORBIDDEN {
             CLIENT_FEATURE_FETCH_FAILURES
@@ -413,6 +1002,11 @@ impl UnleashClient {
         &self,
         request: ClientFeaturesRequest,
     ) -> EdgeResult<ClientFeaturesDeltaResponse> {
+        if !self.circuit_allows_request() {
+            return Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::CircuitOpen,
+            ));
+        }
         let start_time = Utc::now();
         let response = self
             .client_features_delta_req(request.clone())
@@ -420,11 +1014,13 @@ impl UnleashClient {
             .await
             .map_err(|e| {
                 warn!("Failed to fetch. Due to [{e:?}] - Will retry");
+                self.record_circuit_outcome(true);
                 match e.status() {
                     Some(s) => EdgeError::ClientFeaturesFetchError(FeatureError::Retriable(s)),
-                    None => EdgeError::ClientFeaturesFetchError(FeatureError::NotFound),
+                    None => EdgeError::ClientFeaturesFetchError(FeatureError::Unreachable),
                 }
             })?;
+        self.record_circuit_outcome(response.status().is_server_error());
         let stop_time = Utc::now();
         CLIENT_FEATURE_DELTA_FETCH
             .with_label_values(&[&response.status().as_u16().to_string()])
@@ -433,20 +1029,22 @@ impl UnleashClient {
                     .signed_duration_since(start_time)
                     .num_milliseconds() as f64,
             );
+        if response.status() == StatusCode::NOT_MODIFIED || response.status().is_success() {
+            self.record_upstream_contact(response.headers());
+        }
         if response.status() == StatusCode::NOT_MODIFIED {
             Ok(ClientFeaturesDeltaResponse::NoUpdate(
                 request.etag.expect("Got NOT_MODIFIED without an ETag"),
             ))
         } else if response.status().is_success() {
-            let etag = response
-                .headers()
-                .get("ETag")
-                .or_else(|| response.headers().get("etag"))
-                .and_then(|etag| EntityTag::from_str(etag.to_str().unwrap()).ok());
-            let features = response.json::<ClientFeaturesDelta>().await.map_err(|e| {
-                warn!("Could not parse features response to internal representation");
-                EdgeError::ClientFeaturesParseError(e.to_string())
-            })?;
+            let etag = response_etag(&response);
+            let body = self.read_features_body(response).await?;
+            let features =
+                parse_upstream_json::<ClientFeaturesDelta>(&body, self.lenient_json_parsing)
+                    .map_err(|e| {
+                        warn!("Could not parse features response to internal representation");
+                        EdgeError::ClientFeaturesParseError(e.to_string())
+                    })?;
             Ok(ClientFeaturesDeltaResponse::Updated(features, etag))
         } else if response.status() == StatusCode::FORBIDDEN {
             CLIENT_FEATURE_FETCH_FAILURES
@@ -489,7 +1087,7 @@ impl UnleashClient {
     pub async fn send_batch_metrics(&self, request: MetricsBatch) -> EdgeResult<()> {
         trace!("Sending metrics to old /edge/metrics endpoint");
         let result = self
-            .backing_client
+            .backing_client()
             .post(self.urls.edge_metrics_url.to_string())
             .headers(self.header_map(None))
             .json(&request)
@@ -518,17 +1116,27 @@ impl UnleashClient {
         token: &str,
     ) -> EdgeResult<()> {
         trace!("Sending metrics to bulk endpoint");
-        let result = self
-            .backing_client
+        let request_builder = self
+            .backing_client()
             .post(self.urls.client_bulk_metrics_url.to_string())
-            .headers(self.header_map(Some(token.to_string())))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| {
-                info!("Failed to send metrics to /api/client/metrics/bulk endpoint {e:?}");
-                EdgeError::EdgeMetricsError
-            })?;
+            .headers(self.header_map(Some(token.to_string())));
+        let request_builder = match self.metrics_compression {
+            Some(compression) => {
+                let body = compress_json(&request, compression).map_err(|e| {
+                    info!("Failed to compress metrics {e:?}");
+                    EdgeError::EdgeMetricsError
+                })?;
+                request_builder
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::CONTENT_ENCODING, compression.content_encoding())
+                    .body(body)
+            }
+            None => request_builder.json(&request),
+        };
+        let result = request_builder.send().await.map_err(|e| {
+            info!("Failed to send metrics to /api/client/metrics/bulk endpoint {e:?}");
+            EdgeError::EdgeMetricsError
+        })?;
         if result.status().is_success() {
             Ok(())
         } else {
@@ -542,9 +1150,49 @@ impl UnleashClient {
         }
     }
 
+    /// Tokens the primary upstream doesn't consider valid are passed on to the secondary upstream, if configured.
+    /// The secondary failing leaves those tokens invalid, it doesn't fail validation of the primary's tokens
     pub async fn validate_tokens(
         &self,
         request: ValidateTokensRequest,
+    ) -> EdgeResult<Vec<EdgeToken>> {
+        let mut validated = self.validate_tokens_upstream(request.clone()).await?;
+        let Some(secondary) = self.secondary_upstream.as_ref() else {
+            return Ok(validated);
+        };
+        let unknown: Vec<String> = request
+            .tokens
+            .into_iter()
+            .filter(|token| {
+                let known_by_primary = validated.iter().any(|v| &v.token == token);
+                if known_by_primary {
+                    secondary.tokens.remove(token);
+                }
+                !known_by_primary
+            })
+            .collect();
+        if unknown.is_empty() {
+            return Ok(validated);
+        }
+        match secondary
+            .client
+            .validate_tokens_upstream(ValidateTokensRequest { tokens: unknown })
+            .await
+        {
+            Ok(validated_by_secondary) => {
+                for token in validated_by_secondary.iter() {
+                    secondary.pin(token.token.clone());
+                }
+                validated.extend(validated_by_secondary);
+            }
+            Err(e) => warn!("Could not validate tokens against the secondary upstream: {e:?}"),
+        }
+        Ok(validated)
+    }
+
+    async fn validate_tokens_upstream(
+        &self,
+        request: ValidateTokensRequest,
     ) -> EdgeResult<Vec<EdgeToken>> {
         let check_api_suffix = || {
             let base_url = self.urls.base_url.to_string();
@@ -553,17 +1201,19 @@ impl UnleashClient {
             }
         };
 
-        let result = self
-            .backing_client
+        let validate_request = self
+            .backing_client()
             .post(self.urls.edge_validate_url.to_string())
             .headers(self.header_map(None))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| {
-                info!("Failed to validate tokens: [{e:?}]");
-                EdgeError::EdgeTokenError
-            })?;
+            .json(&request);
+        let validate_request = match self.token_validation_timeout {
+            Some(timeout) => validate_request.timeout(timeout),
+            None => validate_request,
+        };
+        let result = validate_request.send().await.map_err(|e| {
+            info!("Failed to validate tokens: [{e:?}]");
+            EdgeError::EdgeTokenError
+        })?;
         match result.status() {
             StatusCode::OK => {
                 let token_response = result.json::<EdgeTokens>().await.map_err(|e| {
@@ -606,8 +1256,11 @@ impl UnleashClient {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
     use std::path::PathBuf;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     use actix_http::{body::MessageBody, HttpService, TlsAcceptorConfig};
     use actix_http_test::{test_server, TestServer};
@@ -615,15 +1268,29 @@ mod tests {
     use actix_service::map_config;
     use actix_web::{
         dev::{AppConfig, ServiceRequest, ServiceResponse},
-        http::header::EntityTag,
-        web, App, HttpResponse,
+        http::header::{self, EntityTag},
+        web, App, HttpRequest, HttpResponse,
+    };
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use chrono::{DateTime, TimeZone, Utc};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
     };
-    use chrono::Duration;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use test_case::test_case;
     use unleash_types::client_features::{ClientFeature, ClientFeatures};
-    use crate::cli::ClientIdentity;
-    use crate::http::unleash_client::new_reqwest_client;
+    use unleash_types::client_metrics::{ClientMetricsEnv, MetricsMetadata};
+    use crate::cli::{string_to_certificate_pin, ClientIdentity, MetricsCompression};
+    use crate::error::{EdgeError, FeatureError};
+    use crate::http::circuit_breaker::{CircuitBreaker, CircuitState};
+    use crate::http::headers::UNLEASH_CLIENT_SPEC_HEADER;
+    use crate::http::unleash_client::{new_reqwest_client, HttpClientArgs};
+    use crate::metrics::client_metrics::MetricsBatch;
     use crate::{
-        cli::TlsOptions,
+        cli::{TlsOptions, TlsVersion},
         middleware::as_async_middleware::as_async_middleware,
         tls,
         types::{
@@ -632,7 +1299,10 @@ mod tests {
         },
     };
 
-    use super::{EdgeTokens, UnleashClient, ClientMetaInformation};
+    use super::{
+        clock_skew_warning, upstream_clock_skew, ClientMetaInformation, EdgeTokens, UnleashClient,
+        UPSTREAM_CLOCK_SKEW_SECONDS, UPSTREAM_MALFORMED_ETAGS,
+    };
 
     impl ClientFeaturesRequest {
         pub(crate) fn new(api_key: String, etag: Option<String>) -> Self {
@@ -678,37 +1348,321 @@ mod tests {
         })
     }
 
-    async fn test_features_server() -> TestServer {
-        test_server(move || {
+    #[actix_web::test]
+    async fn clock_skew_is_measured_from_the_upstream_date_header() {
+        let srv = test_server(move || {
             HttpService::new(map_config(
-                App::new()
-                    .wrap(Etag)
-                    .service(
-                        web::resource("/api/client/features")
-                            .route(web::get().to(return_client_features)),
-                    )
-                    .service(
-                        web::resource("/edge/validate")
-                            .route(web::post().to(return_validate_tokens)),
-                    )
-                    .service(
-                        web::resource("/api/edge/validate")
-                            .route(web::post().to(HttpResponse::Forbidden)),
-                    ),
+                App::new().service(web::resource("/api/client/features").route(web::get().to(
+                    || async {
+                        let an_hour_ahead = Utc::now() + chrono::Duration::hours(1);
+                        HttpResponse::Ok()
+                            .insert_header((header::DATE, an_hour_ahead.to_rfc2822()))
+                            .json(two_client_features())
+                    },
+                ))),
                 |_| AppConfig::default(),
             ))
             .tcp()
         })
-        .await
+        .await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None).unwrap();
+        assert_eq!(client.upstream_info().clock_skew_seconds, None);
+
+        client
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.into(), None))
+            .await
+            .unwrap();
+
+        let skew = client.upstream_info().clock_skew_seconds.unwrap();
+        assert!((3595..=3600).contains(&skew), "Measured {skew} seconds");
     }
 
-    async fn test_features_server_with_untrusted_ssl() -> TestServer {
-        test_server(move || {
-            let tls_options = TlsOptions {
-                tls_server_cert: Some("../examples/server.crt".into()),
-                tls_enable: true,
-                tls_server_key: Some("../examples/server.key".into()),
+    #[test]
+    fn clock_skew_is_parsed_from_http_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::DATE,
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+        );
+        let edge_now = Utc.with_ymd_and_hms(1994, 11, 6, 8, 50, 7).unwrap();
+        assert_eq!(upstream_clock_skew(&headers, edge_now), Some(-30));
+        assert_eq!(upstream_clock_skew(&HeaderMap::new(), edge_now), None);
+    }
+
+    #[test_case(90, "is 90 seconds ahead of Edge's"; "upstream ahead")]
+    #[test_case(-90, "is 90 seconds behind Edge's"; "upstream behind")]
+    fn clock_skew_warning_says_which_clock_is_ahead(skew: i64, expected: &str) {
+        let warning = clock_skew_warning("http://localhost:4242", skew);
+        assert!(warning.contains(expected), "{warning}");
+    }
+
+    #[test]
+    fn clock_skew_is_tracked_per_upstream() {
+        let date_header = |skew: i64| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                reqwest::header::DATE,
+                (Utc::now() + chrono::Duration::seconds(skew))
+                    .to_rfc2822()
+                    .parse()
+                    .unwrap(),
+            );
+            headers
+        };
+        let primary = UnleashClient::new("http://primary.clock-skew.test:4242", None).unwrap();
+        let secondary = UnleashClient::new("http://secondary.clock-skew.test:4242", None).unwrap();
+
+        primary.record_upstream_contact(&date_header(3600));
+        secondary.record_upstream_contact(&date_header(-3600));
+
+        let skew_of = |upstream: &str| {
+            UPSTREAM_CLOCK_SKEW_SECONDS
+                .with_label_values(&[upstream])
+                .get()
+        };
+        assert!((3595..=3600).contains(&skew_of("http://primary.clock-skew.test:4242")));
+        assert!((-3600..=-3595).contains(&skew_of("http://secondary.clock-skew.test:4242")));
+    }
+
+    #[actix_web::test]
+    async fn token_validation_gives_up_after_the_token_validation_timeout() {
+        let srv = test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(web::resource("/edge/validate").route(web::post().to(
+                    || async {
+                        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+                        return_validate_tokens().await
+                    },
+                ))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None)
+            .unwrap()
+            .with_token_validation_timeout(Some(std::time::Duration::from_millis(200)));
+
+        let started = std::time::Instant::now();
+        let result = client
+            .validate_tokens(ValidateTokensRequest {
+                tokens: vec![TEST_TOKEN.to_string()],
+            })
+            .await;
+
+        assert!(matches!(result, Err(EdgeError::EdgeTokenError)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    async fn test_features_server() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new()
+                    .wrap(Etag)
+                    .service(
+                        web::resource("/api/client/features")
+                            .route(web::get().to(return_client_features)),
+                    )
+                    .service(
+                        web::resource("/edge/validate")
+                            .route(web::post().to(return_validate_tokens)),
+                    )
+                    .service(
+                        web::resource("/api/edge/validate")
+                            .route(web::post().to(HttpResponse::Forbidden)),
+                    ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    async fn return_peer_port(req: actix_web::HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(req.peer_addr().unwrap().port().to_string())
+    }
+
+    async fn peer_ports_of_consecutive_requests(
+        pool_max_idle_per_host: Option<usize>,
+    ) -> (String, String) {
+        let srv = test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(web::resource("/port").route(web::get().to(return_peer_port))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client = new_reqwest_client(HttpClientArgs {
+            pool_max_idle_per_host,
+            pool_idle_timeout: chrono::Duration::seconds(30),
+            client_meta_information: ClientMetaInformation::test_config(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut ports = vec![];
+        for _ in 0..2 {
+            let port = client
+                .get(srv.url("/port"))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            ports.push(port);
+        }
+        (ports.remove(0), ports.remove(0))
+    }
+
+    #[actix_web::test]
+    async fn pool_max_idle_per_host_controls_connection_reuse() {
+        let (first, second) = peer_ports_of_consecutive_requests(None).await;
+        assert_eq!(first, second);
+        let (first, second) = peer_ports_of_consecutive_requests(Some(0)).await;
+        assert_ne!(first, second);
+    }
+
+    async fn return_client_features_with_trailing_data() -> HttpResponse {
+        let body = serde_json::to_string(&two_client_features()).unwrap();
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(format!("{body}\n{body}"))
+    }
+
+    async fn test_features_server_with_trailing_data() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(
+                    web::resource("/api/client/features")
+                        .route(web::get().to(return_client_features_with_trailing_data)),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    async fn return_client_features_with_malformed_etag() -> HttpResponse {
+        HttpResponse::Ok()
+            .insert_header((header::ETAG, "not-a-quoted-etag"))
+            .json(two_client_features())
+    }
+
+    async fn test_features_server_with_malformed_etag() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(
+                    web::resource("/api/client/features")
+                        .route(web::get().to(return_client_features_with_malformed_etag)),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    async fn return_streamed_client_features() -> HttpResponse {
+        let body = serde_json::to_vec(&two_client_features()).unwrap();
+        let chunks: Vec<Result<actix_web::web::Bytes, actix_web::Error>> = body
+            .chunks(16)
+            .map(|chunk| Ok(actix_web::web::Bytes::copy_from_slice(chunk)))
+            .collect();
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .streaming(futures::stream::iter(chunks))
+    }
+
+    async fn test_features_server_with_payload_size(streamed: bool) -> TestServer {
+        test_server(move || {
+            let handler = if streamed {
+                web::get().to(return_streamed_client_features)
+            } else {
+                web::get().to(return_client_features)
+            };
+            HttpService::new(map_config(
+                App::new().service(web::resource("/api/client/features").route(handler)),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    fn metrics_batch() -> MetricsBatch {
+        MetricsBatch {
+            applications: vec![],
+            metrics: vec![ClientMetricsEnv {
+                feature_name: "some-feature".into(),
+                app_name: "some-app".into(),
+                environment: "development".into(),
+                timestamp: DateTime::parse_from_rfc3339("1867-11-07T12:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc),
+                yes: 5,
+                no: 2,
+                variants: HashMap::new(),
+                metadata: MetricsMetadata {
+                    platform_name: None,
+                    platform_version: None,
+                    sdk_version: None,
+                    yggdrasil_version: None,
+                },
+            }],
+        }
+    }
+
+    /// Decompresses the posted body by hand and only accepts it if it matches [`metrics_batch`]
+    async fn receive_compressed_metrics(req: HttpRequest, body: web::Payload) -> HttpResponse {
+        let compressed = body.to_bytes().await.unwrap();
+        let mut json = String::new();
+        match req
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .and_then(|h| h.to_str().ok())
+        {
+            Some("gzip") => GzDecoder::new(compressed.as_ref())
+                .read_to_string(&mut json)
+                .unwrap(),
+            Some("deflate") => ZlibDecoder::new(compressed.as_ref())
+                .read_to_string(&mut json)
+                .unwrap(),
+            _ => return HttpResponse::UnsupportedMediaType().finish(),
+        };
+        let received: MetricsBatch = serde_json::from_str(&json).unwrap();
+        if serde_json::to_value(received).unwrap() == serde_json::to_value(metrics_batch()).unwrap()
+        {
+            HttpResponse::Accepted().finish()
+        } else {
+            HttpResponse::BadRequest().finish()
+        }
+    }
+
+    async fn test_compressed_metrics_server() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(
+                    web::resource("/api/client/metrics/bulk")
+                        .route(web::post().to(receive_compressed_metrics)),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    async fn test_features_server_with_untrusted_ssl() -> TestServer {
+        test_server(move || {
+            let tls_options = TlsOptions {
+                tls_server_cert: Some("../examples/server.crt".into()),
+                tls_enable: true,
+                tls_server_key: Some("../examples/server.key".into()),
                 tls_server_port: 443,
+                tls_min_version: TlsVersion::V1_2,
+                tls_cipher_suites: vec![],
             };
             let server_config = tls::config(tls_options).unwrap();
             let tls_acceptor_config =
@@ -798,6 +1752,67 @@ mod tests {
         }
     }
 
+    #[actix_web::test]
+    async fn an_unreachable_primary_upstream_does_not_fail_over_to_the_secondary() {
+        let secondary = test_features_server().await;
+        let client = UnleashClient::new("http://127.0.0.1:1", None)
+            .unwrap()
+            .with_secondary_upstream(
+                Some(UnleashClient::new(secondary.url("/").as_str(), None).unwrap()),
+                chrono::Duration::minutes(5),
+            );
+
+        let result = client
+            .get_client_features(ClientFeaturesRequest::new("somekey".to_string(), None))
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::Unreachable
+            ))
+        ));
+        assert!(!client
+            .secondary_upstream
+            .as_ref()
+            .unwrap()
+            .is_pinned("somekey"));
+    }
+
+    #[test_case(chrono::Duration::minutes(5), true; "until the retry period has passed")]
+    #[test_case(chrono::Duration::zero(), false; "and the primary is retried afterwards")]
+    #[actix_web::test]
+    async fn tokens_the_primary_upstream_does_not_know_are_fetched_from_the_secondary(
+        retry_primary_after: chrono::Duration,
+        pinned: bool,
+    ) {
+        let primary = test_server(move || {
+            HttpService::new(map_config(App::new(), |_| AppConfig::default())).tcp()
+        })
+        .await;
+        let secondary = test_features_server().await;
+        let client = UnleashClient::new(primary.url("/").as_str(), None)
+            .unwrap()
+            .with_secondary_upstream(
+                Some(UnleashClient::new(secondary.url("/").as_str(), None).unwrap()),
+                retry_primary_after,
+            );
+
+        let result = client
+            .get_client_features(ClientFeaturesRequest::new("somekey".to_string(), None))
+            .await;
+
+        assert!(matches!(result, Ok(ClientFeaturesResponse::Updated(_, _))));
+        assert_eq!(
+            client
+                .secondary_upstream
+                .as_ref()
+                .unwrap()
+                .is_pinned("somekey"),
+            pinned
+        );
+    }
+
     #[actix_web::test]
     async fn client_handles_304() {
         let srv = test_features_server().await;
@@ -853,6 +1868,56 @@ mod tests {
         assert!(optimal_304_tag.is_ok());
     }
 
+    #[test]
+    pub fn requests_made_within_a_span_carry_a_traceparent_header() {
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+        );
+        let client = UnleashClient::new("http://localhost:4242", None).unwrap();
+        assert!(client.header_map(None).get("traceparent").is_none());
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let _attached = opentelemetry::Context::current()
+            .with_remote_span_context(span_context)
+            .attach();
+
+        assert_eq!(
+            client.header_map(None).get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+    }
+
+    #[cfg(feature = "enterprise")]
+    #[test]
+    pub fn requests_made_within_a_tracing_span_carry_its_trace_context() {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        use tracing_subscriber::layer::SubscriberExt;
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+        );
+        let client = UnleashClient::new("http://localhost:4242", None).unwrap();
+        let subscriber = tracing_subscriber::Registry::default()
+            .with(crate::prom_metrics::trace_context_layer());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("fetch_features");
+            let _entered = span.enter();
+            let trace_id = span.context().span().span_context().trace_id();
+            let span_id = span.context().span().span_context().span_id();
+
+            assert_eq!(
+                client.header_map(None).get("traceparent").unwrap(),
+                format!("00-{trace_id}-{span_id}-01").as_str()
+            );
+        });
+    }
+
     #[actix_web::test]
     pub async fn custom_client_headers_are_sent_along() {
         let custom_headers = vec![("X-Api-Key".to_string(), "MyMagicKey".to_string())];
@@ -877,6 +1942,225 @@ mod tests {
         assert!(authed_res.is_ok());
     }
 
+    #[actix_web::test]
+    pub async fn lenient_json_parsing_discards_trailing_data_after_features() {
+        let srv = test_features_server_with_trailing_data().await;
+        let strict_client = UnleashClient::new(srv.url("/").as_str(), None).unwrap();
+        let lenient_client = strict_client.clone().with_lenient_json_parsing(true);
+        let strict_res = strict_client
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None))
+            .await;
+        assert!(strict_res.is_err());
+        let lenient_res = lenient_client
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None))
+            .await;
+        match lenient_res {
+            Ok(ClientFeaturesResponse::Updated(features, _)) => {
+                assert_eq!(features, two_client_features());
+            }
+            _ => panic!("Expected lenient parsing to yield the first set of features"),
+        }
+    }
+
+    #[actix_web::test]
+    pub async fn malformed_etags_are_ignored_and_counted() {
+        let srv = test_features_server_with_malformed_etag().await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None).unwrap();
+        let malformed_before = UPSTREAM_MALFORMED_ETAGS.get();
+        let res = client
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None))
+            .await;
+        match res {
+            Ok(ClientFeaturesResponse::Updated(features, etag)) => {
+                assert_eq!(features, two_client_features());
+                assert!(etag.is_none());
+            }
+            _ => panic!("Expected a malformed ETag to still yield updated features"),
+        }
+        assert!(UPSTREAM_MALFORMED_ETAGS.get() > malformed_before);
+    }
+
+    #[actix_web::test]
+    pub async fn circuit_breaker_opens_on_failures_and_closes_after_a_successful_probe() {
+        let healthy = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let (server_healthy, server_hits) = (healthy.clone(), hits.clone());
+        let srv = test_server(move || {
+            let healthy = server_healthy.clone();
+            let hits = server_hits.clone();
+            HttpService::new(map_config(
+                App::new().service(web::resource("/api/client/features").route(web::get().to(
+                    move || {
+                        let healthy = healthy.clone();
+                        let hits = hits.clone();
+                        async move {
+                            hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            if healthy.load(std::sync::atomic::Ordering::SeqCst) {
+                                return_client_features().await
+                            } else {
+                                HttpResponse::ServiceUnavailable().finish()
+                            }
+                        }
+                    },
+                ))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None)
+            .unwrap()
+            .with_circuit_breaker(Some(CircuitBreaker::new(
+                2,
+                std::time::Duration::from_secs(60),
+                std::time::Duration::from_millis(200),
+            )));
+        let fetch =
+            || client.get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None));
+        let breaker_state = || client.circuit_breaker.as_ref().unwrap().state();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                fetch().await,
+                Err(EdgeError::ClientFeaturesFetchError(
+                    FeatureError::Retriable(_)
+                ))
+            ));
+        }
+        assert_eq!(breaker_state(), CircuitState::Open);
+        assert!(matches!(
+            fetch().await,
+            Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::CircuitOpen
+            ))
+        ));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        healthy.store(true, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        assert!(matches!(
+            fetch().await,
+            Ok(ClientFeaturesResponse::Updated(_, _))
+        ));
+        assert_eq!(breaker_state(), CircuitState::Closed);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    pub async fn circuit_breaker_lets_another_probe_through_when_a_probe_is_dropped() {
+        let hanging = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let server_hanging = hanging.clone();
+        let srv = test_server(move || {
+            let hanging = server_hanging.clone();
+            HttpService::new(map_config(
+                App::new().service(web::resource("/api/client/features").route(web::get().to(
+                    move || {
+                        let hanging = hanging.clone();
+                        async move {
+                            if hanging.load(std::sync::atomic::Ordering::SeqCst) {
+                                tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+                            }
+                            return_client_features().await
+                        }
+                    },
+                ))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None)
+            .unwrap()
+            .with_circuit_breaker(Some(CircuitBreaker::new(
+                1,
+                std::time::Duration::from_secs(60),
+                std::time::Duration::from_millis(100),
+            )));
+        let fetch =
+            || client.get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None));
+        let breaker = client.circuit_breaker.as_ref().unwrap();
+        breaker.record_failure();
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+
+        // The probe is dropped before upstream answers, like a handler whose client disconnected
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), fetch())
+                .await
+                .is_err()
+        );
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(matches!(
+            fetch().await,
+            Err(EdgeError::ClientFeaturesFetchError(
+                FeatureError::CircuitOpen
+            ))
+        ));
+
+        hanging.store(false, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        assert!(matches!(
+            fetch().await,
+            Ok(ClientFeaturesResponse::Updated(_, _))
+        ));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test_case(false; "with content length")]
+    #[test_case(true; "streamed")]
+    #[actix_web::test]
+    pub async fn oversized_feature_payloads_are_rejected_before_parsing(streamed: bool) {
+        let srv = test_features_server_with_payload_size(streamed).await;
+        let payload_size = serde_json::to_vec(&two_client_features()).unwrap().len();
+        let client = UnleashClient::new(srv.url("/").as_str(), None).unwrap();
+
+        let too_small = client
+            .clone()
+            .with_max_feature_payload_bytes(Some(payload_size - 1))
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None))
+            .await;
+        match too_small {
+            Err(EdgeError::ClientFeaturesFetchError(FeatureError::PayloadTooLarge(limit))) => {
+                assert_eq!(limit, payload_size - 1)
+            }
+            other => panic!("Expected the payload to be rejected, got {other:?}"),
+        }
+
+        let large_enough = client
+            .with_max_feature_payload_bytes(Some(payload_size))
+            .get_client_features(ClientFeaturesRequest::new(TEST_TOKEN.to_string(), None))
+            .await;
+        match large_enough {
+            Ok(ClientFeaturesResponse::Updated(features, _)) => {
+                assert_eq!(features, two_client_features())
+            }
+            other => panic!("Expected features within the limit to be accepted, got {other:?}"),
+        }
+    }
+
+    #[test_case(MetricsCompression::Gzip; "gzip")]
+    #[test_case(MetricsCompression::Deflate; "deflate")]
+    #[actix_web::test]
+    pub async fn compressed_metrics_round_trip_through_upstream(compression: MetricsCompression) {
+        let srv = test_compressed_metrics_server().await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None)
+            .unwrap()
+            .with_metrics_compression(Some(compression));
+        let result = client
+            .send_bulk_metrics_to_client_endpoint(metrics_batch(), TEST_TOKEN)
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[actix_web::test]
+    pub async fn uncompressed_metrics_are_not_content_encoded() {
+        let srv = test_compressed_metrics_server().await;
+        let client = UnleashClient::new(srv.url("/").as_str(), None).unwrap();
+        let result = client
+            .send_bulk_metrics_to_client_endpoint(metrics_batch(), TEST_TOKEN)
+            .await;
+        assert!(result.is_err());
+    }
+
     #[actix_web::test]
     pub async fn disabling_ssl_verification_allows_communicating_with_upstream_unleash_with_self_signed_cert(
     ) {
@@ -907,6 +2191,272 @@ mod tests {
         assert!(validate_result.is_err());
     }
 
+    /// SHA-256 fingerprint of ../examples/server.crt
+    const EXAMPLE_SERVER_CERT_FINGERPRINT: &str =
+        "37:2A:F9:07:33:C1:34:AF:04:C8:3E:49:17:9B:E4:D0:2A:95:37:9E:A4:D4:D0:BB:B3:3C:54:4A:85:2A:76:02";
+
+    fn client_pinned_to(server_url: &str, pin: &str) -> UnleashClient {
+        let http_client = new_reqwest_client(HttpClientArgs {
+            skip_ssl_verification: true,
+            upstream_certificate_pins: vec![string_to_certificate_pin(pin).unwrap()],
+            client_meta_information: ClientMetaInformation::test_config(),
+            ..Default::default()
+        })
+        .unwrap();
+        UnleashClient::from_url(
+            reqwest::Url::parse(server_url).unwrap(),
+            "Authorization".into(),
+            http_client,
+        )
+    }
+
+    #[actix_web::test]
+    pub async fn pinned_certificate_fingerprint_allows_communicating_with_upstream() {
+        let srv = test_features_server_with_untrusted_ssl().await;
+        let client = client_pinned_to(srv.surl("/").as_str(), EXAMPLE_SERVER_CERT_FINGERPRINT);
+
+        let validate_result = client
+            .validate_tokens(ValidateTokensRequest {
+                tokens: vec![TEST_TOKEN.to_string()],
+            })
+            .await;
+
+        assert!(validate_result.is_ok());
+    }
+
+    #[actix_web::test]
+    pub async fn mismatched_certificate_fingerprint_refuses_to_communicate_with_upstream() {
+        let srv = test_features_server_with_untrusted_ssl().await;
+        let client = client_pinned_to(
+            srv.surl("/").as_str(),
+            "00:2A:F9:07:33:C1:34:AF:04:C8:3E:49:17:9B:E4:D0:2A:95:37:9E:A4:D4:D0:BB:B3:3C:54:4A:85:2A:76:02",
+        );
+
+        let validate_result = client
+            .validate_tokens(ValidateTokensRequest {
+                tokens: vec![TEST_TOKEN.to_string()],
+            })
+            .await;
+
+        assert!(validate_result.is_err());
+    }
+
+    #[test]
+    pub fn certificate_pins_must_be_sha256_fingerprints() {
+        assert!(string_to_certificate_pin(EXAMPLE_SERVER_CERT_FINGERPRINT).is_ok());
+        assert_eq!(
+            string_to_certificate_pin(EXAMPLE_SERVER_CERT_FINGERPRINT),
+            string_to_certificate_pin(
+                &EXAMPLE_SERVER_CERT_FINGERPRINT
+                    .replace(':', "")
+                    .to_lowercase()
+            )
+        );
+        assert!(string_to_certificate_pin("37:2A:F9").is_err());
+        assert!(string_to_certificate_pin(&"zz".repeat(32)).is_err());
+    }
+
+    /// Plays the part of an http proxy: it answers requests for any host, as long as they carry the expected
+    /// proxy credentials
+    async fn proxied_validate_tokens(req: HttpRequest) -> HttpResponse {
+        let expected = format!("Basic {}", BASE64_STANDARD.encode("edge:proxy-secret"));
+        match req.headers().get("Proxy-Authorization") {
+            Some(auth) if auth.to_str().unwrap() == expected => return_validate_tokens().await,
+            _ => HttpResponse::ProxyAuthenticationRequired().finish(),
+        }
+    }
+
+    async fn test_proxy_server() -> TestServer {
+        test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(
+                    web::resource("/edge/validate").route(web::post().to(proxied_validate_tokens)),
+                ),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await
+    }
+
+    fn client_via_proxy(proxy_url: &str, password: &str) -> UnleashClient {
+        let http_client = new_reqwest_client(HttpClientArgs {
+            upstream_proxy_url: Some(proxy_url.into()),
+            upstream_proxy_username: Some("edge".into()),
+            upstream_proxy_password: Some(password.into()),
+            client_meta_information: ClientMetaInformation::test_config(),
+            ..Default::default()
+        })
+        .unwrap();
+        UnleashClient::from_url(
+            reqwest::Url::parse("http://unleash.upstream.invalid/").unwrap(),
+            "Authorization".into(),
+            http_client,
+        )
+    }
+
+    #[actix_web::test]
+    pub async fn upstream_requests_go_through_the_configured_proxy_with_its_credentials() {
+        let proxy = test_proxy_server().await;
+        let validate = |client: UnleashClient| async move {
+            client
+                .validate_tokens(ValidateTokensRequest {
+                    tokens: vec![TEST_TOKEN.to_string()],
+                })
+                .await
+        };
+
+        let validated = validate(client_via_proxy(proxy.url("/").as_str(), "proxy-secret")).await;
+        assert_eq!(validated.unwrap().len(), 1);
+
+        let rejected = validate(client_via_proxy(proxy.url("/").as_str(), "wrong")).await;
+        assert!(matches!(
+            rejected,
+            Err(EdgeError::TokenValidationError(
+                StatusCode::PROXY_AUTHENTICATION_REQUIRED
+            ))
+        ));
+    }
+
+    #[test_case(false, reqwest::Version::HTTP_11; "http1 by default")]
+    #[test_case(true, reqwest::Version::HTTP_2; "http2 with prior knowledge")]
+    #[actix_web::test]
+    pub async fn http2_prior_knowledge_is_applied_to_the_client(
+        http2_prior_knowledge: bool,
+        expected_version: reqwest::Version,
+    ) {
+        let srv = test_server(move || {
+            HttpService::build()
+                .finish(map_config(
+                    App::new().service(
+                        web::resource("/")
+                            .route(web::get().to(|| async { HttpResponse::Ok().finish() })),
+                    ),
+                    |_| AppConfig::default(),
+                ))
+                .tcp_auto_h2c()
+        })
+        .await;
+        let client = new_reqwest_client(HttpClientArgs {
+            http2_prior_knowledge,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let response = client.get(srv.url("/")).send().await.unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(response.version(), expected_version);
+    }
+
+    async fn echo_user_agent(req: HttpRequest) -> HttpResponse {
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        HttpResponse::Ok().body(user_agent.to_string())
+    }
+
+    #[actix_web::test]
+    pub async fn user_agent_suffix_is_appended_to_the_default_user_agent() {
+        let srv = test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(web::resource("/").route(web::get().to(echo_user_agent))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client = new_reqwest_client(HttpClientArgs {
+            user_agent_suffix: Some("cluster/eu-west-1".into()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let user_agent = client
+            .get(srv.url("/"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            user_agent,
+            format!(
+                "unleash-edge-{} cluster/eu-west-1",
+                crate::types::build::PKG_VERSION
+            )
+        );
+    }
+
+    async fn echo_client_spec(req: HttpRequest) -> HttpResponse {
+        let client_spec = req
+            .headers()
+            .get(UNLEASH_CLIENT_SPEC_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        HttpResponse::Ok().body(client_spec.to_string())
+    }
+
+    #[actix_web::test]
+    pub async fn client_spec_version_can_be_overridden() {
+        let srv = test_server(move || {
+            HttpService::new(map_config(
+                App::new().service(web::resource("/").route(web::get().to(echo_client_spec))),
+                |_| AppConfig::default(),
+            ))
+            .tcp()
+        })
+        .await;
+        let client_spec = |client_meta_information: ClientMetaInformation| {
+            let client = new_reqwest_client(HttpClientArgs {
+                client_meta_information,
+                ..Default::default()
+            })
+            .unwrap();
+            let url = srv.url("/");
+            async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+        };
+
+        assert_eq!(
+            client_spec(ClientMetaInformation::default()).await,
+            unleash_yggdrasil::SUPPORTED_SPEC_VERSION
+        );
+        assert_eq!(
+            client_spec(ClientMetaInformation {
+                client_spec_version: "4.3.0".into(),
+                ..ClientMetaInformation::default()
+            })
+            .await,
+            "4.3.0"
+        );
+    }
+
+    #[test]
+    pub fn unsupported_proxy_schemes_are_rejected() {
+        let client = |proxy_url: &str| {
+            new_reqwest_client(HttpClientArgs {
+                upstream_proxy_url: Some(proxy_url.into()),
+                upstream_proxy_username: Some("edge".into()),
+                ..Default::default()
+            })
+        };
+        assert!(client("socks5://127.0.0.1:1080").is_ok());
+        assert!(matches!(
+            client("socks4://proxy.internal:1080"),
+            Err(EdgeError::ClientBuildError(_))
+        ));
+        assert!(matches!(
+            client("not a url"),
+            Err(EdgeError::ClientBuildError(_))
+        ));
+    }
+
     #[cfg(target_os = "linux")]
     #[test]
     pub fn can_instantiate_pkcs_12_client() {
@@ -918,17 +2468,15 @@ mod tests {
             pkcs12_identity_file: Some(PathBuf::from(pfx)),
             pkcs12_passphrase: Some(passphrase.into()),
         };
-        let client = new_reqwest_client(
-            false,
-            Some(identity),
-            None,
-            Duration::seconds(5),
-            Duration::seconds(5),
-            ClientMetaInformation {
+        let client = new_reqwest_client(HttpClientArgs {
+            client_identity: Some(identity),
+            client_meta_information: ClientMetaInformation {
                 app_name: "test-client".into(),
                 instance_id: "test-pkcs12".into(),
+                ..ClientMetaInformation::test_config()
             },
-        );
+            ..Default::default()
+        });
         assert!(client.is_ok());
     }
 
@@ -942,17 +2490,15 @@ mod tests {
             pkcs12_identity_file: Some(PathBuf::from(pfx)),
             pkcs12_passphrase: Some(passphrase.into()),
         };
-        let client = new_reqwest_client(
-            false,
-            Some(identity),
-            None,
-            Duration::seconds(5),
-            Duration::seconds(5),
-            ClientMetaInformation {
+        let client = new_reqwest_client(HttpClientArgs {
+            client_identity: Some(identity),
+            client_meta_information: ClientMetaInformation {
                 app_name: "test-client".into(),
                 instance_id: "test-pkcs12".into(),
+                ..ClientMetaInformation::test_config()
             },
-        );
+            ..Default::default()
+        });
         assert!(client.is_err());
     }
 
@@ -966,17 +2512,15 @@ mod tests {
             pkcs12_identity_file: None,
             pkcs12_passphrase: None,
         };
-        let client = new_reqwest_client(
-            false,
-            Some(identity),
-            None,
-            Duration::seconds(5),
-            Duration::seconds(5),
-            ClientMetaInformation {
+        let client = new_reqwest_client(HttpClientArgs {
+            client_identity: Some(identity),
+            client_meta_information: ClientMetaInformation {
                 app_name: "test-client".into(),
                 instance_id: "test-pkcs8".into(),
+                ..ClientMetaInformation::test_config()
             },
-        );
+            ..Default::default()
+        });
         assert!(client.is_ok());
     }
 }
@@ -1,21 +1,35 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
 
 use actix_web::{
-    get,
+    get, post,
     web::{self, Json},
 };
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use iter_tools::Itertools;
 use serde::{Deserialize, Serialize};
+use tracing::debug;
 use unleash_types::client_features::ClientFeatures;
 use unleash_types::client_metrics::ClientApplication;
 
+use crate::filters::{project_filter_from_projects, FeatureFilterSet};
 use crate::http::refresher::feature_refresher::FeatureRefresher;
+use crate::http::unleash_client::{UpstreamInfo, UpstreamKind};
 use crate::metrics::actix_web_metrics::PrometheusMetricsHandler;
 use crate::metrics::client_metrics::MetricsCache;
-use crate::types::{BuildInfo, EdgeJsonResult, EdgeToken, TokenInfo, TokenRefresh};
+use crate::metrics::json_metrics;
+use crate::middleware::maintenance_mode::{MaintenanceMode, MaintenanceStatus, MaintenanceToggle};
+use crate::tokens::cache_key;
+use crate::types::{
+    BuildInfo, EdgeJsonResult, EdgeResult, EdgeToken, TokenInfo, TokenRefresh, TokenType,
+    TokenValidationStatus,
+};
 use crate::types::{ClientMetric, MetricsInfo, Status};
-use crate::{auth::token_validator::TokenValidator, cli::InternalBackstageArgs};
+use crate::{
+    auth::token_validator::TokenValidator,
+    cli::{EffectiveConfig, InternalBackstageArgs},
+};
 use crate::{error::EdgeError, feature_cache::FeatureCache};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -51,18 +65,63 @@ pub async fn info() -> EdgeJsonResult<BuildInfo> {
     Ok(Json(data))
 }
 
+/// Not ready while features for any of the `--required-environments` are missing
+fn required_environments_hydrated(feature_refresher: &FeatureRefresher) -> bool {
+    let unhydrated = feature_refresher.unhydrated_required_environments();
+    if !unhydrated.is_empty() {
+        debug!("Not ready, still waiting for features for {unhydrated:?}");
+    }
+    unhydrated.is_empty()
+}
+
 #[get("/ready")]
 pub async fn ready(
     token_cache: web::Data<DashMap<String, EdgeToken>>,
     features_cache: web::Data<FeatureCache>,
+    feature_refresher: Option<web::Data<FeatureRefresher>>,
 ) -> EdgeJsonResult<EdgeStatus> {
-    if !token_cache.is_empty() && features_cache.is_empty() {
+    if (!token_cache.is_empty() && features_cache.is_empty())
+        || feature_refresher.is_some_and(|refresher| !required_environments_hydrated(&refresher))
+    {
         Err(EdgeError::NotReady)
     } else {
         Ok(Json(EdgeStatus::ready()))
     }
 }
 
+#[get("/liveness")]
+pub async fn liveness() -> EdgeJsonResult<EdgeStatus> {
+    Ok(Json(EdgeStatus::ok()))
+}
+
+/// Ready once upstream has been reached and features for at least one environment, and all `--required-environments`, have been fetched.
+/// Edge without any tokens yet, like in dynamic mode without startup tokens, has nothing to wait for, since tokens only
+/// show up once SDKs are routed to it. Without a refresher (offline mode) this falls back to the same check as `/ready`
+#[get("/readiness")]
+pub async fn readiness(
+    token_cache: web::Data<DashMap<String, EdgeToken>>,
+    features_cache: web::Data<FeatureCache>,
+    feature_refresher: Option<web::Data<FeatureRefresher>>,
+) -> EdgeJsonResult<EdgeStatus> {
+    let hydrated = match feature_refresher {
+        Some(refresher) => {
+            let no_tokens = token_cache.is_empty() && refresher.tokens_to_refresh.is_empty();
+            (no_tokens
+                || refresher
+                    .tokens_to_refresh
+                    .iter()
+                    .any(|refresh| refresh.last_refreshed.is_some()))
+                && required_environments_hydrated(&refresher)
+        }
+        None => token_cache.is_empty() || !features_cache.is_empty(),
+    };
+    if hydrated {
+        Ok(Json(EdgeStatus::ready()))
+    } else {
+        Err(EdgeError::NotReady)
+    }
+}
+
 #[get("/tokens")]
 pub async fn tokens(
     token_cache: web::Data<DashMap<String, EdgeToken>>,
@@ -114,6 +173,46 @@ fn get_offline_token_info(token_cache: web::Data<DashMap<String, EdgeToken>>) ->
     }
 }
 
+/// What Edge knows about its upstream. Without a refresher (offline mode) the kind is `none`
+#[get("/upstream")]
+pub async fn upstream(
+    feature_refresher: Option<web::Data<FeatureRefresher>>,
+) -> EdgeJsonResult<UpstreamInfo> {
+    match feature_refresher {
+        Some(feature_refresher) => Ok(Json(feature_refresher.unleash_client.upstream_info())),
+        None => Ok(Json(UpstreamInfo {
+            kind: Some(UpstreamKind::None),
+            ..Default::default()
+        })),
+    }
+}
+
+/// The caller's token, if Edge has validated it as a client token. Frontend tokens are held by browsers, so they
+/// don't get to see or change how Edge runs
+fn validated_client_token(
+    edge_token: &EdgeToken,
+    token_cache: &DashMap<String, EdgeToken>,
+) -> EdgeResult<EdgeToken> {
+    token_cache
+        .get(&edge_token.token)
+        .map(|t| t.value().clone())
+        .filter(|t| {
+            t.status == TokenValidationStatus::Validated && t.token_type == Some(TokenType::Client)
+        })
+        .ok_or(EdgeError::AuthorizationDenied)
+}
+
+/// The configuration Edge is running with, secrets redacted. Only available to callers with a client token Edge has validated
+#[get("/config")]
+pub async fn effective_config(
+    edge_token: EdgeToken,
+    token_cache: web::Data<DashMap<String, EdgeToken>>,
+    effective_config: web::Data<EffectiveConfig>,
+) -> EdgeJsonResult<EffectiveConfig> {
+    validated_client_token(&edge_token, &token_cache)?;
+    Ok(Json(effective_config.get_ref().clone()))
+}
+
 #[get("/metricsbatch")]
 pub async fn metrics_batch(metrics_cache: web::Data<MetricsCache>) -> EdgeJsonResult<MetricsInfo> {
     let applications: Vec<ClientApplication> = metrics_cache
@@ -135,39 +234,302 @@ pub async fn metrics_batch(metrics_cache: web::Data<MetricsCache>) -> EdgeJsonRe
     }))
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct BackstageFeaturesQuery {
+    /// Only show the features cached for this environment
+    pub environment: Option<String>,
+    /// Comma separated projects to show features for, `!` excludes a project
+    pub project: Option<String>,
+    /// Features to skip in each environment, after filtering on project
+    pub offset: Option<usize>,
+    /// Maximum number of features to show for each environment
+    pub limit: Option<usize>,
+}
+
 #[get("/features")]
 pub async fn features(
     features_cache: web::Data<FeatureCache>,
+    query: web::Query<BackstageFeaturesQuery>,
 ) -> EdgeJsonResult<HashMap<String, ClientFeatures>> {
+    let filter_set = query
+        .project
+        .as_ref()
+        .map(|projects| {
+            FeatureFilterSet::from(project_filter_from_projects(
+                projects.split(',').map(|p| p.trim().to_string()).collect(),
+            ))
+        })
+        .unwrap_or_default();
     let features = features_cache
         .iter()
-        .map(|e| (e.key().clone(), e.value().clone()))
+        .filter(|e| {
+            query
+                .environment
+                .as_ref()
+                .map_or(true, |environment| e.key() == environment)
+        })
+        .map(|e| {
+            let features = e
+                .value()
+                .features
+                .iter()
+                .filter(|feature| filter_set.apply(feature))
+                .skip(query.offset.unwrap_or(0))
+                .take(query.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect();
+            (
+                e.key().clone(),
+                ClientFeatures {
+                    features,
+                    ..e.value().clone()
+                },
+            )
+        })
         .collect();
     Ok(Json(features))
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SegmentUsage {
+    /// Names of the features referencing each segment, by segment id
+    pub segments: BTreeMap<i32, Vec<String>>,
+    /// Segments in the cache that no feature references
+    pub orphans: Vec<i32>,
+}
+
+fn segment_usage(client_features: &ClientFeatures) -> SegmentUsage {
+    let mut referencing_features: BTreeMap<i32, Vec<String>> = client_features
+        .segments
+        .iter()
+        .flatten()
+        .map(|segment| (segment.id, vec![]))
+        .collect();
+    for feature in &client_features.features {
+        let referenced: BTreeSet<i32> = feature
+            .strategies
+            .iter()
+            .flatten()
+            .flat_map(|strategy| strategy.segments.iter().flatten().copied())
+            .collect();
+        for segment_id in referenced {
+            referencing_features
+                .entry(segment_id)
+                .or_default()
+                .push(feature.name.clone());
+        }
+    }
+    let orphans = referencing_features
+        .iter()
+        .filter(|(_, feature_names)| feature_names.is_empty())
+        .map(|(segment_id, _)| *segment_id)
+        .collect();
+    SegmentUsage {
+        segments: referencing_features,
+        orphans,
+    }
+}
+
+#[get("/segments")]
+pub async fn segments(
+    features_cache: web::Data<FeatureCache>,
+) -> EdgeJsonResult<HashMap<String, SegmentUsage>> {
+    let usage = features_cache
+        .iter()
+        .map(|e| (e.key().clone(), segment_usage(e.value())))
+        .collect();
+    Ok(Json(usage))
+}
+
+#[get("/maintenance")]
+pub async fn maintenance_status(
+    maintenance: web::Data<MaintenanceMode>,
+) -> EdgeJsonResult<MaintenanceStatus> {
+    Ok(Json(maintenance.status()))
+}
+
+/// Switches maintenance mode on or off. Requires a validated client token in the auth header
+#[post("/maintenance")]
+pub async fn toggle_maintenance(
+    edge_token: EdgeToken,
+    token_cache: web::Data<DashMap<String, EdgeToken>>,
+    maintenance: web::Data<MaintenanceMode>,
+    toggle: Json<MaintenanceToggle>,
+) -> EdgeJsonResult<MaintenanceStatus> {
+    validated_client_token(&edge_token, &token_cache)?;
+    maintenance.set_enabled(toggle.enabled);
+    Ok(Json(maintenance.status()))
+}
+
+/// Limits how often POST /internal-backstage/refresh may go to upstream for the same environment
+#[derive(Debug)]
+pub struct ForcedRefreshLimiter {
+    min_interval: Duration,
+    last_forced: DashMap<String, Instant>,
+}
+
+impl Default for ForcedRefreshLimiter {
+    fn default() -> Self {
+        ForcedRefreshLimiter::new(Duration::from_secs(10))
+    }
+}
+
+impl From<&InternalBackstageArgs> for ForcedRefreshLimiter {
+    fn from(args: &InternalBackstageArgs) -> Self {
+        ForcedRefreshLimiter::new(Duration::from_secs(
+            args.refresh_endpoint_min_interval_seconds,
+        ))
+    }
+}
+
+impl ForcedRefreshLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        ForcedRefreshLimiter {
+            min_interval,
+            last_forced: DashMap::default(),
+        }
+    }
+
+    fn try_acquire(&self, environment: &str) -> EdgeResult<()> {
+        let now = Instant::now();
+        match self.last_forced.entry(environment.into()) {
+            Entry::Occupied(mut last_forced) => {
+                let elapsed = now.duration_since(*last_forced.get());
+                if elapsed < self.min_interval {
+                    let retry_after = (self.min_interval - elapsed).as_secs_f64().ceil() as u64;
+                    return Err(EdgeError::RefreshRateLimited(retry_after));
+                }
+                last_forced.insert(now);
+            }
+            Entry::Vacant(never_forced) => {
+                never_forced.insert(now);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForceRefreshQuery {
+    pub environment: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForcedRefresh {
+    pub environment: Option<String>,
+    pub projects: Vec<String>,
+    /// Whether upstream answered, either with new features or with nothing having changed
+    pub refreshed: bool,
+    pub feature_count: Option<usize>,
+    pub revision_id: Option<usize>,
+}
+
+/// Refreshes features from upstream right away instead of waiting for the next interval. Requires a validated
+/// client token in the auth header, and refreshes the registered tokens covering it, or every registered token
+/// in its environment when `?environment=` is given.
+#[post("/refresh")]
+pub async fn force_refresh(
+    edge_token: EdgeToken,
+    query: web::Query<ForceRefreshQuery>,
+    token_validator: Option<web::Data<TokenValidator>>,
+    feature_refresher: Option<web::Data<FeatureRefresher>>,
+    limiter: web::Data<ForcedRefreshLimiter>,
+) -> EdgeJsonResult<Vec<ForcedRefresh>> {
+    let (Some(token_validator), Some(feature_refresher)) = (token_validator, feature_refresher)
+    else {
+        return Err(EdgeError::Forbidden(
+            "Edge is not refreshing features from upstream".into(),
+        ));
+    };
+    let caller = validated_client_token(&edge_token, &token_validator.token_cache)?;
+    let environment = caller.environment.clone().unwrap_or_default();
+    if let Some(requested) = &query.environment {
+        if requested != &environment {
+            return Err(EdgeError::Forbidden(format!(
+                "Token is not valid for environment {requested}"
+            )));
+        }
+    }
+    let refreshes: Vec<TokenRefresh> = feature_refresher
+        .tokens_to_refresh
+        .iter()
+        .filter(|r| r.token.environment == caller.environment)
+        .filter(|r| query.environment.is_some() || r.token.subsumes(&caller))
+        .map(|r| r.value().clone())
+        .collect();
+    if refreshes.is_empty() {
+        return Err(EdgeError::Forbidden(
+            "No token registered for refresh covers this token".into(),
+        ));
+    }
+    limiter.try_acquire(&environment)?;
+
+    let mut forced = vec![];
+    for refresh in refreshes {
+        feature_refresher.refresh_and_report(refresh.clone()).await;
+        let after = feature_refresher
+            .tokens_to_refresh
+            .get(&refresh.token.token)
+            .map(|r| r.value().clone());
+        let refreshed = after.as_ref().is_some_and(|after| {
+            after.last_check > refresh.last_check || after.last_refreshed > refresh.last_refreshed
+        });
+        let revision_id = feature_refresher
+            .features_cache
+            .get(&cache_key(&refresh.token))
+            .and_then(|cached| cached.meta.as_ref().and_then(|meta| meta.revision_id));
+        forced.push(ForcedRefresh {
+            environment: refresh.token.environment,
+            projects: refresh.token.projects,
+            refreshed,
+            feature_count: after.and_then(|after| after.last_feature_count),
+            revision_id,
+        });
+    }
+    Ok(Json(forced))
+}
+
 pub fn configure_internal_backstage(
     cfg: &mut web::ServiceConfig,
     metrics_handler: PrometheusMetricsHandler,
     internal_backtage_args: InternalBackstageArgs,
 ) {
-    cfg.service(health).service(info).service(ready);
+    cfg.service(health)
+        .service(info)
+        .service(ready)
+        .service(liveness)
+        .service(readiness)
+        .service(upstream)
+        .service(effective_config)
+        .service(maintenance_status);
+    if internal_backtage_args.enable_maintenance_endpoint {
+        cfg.service(toggle_maintenance);
+    }
     if !internal_backtage_args.disable_tokens_endpoint {
         cfg.service(tokens);
     }
     if !internal_backtage_args.disable_metrics_endpoint {
-        cfg.service(web::resource("/metrics").route(web::get().to(metrics_handler)));
+        let registry = metrics_handler.registry.clone();
+        cfg.service(web::resource("/metrics").route(web::get().to(metrics_handler)))
+            .service(web::resource("/metrics/json").route(web::get().to(move || {
+                let metric_families = json_metrics::to_json(&registry.gather());
+                async move { Json(metric_families) }
+            })));
     }
     if !internal_backtage_args.disable_metrics_batch_endpoint {
         cfg.service(metrics_batch);
     }
     if !internal_backtage_args.disable_features_endpoint {
-        cfg.service(features);
+        cfg.service(features).service(segments);
+    }
+    if internal_backtage_args.enable_refresh_endpoint {
+        cfg.service(force_refresh);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{BTreeMap, HashMap};
     use std::str::FromStr;
     use std::sync::Arc;
 
@@ -176,19 +538,31 @@ mod tests {
     use actix_web::test;
     use actix_web::{web, App};
     use chrono::Duration;
+    use clap::{CommandFactory, Parser};
     use dashmap::DashMap;
-    use unleash_types::client_features::{ClientFeature, ClientFeatures};
+    use unleash_types::client_features::{ClientFeature, ClientFeatures, Segment, Strategy};
     use unleash_yggdrasil::EngineState;
 
     use crate::auth::token_validator::TokenValidator;
+    use crate::cli::{CliArgs, EffectiveConfig};
     use crate::feature_cache::FeatureCache;
     use crate::http::refresher::feature_refresher::FeatureRefresher;
-    use crate::http::unleash_client::UnleashClient;
-    use crate::internal_backstage::EdgeStatus;
+    use crate::http::unleash_client::{UnleashClient, UpstreamInfo, UpstreamKind};
+    use crate::internal_backstage::{
+        EdgeStatus, ForcedRefresh, ForcedRefreshLimiter, SegmentUsage,
+    };
+    use crate::metrics::actix_web_metrics::PrometheusMetricsHandler;
+    use crate::metrics::json_metrics::{JsonMetricFamily, JsonMetricValue};
     use crate::middleware;
+    use crate::middleware::maintenance_mode::{
+        MaintenanceMode, MaintenanceStatus, MaintenanceToggle,
+    };
     use crate::tests::upstream_server;
     use crate::tokens::cache_key;
-    use crate::types::{BuildInfo, EdgeToken, Status, TokenInfo, TokenType, TokenValidationStatus};
+    use crate::types::{
+        BuildInfo, EdgeToken, Status, TokenInfo, TokenRefresh, TokenType, TokenValidationStatus,
+        EDGE_VERSION,
+    };
 
     #[actix_web::test]
     async fn test_health_ok() {
@@ -308,6 +682,262 @@ mod tests {
         assert_eq!(status.status, Status::Ready);
     }
 
+    #[actix_web::test]
+    async fn segments_list_referencing_features_and_orphans() {
+        let segment = |id: i32| Segment {
+            id,
+            constraints: vec![],
+        };
+        let strategy_with_segments = |segments: Vec<i32>| Strategy {
+            name: "default".into(),
+            sort_order: None,
+            segments: Some(segments),
+            constraints: None,
+            parameters: None,
+            variants: None,
+        };
+        let features_cache = Arc::new(FeatureCache::default());
+        features_cache.insert(
+            "development".into(),
+            ClientFeatures {
+                version: 2,
+                features: vec![
+                    ClientFeature {
+                        name: "feature-one".into(),
+                        strategies: Some(vec![strategy_with_segments(vec![1, 2])]),
+                        ..ClientFeature::default()
+                    },
+                    ClientFeature {
+                        name: "feature-two".into(),
+                        strategies: Some(vec![
+                            strategy_with_segments(vec![2]),
+                            strategy_with_segments(vec![2]),
+                        ]),
+                        ..ClientFeature::default()
+                    },
+                ],
+                segments: Some(vec![segment(1), segment(2), segment(3)]),
+                query: None,
+                meta: None,
+            },
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(features_cache))
+                .service(web::scope("/internal-backstage").service(super::segments)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/segments")
+            .to_request();
+        let usage: HashMap<String, SegmentUsage> = test::call_and_read_body_json(&app, req).await;
+
+        let development = usage.get("development").unwrap();
+        assert_eq!(
+            development.segments,
+            BTreeMap::from([
+                (1, vec!["feature-one".to_string()]),
+                (
+                    2,
+                    vec!["feature-one".to_string(), "feature-two".to_string()]
+                ),
+                (3, vec![]),
+            ])
+        );
+        assert_eq!(development.orphans, vec![3]);
+    }
+
+    #[actix_web::test]
+    async fn features_can_be_filtered_and_paged() {
+        let feature = |name: String, project: &str| ClientFeature {
+            name,
+            project: Some(project.into()),
+            ..ClientFeature::default()
+        };
+        let client_features = |features: Vec<ClientFeature>| ClientFeatures {
+            version: 2,
+            features,
+            segments: None,
+            query: None,
+            meta: None,
+        };
+        let features_cache = Arc::new(FeatureCache::default());
+        features_cache.insert(
+            "development".into(),
+            client_features(
+                (0..10)
+                    .map(|i| feature(format!("feature-{i}"), "default"))
+                    .chain([feature("other-feature".into(), "other")])
+                    .collect(),
+            ),
+        );
+        features_cache.insert(
+            "production".into(),
+            client_features(vec![feature("production-feature".into(), "default")]),
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(features_cache))
+                .service(web::scope("/internal-backstage").service(super::features)),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let all: HashMap<String, ClientFeatures> =
+            test::call_and_read_body_json(&app, get("/internal-backstage/features")).await;
+        assert_eq!(all.len(), 2);
+        assert_eq!(all["development"].features.len(), 11);
+
+        let page: HashMap<String, ClientFeatures> = test::call_and_read_body_json(
+            &app,
+            get("/internal-backstage/features?environment=development&project=default&offset=4&limit=3"),
+        )
+        .await;
+        assert_eq!(page.len(), 1);
+        let names: Vec<&str> = page["development"]
+            .features
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["feature-4", "feature-5", "feature-6"]);
+
+        let last_page: HashMap<String, ClientFeatures> = test::call_and_read_body_json(
+            &app,
+            get("/internal-backstage/features?environment=development&project=default&offset=9&limit=3"),
+        )
+        .await;
+        assert_eq!(last_page["development"].features.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn liveness_is_ok_regardless_of_hydration() {
+        let app = test::init_service(
+            App::new().service(web::scope("/internal-backstage").service(super::liveness)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/liveness")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let status: EdgeStatus = test::read_body_json(resp).await;
+        assert_eq!(status.status, Status::Ok);
+    }
+
+    #[actix_web::test]
+    async fn readiness_is_not_ready_until_an_environment_has_been_hydrated() {
+        let token = EdgeToken::from_str("*:development.somerandomsecretstring").unwrap();
+        let feature_refresher = Arc::new(FeatureRefresher::default());
+        feature_refresher
+            .tokens_to_refresh
+            .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(Arc::new(FeatureCache::default())))
+                .app_data(web::Data::from(Arc::new(
+                    DashMap::<String, EdgeToken>::new(),
+                )))
+                .app_data(web::Data::from(feature_refresher.clone()))
+                .service(web::scope("/internal-backstage").service(super::readiness)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/readiness")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_http::StatusCode::SERVICE_UNAVAILABLE);
+        let status: EdgeStatus = test::read_body_json(resp).await;
+        assert_eq!(status.status, Status::NotReady);
+
+        feature_refresher
+            .tokens_to_refresh
+            .alter(&token.token, |_, refresh| {
+                refresh.successful_refresh(&Duration::seconds(10), None, 1)
+            });
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/readiness")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let status: EdgeStatus = test::read_body_json(resp).await;
+        assert_eq!(status.status, Status::Ready);
+    }
+
+    #[actix_web::test]
+    async fn readiness_is_ready_without_any_tokens_to_wait_for() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(Arc::new(FeatureCache::default())))
+                .app_data(web::Data::from(Arc::new(
+                    DashMap::<String, EdgeToken>::new(),
+                )))
+                .app_data(web::Data::from(Arc::new(FeatureRefresher::default())))
+                .service(web::scope("/internal-backstage").service(super::readiness)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/readiness")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let status: EdgeStatus = test::read_body_json(resp).await;
+        assert_eq!(status.status, Status::Ready);
+    }
+
+    #[actix_web::test]
+    async fn readiness_waits_for_required_environments() {
+        let development = EdgeToken::from_str("*:development.somerandomsecretstring").unwrap();
+        let production = EdgeToken::from_str("*:production.somerandomsecretstring").unwrap();
+        let feature_refresher = Arc::new(FeatureRefresher {
+            required_environments: vec!["production".into()],
+            ..Default::default()
+        });
+        for token in [&development, &production] {
+            feature_refresher
+                .tokens_to_refresh
+                .insert(token.token.clone(), TokenRefresh::new(token.clone(), None));
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(Arc::new(FeatureCache::default())))
+                .app_data(web::Data::from(Arc::new(
+                    DashMap::<String, EdgeToken>::new(),
+                )))
+                .app_data(web::Data::from(feature_refresher.clone()))
+                .service(
+                    web::scope("/internal-backstage")
+                        .service(super::ready)
+                        .service(super::readiness),
+                ),
+        )
+        .await;
+        let hydrate = |token: &EdgeToken| {
+            feature_refresher
+                .tokens_to_refresh
+                .alter(&token.token, |_, refresh| {
+                    refresh.successful_refresh(&Duration::seconds(10), None, 1)
+                });
+        };
+
+        hydrate(&development);
+        for uri in ["/internal-backstage/ready", "/internal-backstage/readiness"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), actix_http::StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        hydrate(&production);
+        for uri in ["/internal-backstage/ready", "/internal-backstage/readiness"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert!(resp.status().is_success());
+            let status: EdgeStatus = test::read_body_json(resp).await;
+            assert_eq!(status.status, Status::Ready);
+        }
+    }
+
     #[actix_web::test]
     async fn if_no_tokens_has_been_received_returns_empty_lists() {
         let upstream_server = upstream_server(
@@ -481,4 +1111,359 @@ mod tests {
         let res = test::call_service(&local_app, client_request).await;
         assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
     }
+
+    #[actix_web::test]
+    async fn upstream_endpoint_reports_the_detected_upstream() {
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let server = upstream_server(
+            upstream_token_cache.clone(),
+            Arc::new(FeatureCache::default()),
+            Arc::new(DashMap::default()),
+        )
+        .await;
+        let mut known_token = EdgeToken::from_str("dx:development.secret123").unwrap();
+        known_token.status = TokenValidationStatus::Validated;
+        known_token.token_type = Some(TokenType::Client);
+        upstream_token_cache.insert(known_token.token.clone(), known_token.clone());
+        let feature_refresher = Arc::new(FeatureRefresher {
+            unleash_client: Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap()),
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(feature_refresher.clone()))
+                .service(web::scope("/internal-backstage").service(super::upstream)),
+        )
+        .await;
+        let upstream_request = || {
+            test::TestRequest::get()
+                .uri("/internal-backstage/upstream")
+                .to_request()
+        };
+
+        let before: UpstreamInfo = test::call_and_read_body_json(&app, upstream_request()).await;
+        assert_eq!(before, UpstreamInfo::default());
+
+        feature_refresher
+            .register_token_for_refresh(known_token, None)
+            .await;
+        let after: UpstreamInfo = test::call_and_read_body_json(&app, upstream_request()).await;
+        assert_eq!(after.kind, Some(UpstreamKind::Edge));
+        assert_eq!(after.version.as_deref(), Some(EDGE_VERSION));
+        assert!(after.last_successful_contact.is_some());
+    }
+
+    #[actix_web::test]
+    async fn upstream_endpoint_reports_no_upstream_in_offline_mode() {
+        let app = test::init_service(
+            App::new().service(web::scope("/internal-backstage").service(super::upstream)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/upstream")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), actix_http::StatusCode::OK);
+        let info: UpstreamInfo = test::read_body_json(res).await;
+        assert_eq!(info.kind, Some(UpstreamKind::None));
+    }
+
+    #[actix_web::test]
+    async fn config_endpoint_redacts_secrets_and_requires_a_validated_client_token() {
+        let command = CliArgs::command();
+        let matches = command.clone().get_matches_from(vec![
+            "unleash-edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--redis-url",
+            "redis://localhost:6379",
+            "--redis-password",
+            "supersecret",
+            "--tokens",
+            "*:development.secret123",
+        ]);
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let known_token = EdgeToken::validated_client_token("*:development.secret123");
+        token_cache.insert(known_token.token.clone(), known_token.clone());
+        let mut frontend_token = EdgeToken::from_str("*:development.frontend").unwrap();
+        frontend_token.status = TokenValidationStatus::Validated;
+        frontend_token.token_type = Some(TokenType::Frontend);
+        token_cache.insert(frontend_token.token.clone(), frontend_token.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(token_cache))
+                .app_data(web::Data::new(EffectiveConfig::from_matches(
+                    &command, &matches,
+                )))
+                .service(web::scope("/internal-backstage").service(super::effective_config)),
+        )
+        .await;
+        let config_request = |token: &str| {
+            test::TestRequest::get()
+                .uri("/internal-backstage/config")
+                .insert_header(("Authorization", token.to_string()))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, config_request("*:development.unknown")).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        let res = test::call_service(&app, config_request(&frontend_token.token)).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        let config: serde_json::Value =
+            test::call_and_read_body_json(&app, config_request(&known_token.token)).await;
+        assert_eq!(config["mode"], "edge");
+        assert_eq!(config["args"]["upstream_url"], "http://localhost:4242");
+        assert_eq!(config["args"]["redis_password"], "***");
+        assert_eq!(config["args"]["redis_url"], serde_json::json!(["***"]));
+        assert_eq!(config["args"]["tokens"], serde_json::json!(["***"]));
+        assert_eq!(config["args"]["strict"], false);
+        assert!(!config.to_string().contains("supersecret"));
+    }
+
+    #[actix_web::test]
+    async fn forcing_a_refresh_fetches_features_and_is_rate_limited() {
+        let upstream_features_cache = Arc::new(FeatureCache::default());
+        let upstream_token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let upstream_engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let server = upstream_server(
+            upstream_token_cache.clone(),
+            upstream_features_cache.clone(),
+            upstream_engine_cache.clone(),
+        )
+        .await;
+        let upstream_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let mut known_token = EdgeToken::from_str("dx:development.secret123").unwrap();
+        known_token.status = TokenValidationStatus::Validated;
+        known_token.token_type = Some(TokenType::Client);
+        upstream_token_cache.insert(known_token.token.clone(), known_token.clone());
+        upstream_features_cache.insert(cache_key(&known_token), upstream_features.clone());
+        let unleash_client = Arc::new(UnleashClient::new(server.url("/").as_str(), None).unwrap());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        token_cache.insert(known_token.token.clone(), known_token.clone());
+        let feature_refresher = Arc::new(FeatureRefresher {
+            unleash_client: unleash_client.clone(),
+            refresh_interval: Duration::seconds(6000),
+            ..Default::default()
+        });
+        feature_refresher
+            .register_token_for_refresh(known_token.clone(), None)
+            .await;
+        let token_validator = Arc::new(TokenValidator {
+            unleash_client,
+            token_cache,
+            persistence: None,
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(token_validator))
+                .app_data(web::Data::from(feature_refresher))
+                .app_data(web::Data::new(ForcedRefreshLimiter::default()))
+                .service(web::scope("/internal-backstage").service(super::force_refresh)),
+        )
+        .await;
+        let force_refresh = |token: &str| {
+            test::TestRequest::post()
+                .uri("/internal-backstage/refresh")
+                .insert_header(("Authorization", token.to_string()))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, force_refresh("dx:development.unknown")).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        let res = test::call_service(&app, force_refresh(&known_token.token)).await;
+        assert_eq!(res.status(), actix_http::StatusCode::OK);
+        let forced: Vec<ForcedRefresh> = test::read_body_json(res).await;
+        assert_eq!(forced.len(), 1);
+        assert!(forced[0].refreshed);
+        assert_eq!(forced[0].environment, Some("development".into()));
+        let dx_features = upstream_features
+            .features
+            .iter()
+            .filter(|f| f.project == Some("dx".into()))
+            .count();
+        assert_eq!(forced[0].feature_count, Some(dx_features));
+
+        let res = test::call_service(&app, force_refresh(&known_token.token)).await;
+        assert_eq!(res.status(), actix_http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn maintenance_mode_blocks_api_but_keeps_health_available() {
+        let maintenance = Arc::new(MaintenanceMode::default());
+        let token_cache = DashMap::<String, EdgeToken>::default();
+        let operator = EdgeToken::validated_client_token("*:development.operator");
+        token_cache.insert(operator.token.clone(), operator.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(maintenance.clone()))
+                .app_data(web::Data::new(FeatureCache::default()))
+                .app_data(web::Data::new(token_cache))
+                .app_data(web::Data::new(DashMap::<String, EngineState>::default()))
+                .service(
+                    web::scope("/internal-backstage")
+                        .service(super::health)
+                        .service(super::maintenance_status)
+                        .service(super::toggle_maintenance),
+                )
+                .service(
+                    web::scope("/api")
+                        .wrap(middleware::as_async_middleware::as_async_middleware(
+                            middleware::maintenance_mode::maintenance_mode,
+                        ))
+                        .configure(crate::client_api::configure_client_api),
+                ),
+        )
+        .await;
+        let toggle = |enabled: bool| {
+            test::TestRequest::post()
+                .uri("/internal-backstage/maintenance")
+                .insert_header(("Authorization", operator.token.clone()))
+                .set_json(MaintenanceToggle { enabled })
+                .to_request()
+        };
+        let client_features = || {
+            test::TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", "*:development.secret"))
+                .to_request()
+        };
+
+        let status: MaintenanceStatus = test::call_and_read_body_json(&app, toggle(true)).await;
+        assert!(status.enabled);
+
+        let res = test::call_service(&app, client_features()).await;
+        assert_eq!(res.status(), actix_http::StatusCode::SERVICE_UNAVAILABLE);
+        let body: MaintenanceStatus = test::read_body_json(res).await;
+        assert_eq!(body.message, "Unleash Edge is down for maintenance");
+
+        let health = test::TestRequest::get()
+            .uri("/internal-backstage/health")
+            .to_request();
+        assert!(test::call_service(&app, health).await.status().is_success());
+
+        let status: MaintenanceStatus = test::call_and_read_body_json(&app, toggle(false)).await;
+        assert!(!status.enabled);
+        let res = test::call_service(&app, client_features()).await;
+        assert_ne!(res.status(), actix_http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn maintenance_can_only_be_toggled_by_validated_client_tokens() {
+        let maintenance = Arc::new(MaintenanceMode::default());
+        let token_cache = DashMap::<String, EdgeToken>::default();
+        let mut frontend_token = EdgeToken::from_str("*:development.frontend").unwrap();
+        frontend_token.status = TokenValidationStatus::Validated;
+        frontend_token.token_type = Some(TokenType::Frontend);
+        token_cache.insert(frontend_token.token.clone(), frontend_token.clone());
+        let args =
+            CliArgs::parse_from(["unleash-edge", "offline", "--enable-maintenance-endpoint"])
+                .internal_backstage;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(maintenance.clone()))
+                .app_data(web::Data::new(token_cache))
+                .service(web::scope("/internal-backstage").configure(|cfg| {
+                    super::configure_internal_backstage(
+                        cfg,
+                        PrometheusMetricsHandler::new(prometheus::Registry::new()),
+                        args,
+                    )
+                })),
+        )
+        .await;
+
+        let unauthenticated = test::TestRequest::post()
+            .uri("/internal-backstage/maintenance")
+            .set_json(MaintenanceToggle { enabled: true })
+            .to_request();
+        let res = test::call_service(&app, unauthenticated).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        let unknown = test::TestRequest::post()
+            .uri("/internal-backstage/maintenance")
+            .insert_header(("Authorization", "*:development.unknown"))
+            .set_json(MaintenanceToggle { enabled: true })
+            .to_request();
+        let res = test::call_service(&app, unknown).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        let frontend = test::TestRequest::post()
+            .uri("/internal-backstage/maintenance")
+            .insert_header(("Authorization", frontend_token.token.clone()))
+            .set_json(MaintenanceToggle { enabled: true })
+            .to_request();
+        let res = test::call_service(&app, frontend).await;
+        assert_eq!(res.status(), actix_http::StatusCode::FORBIDDEN);
+
+        assert!(!maintenance.status().enabled);
+    }
+
+    #[actix_web::test]
+    async fn maintenance_endpoint_is_not_registered_by_default() {
+        let maintenance = Arc::new(MaintenanceMode::default());
+        let token_cache = DashMap::<String, EdgeToken>::default();
+        let operator = EdgeToken::validated_client_token("*:development.operator");
+        token_cache.insert(operator.token.clone(), operator.clone());
+        let args = CliArgs::parse_from(["unleash-edge", "offline"]).internal_backstage;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(maintenance.clone()))
+                .app_data(web::Data::new(token_cache))
+                .service(web::scope("/internal-backstage").configure(|cfg| {
+                    super::configure_internal_backstage(
+                        cfg,
+                        PrometheusMetricsHandler::new(prometheus::Registry::new()),
+                        args,
+                    )
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/internal-backstage/maintenance")
+            .insert_header(("Authorization", operator.token.clone()))
+            .set_json(MaintenanceToggle { enabled: true })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.status().is_client_error());
+        assert!(!maintenance.status().enabled);
+    }
+
+    #[actix_web::test]
+    async fn metrics_are_served_as_json() {
+        let registry = prometheus::Registry::new();
+        let counter = prometheus::IntCounter::new("json_backstage_counter", "A counter").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.inc_by(7);
+        let args = CliArgs::parse_from(["unleash-edge", "offline"]).internal_backstage;
+        let app = test::init_service(App::new().service(
+            web::scope("/internal-backstage").configure(|cfg| {
+                super::configure_internal_backstage(
+                    cfg,
+                    PrometheusMetricsHandler::new(registry.clone()),
+                    args.clone(),
+                )
+            }),
+        ))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/internal-backstage/metrics/json")
+            .to_request();
+
+        let families: Vec<JsonMetricFamily> = test::call_and_read_body_json(&app, req).await;
+
+        let counter = families
+            .iter()
+            .find(|family| family.name == "json_backstage_counter")
+            .unwrap();
+        assert_eq!(counter.metric_type, "counter");
+        assert_eq!(
+            counter.metrics[0].value,
+            JsonMetricValue::Value { value: 7.0 }
+        );
+    }
 }
@@ -4,6 +4,7 @@ pub mod builder;
 #[cfg(not(tarpaulin_include))]
 pub mod cli;
 pub mod client_api;
+pub mod config_file;
 pub mod edge_api;
 #[cfg(not(tarpaulin_include))]
 pub mod error;
@@ -22,8 +23,11 @@ pub mod persistence;
 #[cfg(not(tarpaulin_include))]
 pub mod prom_metrics;
 pub mod ready_checker;
+pub mod single_flight;
+pub mod startup_summary;
 #[cfg(not(tarpaulin_include))]
 pub mod tls;
+pub mod token_introspector;
 pub mod tokens;
 pub mod types;
 pub mod urls;
@@ -88,7 +92,10 @@ mod tests {
                         web::scope("/api")
                             .configure(crate::client_api::configure_client_api)
                             .configure(|cfg| {
-                                crate::frontend_api::configure_frontend_api(cfg, false)
+                                crate::frontend_api::configure_frontend_api(
+                                    cfg,
+                                    crate::frontend_api::AllEndpointAccess::default(),
+                                )
                             }),
                     )
                     .service(web::scope("/edge").configure(crate::edge_api::configure_edge_api)),
@@ -2,9 +2,9 @@ use std::sync::Arc;
 
 use actix_cors::Cors;
 use actix_middleware_etag::Etag;
-use actix_web::middleware::Logger;
+use actix_web::middleware::{Condition, Logger};
 use actix_web::{web, App, HttpServer};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use dashmap::DashMap;
 use futures::future::join_all;
 use unleash_types::client_features::ClientFeatures;
@@ -13,16 +13,34 @@ use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 use tracing::info;
-use unleash_edge::builder::build_caches_and_refreshers;
-use unleash_edge::cli::{CliArgs, EdgeMode};
+use unleash_edge::auth::jwks::JwksValidator;
+use unleash_edge::builder::{self, build_caches_and_refreshers, startup_tokens};
+use unleash_edge::cli::{CliArgs, EdgeMode, EffectiveConfig};
 use unleash_edge::feature_cache::FeatureCache;
+use unleash_edge::frontend_api::{AllEndpointAccess, ContextLimits};
 use unleash_edge::http::background_send_metrics::send_metrics_one_shot;
 use unleash_edge::http::refresher::feature_refresher::FeatureRefresher;
-use unleash_edge::metrics::client_metrics::MetricsCache;
+use unleash_edge::internal_backstage::ForcedRefreshLimiter;
+use unleash_edge::metrics::client_metrics::{MetricsCache, MetricsSampler};
+use unleash_edge::metrics::metrics_sink::{self, MetricsSink};
+use unleash_edge::middleware::access_log;
+use unleash_edge::middleware::as_async_middleware::as_async_middleware;
+use unleash_edge::middleware::in_flight_requests::{self, InFlightRequests};
+use unleash_edge::middleware::maintenance_mode::{self, MaintenanceMode};
+use unleash_edge::middleware::request_body_limit::{self, RequestBodyLimit};
+use unleash_edge::middleware::response_compression::{self, AllowedResponseEncodings};
+use unleash_edge::middleware::response_headers::{self, ResponseHeaders};
+use unleash_edge::middleware::staleness_headers;
 use unleash_edge::offline::offline_hotload;
 use unleash_edge::persistence::{persist_data, EdgePersistence};
+use unleash_edge::single_flight::EvaluationCoalescer;
+use unleash_edge::startup_summary::StartupSummary;
+use unleash_edge::tokens::EndpointTokenHeaders;
 use unleash_edge::types::{EdgeToken, TokenValidationStatus};
-use unleash_edge::{cli, client_api, frontend_api, health_checker, openapi, ready_checker};
+use unleash_edge::{
+    cli, client_api, config_file, frontend_api, health_checker, openapi, ready_checker,
+    token_introspector,
+};
 use unleash_edge::{edge_api, prom_metrics};
 use unleash_edge::{internal_backstage, tls};
 
@@ -30,45 +48,124 @@ use unleash_edge::{internal_backstage, tls};
 #[actix_web::main]
 async fn main() -> Result<(), anyhow::Error> {
     use unleash_edge::{
-        http::{broadcaster::Broadcaster, unleash_client::ClientMetaInformation},
-        metrics::metrics_pusher,
+        http::{
+            broadcaster::Broadcaster, client_identity_watcher::ClientIdentityWatcher,
+            unleash_client::ClientMetaInformation,
+        },
+        metrics::{cache_metrics, metrics_pusher},
     };
 
-    let args = CliArgs::parse();
-    let disable_all_endpoint = args.disable_all_endpoint;
+    let command = CliArgs::command();
+    let matches = config_file::try_get_matches_from(&command, std::env::args_os())
+        .unwrap_or_else(|e| e.exit());
+    let mut args = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let effective_config = Arc::new(EffectiveConfig::from_matches(&command, &matches));
+    let all_endpoint_access = AllEndpointAccess {
+        disabled: args.disable_all_endpoint,
+        enabled_environments: args
+            .all_endpoint_enabled_environments
+            .iter()
+            .cloned()
+            .collect(),
+        disabled_environments: args
+            .all_endpoint_disabled_environments
+            .iter()
+            .cloned()
+            .collect(),
+    };
+    let enable_eval_debug = args.enable_eval_debug;
+    let access_log = args.access_log;
+    let evaluation_coalescer = args
+        .coalesce_frontend_evaluations
+        .then(|| Arc::new(EvaluationCoalescer::default()));
+    let context_limits = Arc::new(ContextLimits {
+        max_properties: args.max_context_properties,
+        max_bytes: args.max_context_bytes,
+        max_bulk_contexts: args.max_bulk_contexts,
+    });
     if args.markdown_help {
         clap_markdown::print_help_markdown::<CliArgs>();
         return Ok(());
     }
-    if let EdgeMode::Health(args) = args.mode {
-        return health_checker::check_health(args)
+    if let EdgeMode::Health(mut health_args) = args.mode {
+        if health_args.base_path.is_empty() {
+            health_args.base_path = args.http.base_path;
+        }
+        return health_checker::check_health(health_args)
             .await
             .map_err(|e| e.into());
     };
-    if let EdgeMode::Ready(args) = args.mode {
-        return ready_checker::check_ready(args).await.map_err(|e| e.into());
+    if let EdgeMode::Introspect(introspect_args) = args.mode {
+        return token_introspector::print_introspection(introspect_args).map_err(|e| e.into());
     }
+    if let EdgeMode::Ready(mut ready_args) = args.mode {
+        if ready_args.base_path.is_empty() {
+            ready_args.base_path = args.http.base_path;
+        }
+        return ready_checker::check_ready(ready_args)
+            .await
+            .map_err(|e| e.into());
+    }
+    let instance_id = builder::instance_id(&args);
+    args.instance_id = Some(instance_id.clone());
     let schedule_args = args.clone();
     let mode_arg = args.clone().mode;
     let http_args = args.clone().http;
     let token_header = args.clone().token_header;
+    let endpoint_token_headers = EndpointTokenHeaders {
+        client: args.client_token_header.clone(),
+        frontend: args.frontend_token_header.clone(),
+    };
     let request_timeout = args.edge_request_timeout;
     let keepalive_timeout = args.edge_keepalive_timeout;
     let trust_proxy = args.clone().trust_proxy;
     let base_path = http_args.base_path.clone();
     let (metrics_handler, request_metrics) = prom_metrics::instantiate(None, &args.log_format);
+    if let Some(startup_summary) = StartupSummary::from_mode(&args.mode) {
+        startup_summary.log();
+    }
     let connect_via = ConnectVia {
         app_name: args.clone().app_name,
-        instance_id: args.clone().instance_id,
+        instance_id: instance_id.clone(),
     };
     let app_name = args.app_name.clone();
-    let instance_id = args.instance_id.clone();
     let custom_headers = match args.mode {
         cli::EdgeMode::Edge(ref edge) => edge.custom_client_headers.clone(),
         _ => vec![],
     };
+    let frontend_jwks_file = match args.mode {
+        cli::EdgeMode::Edge(ref edge) => edge.frontend_jwks_file.as_ref(),
+        cli::EdgeMode::Offline(ref offline) => offline.frontend_jwks_file.as_ref(),
+        _ => None,
+    };
+    let frontend_jwks = frontend_jwks_file
+        .map(|path| JwksValidator::from_file(path).map(Arc::new))
+        .transpose()?;
+    let max_streams_per_token = match args.mode {
+        cli::EdgeMode::Edge(ref edge) => edge.max_streams_per_token,
+        _ => None,
+    };
+    let max_metrics_buffer = match args.mode {
+        cli::EdgeMode::Edge(ref edge) => edge.max_metrics_buffer,
+        _ => None,
+    };
+    let metrics_bin_granularity = match args.mode {
+        cli::EdgeMode::Edge(ref edge) => edge.metrics_bin_granularity,
+        _ => Default::default(),
+    };
+    let metrics_sampler = match args.mode {
+        cli::EdgeMode::Edge(ref edge) => edge.metrics_sampling_rate.map(MetricsSampler::new),
+        _ => None,
+    };
 
     let internal_backstage_args = args.internal_backstage.clone();
+    let maintenance_mode = Arc::new(MaintenanceMode::from(&internal_backstage_args));
+    let in_flight = Arc::new(InFlightRequests::default());
+    let in_flight_for_shutdown = in_flight.clone();
+    let forced_refresh_limiter = Arc::new(ForcedRefreshLimiter::from(&internal_backstage_args));
+    let body_limit = RequestBodyLimit(http_args.max_request_body_bytes);
+    let response_encodings = AllowedResponseEncodings(http_args.response_compression.clone());
+    let configured_response_headers = ResponseHeaders::new(&http_args.response_header);
 
     let (
         (token_cache, features_cache, engine_cache),
@@ -77,20 +174,31 @@ async fn main() -> Result<(), anyhow::Error> {
         persistence,
     ) = build_caches_and_refreshers(args).await.unwrap();
 
+    if let Some(refresher) = feature_refresher.as_ref() {
+        tokio::spawn(FeatureRefresher::log_hydration_progress(
+            refresher.subscribe_to_hydration_progress(),
+        ));
+    }
+
     let token_validator_schedule = token_validator.clone();
     let lazy_feature_cache = features_cache.clone();
     let lazy_token_cache = token_cache.clone();
     let lazy_engine_cache = engine_cache.clone();
     let lazy_feature_refresher = feature_refresher.clone();
 
-    let metrics_cache = Arc::new(MetricsCache::default());
+    let metrics_cache = Arc::new(
+        MetricsCache::new_with_max_buffer(max_metrics_buffer)
+            .with_sampler(metrics_sampler)
+            .with_bin_granularity(metrics_bin_granularity),
+    );
     let metrics_cache_clone = metrics_cache.clone();
 
     let openapi = openapi::ApiDoc::openapi();
     let refresher_for_app_data = feature_refresher.clone();
     let prom_registry_for_write = metrics_handler.registry.clone();
 
-    let broadcaster = Broadcaster::new(features_cache.clone());
+    let broadcaster =
+        Broadcaster::new_with_max_streams_per_token(features_cache.clone(), max_streams_per_token);
 
     let server = HttpServer::new(move || {
         let qs_config =
@@ -104,6 +212,7 @@ async fn main() -> Result<(), anyhow::Error> {
         let mut app = App::new()
             .app_data(qs_config)
             .app_data(web::Data::new(token_header.clone()))
+            .app_data(web::Data::new(endpoint_token_headers.clone()))
             .app_data(web::Data::new(trust_proxy.clone()))
             .app_data(web::Data::new(mode_arg.clone()))
             .app_data(web::Data::new(connect_via.clone()))
@@ -111,24 +220,49 @@ async fn main() -> Result<(), anyhow::Error> {
             .app_data(web::Data::from(token_cache.clone()))
             .app_data(web::Data::from(features_cache.clone()))
             .app_data(web::Data::from(engine_cache.clone()))
-            .app_data(web::Data::from(broadcaster.clone()));
+            .app_data(web::Data::from(broadcaster.clone()))
+            .app_data(web::Data::from(maintenance_mode.clone()))
+            .app_data(web::Data::from(in_flight.clone()))
+            .app_data(web::Data::from(forced_refresh_limiter.clone()))
+            .app_data(web::Data::from(context_limits.clone()))
+            .app_data(web::Data::new(body_limit))
+            .app_data(web::Data::new(response_encodings.clone()))
+            .app_data(web::Data::new(configured_response_headers.clone()))
+            .app_data(web::Data::from(effective_config.clone()))
+            .app_data(body_limit.json_config());
 
         app = match token_validator.clone() {
             Some(v) => app.app_data(web::Data::from(v)),
             None => app,
         };
+        app = match evaluation_coalescer.clone() {
+            Some(coalescer) => app.app_data(web::Data::from(coalescer)),
+            None => app,
+        };
+        app = match frontend_jwks.clone() {
+            Some(jwks) => app.app_data(web::Data::from(jwks)),
+            None => app,
+        };
         app = match refresher_for_app_data.clone() {
             Some(refresher) => app.app_data(web::Data::from(refresher)),
             None => app,
         };
         app.service(
             web::scope(&base_path)
+                .wrap(Condition::new(
+                    access_log,
+                    as_async_middleware(access_log::access_log),
+                ))
                 .wrap(Etag)
                 .wrap(actix_web::middleware::Compress::default())
+                .wrap(as_async_middleware(
+                    response_compression::response_compression,
+                ))
                 .wrap(actix_web::middleware::NormalizePath::default())
                 .wrap(cors_middleware)
                 .wrap(request_metrics.clone())
                 .wrap(Logger::default())
+                .wrap(as_async_middleware(in_flight_requests::in_flight_requests))
                 .service(web::scope("/internal-backstage").configure(|service_cfg| {
                     internal_backstage::configure_internal_backstage(
                         service_cfg,
@@ -138,12 +272,22 @@ async fn main() -> Result<(), anyhow::Error> {
                 }))
                 .service(
                     web::scope("/api")
+                        .wrap(as_async_middleware(maintenance_mode::maintenance_mode))
+                        .wrap(as_async_middleware(request_body_limit::request_body_limit))
+                        .wrap(as_async_middleware(staleness_headers::staleness_headers))
+                        .wrap(as_async_middleware(response_headers::response_headers))
                         .configure(client_api::configure_client_api)
+                        .configure(|cfg| frontend_api::configure_eval_debug(cfg, enable_eval_debug))
                         .configure(|cfg| {
-                            frontend_api::configure_frontend_api(cfg, disable_all_endpoint)
+                            frontend_api::configure_frontend_api(cfg, all_endpoint_access.clone())
                         }),
                 )
-                .service(web::scope("/edge").configure(edge_api::configure_edge_api))
+                .service(
+                    web::scope("/edge")
+                        .wrap(as_async_middleware(maintenance_mode::maintenance_mode))
+                        .wrap(as_async_middleware(request_body_limit::request_body_limit))
+                        .configure(edge_api::configure_edge_api),
+                )
                 .service(
                     SwaggerUi::new("/swagger-ui/{_:.*}")
                         .url("/api-doc/openapi.json", openapi.clone()),
@@ -161,23 +305,38 @@ async fn main() -> Result<(), anyhow::Error> {
     };
     let server = server?
         .workers(http_args.workers)
-        .shutdown_timeout(5)
+        .shutdown_timeout(http_args.shutdown_grace_seconds)
+        .disable_signals()
         .keep_alive(std::time::Duration::from_secs(keepalive_timeout))
         .client_request_timeout(std::time::Duration::from_secs(request_timeout));
+    let server = serve_until_stopped(
+        server.run(),
+        in_flight_for_shutdown,
+        std::time::Duration::from_secs(http_args.shutdown_grace_seconds),
+    );
 
+    let metrics_sink = match (&schedule_args.mode, feature_refresher.clone()) {
+        (cli::EdgeMode::Edge(edge), Some(refresher)) => {
+            Some(metrics_sink::metrics_sink(edge, refresher))
+        }
+        _ => None,
+    };
     match schedule_args.mode {
         cli::EdgeMode::Edge(edge) => {
+            let startup_tokens = startup_tokens(&edge);
             let refresher_for_background = feature_refresher.clone().unwrap();
+            let client_meta_information = ClientMetaInformation {
+                app_name: app_name.clone(),
+                instance_id,
+                client_spec_version: edge.client_spec_version.clone(),
+            };
             if edge.streaming {
-                let app_name = app_name.clone();
+                let client_meta_information = client_meta_information.clone();
                 let custom_headers = custom_headers.clone();
                 tokio::spawn(async move {
                     let _ = refresher_for_background
                         .start_streaming_features_background_task(
-                            ClientMetaInformation {
-                                app_name,
-                                instance_id,
-                            },
+                            client_meta_information,
                             custom_headers,
                         )
                         .await;
@@ -186,29 +345,45 @@ async fn main() -> Result<(), anyhow::Error> {
 
             let refresher = feature_refresher.clone().unwrap();
 
+            if edge.client_identity_reload_interval_seconds > 0 {
+                if let Some(watcher) = ClientIdentityWatcher::new(
+                    refresher.unleash_client.clone(),
+                    builder::http_client_args(&edge, client_meta_information),
+                ) {
+                    tokio::spawn(watcher.watch(std::time::Duration::from_secs(
+                        edge.client_identity_reload_interval_seconds,
+                    )));
+                }
+            }
+
             let validator = token_validator_schedule.clone().unwrap();
+            // A snapshot stands in for upstream, so features and tokens are not fetched from it and metrics are not posted to it
+            let from_upstream = edge.features_snapshot.is_none();
 
             tokio::select! {
-                _ = server.run() => {
+                _ = server => {
                     tracing::info!("Actix is shutting down. Persisting data");
-                    clean_shutdown(persistence.clone(), lazy_feature_cache.clone(), lazy_token_cache.clone(), metrics_cache_clone.clone(), feature_refresher.clone()).await;
+                    clean_shutdown(persistence.clone(), lazy_feature_cache.clone(), lazy_token_cache.clone(), metrics_cache_clone.clone(), feature_refresher.clone(), metrics_sink.clone().filter(|_| from_upstream)).await;
                     tracing::info!("Actix was shutdown properly");
                 },
-                _ = refresher.start_refresh_features_background_task() => {
+                _ = refresher.start_refresh_features_background_task(), if from_upstream => {
                     tracing::info!("Feature refresher unexpectedly shut down");
                 }
-                _ = unleash_edge::http::background_send_metrics::send_metrics_task(metrics_cache_clone.clone(), refresher.clone(), edge.metrics_interval_seconds.try_into().unwrap()) => {
+                _ = unleash_edge::http::background_send_metrics::send_metrics_task(metrics_cache_clone.clone(), metrics_sink.clone().unwrap(), edge.metrics_interval_seconds.try_into().unwrap()), if from_upstream => {
                     tracing::info!("Metrics poster unexpectedly shut down");
                 }
-                _ = persist_data(persistence.clone(), lazy_token_cache.clone(), lazy_feature_cache.clone()) => {
+                _ = persist_data(persistence.clone(), lazy_token_cache.clone(), lazy_feature_cache.clone(), feature_refresher.clone()) => {
                     tracing::info!("Persister was unexpectedly shut down");
                 }
-                _ = validator.schedule_validation_of_known_tokens(edge.token_revalidation_interval_seconds) => {
+                _ = validator.schedule_validation_of_known_tokens(edge.token_revalidation_interval_seconds, feature_refresher.clone()), if from_upstream => {
                     tracing::info!("Token validator validation of known tokens was unexpectedly shut down");
                 }
-                _ = validator.schedule_revalidation_of_startup_tokens(edge.tokens, lazy_feature_refresher) => {
+                _ = validator.schedule_revalidation_of_startup_tokens(startup_tokens, lazy_feature_refresher), if from_upstream => {
                     tracing::info!("Token validator validation of startup tokens was unexpectedly shut down");
                 }
+                _ = cache_metrics::record_cache_sizes_periodically(lazy_feature_cache.clone(), lazy_engine_cache.clone(), feature_refresher.clone()) => {
+                    tracing::info!("Cache size metrics unexpectedly shut down");
+                }
                 _ = metrics_pusher::prometheus_remote_write(prom_registry_for_write, edge.prometheus_remote_write_url, edge.prometheus_push_interval, edge.prometheus_username, edge.prometheus_password, app_name) => {
                     tracing::info!("Prometheus push unexpectedly shut down");
                 }
@@ -216,18 +391,18 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         cli::EdgeMode::Offline(offline_args) if offline_args.reload_interval > 0 => {
             tokio::select! {
-                _ = offline_hotload::start_hotload_loop(lazy_feature_cache, lazy_engine_cache, offline_args) => {
+                _ = offline_hotload::start_hotload_loop(lazy_feature_cache, lazy_engine_cache, lazy_token_cache, offline_args) => {
                     tracing::info!("Hotloader unexpectedly shut down.");
                 },
-                _ = server.run() => {
+                _ = server => {
                     tracing::info!("Actix is shutting down. No pending tasks.");
                 },
             }
         }
         _ => tokio::select! {
-            _ = server.run() => {
+            _ = server => {
                 tracing::info!("Actix is shutting down. Persisting data");
-                clean_shutdown(persistence, lazy_feature_cache.clone(), lazy_token_cache.clone(), metrics_cache_clone.clone(), feature_refresher.clone()).await;
+                clean_shutdown(persistence, lazy_feature_cache.clone(), lazy_token_cache.clone(), metrics_cache_clone.clone(), feature_refresher.clone(), metrics_sink.clone()).await;
                 tracing::info!("Actix was shutdown properly");
 
             }
@@ -237,6 +412,58 @@ async fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Stops the server on SIGTERM or SIGINT. After a SIGTERM, Edge stops accepting connections and gives the requests
+/// still in flight up to `grace` to finish before stopping the workers. Actix's own signal handling stops the workers
+/// right away, and a worker that notices the accept loop has gone before it gets its stop message exits without
+/// finishing its connections
+#[cfg(not(tarpaulin_include))]
+async fn serve_until_stopped(
+    server: actix_web::dev::Server,
+    in_flight: Arc<InFlightRequests>,
+    grace: std::time::Duration,
+) -> std::io::Result<()> {
+    #[cfg(unix)]
+    let terminate = async {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Could not listen for SIGTERM: {e:?}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    let handle = server.handle();
+    let mut server = actix_web::rt::spawn(server);
+    let graceful = tokio::select! {
+        result = &mut server => return result.map_err(std::io::Error::other)?,
+        _ = actix_web::rt::signal::ctrl_c() => {
+            info!("SIGINT received; stopping");
+            false
+        }
+        _ = terminate => {
+            info!("SIGTERM received; finishing in-flight requests for up to {} seconds", grace.as_secs());
+            true
+        }
+    };
+    if graceful {
+        handle.pause().await;
+        if !in_flight.drained(grace).await {
+            tracing::warn!(
+                "Shutting down with {} requests still in flight",
+                in_flight.count()
+            );
+        }
+    }
+    handle.stop(graceful).await;
+    server.await.map_err(std::io::Error::other)?
+}
+
 #[cfg(not(tarpaulin_include))]
 async fn clean_shutdown(
     persistence: Option<Arc<dyn EdgePersistence>>,
@@ -244,6 +471,7 @@ async fn clean_shutdown(
     token_cache: Arc<DashMap<String, EdgeToken>>,
     metrics_cache: Arc<MetricsCache>,
     feature_refresher: Option<Arc<FeatureRefresher>>,
+    metrics_sink: Option<Arc<dyn MetricsSink>>,
 ) {
     let tokens: Vec<EdgeToken> = token_cache
         .iter()
@@ -256,10 +484,17 @@ async fn clean_shutdown(
         .map(|entry| (entry.key().clone(), entry.value().clone()))
         .collect();
 
+    let delta = feature_refresher
+        .as_ref()
+        .filter(|refresher| refresher.delta)
+        .map(|refresher| refresher.delta_cache_snapshot())
+        .unwrap_or_default();
+
     if let Some(persistence) = persistence {
         let res = join_all(vec![
             persistence.save_tokens(tokens),
             persistence.save_features(features),
+            persistence.save_delta(delta),
         ])
         .await;
         if res.iter().all(|save| save.is_ok()) {
@@ -270,8 +505,8 @@ async fn clean_shutdown(
                 .for_each(|failed_save| tracing::error!("Failed backing up: {failed_save:?}"));
         }
     }
-    if let Some(feature_refresher) = feature_refresher {
-        info!("Connected to an upstream, flushing last set of metrics");
-        send_metrics_one_shot(metrics_cache, feature_refresher).await;
+    if let Some(metrics_sink) = metrics_sink {
+        info!("Flushing last set of metrics");
+        send_metrics_one_shot(metrics_cache, metrics_sink).await;
     }
 }
@@ -0,0 +1,125 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use prometheus::{register_int_gauge, register_int_gauge_vec, IntGauge, IntGaugeVec, Opts};
+use unleash_yggdrasil::EngineState;
+
+use crate::feature_cache::FeatureCache;
+use crate::http::refresher::feature_refresher::FeatureRefresher;
+use crate::types::TokenRefresh;
+
+/// How often cache sizes are recorded
+pub const CACHE_METRICS_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    pub static ref CACHE_ENTRIES: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("edge_cache_entries", "Number of entries in Edge's in-memory caches"),
+        &["cache"]
+    )
+    .unwrap();
+    pub static ref FEATURE_CACHE_ESTIMATED_BYTES: IntGauge = register_int_gauge!(Opts::new(
+        "edge_feature_cache_estimated_bytes",
+        "Estimated size of the feature cache in bytes, measured as the size of the cached features serialized to JSON"
+    ))
+    .unwrap();
+}
+
+/// Counts the bytes written to it, so sizes can be measured without buffering the serialized output
+#[derive(Default)]
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn record_cache_sizes(
+    features_cache: &FeatureCache,
+    engine_cache: &DashMap<String, EngineState>,
+    tokens_to_refresh: Option<&DashMap<String, TokenRefresh>>,
+) {
+    CACHE_ENTRIES
+        .with_label_values(&["features"])
+        .set(features_cache.len() as i64);
+    CACHE_ENTRIES
+        .with_label_values(&["engine"])
+        .set(engine_cache.len() as i64);
+    if let Some(tokens_to_refresh) = tokens_to_refresh {
+        CACHE_ENTRIES
+            .with_label_values(&["tokens"])
+            .set(tokens_to_refresh.len() as i64);
+    }
+    let mut counter = ByteCounter::default();
+    for features in features_cache.iter() {
+        let _ = serde_json::to_writer(&mut counter, features.value());
+    }
+    FEATURE_CACHE_ESTIMATED_BYTES.set(counter.0 as i64);
+}
+
+pub async fn record_cache_sizes_periodically(
+    features_cache: Arc<FeatureCache>,
+    engine_cache: Arc<DashMap<String, EngineState>>,
+    feature_refresher: Option<Arc<FeatureRefresher>>,
+) {
+    loop {
+        record_cache_sizes(
+            &features_cache,
+            &engine_cache,
+            feature_refresher
+                .as_ref()
+                .map(|refresher| refresher.tokens_to_refresh.as_ref()),
+        );
+        tokio::time::sleep(CACHE_METRICS_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dashmap::DashMap;
+    use unleash_types::client_features::{ClientFeature, ClientFeatures};
+    use unleash_yggdrasil::EngineState;
+
+    use crate::feature_cache::FeatureCache;
+    use crate::types::{EdgeToken, TokenRefresh};
+
+    use super::{record_cache_sizes, CACHE_ENTRIES, FEATURE_CACHE_ESTIMATED_BYTES};
+
+    #[test]
+    fn cache_sizes_are_recorded_per_cache() {
+        let features = ClientFeatures {
+            version: 2,
+            features: vec![ClientFeature {
+                name: "some-feature".into(),
+                ..ClientFeature::default()
+            }],
+            segments: None,
+            query: None,
+            meta: None,
+        };
+        let features_cache = FeatureCache::default();
+        features_cache.insert("development".into(), features.clone());
+        features_cache.insert("production".into(), features.clone());
+        let engine_cache = DashMap::new();
+        engine_cache.insert("development".to_string(), EngineState::default());
+        let tokens_to_refresh = DashMap::new();
+        let token = EdgeToken::try_from("*:development.secret123".to_string()).unwrap();
+        tokens_to_refresh.insert(token.token.clone(), TokenRefresh::new(token, None));
+
+        record_cache_sizes(&features_cache, &engine_cache, Some(&tokens_to_refresh));
+
+        assert_eq!(CACHE_ENTRIES.with_label_values(&["features"]).get(), 2);
+        assert_eq!(CACHE_ENTRIES.with_label_values(&["engine"]).get(), 1);
+        assert_eq!(CACHE_ENTRIES.with_label_values(&["tokens"]).get(), 1);
+        let serialized_size = serde_json::to_vec(&features).unwrap().len() as i64;
+        assert_eq!(FEATURE_CACHE_ESTIMATED_BYTES.get(), 2 * serialized_size);
+    }
+}
@@ -1,14 +1,21 @@
+use crate::cli::MetricsBinGranularity;
 use crate::types::{BatchMetricsRequestBody, EdgeToken};
 use actix_web::web::Data;
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use iter_tools::Itertools;
 use lazy_static::lazy_static;
-use prometheus::{register_histogram, register_int_counter_vec, Histogram, IntCounterVec};
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, Histogram, IntCounter,
+    IntCounterVec,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     hash::{Hash, Hasher},
+    sync::Mutex,
 };
 use tracing::{debug, instrument};
 use unleash_types::client_metrics::{
@@ -32,6 +39,11 @@ lazy_static! {
         &["appName", "toggle", "active"]
     )
     .unwrap();
+    pub static ref METRICS_DROPPED: IntCounter = register_int_counter!(
+        "metrics_dropped_total",
+        "Number of metric buckets dropped because the in-memory metrics buffer was full"
+    )
+    .unwrap();
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -56,6 +68,7 @@ impl From<ClientMetricsEnv> for MetricsKey {
             feature_name: value.feature_name,
             timestamp: value.timestamp,
             environment: value.environment,
+            bin_granularity: MetricsBinGranularity::default(),
         }
     }
 }
@@ -66,6 +79,8 @@ pub struct MetricsKey {
     pub feature_name: String,
     pub environment: String,
     pub timestamp: DateTime<Utc>,
+    #[serde(skip)]
+    pub bin_granularity: MetricsBinGranularity,
 }
 
 impl Hash for MetricsKey {
@@ -73,23 +88,26 @@ impl Hash for MetricsKey {
         self.app_name.hash(state);
         self.feature_name.hash(state);
         self.environment.hash(state);
-        to_time_key(&self.timestamp).hash(state);
+        to_time_key(&self.timestamp, self.bin_granularity).hash(state);
     }
 }
 
-fn to_time_key(timestamp: &DateTime<Utc>) -> String {
-    format!("{}", timestamp.format("%Y-%m-%d %H"))
+fn to_time_key(timestamp: &DateTime<Utc>, bin_granularity: MetricsBinGranularity) -> String {
+    match bin_granularity {
+        MetricsBinGranularity::Hour => format!("{}", timestamp.format("%Y-%m-%d %H")),
+        MetricsBinGranularity::Minute => format!("{}", timestamp.format("%Y-%m-%d %H:%M")),
+    }
 }
 
 impl PartialEq for MetricsKey {
     fn eq(&self, other: &Self) -> bool {
-        let other_hour_bin = to_time_key(&other.timestamp);
-        let self_hour_bin = to_time_key(&self.timestamp);
+        let other_bin = to_time_key(&other.timestamp, other.bin_granularity);
+        let self_bin = to_time_key(&self.timestamp, self.bin_granularity);
 
         self.app_name == other.app_name
             && self.feature_name == other.feature_name
             && self.environment == other.environment
-            && self_hour_bin == other_hour_bin
+            && self_bin == other_bin
     }
 }
 
@@ -103,6 +121,59 @@ pub struct MetricsBatch {
 pub struct MetricsCache {
     pub(crate) applications: DashMap<ApplicationKey, ClientApplication>,
     pub(crate) metrics: DashMap<MetricsKey, ClientMetricsEnv>,
+    pub(crate) max_buffer: Option<usize>,
+    pub(crate) sampler: Option<MetricsSampler>,
+    pub(crate) bin_granularity: MetricsBinGranularity,
+}
+
+/// Keeps each incoming metric bucket with probability `rate`, scaling the counts of the kept ones by `1 / rate`,
+/// so the totals upstream sees stay the same on average
+#[derive(Debug)]
+pub struct MetricsSampler {
+    rate: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl MetricsSampler {
+    pub fn new(rate: f64) -> Self {
+        Self::with_rng(rate, StdRng::from_os_rng())
+    }
+
+    pub fn seeded(rate: f64, seed: u64) -> Self {
+        Self::with_rng(rate, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rate: f64, rng: StdRng) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    pub fn sample(&self, metrics: Vec<ClientMetricsEnv>) -> Vec<ClientMetricsEnv> {
+        if self.rate >= 1.0 {
+            return metrics;
+        }
+        if self.rate <= 0.0 {
+            return vec![];
+        }
+        let scale = |count: u32| (count as f64 / self.rate).round() as u32;
+        let mut rng = self.rng.lock().unwrap();
+        metrics
+            .into_iter()
+            .filter(|_| rng.random_bool(self.rate))
+            .map(|metric| ClientMetricsEnv {
+                yes: scale(metric.yes),
+                no: scale(metric.no),
+                variants: metric
+                    .variants
+                    .into_iter()
+                    .map(|(variant, count)| (variant, scale(count)))
+                    .collect(),
+                ..metric
+            })
+            .collect()
+    }
 }
 
 pub(crate) fn size_of_batch(batch: &MetricsBatch) -> usize {
@@ -227,6 +298,37 @@ pub(crate) fn cut_into_sendable_batches(batch: MetricsBatch) -> Vec<MetricsBatch
 }
 
 impl MetricsCache {
+    /// Caps the number of metric buckets held in memory. When exceeded, the buckets with the oldest timestamps are dropped
+    pub fn new_with_max_buffer(max_buffer: Option<usize>) -> Self {
+        Self {
+            max_buffer,
+            ..Default::default()
+        }
+    }
+
+    /// Only forwards a `rate` fraction of the metric buckets upstream, see [MetricsSampler]
+    pub fn with_sampler(self, sampler: Option<MetricsSampler>) -> Self {
+        Self { sampler, ..self }
+    }
+
+    /// How finely metrics are binned in time, hourly unless set
+    pub fn with_bin_granularity(self, bin_granularity: MetricsBinGranularity) -> Self {
+        Self {
+            bin_granularity,
+            ..self
+        }
+    }
+
+    fn key_for(&self, metric: &ClientMetricsEnv) -> MetricsKey {
+        MetricsKey {
+            app_name: metric.app_name.clone(),
+            feature_name: metric.feature_name.clone(),
+            timestamp: metric.timestamp,
+            environment: metric.environment.clone(),
+            bin_granularity: self.bin_granularity,
+        }
+    }
+
     pub fn get_metrics_by_environment(&self) -> HashMap<String, MetricsBatch> {
         let mut batches_by_environment = HashMap::new();
 
@@ -258,7 +360,7 @@ impl MetricsCache {
             self.applications.remove(&ApplicationKey::from(app.clone()));
         }
         for metric in batch.metrics.clone() {
-            self.metrics.remove(&MetricsKey::from(metric.clone()));
+            self.metrics.remove(&self.key_for(&metric));
         }
         METRICS_SIZE_HISTOGRAM.observe(size_of_batch(batch) as f64);
         if sendable(batch) {
@@ -292,7 +394,7 @@ impl MetricsCache {
             self.applications.remove(&ApplicationKey::from(app.clone()));
         }
         for metric in batch.metrics.clone() {
-            self.metrics.remove(&MetricsKey::from(metric.clone()));
+            self.metrics.remove(&self.key_for(&metric));
         }
         METRICS_SIZE_HISTOGRAM.observe(size_of_batch(&batch) as f64);
         if sendable(&batch) {
@@ -311,7 +413,8 @@ impl MetricsCache {
         for application in batch.applications {
             self.register_application(application);
         }
-        self.sink_metrics(&batch.metrics);
+        // These were counted and sampled when they were first sunk
+        self.merge_metrics(&batch.metrics);
     }
 
     pub fn sink_bulk_metrics(&self, metrics: BatchMetricsRequestBody, connect_via: &ConnectVia) {
@@ -342,13 +445,17 @@ impl MetricsCache {
             FEATURE_TOGGLE_USAGE_TOTAL
                 .with_label_values(&[&metric.app_name, &metric.feature_name, "false"])
                 .inc_by(metric.no as u64);
+        }
+        match &self.sampler {
+            Some(sampler) => self.merge_metrics(&sampler.sample(metrics.to_vec())),
+            None => self.merge_metrics(metrics),
+        }
+    }
+
+    fn merge_metrics(&self, metrics: &[ClientMetricsEnv]) {
+        for metric in metrics.iter() {
             self.metrics
-                .entry(MetricsKey {
-                    app_name: metric.app_name.clone(),
-                    feature_name: metric.feature_name.clone(),
-                    timestamp: metric.timestamp,
-                    environment: metric.environment.clone(),
-                })
+                .entry(self.key_for(metric))
                 .and_modify(|feature_stats| {
                     feature_stats.yes += metric.yes;
                     feature_stats.no += metric.no;
@@ -364,6 +471,26 @@ impl MetricsCache {
                 })
                 .or_insert_with(|| metric.clone());
         }
+        self.drop_oldest_over_max_buffer();
+    }
+
+    fn drop_oldest_over_max_buffer(&self) {
+        let Some(max_buffer) = self.max_buffer else {
+            return;
+        };
+        let overflow = self.metrics.len().saturating_sub(max_buffer);
+        if overflow == 0 {
+            return;
+        }
+        let mut keys: Vec<MetricsKey> = self.metrics.iter().map(|e| e.key().clone()).collect();
+        if overflow < keys.len() {
+            keys.select_nth_unstable_by_key(overflow, |key| key.timestamp);
+        }
+        for key in keys.iter().take(overflow) {
+            self.metrics.remove(key);
+        }
+        METRICS_DROPPED.inc_by(overflow as u64);
+        debug!("Metrics buffer is full with {max_buffer} buckets, dropped the {overflow} oldest");
     }
 }
 
@@ -419,6 +546,7 @@ mod test {
                     .unwrap()
                     .with_timezone(&Utc),
                 environment: "development".into(),
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -446,6 +574,49 @@ mod test {
         assert_eq!(found_metric.no, expected.no);
     }
 
+    #[test_case(MetricsBinGranularity::Hour, &["12:05:10", "12:45:00", "13:05:40"], 2; "hourly")]
+    #[test_case(MetricsBinGranularity::Minute, &["12:05:10", "12:05:50", "13:05:40"], 2; "per minute")]
+    #[test_case(MetricsBinGranularity::Minute, &["12:05:10", "12:45:00", "13:05:40"], 3; "per minute across hours")]
+    fn metrics_are_binned_by_the_configured_granularity(
+        bin_granularity: MetricsBinGranularity,
+        times: &[&str],
+        expected_buckets: usize,
+    ) {
+        let cache = MetricsCache::default().with_bin_granularity(bin_granularity);
+        let metrics: Vec<ClientMetricsEnv> = times
+            .iter()
+            .map(|time| ClientMetricsEnv {
+                app_name: "some-app".into(),
+                feature_name: "some-feature".into(),
+                environment: "development".into(),
+                timestamp: DateTime::parse_from_rfc3339(&format!("1867-11-07T{time}Z"))
+                    .unwrap()
+                    .with_timezone(&Utc),
+                yes: 1,
+                no: 0,
+                variants: HashMap::new(),
+                metadata: MetricsMetadata {
+                    platform_name: None,
+                    platform_version: None,
+                    sdk_version: None,
+                    yggdrasil_version: None,
+                },
+            })
+            .collect();
+
+        cache.sink_metrics(&metrics);
+
+        assert_eq!(cache.metrics.len(), expected_buckets);
+        let batches = cache.get_appropriately_sized_batches();
+        let total_yes: u32 = batches
+            .iter()
+            .flat_map(|batch| batch.metrics.iter())
+            .map(|metric| metric.yes)
+            .sum();
+        assert_eq!(total_yes, 3);
+        assert!(cache.metrics.is_empty());
+    }
+
     #[test]
     fn cache_aggregates_data_correctly_across_date_boundaries() {
         let cache = MetricsCache::default();
@@ -492,6 +663,7 @@ mod test {
                 feature_name: "some-feature".into(),
                 environment: "development".into(),
                 timestamp: a_long_time_ago,
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -518,6 +690,7 @@ mod test {
                 feature_name: "some-feature".into(),
                 environment: "development".into(),
                 timestamp: hundred_years_later,
+                bin_granularity: Default::default(),
             })
             .unwrap();
 
@@ -832,4 +1005,134 @@ mod test {
         assert!(metrics_by_env_map.contains_key("development"));
         assert!(metrics_by_env_map.contains_key("production"));
     }
+
+    #[test]
+    pub fn oldest_buckets_are_dropped_when_buffer_is_full() {
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let metrics: Vec<ClientMetricsEnv> = (0..5)
+            .map(|hour| ClientMetricsEnv {
+                feature_name: "feature_one".into(),
+                app_name: "my_app".into(),
+                environment: "development".into(),
+                timestamp: start + chrono::Duration::hours(hour),
+                yes: 1,
+                no: 0,
+                variants: Default::default(),
+                metadata: MetricsMetadata {
+                    platform_name: None,
+                    platform_version: None,
+                    sdk_version: None,
+                    yggdrasil_version: None,
+                },
+            })
+            .collect();
+        let cache = MetricsCache::new_with_max_buffer(Some(3));
+        let dropped_before = METRICS_DROPPED.get();
+
+        cache.sink_metrics(&metrics[..3]);
+        assert_eq!(cache.metrics.len(), 3);
+        cache.sink_metrics(&metrics[3..]);
+
+        assert_eq!(cache.metrics.len(), 3);
+        assert!(METRICS_DROPPED.get() - dropped_before >= 2);
+        let mut remaining: Vec<DateTime<Utc>> =
+            cache.metrics.iter().map(|e| e.key().timestamp).collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            metrics[2..].iter().map(|m| m.timestamp).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    pub fn sampling_forwards_a_fraction_of_the_buckets_with_scaled_counts() {
+        let metrics: Vec<ClientMetricsEnv> = (0..1000)
+            .map(|i| ClientMetricsEnv {
+                feature_name: format!("feature_{i}"),
+                app_name: "my_app".into(),
+                environment: "development".into(),
+                timestamp: Utc::now(),
+                yes: 3,
+                no: 1,
+                variants: HashMap::from([("blue".to_string(), 2)]),
+                metadata: MetricsMetadata {
+                    platform_name: None,
+                    platform_version: None,
+                    sdk_version: None,
+                    yggdrasil_version: None,
+                },
+            })
+            .collect();
+        let cache = MetricsCache::default().with_sampler(Some(MetricsSampler::seeded(0.5, 42)));
+        cache.sink_metrics(&metrics);
+
+        let batch = cache
+            .get_metrics_by_environment()
+            .remove("development")
+            .unwrap();
+        let forwarded: Vec<ClientMetricsEnv> = cache
+            .get_appropriately_sized_env_batches(&batch)
+            .into_iter()
+            .flat_map(|batch| batch.metrics)
+            .collect();
+
+        assert!(cache.metrics.is_empty());
+        assert!(
+            (400..=600).contains(&forwarded.len()),
+            "forwarded {} of 1000 buckets",
+            forwarded.len()
+        );
+        assert!(forwarded
+            .iter()
+            .all(|m| m.yes == 6 && m.no == 2 && m.variants["blue"] == 4));
+    }
+
+    #[test]
+    pub fn reinserted_batches_are_not_sampled_again() {
+        let metrics: Vec<ClientMetricsEnv> = (0..100)
+            .map(|i| ClientMetricsEnv {
+                feature_name: format!("feature_{i}"),
+                app_name: "my_app".into(),
+                environment: "development".into(),
+                timestamp: Utc::now(),
+                yes: 3,
+                no: 1,
+                variants: HashMap::new(),
+                metadata: MetricsMetadata {
+                    platform_name: None,
+                    platform_version: None,
+                    sdk_version: None,
+                    yggdrasil_version: None,
+                },
+            })
+            .collect();
+        let cache = MetricsCache::default().with_sampler(Some(MetricsSampler::seeded(0.5, 42)));
+        cache.sink_metrics(&metrics);
+        let drain = || {
+            let batch = cache
+                .get_metrics_by_environment()
+                .remove("development")
+                .unwrap();
+            let mut metrics: Vec<ClientMetricsEnv> = cache
+                .get_appropriately_sized_env_batches(&batch)
+                .into_iter()
+                .flat_map(|batch| batch.metrics)
+                .collect();
+            metrics.sort_by(|a, b| a.feature_name.cmp(&b.feature_name));
+            metrics
+        };
+
+        let first_attempt = drain();
+        cache.reinsert_batch(MetricsBatch {
+            applications: vec![],
+            metrics: first_attempt.clone(),
+        });
+        let retry = drain();
+
+        assert!(!first_attempt.is_empty());
+        assert_eq!(retry.len(), first_attempt.len());
+        assert!(retry.iter().all(|m| m.yes == 6 && m.no == 2));
+    }
 }
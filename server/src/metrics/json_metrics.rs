@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use serde::{Deserialize, Serialize};
+
+/// A metric family from the Prometheus registry, for tooling that ingests JSON rather than the Prometheus text format
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonMetricFamily {
+    pub name: String,
+    pub help: String,
+    #[serde(rename = "type")]
+    pub metric_type: String,
+    pub metrics: Vec<JsonMetric>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonMetric {
+    pub labels: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub value: JsonMetricValue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum JsonMetricValue {
+    Histogram {
+        count: u64,
+        sum: f64,
+        buckets: Vec<JsonBucket>,
+    },
+    Summary {
+        count: u64,
+        sum: f64,
+        quantiles: Vec<JsonQuantile>,
+    },
+    Value {
+        value: f64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonBucket {
+    pub le: f64,
+    /// Observations less than or equal to `le`
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonQuantile {
+    pub quantile: f64,
+    pub value: f64,
+}
+
+pub fn to_json(metric_families: &[MetricFamily]) -> Vec<JsonMetricFamily> {
+    metric_families
+        .iter()
+        .map(|family| JsonMetricFamily {
+            name: family.get_name().into(),
+            help: family.get_help().into(),
+            metric_type: type_name(family.get_field_type()).into(),
+            metrics: family
+                .get_metric()
+                .iter()
+                .map(|metric| JsonMetric {
+                    labels: metric
+                        .get_label()
+                        .iter()
+                        .map(|label| (label.get_name().into(), label.get_value().into()))
+                        .collect(),
+                    value: metric_value(family.get_field_type(), metric),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn type_name(metric_type: MetricType) -> &'static str {
+    match metric_type {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::SUMMARY => "summary",
+        MetricType::HISTOGRAM => "histogram",
+        MetricType::UNTYPED => "untyped",
+    }
+}
+
+fn metric_value(metric_type: MetricType, metric: &Metric) -> JsonMetricValue {
+    match metric_type {
+        MetricType::COUNTER => JsonMetricValue::Value {
+            value: metric.get_counter().get_value(),
+        },
+        MetricType::GAUGE => JsonMetricValue::Value {
+            value: metric.get_gauge().get_value(),
+        },
+        MetricType::UNTYPED => JsonMetricValue::Value {
+            value: metric.get_untyped().get_value(),
+        },
+        MetricType::HISTOGRAM => {
+            let histogram = metric.get_histogram();
+            JsonMetricValue::Histogram {
+                count: histogram.get_sample_count(),
+                sum: histogram.get_sample_sum(),
+                buckets: histogram
+                    .get_bucket()
+                    .iter()
+                    .map(|bucket| JsonBucket {
+                        le: bucket.get_upper_bound(),
+                        count: bucket.get_cumulative_count(),
+                    })
+                    .collect(),
+            }
+        }
+        MetricType::SUMMARY => {
+            let summary = metric.get_summary();
+            JsonMetricValue::Summary {
+                count: summary.get_sample_count(),
+                sum: summary.get_sample_sum(),
+                quantiles: summary
+                    .get_quantile()
+                    .iter()
+                    .map(|quantile| JsonQuantile {
+                        quantile: quantile.get_quantile(),
+                        value: quantile.get_value(),
+                    })
+                    .collect(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+
+    use super::{to_json, JsonMetricValue};
+
+    #[test]
+    fn counters_and_histograms_are_mapped_with_their_labels() {
+        let registry = Registry::new();
+        let counter = IntCounterVec::new(
+            Opts::new("json_test_counter", "A counter"),
+            &["environment"],
+        )
+        .unwrap();
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("json_test_histogram", "A histogram").buckets(vec![1.0, 5.0]),
+            &["environment"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        counter.with_label_values(&["development"]).inc_by(3);
+        histogram.with_label_values(&["development"]).observe(2.0);
+
+        let families = to_json(&registry.gather());
+
+        let counter = families
+            .iter()
+            .find(|family| family.name == "json_test_counter")
+            .unwrap();
+        assert_eq!(counter.metric_type, "counter");
+        assert_eq!(counter.metrics[0].labels["environment"], "development");
+        assert_eq!(
+            counter.metrics[0].value,
+            JsonMetricValue::Value { value: 3.0 }
+        );
+
+        let histogram = families
+            .iter()
+            .find(|family| family.name == "json_test_histogram")
+            .unwrap();
+        let JsonMetricValue::Histogram { count, buckets, .. } = &histogram.metrics[0].value else {
+            panic!("Expected a histogram");
+        };
+        assert_eq!(*count, 1);
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].count, 0);
+        assert_eq!(buckets[1].count, 1);
+    }
+}
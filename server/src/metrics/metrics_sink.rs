@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::cli::{EdgeArgs, MetricsSinkType};
+use crate::error::EdgeError;
+use crate::http::background_send_metrics::UpstreamMetricsSink;
+use crate::http::refresher::feature_refresher::FeatureRefresher;
+use crate::metrics::client_metrics::MetricsBatch;
+use crate::types::EdgeResult;
+
+/// Where the metrics Edge collects from SDKs end up. Batches are handed over per environment
+#[async_trait]
+pub trait MetricsSink: Send + Sync {
+    async fn send(&self, environment: &str, batch: MetricsBatch) -> EdgeResult<()>;
+}
+
+pub fn metrics_sink(
+    args: &EdgeArgs,
+    feature_refresher: Arc<FeatureRefresher>,
+) -> Arc<dyn MetricsSink> {
+    match args.metrics_sink {
+        MetricsSinkType::Upstream => Arc::new(UpstreamMetricsSink::new(feature_refresher)),
+        MetricsSinkType::File => Arc::new(FileMetricsSink::new(
+            args.metrics_sink_file
+                .clone()
+                .expect("clap requires --metrics-sink-file for the file metrics sink"),
+            args.metrics_sink_file_max_bytes,
+        )),
+        MetricsSinkType::Stdout => Arc::new(StdoutMetricsSink),
+        MetricsSinkType::None => Arc::new(DiscardingMetricsSink),
+    }
+}
+
+/// Appends every batch as a JSON line. Once the file would grow beyond `max_bytes`, it's moved to `<path>.1`,
+/// replacing the previous rotation, and a new file is started
+pub struct FileMetricsSink {
+    path: PathBuf,
+    max_bytes: u64,
+    write_lock: Mutex<()>,
+}
+
+impl FileMetricsSink {
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            path,
+            max_bytes,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        rotated.into()
+    }
+}
+
+fn sink_error(path: &Path, error: std::io::Error) -> EdgeError {
+    EdgeError::PersistenceError(format!(
+        "Could not write metrics to {}: {error}",
+        path.display()
+    ))
+}
+
+#[async_trait]
+impl MetricsSink for FileMetricsSink {
+    async fn send(&self, _environment: &str, batch: MetricsBatch) -> EdgeResult<()> {
+        let mut line = serde_json::to_vec(&batch)?;
+        line.push(b'\n');
+        let _guard = self.write_lock.lock().await;
+        let size = tokio::fs::metadata(&self.path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            tokio::fs::rename(&self.path, self.rotated_path())
+                .await
+                .map_err(|e| sink_error(&self.path, e))?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| sink_error(&self.path, e))?;
+        file.write_all(&line)
+            .await
+            .map_err(|e| sink_error(&self.path, e))?;
+        // tokio hands writes to a background task, flushing waits for them to land
+        file.flush().await.map_err(|e| sink_error(&self.path, e))
+    }
+}
+
+/// Prints every batch as a JSON line
+pub struct StdoutMetricsSink;
+
+#[async_trait]
+impl MetricsSink for StdoutMetricsSink {
+    async fn send(&self, _environment: &str, batch: MetricsBatch) -> EdgeResult<()> {
+        println!("{}", serde_json::to_string(&batch)?);
+        Ok(())
+    }
+}
+
+/// Drops metrics, so they don't pile up in memory when there's nowhere to send them
+pub struct DiscardingMetricsSink;
+
+#[async_trait]
+impl MetricsSink for DiscardingMetricsSink {
+    async fn send(&self, _environment: &str, _batch: MetricsBatch) -> EdgeResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use unleash_types::client_metrics::{ClientMetricsEnv, MetricsMetadata};
+
+    use super::{FileMetricsSink, MetricsSink};
+    use crate::http::background_send_metrics::send_metrics_one_shot;
+    use crate::metrics::client_metrics::{MetricsBatch, MetricsCache};
+
+    fn metric(feature_name: &str, environment: &str) -> ClientMetricsEnv {
+        ClientMetricsEnv {
+            feature_name: feature_name.into(),
+            app_name: "my_app".into(),
+            environment: environment.into(),
+            timestamp: Utc::now(),
+            yes: 1,
+            no: 0,
+            variants: Default::default(),
+            metadata: MetricsMetadata {
+                platform_name: None,
+                platform_version: None,
+                sdk_version: None,
+                yggdrasil_version: None,
+            },
+        }
+    }
+
+    fn read_batches(path: &std::path::Path) -> Vec<MetricsBatch> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    pub async fn file_sink_writes_a_json_line_per_batch() {
+        let path = std::env::temp_dir().join(format!("edge-metrics-{}.jsonl", ulid::Ulid::new()));
+        let sink: Arc<dyn MetricsSink> = Arc::new(FileMetricsSink::new(path.clone(), 1_000_000));
+        let metrics_cache = Arc::new(MetricsCache::default());
+        metrics_cache.sink_metrics(&[
+            metric("feature_one", "development"),
+            metric("feature_two", "production"),
+        ]);
+
+        send_metrics_one_shot(metrics_cache.clone(), sink).await;
+
+        let mut written: Vec<String> = read_batches(&path)
+            .into_iter()
+            .flat_map(|batch| batch.metrics)
+            .map(|metric| format!("{}:{}", metric.environment, metric.feature_name))
+            .collect();
+        written.sort();
+        assert_eq!(
+            written,
+            vec!["development:feature_one", "production:feature_two"]
+        );
+        assert!(metrics_cache.get_metrics_by_environment().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn file_sink_rotates_when_the_file_gets_too_large() {
+        let path = std::env::temp_dir().join(format!("edge-metrics-{}.jsonl", ulid::Ulid::new()));
+        let sink = FileMetricsSink::new(path.clone(), 10);
+        let batch = |feature_name: &str| MetricsBatch {
+            applications: vec![],
+            metrics: vec![metric(feature_name, "development")],
+        };
+
+        sink.send("development", batch("first")).await.unwrap();
+        sink.send("development", batch("second")).await.unwrap();
+
+        let current = read_batches(&path);
+        let rotated = read_batches(&sink.rotated_path());
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].metrics[0].feature_name, "second");
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(rotated[0].metrics[0].feature_name, "first");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(sink.rotated_path()).unwrap();
+    }
+}
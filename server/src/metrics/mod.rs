@@ -5,9 +5,13 @@ use tracing::trace;
 #[cfg(not(tarpaulin_include))]
 pub mod actix_web_metrics;
 
+pub mod cache_metrics;
 pub mod client_metrics;
+pub mod json_metrics;
 pub mod metrics_pusher;
+pub mod metrics_sink;
 pub mod route_formatter;
+pub mod upstream_latency;
 
 const EDGE_REQUIREMENT: &str = ">=17.0.0";
 const UNLEASH_REQUIREMENT: &str = ">=5.9.0";
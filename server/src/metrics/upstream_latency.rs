@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, Opts};
+
+/// How many of the most recent fetches per environment the quantiles are computed from
+const WINDOW_SIZE: usize = 128;
+/// Weight of a new window quantile in the smoothed value, lower values react slower to latency spikes
+const SMOOTHING_FACTOR: f64 = 0.2;
+const QUANTILES: [f64; 2] = [0.5, 0.95];
+
+lazy_static! {
+    pub static ref UPSTREAM_FEATURE_FETCH_LATENCY: UpstreamLatency = UpstreamLatency::default();
+}
+
+#[derive(Debug, Default)]
+struct LatencyWindow {
+    samples: VecDeque<f64>,
+    smoothed: [Option<f64>; QUANTILES.len()],
+}
+
+impl LatencyWindow {
+    fn record(&mut self, latency_ms: f64) {
+        if self.samples.len() == WINDOW_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency_ms);
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        for (smoothed, quantile) in self.smoothed.iter_mut().zip(QUANTILES) {
+            let current = nearest_rank(&sorted, quantile);
+            *smoothed = Some(match smoothed {
+                Some(previous) => SMOOTHING_FACTOR * current + (1.0 - SMOOTHING_FACTOR) * *previous,
+                None => current,
+            });
+        }
+    }
+}
+
+fn nearest_rank(sorted: &[f64], quantile: f64) -> f64 {
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Exports p50 and p95 of how long fetching features from upstream took per environment, in milliseconds.
+/// Quantiles are taken over the last fetches and exponentially smoothed, so a single slow fetch doesn't make
+/// dashboards jump
+#[derive(Clone, Debug)]
+pub struct UpstreamLatency {
+    template: GaugeVec,
+    windows: Arc<DashMap<String, LatencyWindow>>,
+}
+
+impl UpstreamLatency {
+    fn gauge() -> GaugeVec {
+        GaugeVec::new(
+            Opts::new(
+                "upstream_feature_fetch_latency_ms",
+                "Smoothed quantiles of how long fetching features from upstream took, in milliseconds",
+            ),
+            &["environment", "quantile"],
+        )
+        .unwrap()
+    }
+
+    pub fn record(&self, environment: &str, latency: Duration) {
+        self.windows
+            .entry(environment.into())
+            .or_default()
+            .record(latency.as_secs_f64() * 1000.0);
+    }
+
+    pub fn quantile(&self, environment: &str, quantile: f64) -> Option<f64> {
+        let window = self.windows.get(environment)?;
+        QUANTILES
+            .iter()
+            .position(|q| *q == quantile)
+            .and_then(|index| window.smoothed[index])
+    }
+}
+
+impl Default for UpstreamLatency {
+    fn default() -> Self {
+        Self {
+            template: Self::gauge(),
+            windows: Arc::new(DashMap::default()),
+        }
+    }
+}
+
+impl Collector for UpstreamLatency {
+    fn desc(&self) -> Vec<&Desc> {
+        self.template.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let gauge = Self::gauge();
+        for window in self.windows.iter() {
+            for (smoothed, quantile) in window.smoothed.iter().zip(QUANTILES) {
+                if let Some(smoothed) = smoothed {
+                    gauge
+                        .with_label_values(&[window.key().as_str(), &quantile.to_string()])
+                        .set(*smoothed);
+                }
+            }
+        }
+        gauge.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prometheus::core::Collector;
+
+    use super::{UpstreamLatency, WINDOW_SIZE};
+
+    #[test]
+    fn quantiles_converge_on_the_observed_latencies() {
+        let latency = UpstreamLatency::default();
+        for _ in 0..10 {
+            for ms in 1..=WINDOW_SIZE as u64 {
+                latency.record("development", Duration::from_millis(ms));
+            }
+        }
+        let p50 = latency.quantile("development", 0.5).unwrap();
+        let p95 = latency.quantile("development", 0.95).unwrap();
+        assert!((60.0..=68.0).contains(&p50), "p50 was {p50}");
+        assert!((118.0..=126.0).contains(&p95), "p95 was {p95}");
+        assert!(latency.quantile("production", 0.5).is_none());
+
+        let families = latency.collect();
+        assert_eq!(families[0].get_metric().len(), 2);
+    }
+
+    #[test]
+    fn a_single_slow_fetch_is_smoothed_out() {
+        let latency = UpstreamLatency::default();
+        for _ in 0..50 {
+            latency.record("development", Duration::from_millis(10));
+        }
+        latency.record("development", Duration::from_secs(5));
+        assert_eq!(latency.quantile("development", 0.5), Some(10.0));
+        assert!(latency.quantile("development", 0.95).unwrap() < 20.0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::CONTENT_LENGTH,
+};
+use tracing::info;
+
+/// Logs one line per request, with the route pattern as `endpoint` like the request metrics use. Fields
+/// become JSON properties when running with `--log-format json`.
+pub async fn access_log(
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let endpoint = req.match_pattern().unwrap_or_else(|| "default".into());
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let request_bytes = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok().and_then(|s| s.parse::<u64>().ok()));
+
+    let res = srv.call(req).await;
+
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    }
+    .as_u16();
+    let response_bytes = res
+        .as_ref()
+        .ok()
+        .and_then(|res| match res.response().body().size() {
+            BodySize::Sized(size) => Some(size),
+            _ => None,
+        });
+    info!(
+        endpoint,
+        method,
+        path,
+        status,
+        latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+        request_bytes,
+        response_bytes,
+        "{method} {path} {status}"
+    );
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+    use tracing_test::traced_test;
+
+    use crate::middleware::as_async_middleware::as_async_middleware;
+
+    #[actix_web::test]
+    #[traced_test]
+    async fn logs_one_line_per_request_with_route_status_and_size() {
+        let app = test::init_service(
+            App::new()
+                .wrap(as_async_middleware(super::access_log))
+                .route(
+                    "/api/frontend/features/{feature_name}",
+                    web::get().to(|| async { HttpResponse::Ok().body("enabled") }),
+                ),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/api/frontend/features/my-feature")
+            .to_request();
+
+        let res = test::call_service(&app, req).await;
+
+        assert!(res.status().is_success());
+        assert!(logs_contain(
+            "endpoint=\"/api/frontend/features/{feature_name}\""
+        ));
+        assert!(logs_contain("method=\"GET\""));
+        assert!(logs_contain("status=200"));
+        assert!(logs_contain("response_bytes=7"));
+    }
+}
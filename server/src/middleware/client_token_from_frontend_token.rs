@@ -31,10 +31,10 @@ pub async fn client_token_from_frontend_token(
     srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     if let Some(token_cache) = req.app_data::<Data<DashMap<String, EdgeToken>>>() {
-        if let Some(fe_token) = token_cache.get(&token.token) {
+        if let Some(fe_token) = tokens::known_token(token_cache, &token) {
             debug!(
                 "Token got extracted to {:#?}",
-                tokens::anonymize_token(fe_token.value())
+                tokens::anonymize_token(&fe_token)
             );
             if fe_token.status == TokenValidationStatus::Validated {
                 create_client_token_for_fe_token(&req, &fe_token).await?;
@@ -65,7 +65,7 @@ mod tests {
     use crate::auth::token_validator::TokenValidator;
     use crate::feature_cache::FeatureCache;
     use crate::http::refresher::feature_refresher::FeatureRefresher;
-    use crate::http::unleash_client::{new_reqwest_client, UnleashClient};
+    use crate::http::unleash_client::{new_reqwest_client, HttpClientArgs, UnleashClient};
     use crate::tests::upstream_server;
     use crate::types::{EdgeToken, TokenType, TokenValidationStatus};
 
@@ -103,7 +103,10 @@ mod tests {
                         web::scope("/api")
                             .configure(crate::client_api::configure_client_api)
                             .configure(|cfg| {
-                                crate::frontend_api::configure_frontend_api(cfg, false)
+                                crate::frontend_api::configure_frontend_api(
+                                    cfg,
+                                    crate::frontend_api::AllEndpointAccess::default(),
+                                )
                             }),
                     )
                     .service(web::scope("/edge").configure(crate::edge_api::configure_edge_api)),
@@ -131,14 +134,11 @@ mod tests {
         )
         .await;
 
-        let http_client = new_reqwest_client(
-            false,
-            None,
-            None,
-            Duration::seconds(5),
-            Duration::seconds(5),
-            crate::http::unleash_client::ClientMetaInformation::test_config(),
-        )
+        let http_client = new_reqwest_client(HttpClientArgs {
+            client_meta_information:
+                crate::http::unleash_client::ClientMetaInformation::test_config(),
+            ..Default::default()
+        })
         .expect("Failed to create client");
 
         let unleash_client = UnleashClient::from_url(
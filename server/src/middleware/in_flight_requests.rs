@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    web::Data,
+};
+
+/// The number of requests Edge is still handling. A graceful shutdown waits for this to reach zero, as actix may stop
+/// its workers before they have finished the connections they were serving
+#[derive(Debug, Default)]
+pub struct InFlightRequests(AtomicUsize);
+
+struct InFlightGuard(Arc<InFlightRequests>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl InFlightRequests {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn track(self: Arc<Self>) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self)
+    }
+
+    /// Waits until no requests are in flight, or `timeout` has passed. Returns whether every request finished
+    pub async fn drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.count() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+}
+
+/// Streaming responses stop counting once their headers are sent, so open streams don't hold up a shutdown
+pub async fn in_flight_requests(
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let _guard = req
+        .app_data::<Data<InFlightRequests>>()
+        .map(|in_flight| in_flight.clone().into_inner().track());
+    srv.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix_web::{
+        test::{call_service, init_service, read_body, TestRequest},
+        web::{self, Data},
+        App, HttpResponse,
+    };
+
+    use super::{in_flight_requests, InFlightRequests};
+    use crate::middleware::as_async_middleware::as_async_middleware;
+
+    #[actix_web::test]
+    async fn requests_are_counted_until_they_have_been_handled() {
+        let in_flight = Arc::new(InFlightRequests::default());
+        let observed = in_flight.clone();
+        let app = init_service(
+            App::new()
+                .app_data(Data::from(in_flight.clone()))
+                .wrap(as_async_middleware(in_flight_requests))
+                .route(
+                    "/",
+                    web::get().to(move || {
+                        let count = observed.count();
+                        async move { HttpResponse::Ok().body(count.to_string()) }
+                    }),
+                ),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let body = read_body(response).await;
+
+        assert_eq!(body, "1");
+        assert_eq!(in_flight.count(), 0);
+        assert!(in_flight.drained(Duration::from_millis(10)).await);
+    }
+
+    #[tokio::test]
+    async fn draining_gives_up_after_the_timeout() {
+        let in_flight = Arc::new(InFlightRequests::default());
+        let _guard = in_flight.clone().track();
+
+        assert!(!in_flight.drained(Duration::from_millis(100)).await);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    web::Data,
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::cli::InternalBackstageArgs;
+
+/// Lets operators take an Edge out of rotation without stopping it. While enabled, client and
+/// frontend endpoints answer with the configured status and message, but health and backstage keep working.
+#[derive(Debug)]
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+    status_code: StatusCode,
+    message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MaintenanceToggle {
+    pub enabled: bool,
+}
+
+impl Default for MaintenanceMode {
+    fn default() -> Self {
+        MaintenanceMode::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Unleash Edge is down for maintenance".into(),
+        )
+    }
+}
+
+impl From<&InternalBackstageArgs> for MaintenanceMode {
+    fn from(args: &InternalBackstageArgs) -> Self {
+        MaintenanceMode::new(
+            StatusCode::from_u16(args.maintenance_status_code)
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+            args.maintenance_message.clone(),
+        )
+    }
+}
+
+impl MaintenanceMode {
+    pub fn new(status_code: StatusCode, message: String) -> Self {
+        MaintenanceMode {
+            enabled: AtomicBool::new(false),
+            status_code,
+            message,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            info!(
+                "Maintenance mode {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            enabled: self.is_enabled(),
+            message: self.message.clone(),
+        }
+    }
+}
+
+pub async fn maintenance_mode(
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    match req.app_data::<Data<MaintenanceMode>>() {
+        Some(maintenance) if maintenance.is_enabled() => {
+            let response = HttpResponse::build(maintenance.status_code).json(maintenance.status());
+            Ok(req.into_response(response).map_into_right_body())
+        }
+        _ => Ok(srv.call(req).await?.map_into_left_body()),
+    }
+}
@@ -5,3 +5,17 @@ pub mod validate_token;
 pub mod client_token_from_frontend_token;
 
 pub mod enrich_with_client_ip;
+
+pub mod maintenance_mode;
+
+pub mod access_log;
+
+pub mod request_body_limit;
+
+pub mod staleness_headers;
+
+pub mod response_compression;
+
+pub mod response_headers;
+
+pub mod in_flight_requests;
@@ -0,0 +1,126 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::CONTENT_LENGTH,
+    web::Data,
+};
+
+use crate::error::EdgeError;
+
+/// Matches the default limit of actix' JSON extractor
+pub const DEFAULT_MAX_REQUEST_BODY_BYTES: usize = 2_097_152;
+
+/// Largest request body accepted by the API endpoints. Requests announcing a larger body are rejected
+/// before it is read; bodies without a Content-Length are capped by the JSON extractor config instead.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestBodyLimit(pub usize);
+
+impl Default for RequestBodyLimit {
+    fn default() -> Self {
+        RequestBodyLimit(DEFAULT_MAX_REQUEST_BODY_BYTES)
+    }
+}
+
+impl RequestBodyLimit {
+    pub fn json_config(&self) -> actix_web::web::JsonConfig {
+        let limit = self.0;
+        actix_web::web::JsonConfig::default()
+            .limit(limit)
+            .error_handler(move |err, _req| match err {
+                actix_web::error::JsonPayloadError::Overflow { .. }
+                | actix_web::error::JsonPayloadError::OverflowKnownLength { .. } => {
+                    EdgeError::PayloadTooLarge(limit).into()
+                }
+                err => err.into(),
+            })
+    }
+}
+
+pub async fn request_body_limit(
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(limit) = req.app_data::<Data<RequestBodyLimit>>() {
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        if content_length.is_some_and(|length| length > limit.0) {
+            return Err(EdgeError::PayloadTooLarge(limit.0).into());
+        }
+    }
+    srv.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        post,
+        test::{call_service, init_service, TestRequest},
+        web::{self, Data, Json},
+        App, HttpResponse,
+    };
+    use serde_json::Value;
+
+    use super::{request_body_limit, RequestBodyLimit};
+    use crate::middleware::as_async_middleware::as_async_middleware;
+
+    #[post("/echo")]
+    async fn echo(body: Json<Value>) -> HttpResponse {
+        HttpResponse::Ok().json(body.into_inner())
+    }
+
+    fn body_of_size(size: usize) -> String {
+        // A JSON string literal, so the quotes count towards the size
+        format!("\"{}\"", "a".repeat(size - 2))
+    }
+
+    #[actix_web::test]
+    async fn bodies_at_the_limit_are_accepted_and_larger_bodies_get_413() {
+        let limit = RequestBodyLimit(64);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(limit))
+                .app_data(limit.json_config())
+                .service(
+                    web::scope("/api")
+                        .wrap(as_async_middleware(request_body_limit))
+                        .service(echo),
+                ),
+        )
+        .await;
+
+        let at_limit = TestRequest::post()
+            .uri("/api/echo")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body_of_size(64))
+            .to_request();
+        let res = call_service(&app, at_limit).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let over_limit = TestRequest::post()
+            .uri("/api/echo")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body_of_size(65))
+            .to_request();
+        let res = call_service(&app, over_limit).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn bodies_without_content_length_are_capped_by_the_json_config() {
+        let limit = RequestBodyLimit(64);
+        let app = init_service(App::new().app_data(limit.json_config()).service(echo)).await;
+
+        let mut req = TestRequest::post()
+            .uri("/echo")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body_of_size(65))
+            .to_request();
+        req.headers_mut().remove("Content-Length");
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
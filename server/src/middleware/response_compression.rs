@@ -0,0 +1,169 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderValue, ACCEPT_ENCODING},
+    web::Data,
+};
+
+use crate::cli::ResponseCompression;
+
+/// Content-Encodings responses may be compressed with. Empty allows everything actix' Compress middleware supports
+#[derive(Clone, Debug, Default)]
+pub struct AllowedResponseEncodings(pub Vec<ResponseCompression>);
+
+impl AllowedResponseEncodings {
+    /// Drops the codings we're not allowed to use from an Accept-Encoding header, expanding `*` into the allowed ones.
+    /// Returns None when nothing but identity is left, so the header can be removed
+    fn filter_accept_encoding(&self, accept_encoding: &str) -> Option<String> {
+        if self.0.contains(&ResponseCompression::None) {
+            return None;
+        }
+        let allowed: Vec<&str> = self.0.iter().filter_map(|c| c.content_coding()).collect();
+        let mut accepted = vec![];
+        for entry in accept_encoding.split(',').map(str::trim) {
+            let (coding, params) = entry.split_once(';').unwrap_or((entry, ""));
+            let coding = coding.trim();
+            if coding == "*" {
+                accepted.extend(allowed.iter().map(|allowed| match params {
+                    "" => allowed.to_string(),
+                    params => format!("{allowed};{params}"),
+                }));
+            } else if allowed.iter().any(|a| a.eq_ignore_ascii_case(coding)) {
+                accepted.push(entry.to_string());
+            }
+        }
+        if accepted.is_empty() {
+            None
+        } else {
+            Some(accepted.join(", "))
+        }
+    }
+}
+
+/// Needs to wrap the Compress middleware, so it only sees the encodings `--response-compression` allows
+pub async fn response_compression(
+    mut req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let filtered = match req.app_data::<Data<AllowedResponseEncodings>>() {
+        Some(allowed) if !allowed.0.is_empty() => req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .map(|value| allowed.filter_accept_encoding(value.to_str().unwrap_or_default())),
+        _ => None,
+    };
+    match filtered {
+        Some(Some(accept_encoding)) => {
+            if let Ok(value) = HeaderValue::from_str(&accept_encoding) {
+                req.headers_mut().insert(ACCEPT_ENCODING, value);
+            }
+        }
+        Some(None) => {
+            req.headers_mut().remove(ACCEPT_ENCODING);
+        }
+        None => {}
+    }
+    srv.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use actix_middleware_etag::Etag;
+    use actix_web::{
+        get,
+        http::header::{CONTENT_ENCODING, ETAG},
+        middleware::Compress,
+        test::{call_service, init_service, read_body, TestRequest},
+        web::Data,
+        App, HttpResponse,
+    };
+    use test_case::test_case;
+
+    use super::{response_compression, AllowedResponseEncodings};
+    use crate::cli::ResponseCompression;
+    use crate::middleware::as_async_middleware::as_async_middleware;
+
+    fn large_body() -> String {
+        format!(
+            "{{\"features\":[{}]}}",
+            "{\"name\":\"feature\"},".repeat(200)
+        )
+    }
+
+    #[get("/features")]
+    async fn features() -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(large_body())
+    }
+
+    #[test_case(vec![], "br, gzip", Some("br"); "everything allowed by default")]
+    #[test_case(vec![ResponseCompression::Br, ResponseCompression::Gzip], "br", Some("br"); "brotli")]
+    #[test_case(vec![ResponseCompression::Gzip], "br, gzip", Some("gzip"); "brotli not allowed")]
+    #[test_case(vec![ResponseCompression::Gzip], "br", None; "nothing acceptable")]
+    #[test_case(vec![ResponseCompression::Br], "*", Some("br"); "wildcard")]
+    #[test_case(vec![ResponseCompression::None], "br, gzip", None; "compression disabled")]
+    #[actix_web::test]
+    async fn responses_are_compressed_with_allowed_encodings_only(
+        allowed: Vec<ResponseCompression>,
+        accept_encoding: &str,
+        expected_encoding: Option<&str>,
+    ) {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(AllowedResponseEncodings(allowed)))
+                .wrap(Etag)
+                .wrap(Compress::default())
+                .wrap(as_async_middleware(response_compression))
+                .service(features),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/features")
+            .insert_header(("Accept-Encoding", accept_encoding))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            res.headers()
+                .get(CONTENT_ENCODING)
+                .map(|v| v.to_str().unwrap()),
+            expected_encoding
+        );
+    }
+
+    #[actix_web::test]
+    async fn brotli_bodies_decode_to_the_uncompressed_body_with_the_same_etag() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(AllowedResponseEncodings(vec![
+                    ResponseCompression::Br,
+                    ResponseCompression::Gzip,
+                ])))
+                .wrap(Etag)
+                .wrap(Compress::default())
+                .wrap(as_async_middleware(response_compression))
+                .service(features),
+        )
+        .await;
+        let plain = call_service(&app, TestRequest::get().uri("/features").to_request()).await;
+        let plain_etag = plain.headers().get(ETAG).unwrap().clone();
+        assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+
+        let req = TestRequest::get()
+            .uri("/features")
+            .insert_header(("Accept-Encoding", "br"))
+            .to_request();
+        let compressed = call_service(&app, req).await;
+        assert_eq!(compressed.headers().get(CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(compressed.headers().get(ETAG).unwrap(), &plain_etag);
+        let body = read_body(compressed).await;
+        assert!(body.len() < large_body().len());
+        let mut decoded = String::new();
+        brotli::Decompressor::new(body.as_ref(), 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, large_body());
+    }
+}
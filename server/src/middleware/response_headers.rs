@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    web::Data,
+};
+
+/// Headers from `--response-header` added to every response from the client and frontend APIs
+#[derive(Clone, Debug, Default)]
+pub struct ResponseHeaders(pub Vec<(HeaderName, HeaderValue)>);
+
+impl ResponseHeaders {
+    /// Header names and values are validated by clap when parsing `--response-header`, anything invalid left is skipped
+    pub fn new(headers: &[(String, String)]) -> Self {
+        ResponseHeaders(
+            headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((
+                        HeaderName::from_str(name).ok()?,
+                        HeaderValue::from_str(value).ok()?,
+                    ))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Configured headers replace any the handler set with the same name, so e.g. Cache-Control can be tuned for CDNs
+pub async fn response_headers(
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let configured = req.app_data::<Data<ResponseHeaders>>().cloned();
+    let mut res = srv.call(req).await?;
+    if let Some(configured) = configured {
+        let headers = res.headers_mut();
+        for (name, _) in configured.0.iter() {
+            headers.remove(name);
+        }
+        for (name, value) in configured.0.iter() {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::header::{CACHE_CONTROL, VARY},
+        test::{call_service, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+
+    use super::{response_headers, ResponseHeaders};
+    use crate::builder::build_offline_mode;
+    use crate::middleware::as_async_middleware::as_async_middleware;
+
+    #[actix_web::test]
+    async fn configured_headers_are_added_to_frontend_responses() {
+        let client_features = crate::tests::features_from_disk("../examples/hostedexample.json");
+        let (token_cache, feature_cache, engine_cache) = build_offline_mode(
+            client_features,
+            vec!["dx:development.secret123".to_string()],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_cache))
+                .app_data(Data::from(engine_cache))
+                .app_data(Data::new(ResponseHeaders::new(&[
+                    ("Cache-Control".into(), "max-age=5".into()),
+                    ("Vary".into(), "Authorization".into()),
+                ])))
+                .service(
+                    web::scope("/api/frontend")
+                        .wrap(as_async_middleware(response_headers))
+                        .service(crate::frontend_api::get_frontend_all_features),
+                ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/api/frontend/all")
+            .insert_header(("Authorization", "dx:development.secret123"))
+            .to_request();
+
+        let res = call_service(&app, req).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "max-age=5");
+        assert_eq!(res.headers().get(VARY).unwrap(), "Authorization");
+    }
+}
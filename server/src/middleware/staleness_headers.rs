@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{HeaderName, HeaderValue},
+        StatusCode,
+    },
+    web::Data,
+};
+use dashmap::DashMap;
+
+use crate::http::headers::{UNLEASH_EDGE_AGE_HEADER, UNLEASH_EDGE_STALE_HEADER};
+use crate::http::refresher::feature_refresher::FeatureRefresher;
+use crate::types::EdgeToken;
+
+/// Tells SDKs that the features they got may be outdated, because upstream hasn't been reachable for longer
+/// than `--stale-threshold-seconds`
+pub async fn staleness_headers(
+    token: Option<EdgeToken>,
+    req: ServiceRequest,
+    srv: crate::middleware::as_async_middleware::Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let staleness = match (token, req.app_data::<Data<FeatureRefresher>>()) {
+        (Some(token), Some(refresher)) => {
+            // The token cache knows the environment of frontend and offline tokens, which the header alone may not
+            let token = req
+                .app_data::<Data<DashMap<String, EdgeToken>>>()
+                .and_then(|token_cache| token_cache.get(&token.token).map(|t| t.value().clone()))
+                .unwrap_or(token);
+            refresher.staleness(&token)
+        }
+        _ => None,
+    };
+    let mut res = srv.call(req).await?;
+    if let Some(age) = staleness {
+        if res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED {
+            let headers = res.headers_mut();
+            headers.insert(
+                HeaderName::from_str(UNLEASH_EDGE_STALE_HEADER).unwrap(),
+                HeaderValue::from_static("true"),
+            );
+            headers.insert(
+                HeaderName::from_str(UNLEASH_EDGE_AGE_HEADER).unwrap(),
+                HeaderValue::from(age.num_seconds()),
+            );
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use chrono::{Duration, Utc};
+    use dashmap::DashMap;
+
+    use super::staleness_headers;
+    use crate::feature_cache::FeatureCache;
+    use crate::http::refresher::feature_refresher::FeatureRefresher;
+    use crate::middleware::as_async_middleware::as_async_middleware;
+    use crate::tests::features_from_disk;
+    use crate::types::{EdgeToken, TokenRefresh, TokenType, TokenValidationStatus};
+
+    fn validated_client_token(token: &str) -> EdgeToken {
+        let mut token = EdgeToken::try_from(token.to_string()).unwrap();
+        token.token_type = Some(TokenType::Client);
+        token.status = TokenValidationStatus::Validated;
+        token
+    }
+
+    #[actix_web::test]
+    async fn responses_for_environments_not_refreshed_within_the_threshold_are_marked_stale() {
+        let stale_token = validated_client_token("*:development.stale");
+        let fresh_token = validated_client_token("*:production.fresh");
+        let features_cache = Arc::new(FeatureCache::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let tokens_to_refresh = Arc::new(DashMap::default());
+        for (token, age) in [
+            (stale_token.clone(), Duration::minutes(10)),
+            (fresh_token.clone(), Duration::seconds(5)),
+        ] {
+            features_cache.insert(
+                token.environment.clone().unwrap(),
+                features_from_disk("../examples/features.json"),
+            );
+            token_cache.insert(token.token.clone(), token.clone());
+            let mut refresh = TokenRefresh::new(token.clone(), None);
+            refresh.last_refreshed = Some(Utc::now() - age);
+            tokens_to_refresh.insert(token.token.clone(), refresh);
+        }
+        let feature_refresher = Arc::new(FeatureRefresher {
+            features_cache: features_cache.clone(),
+            tokens_to_refresh,
+            stale_threshold: Some(Duration::seconds(60)),
+            ..Default::default()
+        });
+        let app = init_service(
+            App::new()
+                .app_data(Data::from(features_cache))
+                .app_data(Data::from(token_cache))
+                .app_data(Data::from(feature_refresher))
+                .service(
+                    web::scope("/api")
+                        .wrap(as_async_middleware(staleness_headers))
+                        .configure(crate::client_api::configure_client_api),
+                ),
+        )
+        .await;
+        let features_request = |token: &EdgeToken| {
+            TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token.token.clone()))
+                .to_request()
+        };
+
+        let res = call_service(&app, features_request(&stale_token)).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("Unleash-Edge-Stale").unwrap(), "true");
+        let age: i64 = res
+            .headers()
+            .get("Unleash-Edge-Age")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((600..610).contains(&age));
+
+        let res = call_service(&app, features_request(&fresh_token)).await;
+        assert!(res.status().is_success());
+        assert!(res.headers().get("Unleash-Edge-Stale").is_none());
+        assert!(res.headers().get("Unleash-Edge-Age").is_none());
+    }
+}
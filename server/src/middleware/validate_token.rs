@@ -21,7 +21,12 @@ pub async fn validate_token(
         .into_inner();
     match maybe_validator {
         Some(validator) => {
-            let known_token = validator.register_token(token.token.clone()).await?;
+            // Tokens validated against a local JWKS already know their status and are unknown to upstream
+            let known_token = if token.status == TokenValidationStatus::Unknown {
+                validator.register_token(token.token.clone()).await?
+            } else {
+                token
+            };
             let res = match known_token.status {
                 TokenValidationStatus::Validated => match known_token.token_type {
                     Some(TokenType::Frontend) => {
@@ -55,34 +60,35 @@ pub async fn validate_token(
             Ok(res)
         }
         None => {
-            let res = match token_cache.get(&token.token) {
-                Some(t) => {
-                    let token = t.value();
-                    match token.token_type {
-                        Some(TokenType::Client) => {
-                            if req.path().contains("/api/client") {
-                                srv.call(req).await?.map_into_left_body()
-                            } else {
-                                req.into_response(HttpResponse::Forbidden().finish())
-                                    .map_into_right_body()
-                            }
+            let known_token = token_cache
+                .get(&token.token)
+                .map(|t| t.value().clone())
+                // Self-issued frontend tokens were validated against the JWKS when extracted and aren't cached
+                .or_else(|| (token.token_type == Some(TokenType::Frontend)).then_some(token));
+            let res = match known_token {
+                Some(token) => match token.token_type {
+                    Some(TokenType::Client) => {
+                        if req.path().contains("/api/client") {
+                            srv.call(req).await?.map_into_left_body()
+                        } else {
+                            req.into_response(HttpResponse::Forbidden().finish())
+                                .map_into_right_body()
                         }
-                        Some(TokenType::Frontend) => {
-                            if req.path().contains("/api/frontend")
-                                || req.path().contains("/api/proxy")
-                            {
-                                srv.call(req).await?.map_into_left_body()
-                            } else {
-                                req.into_response(HttpResponse::Forbidden().finish())
-                                    .map_into_right_body()
-                            }
+                    }
+                    Some(TokenType::Frontend) => {
+                        if req.path().contains("/api/frontend") || req.path().contains("/api/proxy")
+                        {
+                            srv.call(req).await?.map_into_left_body()
+                        } else {
+                            req.into_response(HttpResponse::Forbidden().finish())
+                                .map_into_right_body()
                         }
-                        None => srv.call(req).await?.map_into_left_body(),
-                        _ => req
-                            .into_response(HttpResponse::Forbidden().finish())
-                            .map_into_right_body(),
                     }
-                }
+                    None => srv.call(req).await?.map_into_left_body(),
+                    _ => req
+                        .into_response(HttpResponse::Forbidden().finish())
+                        .map_into_right_body(),
+                },
                 None => req
                     .into_response(HttpResponse::Forbidden().finish())
                     .map_into_right_body(),
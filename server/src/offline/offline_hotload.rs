@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::{BufReader, Read},
     path::Path,
@@ -21,24 +21,34 @@ use crate::{cli::OfflineArgs, error::EdgeError, feature_cache::FeatureCache, typ
 pub async fn start_hotload_loop(
     features_cache: Arc<FeatureCache>,
     engine_cache: Arc<DashMap<std::string::String, EngineState>>,
+    token_cache: Arc<DashMap<String, EdgeToken>>,
     offline_args: OfflineArgs,
 ) {
-    let mut known_tokens = offline_args.tokens;
-    known_tokens.extend(offline_args.client_tokens);
-    known_tokens.extend(offline_args.frontend_tokens);
+    let mut static_tokens = offline_args.tokens;
+    static_tokens.extend(offline_args.client_tokens);
+    static_tokens.extend(offline_args.frontend_tokens);
     let bootstrap_path = offline_args.bootstrap_file;
+    let mut file_tokens = vec![];
 
     loop {
         tokio::select! {
             _ = tokio::time::sleep(Duration::from_secs(offline_args.reload_interval)) => {
+                if let Some(tokens_file) = offline_args.tokens_file.as_ref() {
+                    tracing::info!("Reloading tokens file");
+                    match reload_tokens_file(tokens_file, &static_tokens, &token_cache, &features_cache, &engine_cache) {
+                        Ok(tokens) => file_tokens = tokens,
+                        Err(e) => tracing::error!("Error loading tokens file, keeping the previous tokens: {:?}", e),
+                    }
+                }
                 let bootstrap = bootstrap_path.as_ref().map(|bootstrap_path|load_bootstrap(bootstrap_path));
                 tracing::info!("Reloading bootstrap file");
                 match bootstrap {
                     Some(Ok(bootstrap)) => {
                         tracing::info!("Found bootstrap file");
-                        let edge_tokens: Vec<EdgeToken> = known_tokens
+                        let edge_tokens: Vec<EdgeToken> = static_tokens
                         .iter()
-                        .map(|token| EdgeToken::from_str(token).unwrap_or_else(|_| EdgeToken::offline_token(token)))
+                        .chain(file_tokens.iter())
+                        .map(|token| parse_offline_token(token))
                         .collect();
                         tracing::info!("Edge tokens: {:?}", edge_tokens);
                         for edge_token in edge_tokens {
@@ -58,6 +68,65 @@ pub async fn start_hotload_loop(
     }
 }
 
+fn parse_offline_token(token: &str) -> EdgeToken {
+    EdgeToken::from_str(token).unwrap_or_else(|_| EdgeToken::offline_token(token))
+}
+
+/// Reads the tokens allowed to connect to Edge from a file with one token per line. Blank lines and lines
+/// starting with # are skipped
+pub(crate) fn load_tokens_file(tokens_file: &Path) -> Result<Vec<String>, EdgeError> {
+    let content = std::fs::read_to_string(tokens_file).map_err(|e| {
+        EdgeError::NoTokens(format!(
+            "Could not read tokens file {}: {e}",
+            tokens_file.display()
+        ))
+    })?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Brings the token cache in line with the tokens file. Tokens that were added to the file are accepted from now on,
+/// while tokens that are neither in the file nor passed on the command line are revoked. Returns the tokens in the file
+pub(crate) fn reload_tokens_file(
+    tokens_file: &Path,
+    static_tokens: &[String],
+    token_cache: &DashMap<String, EdgeToken>,
+    features_cache: &FeatureCache,
+    engine_cache: &DashMap<String, EngineState>,
+) -> Result<Vec<String>, EdgeError> {
+    let file_tokens = load_tokens_file(tokens_file)?;
+    let allowed: HashSet<String> = static_tokens
+        .iter()
+        .chain(file_tokens.iter())
+        .map(|token| parse_offline_token(token).token)
+        .collect();
+    let revoked: Vec<EdgeToken> = token_cache
+        .iter()
+        .filter(|entry| !allowed.contains(entry.key()))
+        .map(|entry| entry.value().clone())
+        .collect();
+    for token in revoked {
+        tracing::info!("Revoking token that was removed from the tokens file");
+        token_cache.remove(&token.token);
+        let key = crate::tokens::cache_key(&token);
+        if !token_cache
+            .iter()
+            .any(|entry| crate::tokens::cache_key(entry.value()) == key)
+        {
+            features_cache.remove(&key);
+            engine_cache.remove(&key);
+        }
+    }
+    for token in file_tokens.iter().map(|token| parse_offline_token(token)) {
+        token_cache.entry(token.token.clone()).or_insert(token);
+    }
+    Ok(file_tokens)
+}
+
 pub(crate) fn load_offline_engine_cache(
     edge_token: &EdgeToken,
     features_cache: Arc<FeatureCache>,
@@ -150,7 +219,99 @@ fn parse_bootstrap(content: String) -> Result<ClientFeatures, serde_json::Error>
 
 #[cfg(test)]
 mod tests {
-    use super::parse_bootstrap;
+    use std::env::temp_dir;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::web::Data;
+    use actix_web::{web, App};
+    use dashmap::DashMap;
+    use unleash_yggdrasil::EngineState;
+
+    use super::{load_bootstrap, load_offline_engine_cache, parse_bootstrap, reload_tokens_file};
+    use crate::cli::{EdgeMode, OfflineArgs};
+    use crate::feature_cache::FeatureCache;
+    use crate::types::EdgeToken;
+
+    #[actix_web::test]
+    async fn tokens_removed_from_the_tokens_file_stop_being_accepted_after_reload() {
+        let tokens_file = temp_dir().join(format!("offline-tokens-file-{}.txt", ulid::Ulid::new()));
+        std::fs::write(&tokens_file, "kept-token\n# a comment\nremoved-token\n").unwrap();
+        let features_cache = Arc::new(FeatureCache::default());
+        let engine_cache: Arc<DashMap<String, EngineState>> = Arc::new(DashMap::default());
+        let token_cache: Arc<DashMap<String, EdgeToken>> = Arc::new(DashMap::default());
+        let bootstrap = load_bootstrap(&PathBuf::from("../examples/features.json")).unwrap();
+        let static_tokens = vec!["static-token".to_string()];
+        token_cache.insert(
+            "static-token".into(),
+            EdgeToken::offline_token("static-token"),
+        );
+        let file_tokens = reload_tokens_file(
+            &tokens_file,
+            &static_tokens,
+            &token_cache,
+            &features_cache,
+            &engine_cache,
+        )
+        .unwrap();
+        assert_eq!(file_tokens, vec!["kept-token", "removed-token"]);
+        for token in static_tokens.iter().chain(file_tokens.iter()) {
+            load_offline_engine_cache(
+                &EdgeToken::offline_token(token),
+                features_cache.clone(),
+                engine_cache.clone(),
+                bootstrap.clone(),
+            );
+        }
+        let app = init_service(
+            App::new()
+                .app_data(Data::from(features_cache.clone()))
+                .app_data(Data::from(token_cache.clone()))
+                .app_data(Data::new(EdgeMode::Offline(OfflineArgs {
+                    bootstrap_file: None,
+                    tokens: static_tokens.clone(),
+                    client_tokens: vec![],
+                    frontend_tokens: vec![],
+                    tokens_file: Some(tokens_file.clone()),
+                    reload_interval: 1,
+                    frontend_jwks_file: None,
+                    fail_on_compile_warnings: false,
+                })))
+                .service(web::scope("/api/client").service(crate::client_api::get_features)),
+        )
+        .await;
+        let request_with = |token: &str| {
+            TestRequest::get()
+                .uri("/api/client/features")
+                .insert_header(("Authorization", token))
+                .to_request()
+        };
+        let res = call_service(&app, request_with("removed-token")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        std::fs::write(&tokens_file, "kept-token\nadded-token\n").unwrap();
+        reload_tokens_file(
+            &tokens_file,
+            &static_tokens,
+            &token_cache,
+            &features_cache,
+            &engine_cache,
+        )
+        .unwrap();
+
+        let res = call_service(&app, request_with("removed-token")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!features_cache.is_empty());
+        assert!(features_cache.get("removed-token").is_none());
+        assert!(token_cache.contains_key("added-token"));
+        for token in ["kept-token", "static-token"] {
+            let res = call_service(&app, request_with(token)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        std::fs::remove_file(tokens_file).unwrap();
+    }
 
     #[test]
     fn loads_simple_bootstrap_format() {
@@ -23,10 +23,13 @@ use utoipa::{
         crate::frontend_api::post_proxy_metrics,
         crate::frontend_api::post_frontend_evaluate_single_feature,
         crate::frontend_api::get_frontend_evaluate_single_feature,
+        crate::frontend_api::post_frontend_bulk_evaluate,
+        crate::frontend_api::post_frontend_explain_feature,
         crate::client_api::get_features,
         crate::client_api::register,
         crate::client_api::metrics,
         crate::client_api::get_feature,
+        crate::client_api::get_delta,
         crate::edge_api::validate,
     ),
     components(schemas(
@@ -35,6 +38,8 @@ use utoipa::{
         unleash_types::frontend::EvaluatedVariant,
         unleash_types::client_features::Payload,
         unleash_types::client_features::ClientFeatures,
+        unleash_types::client_features::ClientFeaturesDelta,
+        unleash_types::client_features::DeltaEvent,
         unleash_types::client_features::Context,
         unleash_types::client_features::ClientFeature,
         unleash_types::client_features::Query,
@@ -54,7 +59,10 @@ use utoipa::{
         crate::types::BatchMetricsRequestBody,
         crate::types::EdgeToken,
         crate::types::TokenValidationStatus,
-        crate::types::TokenType
+        crate::types::TokenType,
+        crate::types::EvaluationExplanation,
+        crate::types::EvaluationReason,
+        crate::types::StrategyEvaluation
     )),
     modifiers(&SecurityAddon)
 )]
@@ -5,7 +5,7 @@ use std::{path::PathBuf, str::FromStr};
 use async_trait::async_trait;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use unleash_types::client_features::ClientFeatures;
+use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
 
 use crate::types::EdgeToken;
 use crate::{error::EdgeError, types::EdgeResult};
@@ -41,12 +41,24 @@ impl FilePersister {
         features_path
     }
 
+    pub fn delta_path(&self) -> PathBuf {
+        let mut delta_path = self.storage_path.clone();
+        delta_path.push("unleash_delta.json");
+        delta_path
+    }
+
     pub fn refresh_target_path(&self) -> PathBuf {
         let mut refresh_target_path = self.storage_path.clone();
         refresh_target_path.push("unleash_refresh_targets.json");
         refresh_target_path
     }
 
+    pub fn instance_id_path(&self) -> PathBuf {
+        let mut instance_id_path = self.storage_path.clone();
+        instance_id_path.push("unleash_instance_id");
+        instance_id_path
+    }
+
     pub fn new(storage_path: &Path) -> Self {
         let _ = std::fs::create_dir_all(storage_path);
         FilePersister {
@@ -81,22 +93,13 @@ impl EdgePersistence for FilePersister {
     }
 
     async fn save_tokens(&self, tokens: Vec<EdgeToken>) -> EdgeResult<()> {
-        let mut file = tokio::fs::File::create(self.token_path())
+        let contents = serde_json::to_vec(&tokens)
+            .map_err(|_| EdgeError::PersistenceError("Failed to serialize tokens".to_string()))?;
+        write_atomically(&self.token_path(), &contents)
             .await
             .map_err(|_| {
-                EdgeError::PersistenceError(
-                    "Cannot write tokens to backup. Opening backup file for writing failed"
-                        .to_string(),
-                )
-            })?;
-        file.write_all(
-            &serde_json::to_vec(&tokens).map_err(|_| {
-                EdgeError::PersistenceError("Failed to serialize tokens".to_string())
-            })?,
-        )
-        .await
-        .map_err(|_| EdgeError::PersistenceError("Could not serialize tokens to disc".to_string()))
-        .map(|_| ())
+                EdgeError::PersistenceError("Could not serialize tokens to disc".to_string())
+            })
     }
 
     async fn load_features(&self) -> EdgeResult<HashMap<String, ClientFeatures>> {
@@ -125,32 +128,77 @@ impl EdgePersistence for FilePersister {
     }
 
     async fn save_features(&self, features: Vec<(String, ClientFeatures)>) -> EdgeResult<()> {
-        let mut file = tokio::fs::File::create(self.features_path())
+        let contents = serde_json::to_vec(&features)
+            .map_err(|_| EdgeError::PersistenceError("Failed to serialize features".to_string()))?;
+        write_atomically(&self.features_path(), &contents)
+            .await
+            .map_err(|_| {
+                EdgeError::PersistenceError("Could not serialize features to disc".to_string())
+            })
+    }
+
+    async fn load_delta(&self) -> EdgeResult<HashMap<String, ClientFeaturesDelta>> {
+        let mut file = tokio::fs::File::open(self.delta_path())
             .await
             .map_err(|_| {
                 EdgeError::PersistenceError(
-                    "Cannot write tokens to backup. Opening backup file for writing failed"
-                        .to_string(),
+                    "Cannot load delta state from backup, opening backup file failed".to_string(),
                 )
             })?;
-        file.write_all(
-            &serde_json::to_vec(&features).map_err(|_| {
-                EdgeError::PersistenceError("Failed to serialize features".to_string())
-            })?,
-        )
-        .await
-        .map_err(|_| EdgeError::PersistenceError("Could not serialize tokens to disc".to_string()))
-        .map(|_| ())
+
+        let mut contents = vec![];
+
+        file.read_to_end(&mut contents).await.map_err(|_| {
+            EdgeError::PersistenceError(
+                "Cannot load delta state from backup, reading backup file failed".to_string(),
+            )
+        })?;
+        let contents: Vec<(String, ClientFeaturesDelta)> = serde_json::from_slice(&contents)
+            .map_err(|_| {
+                EdgeError::PersistenceError(
+                    "Cannot load delta state from backup, parsing backup file failed".to_string(),
+                )
+            })?;
+        Ok(contents.into_iter().collect())
+    }
+
+    async fn save_delta(&self, delta: Vec<(String, ClientFeaturesDelta)>) -> EdgeResult<()> {
+        let contents = serde_json::to_vec(&delta).map_err(|_| {
+            EdgeError::PersistenceError("Failed to serialize delta state".to_string())
+        })?;
+        write_atomically(&self.delta_path(), &contents)
+            .await
+            .map_err(|_| {
+                EdgeError::PersistenceError("Could not serialize delta state to disc".to_string())
+            })
     }
 }
 
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// Writes to a temporary file next to the target and renames it over the target, so a crash mid-write never leaves a truncated backup behind
+async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = temp_path(path);
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&temp_path, path).await
+}
+
 #[cfg(test)]
 mod tests {
     use std::env::temp_dir;
 
-    use unleash_types::client_features::{ClientFeature, ClientFeatures};
+    use unleash_types::client_features::{
+        ClientFeature, ClientFeatures, ClientFeaturesDelta, DeltaEvent, Segment,
+    };
 
-    use crate::persistence::file::FilePersister;
+    use crate::persistence::file::{temp_path, FilePersister};
     use crate::persistence::EdgePersistence;
     use crate::types::{EdgeToken, TokenType, TokenValidationStatus};
 
@@ -217,4 +265,69 @@ mod tests {
 
         assert_eq!(reloaded, tokens);
     }
+
+    #[tokio::test]
+    async fn file_persister_can_save_and_load_delta() {
+        let persister = FilePersister::try_from(temp_dir().to_str().unwrap()).unwrap();
+        let delta = ClientFeaturesDelta {
+            events: vec![DeltaEvent::Hydration {
+                event_id: 42,
+                features: vec![ClientFeature {
+                    name: "test1".to_string(),
+                    enabled: true,
+                    project: Some("default".to_string()),
+                    ..ClientFeature::default()
+                }],
+                segments: vec![Segment {
+                    id: 1,
+                    constraints: vec![],
+                }],
+            }],
+        };
+
+        let formatted_data = vec![("some-environment".into(), delta)];
+
+        persister.save_delta(formatted_data.clone()).await.unwrap();
+        let reloaded = persister.load_delta().await.unwrap();
+        assert_eq!(reloaded, formatted_data.into_iter().collect());
+    }
+
+    #[tokio::test]
+    async fn interrupted_feature_write_keeps_previous_backup() {
+        let backup_folder =
+            temp_dir().join(format!("edge-file-persister-atomic-{}", ulid::Ulid::new()));
+        let persister = FilePersister::new(&backup_folder);
+        let good_backup = vec![(
+            "development".into(),
+            ClientFeatures {
+                features: vec![ClientFeature {
+                    name: "test1".to_string(),
+                    ..ClientFeature::default()
+                }],
+                version: 2,
+                segments: None,
+                query: None,
+                meta: None,
+            },
+        )];
+        persister.save_features(good_backup.clone()).await.unwrap();
+
+        // Simulate Edge dying halfway through writing the next backup
+        let partial = serde_json::to_vec(&good_backup).unwrap();
+        tokio::fs::write(
+            temp_path(&persister.features_path()),
+            &partial[..partial.len() / 2],
+        )
+        .await
+        .unwrap();
+
+        let reloaded = persister.load_features().await.unwrap();
+        assert_eq!(reloaded, good_backup.clone().into_iter().collect());
+
+        persister.save_features(good_backup.clone()).await.unwrap();
+        assert!(!temp_path(&persister.features_path()).exists());
+        let reloaded = persister.load_features().await.unwrap();
+        assert_eq!(reloaded, good_backup.into_iter().collect());
+        std::fs::remove_dir_all(backup_folder).unwrap();
+    }
 }
@@ -1,11 +1,12 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use crate::feature_cache::FeatureCache;
+use crate::http::refresher::feature_refresher::FeatureRefresher;
 use crate::types::{EdgeResult, EdgeToken, TokenValidationStatus};
 use async_trait::async_trait;
 use dashmap::DashMap;
 use tracing::{debug, warn};
-use unleash_types::client_features::ClientFeatures;
+use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
 
 pub mod file;
 pub mod redis;
@@ -17,6 +18,14 @@ pub trait EdgePersistence: Send + Sync {
     async fn save_tokens(&self, tokens: Vec<EdgeToken>) -> EdgeResult<()>;
     async fn load_features(&self) -> EdgeResult<HashMap<String, ClientFeatures>>;
     async fn save_features(&self, features: Vec<(String, ClientFeatures)>) -> EdgeResult<()>;
+    /// Loads the delta state per cache key, used to resume `--delta` mode without a full re-hydration.
+    /// Backends that do not support storing delta state return an empty map.
+    async fn load_delta(&self) -> EdgeResult<HashMap<String, ClientFeaturesDelta>> {
+        Ok(HashMap::new())
+    }
+    async fn save_delta(&self, _delta: Vec<(String, ClientFeaturesDelta)>) -> EdgeResult<()> {
+        Ok(())
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -24,6 +33,7 @@ pub async fn persist_data(
     persistence: Option<Arc<dyn EdgePersistence>>,
     token_cache: Arc<DashMap<String, EdgeToken>>,
     features_cache: Arc<FeatureCache>,
+    feature_refresher: Option<Arc<FeatureRefresher>>,
 ) {
     loop {
         tokio::select! {
@@ -32,6 +42,9 @@ pub async fn persist_data(
 
                     save_known_tokens(&token_cache, &persister).await;
                     save_features(&features_cache, &persister).await;
+                    if let Some(refresher) = feature_refresher.clone() {
+                        save_delta(&refresher, &persister).await;
+                    }
                 } else {
                     debug!("No persistence configured, skipping persistence");
                 }
@@ -82,6 +95,21 @@ async fn save_features(features_cache: &FeatureCache, persister: &Arc<dyn EdgePe
     }
 }
 
+async fn save_delta(feature_refresher: &FeatureRefresher, persister: &Arc<dyn EdgePersistence>) {
+    if !feature_refresher.delta {
+        return;
+    }
+    let delta = feature_refresher.delta_cache_snapshot();
+    if !delta.is_empty() {
+        match persister.save_delta(delta).await {
+            Ok(()) => debug!("Persisted delta state"),
+            Err(save_error) => warn!("Could not persist delta state: {save_error:?}"),
+        }
+    } else {
+        debug!("No delta state found, skipping delta persistence");
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
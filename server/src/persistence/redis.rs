@@ -1,13 +1,15 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
 use redis::cluster::ClusterClient;
-use redis::{AsyncCommands, Client, Commands, RedisError};
-use tokio::sync::RwLock;
+use redis::cluster_async::ClusterConnection;
+use redis::{AsyncConnectionConfig, Client, Cmd, FromRedisValue, RedisError};
+use tokio::sync::Mutex;
 use tracing::{debug, info};
-use unleash_types::client_features::ClientFeatures;
+use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
 
 use crate::persistence::redis::RedisClientOptions::{Cluster, Single};
 use crate::types::EdgeToken;
@@ -17,6 +19,7 @@ use super::EdgePersistence;
 
 pub const FEATURES_KEY: &str = "unleash-features";
 pub const TOKENS_KEY: &str = "unleash-tokens";
+pub const DELTA_KEY: &str = "unleash-delta";
 
 impl From<RedisError> for EdgeError {
     fn from(err: RedisError) -> Self {
@@ -29,10 +32,20 @@ enum RedisClientOptions {
     Cluster(ClusterClient),
 }
 
+/// Both kinds of connection are cheap clones multiplexing commands over their sockets
+#[derive(Clone)]
+enum PooledConnection {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+/// Connections are opened lazily and kept for reuse. A slot whose connection breaks is emptied, so the next operation using it reconnects
 pub struct RedisPersister {
     read_timeout: Duration,
     write_timeout: Duration,
-    redis_client: Arc<RwLock<RedisClientOptions>>,
+    redis_client: RedisClientOptions,
+    connections: Vec<Mutex<Option<PooledConnection>>>,
+    next_connection: AtomicUsize,
 }
 impl RedisPersister {
     pub fn new(
@@ -43,11 +56,11 @@ impl RedisPersister {
         let client = Client::open(url)?;
         let addr = client.get_connection_info().addr.clone();
         info!("[REDIS Persister]: Configured single node client {addr:?}");
-        Ok(Self {
-            redis_client: Arc::new(RwLock::new(Single(client))),
+        Ok(Self::from_client(
+            Single(client),
             read_timeout,
             write_timeout,
-        })
+        ))
     }
     pub fn new_with_cluster(
         urls: Vec<String>,
@@ -58,79 +71,121 @@ impl RedisPersister {
         let client = ClusterClient::builder(urls)
             .connection_timeout(read_timeout)
             .build()?;
-        Ok(Self {
-            redis_client: Arc::new(RwLock::new(Cluster(client))),
+        Ok(Self::from_client(
+            Cluster(client),
+            read_timeout,
+            write_timeout,
+        ))
+    }
+
+    fn from_client(
+        redis_client: RedisClientOptions,
+        read_timeout: Duration,
+        write_timeout: Duration,
+    ) -> Self {
+        Self {
             read_timeout,
             write_timeout,
-        })
+            redis_client,
+            connections: vec![Mutex::new(None)],
+            next_connection: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets how many connections are kept open and shared between persistence operations. Values below 1 are treated as 1
+    pub fn with_pool_size(self, pool_size: usize) -> Self {
+        Self {
+            connections: (0..pool_size.max(1)).map(|_| Mutex::new(None)).collect(),
+            ..self
+        }
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.connections.len()
+    }
+
+    async fn connect(&self, timeout: Duration) -> EdgeResult<PooledConnection> {
+        match &self.redis_client {
+            Single(client) => {
+                let config = AsyncConnectionConfig::new().set_connection_timeout(timeout);
+                let conn = client
+                    .get_multiplexed_async_connection_with_config(&config)
+                    .await?;
+                Ok(PooledConnection::Single(conn))
+            }
+            Cluster(client) => {
+                let conn = tokio::time::timeout(timeout, client.get_async_connection())
+                    .await
+                    .unwrap_or_else(|_| Err(timed_out()))?;
+                Ok(PooledConnection::Cluster(conn))
+            }
+        }
+    }
+
+    async fn get_value(&self, key: &str) -> EdgeResult<String> {
+        self.query(redis::cmd("GET").arg(key), self.read_timeout)
+            .await
+    }
+
+    async fn set_value(&self, key: &str, value: String) -> EdgeResult<()> {
+        self.query(redis::cmd("SET").arg(key).arg(value), self.write_timeout)
+            .await
+    }
+
+    async fn query<T: FromRedisValue>(&self, cmd: &Cmd, timeout: Duration) -> EdgeResult<T> {
+        let slot = self.next_connection.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        let mut pooled = self.connections[slot].lock().await;
+        if pooled.is_none() {
+            debug!("[REDIS Persister]: Opening pooled connection {slot}");
+            *pooled = Some(self.connect(timeout).await?);
+        }
+        // The connection is cloned, so the slot doesn't stay locked while the command runs
+        let Some(mut conn) = pooled.clone() else {
+            unreachable!("pooled connection was opened above");
+        };
+        drop(pooled);
+        let query = async {
+            match &mut conn {
+                PooledConnection::Single(conn) => cmd.query_async(conn).await,
+                PooledConnection::Cluster(conn) => cmd.query_async(conn).await,
+            }
+        };
+        let result = tokio::time::timeout(timeout, query)
+            .await
+            .unwrap_or_else(|_| Err(timed_out()));
+        if result.as_ref().is_err_and(is_connection_error) {
+            *self.connections[slot].lock().await = None;
+        }
+        result.map_err(EdgeError::from)
     }
 }
 
+fn timed_out() -> RedisError {
+    RedisError::from(std::io::Error::from(std::io::ErrorKind::TimedOut))
+}
+
+fn is_connection_error(err: &RedisError) -> bool {
+    err.is_io_error() || err.is_connection_dropped() || err.is_timeout()
+}
+
 #[async_trait]
 impl EdgePersistence for RedisPersister {
     async fn load_tokens(&self) -> EdgeResult<Vec<EdgeToken>> {
         debug!("Loading tokens from persistence");
-        let mut client = self.redis_client.write().await;
-        let raw_tokens: String = match &mut *client {
-            Single(c) => {
-                let mut conn = c
-                    .get_multiplexed_tokio_connection_with_response_timeouts(
-                        self.read_timeout,
-                        self.read_timeout,
-                    )
-                    .await?;
-                conn.get(TOKENS_KEY).await?
-            }
-            Cluster(c) => {
-                let mut conn = c.get_connection()?;
-                conn.get(TOKENS_KEY)?
-            }
-        };
+        let raw_tokens = self.get_value(TOKENS_KEY).await?;
         serde_json::from_str::<Vec<EdgeToken>>(&raw_tokens)
             .map_err(|_e| EdgeError::TokenParseError("Failed to load tokens from redis".into()))
     }
 
     async fn save_tokens(&self, tokens: Vec<EdgeToken>) -> EdgeResult<()> {
         debug!("Saving {} tokens to persistence", tokens.len());
-        let mut client = self.redis_client.write().await;
         let raw_tokens = serde_json::to_string(&tokens)?;
-        match &mut *client {
-            RedisClientOptions::Single(c) => {
-                let mut conn = c
-                    .get_multiplexed_tokio_connection_with_response_timeouts(
-                        self.write_timeout,
-                        self.write_timeout,
-                    )
-                    .await?;
-                let res: Result<(), RedisError> = conn.set(TOKENS_KEY, raw_tokens).await;
-                res?;
-            }
-            RedisClientOptions::Cluster(c) => {
-                let mut conn = c.get_connection()?;
-                conn.set(TOKENS_KEY, raw_tokens)?
-            }
-        };
-        Ok(())
+        self.set_value(TOKENS_KEY, raw_tokens).await
     }
 
     async fn load_features(&self) -> EdgeResult<HashMap<String, ClientFeatures>> {
         debug!("Loading features from persistence");
-        let mut client = self.redis_client.write().await;
-        let raw_features: String = match &mut *client {
-            Single(client) => {
-                let mut conn = client
-                    .get_multiplexed_tokio_connection_with_response_timeouts(
-                        self.read_timeout,
-                        self.read_timeout,
-                    )
-                    .await?;
-                conn.get(FEATURES_KEY).await?
-            }
-            Cluster(client) => {
-                let mut conn = client.get_connection()?;
-                conn.get(FEATURES_KEY)?
-            }
-        };
+        let raw_features = self.get_value(FEATURES_KEY).await?;
         let raw_features = serde_json::from_str::<Vec<(String, ClientFeatures)>>(&raw_features)
             .map_err(|e| EdgeError::ClientFeaturesParseError(e.to_string()))?;
         Ok(raw_features.into_iter().collect())
@@ -138,29 +193,27 @@ impl EdgePersistence for RedisPersister {
 
     async fn save_features(&self, features: Vec<(String, ClientFeatures)>) -> EdgeResult<()> {
         debug!("Saving {} features to persistence", features.len());
-        let mut client = self.redis_client.write().await;
         let raw_features = serde_json::to_string(&features)?;
-        match &mut *client {
-            Single(client) => {
-                let mut conn = client
-                    .get_multiplexed_tokio_connection_with_response_timeouts(
-                        self.write_timeout,
-                        self.write_timeout,
-                    )
-                    .await?;
-                conn.set(FEATURES_KEY, raw_features)
-                    .await
-                    .map_err(EdgeError::from)?
-            }
-            Cluster(cluster) => {
-                let mut conn = cluster.get_connection()?;
-                let res: Result<(), EdgeError> = conn
-                    .set(FEATURES_KEY, raw_features)
-                    .map_err(EdgeError::from);
-                res?;
-            }
-        };
+        self.set_value(FEATURES_KEY, raw_features).await?;
         debug!("Done saving to persistence");
         Ok(())
     }
+    async fn load_delta(&self) -> EdgeResult<HashMap<String, ClientFeaturesDelta>> {
+        debug!("Loading delta state from persistence");
+        let raw_delta = self.get_value(DELTA_KEY).await?;
+        let raw_delta = serde_json::from_str::<Vec<(String, ClientFeaturesDelta)>>(&raw_delta)
+            .map_err(|e| EdgeError::ClientFeaturesParseError(e.to_string()))?;
+        Ok(raw_delta.into_iter().collect())
+    }
+
+    async fn save_delta(&self, delta: Vec<(String, ClientFeaturesDelta)>) -> EdgeResult<()> {
+        debug!(
+            "Saving delta state for {} environments to persistence",
+            delta.len()
+        );
+        let raw_delta = serde_json::to_string(&delta)?;
+        self.set_value(DELTA_KEY, raw_delta).await?;
+        debug!("Done saving delta state to persistence");
+        Ok(())
+    }
 }
@@ -1,10 +1,11 @@
 use crate::cli::LogFormat;
 use opentelemetry::global;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
 use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
 #[cfg(target_os = "linux")]
 use prometheus::process_collector::ProcessCollector;
-use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
 use tracing_subscriber::{EnvFilter, Registry};
 
 use crate::http::background_send_metrics;
@@ -12,24 +13,51 @@ use crate::metrics::actix_web_metrics::{
     PrometheusMetricsHandler, RequestMetrics, RequestMetricsBuilder,
 };
 
+/// Bridges `tracing` spans to OpenTelemetry, so the trace context of the span a request to upstream is made in can be
+/// propagated, see UnleashClient::header_map
+#[cfg(feature = "enterprise")]
+pub(crate) fn trace_context_layer() -> impl Layer<Registry> {
+    use opentelemetry::trace::TracerProvider as _;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+    let tracer = provider.tracer("unleash-edge");
+    global::set_tracer_provider(provider);
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+#[cfg(not(feature = "enterprise"))]
+pub(crate) fn trace_context_layer() -> impl Layer<Registry> {
+    tracing_subscriber::layer::Identity::new()
+}
+
 fn instantiate_tracing_and_logging(log_format: &LogFormat) {
+    // Lets requests to upstream carry the active trace context, see UnleashClient::header_map
+    global::set_text_map_propagator(TraceContextPropagator::new());
     let env_filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))
         .unwrap();
     match log_format {
         LogFormat::Plain => {
             let logger = tracing_subscriber::fmt::layer();
-            let collector = Registry::default().with(logger).with(env_filter);
+            let collector = Registry::default()
+                .with(trace_context_layer())
+                .with(logger)
+                .with(env_filter);
             tracing::subscriber::set_global_default(collector).unwrap();
         }
         LogFormat::Json => {
             let logger = tracing_subscriber::fmt::layer().json();
-            let collector = Registry::default().with(logger).with(env_filter);
+            let collector = Registry::default()
+                .with(trace_context_layer())
+                .with(logger)
+                .with(env_filter);
             tracing::subscriber::set_global_default(collector).unwrap();
         }
         LogFormat::Pretty => {
             let logger = tracing_subscriber::fmt::layer().pretty();
-            let collector = Registry::default().with(logger).with(env_filter);
+            let collector = Registry::default()
+                .with(trace_context_layer())
+                .with(logger)
+                .with(env_filter);
             tracing::subscriber::set_global_default(collector).unwrap();
         }
     };
@@ -143,11 +171,121 @@ fn register_custom_metrics(registry: &prometheus::Registry) {
             crate::metrics::client_metrics::FEATURE_TOGGLE_USAGE_TOTAL.clone(),
         ))
         .unwrap();
+    registry
+        .register(Box::new(
+            crate::metrics::client_metrics::METRICS_DROPPED.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::unleash_client::UPSTREAM_MALFORMED_ETAGS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::unleash_client::UPSTREAM_CLOCK_SKEW_SECONDS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::circuit_breaker::UPSTREAM_CIRCUIT_BREAKER_STATE.clone(),
+        ))
+        .unwrap();
     registry
         .register(Box::new(
             crate::http::broadcaster::CONNECTED_STREAMING_CLIENTS.clone(),
         ))
         .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::REGISTERED_TOKENS_PER_ENVIRONMENT.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::REGISTERED_TOKENS_BY_SCOPE.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::REGISTERED_TOKEN_PROJECTS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::UPSTREAM_ENVIRONMENT_MISMATCHES.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::REVISION_REGRESSIONS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::FEATURE_CACHE_UPDATE_DURATION.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::FEATURE_COUNT_DROPS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::EMPTY_UPSTREAM_RESPONSES_IGNORED.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::ENGINE_REBUILDS_SKIPPED.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::TOKENS_SUBSUMED.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::STRICT_MODE_REJECTIONS.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::builder::STARTUP_TOKEN_ENVIRONMENT_MISMATCHES.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::CACHED_FEATURE_COUNT.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::http::refresher::feature_refresher::SECONDS_SINCE_LAST_UPDATE.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::metrics::upstream_latency::UPSTREAM_FEATURE_FETCH_LATENCY.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::frontend_api::FRONTEND_EVAL_DURATION.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::metrics::cache_metrics::CACHE_ENTRIES.clone(),
+        ))
+        .unwrap();
+    registry
+        .register(Box::new(
+            crate::metrics::cache_metrics::FEATURE_CACHE_ESTIMATED_BYTES.clone(),
+        ))
+        .unwrap();
 }
 
 #[cfg(test)]
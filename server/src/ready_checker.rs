@@ -6,11 +6,13 @@ use crate::internal_backstage::EdgeStatus;
 use crate::tls::build_upstream_certificate;
 use crate::types::Status;
 
-fn build_ready_url(url: &Url) -> Url {
+fn build_ready_url(url: &Url, base_path: &str) -> Url {
     let mut with_path = url.clone();
     with_path
         .path_segments_mut()
         .expect("Could not build ready url")
+        .pop_if_empty()
+        .extend(base_path.split('/').filter(|segment| !segment.is_empty()))
         .push("internal-backstage")
         .push("ready");
     with_path
@@ -26,7 +28,7 @@ pub async fn check_ready(ready_check_args: ReadyCheckArgs) -> Result<(), EdgeErr
     };
     let base_url = Url::parse(&ready_check_args.edge_url)
         .map_err(|p| EdgeError::ReadyCheckError(format!("Invalid ready check url: {p:?}")))?;
-    let ready_check_url = build_ready_url(&base_url);
+    let ready_check_url = build_ready_url(&base_url, &ready_check_args.base_path);
     let r = client
         .get(ready_check_url.clone())
         .send()
@@ -117,6 +119,7 @@ mod tests {
         let check_result = check_ready(ReadyCheckArgs {
             ca_certificate_file: None,
             edge_url: url,
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_ok());
@@ -127,6 +130,7 @@ mod tests {
         let check_result = check_ready(ReadyCheckArgs {
             ca_certificate_file: None,
             edge_url: "http://bogusurl".into(),
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
@@ -152,6 +156,7 @@ mod tests {
         let check_result = check_ready(ReadyCheckArgs {
             ca_certificate_file: None,
             edge_url: url,
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
@@ -162,6 +167,7 @@ mod tests {
         let check_result = check_ready(ReadyCheckArgs {
             ca_certificate_file: None,
             edge_url: ":\\///\\/".into(),
+            base_path: "".into(),
         })
         .await;
         assert!(check_result.is_err());
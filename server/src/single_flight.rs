@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::OnceCell;
+use unleash_yggdrasil::ResolvedToggle;
+
+/// Coalesces concurrent identical computations, so callers asking for the same key while a computation is
+/// running await it and share its result instead of computing it again. Results are not kept around once
+/// the computation finishes, so this never serves stale data.
+#[derive(Debug)]
+pub struct SingleFlight<T> {
+    in_flight: DashMap<String, Arc<OnceCell<T>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            in_flight: DashMap::default(),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub async fn run<F, Fut>(&self, key: String, compute: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let flight = self
+            .in_flight
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone();
+        let result = flight.get_or_init(compute).await.clone();
+        self.in_flight
+            .remove_if(&key, |_, current| Arc::ptr_eq(current, &flight));
+        result
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+/// Shares the result of evaluating all features for a token and context between concurrent frontend requests
+pub type EvaluationCoalescer = SingleFlight<Option<HashMap<String, ResolvedToggle>>>;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::future::join_all;
+    use tokio::sync::Barrier;
+
+    use super::SingleFlight;
+
+    #[tokio::test]
+    async fn concurrent_identical_computations_run_once() {
+        let single_flight = SingleFlight::<usize>::default();
+        let computations = AtomicUsize::new(0);
+
+        // All callers share one thread here, so this only finishes if waiting callers yield instead of blocking
+        let results = join_all((0..8).map(|_| {
+            single_flight.run("development:{}".into(), || async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                computations.fetch_add(1, Ordering::SeqCst) + 42
+            })
+        }))
+        .await;
+
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| *r == 42));
+        assert_eq!(single_flight.in_flight(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_identical_computations_across_threads_run_once() {
+        let single_flight = Arc::new(SingleFlight::<usize>::default());
+        let computations = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let single_flight = single_flight.clone();
+                let computations = computations.clone();
+                let barrier = barrier.clone();
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    single_flight
+                        .run("development:{}".into(), || async {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            computations.fetch_add(1, Ordering::SeqCst) + 42
+                        })
+                        .await
+                })
+            })
+            .collect();
+        let results: Vec<usize> = join_all(handles)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| *r == 42));
+        assert_eq!(single_flight.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn different_keys_and_later_calls_compute_separately() {
+        let single_flight = SingleFlight::<usize>::default();
+        let computations = AtomicUsize::new(0);
+        let compute = || async { computations.fetch_add(1, Ordering::SeqCst) };
+
+        single_flight.run("development:{}".into(), compute).await;
+        single_flight.run("production:{}".into(), compute).await;
+        single_flight.run("development:{}".into(), compute).await;
+
+        assert_eq!(computations.load(Ordering::SeqCst), 3);
+    }
+}
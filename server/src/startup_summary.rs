@@ -0,0 +1,155 @@
+use serde::Serialize;
+use tracing::info;
+
+use crate::builder::startup_tokens;
+use crate::cli::EdgeMode;
+
+/// The configuration Edge was started with, logged as a single event so fleet inventory tooling can scrape it from
+/// the logs instead of piecing it together from the many messages logged while starting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StartupSummary {
+    pub mode: &'static str,
+    pub upstream_url: Option<String>,
+    pub persistence: &'static str,
+    pub streaming: bool,
+    pub delta: bool,
+    pub strict: bool,
+    pub startup_tokens: usize,
+}
+
+impl StartupSummary {
+    /// None for the modes that don't start a server, like `health` and `ready`
+    pub fn from_mode(mode: &EdgeMode) -> Option<Self> {
+        match mode {
+            EdgeMode::Edge(edge_args) => Some(Self {
+                mode: "edge",
+                upstream_url: Some(edge_args.upstream_url.clone()),
+                persistence: if edge_args.redis.is_some() {
+                    "redis"
+                } else if edge_args.s3.is_some() {
+                    "s3"
+                } else if edge_args.backup_folder.is_some() {
+                    "file"
+                } else {
+                    "none"
+                },
+                streaming: edge_args.streaming,
+                delta: edge_args.delta,
+                strict: edge_args.strict,
+                startup_tokens: startup_tokens(edge_args).len(),
+            }),
+            EdgeMode::Offline(offline_args) => Some(Self {
+                mode: "offline",
+                upstream_url: None,
+                persistence: "none",
+                streaming: false,
+                delta: false,
+                strict: false,
+                startup_tokens: offline_args.tokens.len()
+                    + offline_args.client_tokens.len()
+                    + offline_args.frontend_tokens.len(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn log(&self) {
+        info!(
+            mode = self.mode,
+            upstream_url = self.upstream_url.as_deref(),
+            persistence = self.persistence,
+            streaming = self.streaming,
+            delta = self.delta,
+            strict = self.strict,
+            startup_tokens = self.startup_tokens,
+            "Starting Unleash Edge"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use clap::Parser;
+    use serde_json::Value;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::StartupSummary;
+    use crate::cli::CliArgs;
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedLogs {
+        type Writer = CapturedLogs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    pub fn startup_summary_is_logged_as_a_single_json_event() {
+        let args = CliArgs::parse_from([
+            "unleash-edge",
+            "edge",
+            "--upstream-url",
+            "http://localhost:4242",
+            "--strict",
+            "--streaming",
+            "--redis-url",
+            "redis://localhost:6379",
+            "--tokens",
+            "*:development.secret123,*:production.secret123",
+        ]);
+        let summary = StartupSummary::from_mode(&args.mode).unwrap();
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(logs.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || summary.log());
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let event: Value = serde_json::from_str(lines[0]).unwrap();
+        let fields = &event["fields"];
+        assert_eq!(fields["mode"], "edge");
+        assert_eq!(fields["upstream_url"], "http://localhost:4242");
+        assert_eq!(fields["persistence"], "redis");
+        assert_eq!(fields["streaming"], true);
+        assert_eq!(fields["delta"], false);
+        assert_eq!(fields["strict"], true);
+        assert_eq!(fields["startup_tokens"], 2);
+    }
+
+    #[test]
+    pub fn offline_mode_has_no_upstream() {
+        let args = CliArgs::parse_from([
+            "unleash-edge",
+            "offline",
+            "--bootstrap-file",
+            "../examples/features.json",
+            "--client-tokens",
+            "*:development.secret123",
+        ]);
+        let summary = StartupSummary::from_mode(&args.mode).unwrap();
+        assert_eq!(summary.mode, "offline");
+        assert_eq!(summary.upstream_url, None);
+        assert_eq!(summary.startup_tokens, 1);
+    }
+}
@@ -1,11 +1,20 @@
-use rustls::crypto::CryptoProvider;
-use rustls::pki_types::PrivateKeyDer;
-use rustls::ServerConfig;
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::version::{TLS12, TLS13};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+    SupportedProtocolVersion,
+};
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{fs, fs::File, io::BufReader};
+use tracing::warn;
 
-use crate::cli::TlsOptions;
+use crate::cli::{CertificatePin, ClientIdentity, TlsOptions, TlsVersion};
 use crate::error::{CertificateError, EdgeError};
 use crate::types::EdgeResult;
 
@@ -32,9 +41,19 @@ pub(crate) fn build_upstream_certificate(
         .unwrap_or(Ok(None))
 }
 
+/// Builds the server's TLS configuration. rustls has no support for TLS 1.0 or 1.1, so the lowest version
+/// accepted is 1.2 unless `--tls-min-version` raises it to 1.3.
 pub fn config(tls_config: TlsOptions) -> Result<ServerConfig, EdgeError> {
-    let provider = rustls::crypto::ring::default_provider();
-    CryptoProvider::install_default(provider).expect("Failed to setup default crypto provider");
+    // Fails if a provider is already installed, which is fine when several servers are configured in one process
+    let _ = CryptoProvider::install_default(rustls::crypto::ring::default_provider());
+    let mut provider = rustls::crypto::ring::default_provider();
+    if !tls_config.tls_cipher_suites.is_empty() {
+        provider.cipher_suites = tls_config.tls_cipher_suites.clone();
+    }
+    let versions: &[&'static SupportedProtocolVersion] = match tls_config.tls_min_version {
+        TlsVersion::V1_2 => &[&TLS12, &TLS13],
+        TlsVersion::V1_3 => &[&TLS13],
+    };
     let mut cert_file = BufReader::new(
         File::open(
             tls_config
@@ -52,8 +71,299 @@ pub fn config(tls_config: TlsOptions) -> Result<ServerConfig, EdgeError> {
     let mut keys: Vec<PrivateKeyDer> = pkcs8_private_keys(&mut key_file)
         .filter_map(|f| f.map(PrivateKeyDer::from).ok())
         .collect();
-    ServerConfig::builder()
+    ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|_e| EdgeError::TlsError)?
         .with_no_client_auth()
         .with_single_cert(cert_chain, keys.remove(0))
         .map_err(|_e| EdgeError::TlsError)
 }
+
+/// Only accepts upstream certificates whose SHA-256 fingerprint matches one of the configured pins. Unless
+/// SSL verification is skipped, the certificate chain and hostname are validated as well.
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    pins: Vec<CertificatePin>,
+    chain_verifier: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = digest(&SHA256, end_entity.as_ref());
+        if !self
+            .pins
+            .iter()
+            .any(|pin| pin.0.as_slice() == fingerprint.as_ref())
+        {
+            return Err(rustls::Error::General(
+                "Upstream certificate does not match any pinned fingerprint".into(),
+            ));
+        }
+        match &self.chain_verifier {
+            Some(verifier) => verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            ),
+            None => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn read_pem_certificates(path: &PathBuf) -> EdgeResult<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).map_err(|e| {
+        EdgeError::ClientCertificateError(CertificateError::RootCertificatesError(format!("{e:?}")))
+    })?;
+    certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            EdgeError::ClientCertificateError(CertificateError::RootCertificatesError(format!(
+                "{e:?}"
+            )))
+        })
+}
+
+/// The roots upstream certificates are validated against when pins are configured. Without pins reqwest trusts the
+/// operating system's trust store, so that is loaded here too, next to the bundled webpki roots and the optional
+/// `--upstream-certificate-file`. Adding a pin should never make a previously trusted upstream untrusted.
+fn upstream_root_store(upstream_certificate: Option<PathBuf>) -> EdgeResult<RootCertStore> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    match rustls_native_certs::load_native_certs() {
+        Ok(native_roots) => {
+            let (_, ignored) = roots.add_parsable_certificates(native_roots);
+            if ignored > 0 {
+                warn!("Ignored {ignored} certificate(s) from the system trust store that could not be parsed");
+            }
+        }
+        Err(e) => warn!("Could not load the system trust store: {e:?}"),
+    }
+    if let Some(path) = upstream_certificate {
+        let (_, ignored) = roots.add_parsable_certificates(read_pem_certificates(&path)?);
+        if ignored > 0 {
+            return Err(EdgeError::ClientCertificateError(
+                CertificateError::RootCertificatesError(format!(
+                    "{ignored} certificate(s) in {} could not be parsed",
+                    path.display()
+                )),
+            ));
+        }
+    }
+    Ok(roots)
+}
+
+/// Builds the rustls configuration used to talk to upstream when certificate pins are configured. reqwest
+/// ignores its own certificate and identity settings for preconfigured TLS, so they're all applied here.
+pub(crate) fn pinned_upstream_client_config(
+    pins: Vec<CertificatePin>,
+    skip_ssl_verification: bool,
+    upstream_certificate: Option<PathBuf>,
+    client_identity: Option<ClientIdentity>,
+) -> EdgeResult<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let chain_verifier = if skip_ssl_verification {
+        None
+    } else {
+        let roots = upstream_root_store(upstream_certificate)?;
+        Some(
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| EdgeError::ClientBuildError(format!("{e:?}")))?,
+        )
+    };
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| EdgeError::ClientBuildError(format!("{e:?}")))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertificateVerifier {
+            pins,
+            chain_verifier,
+            provider,
+        }));
+    match client_identity {
+        None => Ok(builder.with_no_client_auth()),
+        Some(ClientIdentity {
+            pkcs8_client_certificate_file: Some(cert),
+            pkcs8_client_key_file: Some(key),
+            ..
+        }) => {
+            let cert_chain = read_pem_certificates(&cert)?;
+            let key = File::open(&key)
+                .ok()
+                .and_then(|file| rustls_pemfile::private_key(&mut BufReader::new(file)).ok())
+                .flatten()
+                .ok_or_else(|| {
+                    EdgeError::ClientCertificateError(CertificateError::Pem8ClientKeyNotFound(
+                        key.display().to_string(),
+                    ))
+                })?;
+            builder.with_client_auth_cert(cert_chain, key).map_err(|e| {
+                EdgeError::ClientCertificateError(CertificateError::Pem8IdentityGeneration(
+                    format!("{e:?}"),
+                ))
+            })
+        }
+        Some(_) => Err(EdgeError::ClientBuildError(
+            "Pinned upstream certificates can only be combined with a pkcs#8 client identity"
+                .into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use ring::digest::{digest, SHA256};
+    use rustls::pki_types::ServerName;
+    use rustls::version::{TLS12, TLS13};
+    use rustls::{ClientConfig, ClientConnection, ServerConnection, SupportedProtocolVersion};
+
+    use crate::cli::{CertificatePin, TlsOptions, TlsVersion};
+
+    use super::{
+        config, pinned_upstream_client_config, read_pem_certificates, PinnedCertificateVerifier,
+    };
+
+    fn tls_options(min_version: TlsVersion) -> TlsOptions {
+        TlsOptions {
+            tls_enable: true,
+            tls_server_key: Some("../examples/server.key".into()),
+            tls_server_cert: Some("../examples/server.crt".into()),
+            tls_server_port: 443,
+            tls_min_version: min_version,
+            tls_cipher_suites: vec![],
+        }
+    }
+
+    fn client_config(version: &'static SupportedProtocolVersion) -> ClientConfig {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_cert = read_pem_certificates(&PathBuf::from("../examples/server.crt"))
+            .unwrap()
+            .remove(0);
+        let pin = CertificatePin(
+            digest(&SHA256, server_cert.as_ref())
+                .as_ref()
+                .try_into()
+                .unwrap(),
+        );
+        ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[version])
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertificateVerifier {
+                pins: vec![pin],
+                chain_verifier: None,
+                provider,
+            }))
+            .with_no_client_auth()
+    }
+
+    fn handshake(
+        client_config: ClientConfig,
+        server_config: rustls::ServerConfig,
+    ) -> Result<(), rustls::Error> {
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            ServerName::try_from("localhost").unwrap(),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buf = vec![];
+            client.write_tls(&mut buf).unwrap();
+            server.read_tls(&mut buf.as_slice()).unwrap();
+            server.process_new_packets()?;
+            let mut buf = vec![];
+            server.write_tls(&mut buf).unwrap();
+            client.read_tls(&mut buf.as_slice()).unwrap();
+            client.process_new_packets()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tls_1_3_only_server_rejects_tls_1_2_clients() {
+        let server_config = config(tls_options(TlsVersion::V1_3)).unwrap();
+        assert!(handshake(client_config(&TLS12), server_config.clone()).is_err());
+        assert!(handshake(client_config(&TLS13), server_config).is_ok());
+    }
+
+    #[test]
+    fn server_accepts_tls_1_2_by_default() {
+        let server_config = config(tls_options(TlsVersion::V1_2)).unwrap();
+        assert!(handshake(client_config(&TLS12), server_config).is_ok());
+    }
+
+    #[test]
+    fn pinned_upstream_trusts_cas_from_the_system_trust_store() {
+        let upstream_cert = read_pem_certificates(&PathBuf::from("../examples/upstream.crt"))
+            .unwrap()
+            .remove(0);
+        let pin = CertificatePin(
+            digest(&SHA256, upstream_cert.as_ref())
+                .as_ref()
+                .try_into()
+                .unwrap(),
+        );
+        let server_config = config(TlsOptions {
+            tls_server_key: Some("../examples/upstream.key".into()),
+            tls_server_cert: Some("../examples/upstream.crt".into()),
+            ..tls_options(TlsVersion::V1_2)
+        })
+        .unwrap();
+
+        // The test CA is only in the system trust store, not passed as --upstream-certificate-file
+        std::env::set_var("SSL_CERT_FILE", "../examples/upstream-ca.crt");
+        let client_config = pinned_upstream_client_config(vec![pin], false, None, None);
+        std::env::remove_var("SSL_CERT_FILE");
+
+        assert!(handshake(client_config.unwrap(), server_config).is_ok());
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::cli::IntrospectArgs;
+use crate::error::EdgeError;
+use crate::types::EdgeToken;
+
+/// What can be told about a token from its format alone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenIntrospection {
+    pub environment: Option<String>,
+    /// Empty for multi-project tokens (`[]:`), whose projects are only known to upstream
+    pub projects: Vec<String>,
+    /// Whether the token has access to every project
+    pub wildcard: bool,
+}
+
+impl From<&EdgeToken> for TokenIntrospection {
+    fn from(token: &EdgeToken) -> Self {
+        Self {
+            environment: token.environment.clone(),
+            projects: token.projects.clone(),
+            wildcard: token.projects.iter().any(|project| project == "*"),
+        }
+    }
+}
+
+impl Display for TokenIntrospection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "environment: {}",
+            self.environment.as_deref().unwrap_or("unknown")
+        )?;
+        if self.projects.is_empty() {
+            writeln!(
+                f,
+                "projects: unknown, this is a multi-project token which only upstream can resolve"
+            )?;
+        } else {
+            writeln!(f, "projects: {}", self.projects.join(", "))?;
+        }
+        write!(f, "wildcard: {}", self.wildcard)
+    }
+}
+
+pub fn introspect(token: &str) -> Result<TokenIntrospection, EdgeError> {
+    EdgeToken::from_str(token).map(|token| TokenIntrospection::from(&token))
+}
+
+/// Prints what the token gives access to, without contacting upstream
+pub fn print_introspection(introspect_args: IntrospectArgs) -> Result<(), EdgeError> {
+    let introspection = introspect(&introspect_args.token)?;
+    println!("{introspection}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{introspect, TokenIntrospection};
+
+    #[test]
+    pub fn introspects_a_project_scoped_token() {
+        let introspection = introspect("dx:development.secret123").unwrap();
+        assert_eq!(
+            introspection,
+            TokenIntrospection {
+                environment: Some("development".into()),
+                projects: vec!["dx".into()],
+                wildcard: false,
+            }
+        );
+        assert_eq!(
+            introspection.to_string(),
+            "environment: development\nprojects: dx\nwildcard: false"
+        );
+    }
+
+    #[test]
+    pub fn introspects_a_wildcard_token() {
+        let introspection = introspect("*:production.secret123").unwrap();
+        assert_eq!(
+            introspection,
+            TokenIntrospection {
+                environment: Some("production".into()),
+                projects: vec!["*".into()],
+                wildcard: true,
+            }
+        );
+    }
+
+    #[test]
+    pub fn malformed_tokens_are_an_error() {
+        assert!(introspect("not-a-token").is_err());
+    }
+}
@@ -7,7 +7,9 @@ use actix_web::http::header::HeaderValue;
 use actix_web::web::Data;
 use actix_web::FromRequest;
 use actix_web::HttpRequest;
+use dashmap::DashMap;
 
+use crate::auth::jwks::JwksValidator;
 use crate::cli::EdgeMode;
 use crate::cli::TokenHeader;
 use crate::error::EdgeError;
@@ -21,6 +23,9 @@ pub(crate) fn simplify(tokens: &[TokenRefresh]) -> Vec<TokenRefresh> {
     uniques
         .iter()
         .filter_map(|token| {
+            if token.pinned {
+                return Some(token);
+            }
             uniques.iter().try_fold(token, |acc, current| {
                 if current.token.token != acc.token.token && current.token.subsumes(&acc.token) {
                     None
@@ -42,7 +47,7 @@ fn filter_unique_tokens(tokens: &[TokenRefresh]) -> Vec<TokenRefresh> {
             token.token.projects.clone(),
             token.token.environment.clone(),
         );
-        if !unique_keys.contains(&key) {
+        if token.pinned || !unique_keys.contains(&key) {
             unique_tokens.push(token.clone());
             unique_keys.insert(key);
         }
@@ -74,6 +79,12 @@ fn clean_hash(hash: &str) -> String {
     )
 }
 
+/// The key under which features and engines for this token are stored in the feature and engine caches.
+///
+/// Every token for the same environment maps to the same key, regardless of which projects it has access to.
+/// Refreshes merge each token's projects into that shared entry, and project access is enforced at read time by
+/// filtering (see `FeatureRefresher::features_for_filter`). Tokens without a known environment fall back to the
+/// token string itself.
 pub fn cache_key(token: &EdgeToken) -> String {
     token
         .environment
@@ -108,28 +119,82 @@ impl EdgeToken {
     }
 }
 
+/// Falls back to validating the header as a self-issued JWT when Edge has been given a JWKS.
+/// These tokens are validated again on every request and expire on their own, so they're kept out of the token cache
+fn signed_frontend_token(req: &HttpRequest, value: &HeaderValue) -> Option<EdgeToken> {
+    let jwks = req.app_data::<Data<JwksValidator>>()?;
+    jwks.validate(value.to_str().ok()?)
+}
+
+/// The cached copy of a token, or the token itself if it was validated while being extracted from the request,
+/// as self-issued frontend tokens are
+pub(crate) fn known_token(
+    token_cache: &DashMap<String, EdgeToken>,
+    token: &EdgeToken,
+) -> Option<EdgeToken> {
+    token_cache
+        .get(&token.token)
+        .map(|t| t.value().clone())
+        .or_else(|| (token.status == TokenValidationStatus::Validated).then(|| token.clone()))
+}
+
+/// Token header names for the client and frontend APIs, set with `--client-token-header` and `--frontend-token-header`.
+/// Requests to other routes, or when no header name is set for the API, use `--token-header`
+#[derive(Clone, Debug, Default)]
+pub struct EndpointTokenHeaders {
+    pub client: Option<String>,
+    pub frontend: Option<String>,
+}
+
+impl EndpointTokenHeaders {
+    /// Looks for the API in the path rather than at its start, since routes are nested under `--base-path`
+    fn for_path(&self, path: &str) -> Option<&String> {
+        let segments: Vec<&str> = path.split('/').collect();
+        segments
+            .windows(2)
+            .find_map(|pair| match pair {
+                ["api", "client"] => Some(self.client.as_ref()),
+                ["api", "frontend"] | ["api", "proxy"] => Some(self.frontend.as_ref()),
+                _ => None,
+            })
+            .flatten()
+    }
+}
+
+fn token_header_name(req: &HttpRequest) -> String {
+    let endpoint_header = req
+        .app_data::<Data<EndpointTokenHeaders>>()
+        .and_then(|headers| headers.for_path(req.path()).cloned());
+    match endpoint_header {
+        Some(header) => header,
+        None => match req.app_data::<Data<TokenHeader>>() {
+            Some(data) => data.clone().into_inner().token_header.clone(),
+            None => "Authorization".to_string(),
+        },
+    }
+}
+
 impl FromRequest for EdgeToken {
     type Error = EdgeError;
     type Future = Ready<EdgeResult<Self>>;
 
     fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
-        let token_header = match req.app_data::<Data<TokenHeader>>() {
-            Some(data) => data.clone().into_inner().token_header.clone(),
-            None => "Authorization".to_string(),
-        };
+        let token_header = token_header_name(req);
         let value = req.headers().get(token_header);
         if let Some(data_mode) = req.app_data::<Data<EdgeMode>>() {
             let mode = data_mode.clone().into_inner();
             let key = match *mode {
                 EdgeMode::Offline(_) => match value {
                     Some(v) => match v.to_str() {
-                        Ok(value) => Ok(EdgeToken::offline_token(value)),
+                        Ok(value) => Ok(signed_frontend_token(req, v)
+                            .unwrap_or_else(|| EdgeToken::offline_token(value))),
                         Err(_) => Err(EdgeError::AuthorizationDenied),
                     },
                     None => Err(EdgeError::AuthorizationDenied),
                 },
                 EdgeMode::Edge(_) => match value {
-                    Some(v) => EdgeToken::try_from(v.clone()),
+                    Some(v) => EdgeToken::try_from(v.clone())
+                        .or_else(|parse_error| signed_frontend_token(req, v).ok_or(parse_error)),
                     None => Err(EdgeError::AuthorizationDenied),
                 },
                 _ => unreachable!(),
@@ -220,11 +285,16 @@ impl EdgeToken {
 mod tests {
     use std::str::FromStr;
 
+    use actix_web::http::header::HeaderValue;
+    use actix_web::test::TestRequest;
+    use actix_web::web::Data;
+    use dashmap::DashMap;
     use ulid::Ulid;
 
     use crate::{
+        auth::jwks::tests::{jwks_for, key_pair, sign},
         tokens::simplify,
-        types::{EdgeToken, TokenRefresh, TokenType},
+        types::{EdgeToken, TokenRefresh, TokenType, TokenValidationStatus},
     };
 
     fn test_token(token: Option<&str>, env: Option<&str>, projects: Vec<&str>) -> EdgeToken {
@@ -360,6 +430,23 @@ mod tests {
             .collect();
         assert_eq!(actual.len(), 2);
     }
+
+    #[test]
+    fn pinned_tokens_are_kept_even_when_subsumed() {
+        let mut pinned = TokenRefresh::new(test_token(Some("pinned"), None, vec!["p1"]), None);
+        pinned.pinned = true;
+        let tokens = vec![
+            TokenRefresh::new(test_token(Some("wildcard"), None, vec!["*"]), None),
+            pinned,
+            TokenRefresh::new(test_token(Some("p2project"), None, vec!["p2"]), None),
+        ];
+
+        let actual: Vec<String> = simplify(&tokens)
+            .iter()
+            .map(|x| x.token.token.clone())
+            .collect();
+        assert_eq!(actual, vec!["wildcard".to_string(), "pinned".to_string()]);
+    }
     #[test]
     fn test_single_project_token_is_covered_by_wildcard() {
         let self_token = EdgeToken {
@@ -454,4 +541,37 @@ mod tests {
         assert_eq!(token1, token2);
         assert_eq!(token2, token3);
     }
+
+    #[test]
+    fn project_tokens_in_the_same_environment_get_the_same_cache_key() {
+        let dx = EdgeToken::from_str("dx:development.secret123").unwrap();
+        let eg = EdgeToken::from_str("eg:development.secret123").unwrap();
+        let prod = EdgeToken::from_str("dx:production.secret123").unwrap();
+        assert_eq!(super::cache_key(&dx), "development");
+        assert_eq!(super::cache_key(&dx), super::cache_key(&eg));
+        assert_ne!(super::cache_key(&dx), super::cache_key(&prod));
+    }
+
+    #[test]
+    fn signed_frontend_tokens_are_not_added_to_the_token_cache() {
+        let key_pair = key_pair();
+        let jwt = sign(&key_pair, 3600);
+        let token_cache: Data<DashMap<String, EdgeToken>> = Data::new(DashMap::default());
+        let req = TestRequest::default()
+            .app_data(Data::new(jwks_for(&key_pair)))
+            .app_data(token_cache.clone())
+            .to_http_request();
+        let token =
+            super::signed_frontend_token(&req, &HeaderValue::from_str(&jwt).unwrap()).unwrap();
+        assert_eq!(token.status, TokenValidationStatus::Validated);
+        assert!(token_cache.is_empty());
+        assert_eq!(super::known_token(&token_cache, &token), Some(token));
+    }
+
+    #[test]
+    fn unvalidated_tokens_missing_from_the_token_cache_are_not_known() {
+        let token_cache = DashMap::default();
+        let token = EdgeToken::from_str("*:development.secret123").unwrap();
+        assert_eq!(super::known_token(&token_cache, &token), None);
+    }
 }
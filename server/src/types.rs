@@ -18,6 +18,7 @@ use shadow_rs::shadow;
 use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
 use unleash_types::client_features::Context;
 use unleash_types::client_metrics::{ClientApplication, ClientMetricsEnv};
+use unleash_types::frontend::EvaluatedVariant;
 use unleash_yggdrasil::EngineState;
 use utoipa::{IntoParams, ToSchema};
 
@@ -281,6 +282,15 @@ pub struct TokenRefresh {
     pub last_feature_count: Option<usize>,
     pub last_check: Option<DateTime<Utc>>,
     pub failure_count: u32,
+    /// Due refreshes with a higher priority are refreshed first
+    #[serde(default)]
+    pub priority: i32,
+    /// When upstream started denying this token. Cleared again on the next successful check or refresh
+    #[serde(default)]
+    pub first_denied_at: Option<DateTime<Utc>>,
+    /// Pinned tokens are refreshed on their own, even when another registered token subsumes them
+    #[serde(default)]
+    pub pinned: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -308,6 +318,9 @@ impl TokenRefresh {
             next_refresh: None,
             failure_count: 0,
             last_feature_count: None,
+            priority: 0,
+            first_denied_at: None,
+            pinned: false,
         }
     }
 
@@ -323,6 +336,13 @@ impl TokenRefresh {
             ..self.clone()
         }
     }
+    /// Upstream denied the token. Remember when the denials started and back off like any other failure
+    pub fn denied(&self, refresh_interval: &Duration) -> Self {
+        Self {
+            first_denied_at: self.first_denied_at.or(Some(Utc::now())),
+            ..self.backoff(refresh_interval)
+        }
+    }
     /// We successfully talked to upstream, but there was no updates. Update our next_refresh, decrement our failure count and set when we last_checked
     pub fn successful_check(&self, refresh_interval: &Duration) -> Self {
         let failure_count = if self.failure_count > 0 {
@@ -336,6 +356,7 @@ impl TokenRefresh {
             failure_count,
             next_refresh: Some(next_refresh),
             last_check: Some(now),
+            first_denied_at: None,
             ..self.clone()
         }
     }
@@ -360,6 +381,7 @@ impl TokenRefresh {
             last_check: Some(now),
             last_feature_count: Some(feature_count),
             etag,
+            first_denied_at: None,
             ..self.clone()
         }
     }
@@ -500,6 +522,23 @@ impl Default for BuildInfo {
 #[serde(rename_all = "camelCase")]
 pub struct FeatureFilters {
     pub name_prefix: Option<String>,
+    /// Inline segment constraints into strategies, for SDKs that can't resolve segment references
+    pub inline_segment_constraints: Option<bool>,
+}
+
+/// Query parameters for `/api/client/delta`
+#[derive(Clone, Debug, Serialize, Deserialize, IntoParams)]
+pub struct DeltaQuery {
+    /// Revision the SDK already has. When left out, or too old to replay the changes since, Edge responds with a hydration
+    pub since: Option<i32>,
+}
+
+/// The filter `/api/client/features` applies for a token, and the features that pass it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureFilterDebug {
+    pub query: unleash_types::client_features::Query,
+    pub features: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -520,6 +559,41 @@ pub struct MetricsInfo {
     pub metrics: Vec<ClientMetric>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EvaluationReason {
+    /// The feature itself is turned off in this environment
+    FeatureDisabled,
+    /// The feature is on and has no strategies, so it is enabled for everyone
+    NoStrategies,
+    /// At least one strategy matched the context
+    StrategyMatched,
+    /// None of the strategies matched the context
+    NoStrategyMatched,
+    /// A strategy matched, but the feature's dependencies on other features were not satisfied
+    DependenciesNotSatisfied,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyEvaluation {
+    pub index: usize,
+    pub name: String,
+    pub matched: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationExplanation {
+    pub name: String,
+    pub enabled: bool,
+    pub variant: EvaluatedVariant,
+    pub reason: EvaluationReason,
+    /// The first strategy that matched, if any
+    pub matched_strategy: Option<StrategyEvaluation>,
+    pub strategies: Vec<StrategyEvaluation>,
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -70,4 +70,57 @@ mod base_path_tests {
             .wait()
             .expect("Failed to wait for the app process");
     }
+
+    #[actix_web::test]
+    async fn frontend_and_backstage_routes_are_served_under_a_normalized_base_path() {
+        // Trailing slashes and a missing leading slash are normalized to /shared/ingress
+        let mut app_process = Command::new("./../target/debug/unleash-edge")
+            .arg("--port")
+            .arg("3072")
+            .arg("--base-path")
+            .arg("shared/ingress/")
+            .arg("offline")
+            .arg("-t")
+            .arg("*:test.test")
+            .arg("-f")
+            .arg("*:test.frontend")
+            .arg("--bootstrap-file")
+            .arg("../examples/features.json")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start the app");
+
+        std::thread::sleep(std::time::Duration::from_secs(1));
+
+        let client = Client::new();
+        let frontend = client
+            .get("http://localhost:3072/shared/ingress/api/frontend")
+            .header("Authorization", "*:test.frontend")
+            .send()
+            .await
+            .expect("Failed to send request");
+        let health = client
+            .get("http://localhost:3072/shared/ingress/internal-backstage/health")
+            .send()
+            .await
+            .expect("Failed to send request");
+        let without_base_path = client
+            .get("http://localhost:3072/api/frontend")
+            .header("Authorization", "*:test.frontend")
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        app_process.kill().expect("Failed to kill the app process");
+        app_process
+            .wait()
+            .expect("Failed to wait for the app process");
+
+        assert!(frontend.status().is_success());
+        let toggles: serde_json::Value = frontend.json().await.unwrap();
+        assert!(!toggles["toggles"].as_array().unwrap().is_empty());
+        assert!(health.status().is_success());
+        assert_eq!(without_base_path.status(), 404);
+    }
 }
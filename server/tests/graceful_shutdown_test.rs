@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod graceful_shutdown_tests {
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[actix_web::test]
+    async fn in_flight_requests_complete_while_draining() {
+        let mut app_process = Command::new("./../target/debug/unleash-edge")
+            .arg("--port")
+            .arg("3074")
+            .arg("--shutdown-grace-seconds")
+            .arg("10")
+            .arg("offline")
+            .arg("-t")
+            .arg("*:development.secret123")
+            .arg("--bootstrap-file")
+            .arg("../examples/features.json")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start the app");
+        // The port accepts connections before Edge's workers are serving, so wait for a response
+        let mut serving = false;
+        for _ in 0..100 {
+            if reqwest::get("http://127.0.0.1:3074/internal-backstage/health")
+                .await
+                .is_ok_and(|response| response.status().is_success())
+            {
+                serving = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(serving, "Edge did not start serving");
+
+        // Only send half of the body, so the request is still in flight when Edge is told to stop
+        let body = r#"{"appName":"drain","instanceId":"drain","bucket":{"start":"2024-01-01T00:00:00Z","stop":"2024-01-01T00:01:00Z","toggles":{}}}"#;
+        let (first_half, second_half) = body.split_at(body.len() / 2);
+        let mut stream = TcpStream::connect("127.0.0.1:3074").await.unwrap();
+        let head = format!(
+            "POST /api/client/metrics HTTP/1.1\r\nHost: localhost\r\nAuthorization: *:development.secret123\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(first_half.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        Command::new("kill")
+            .arg("-TERM")
+            .arg(app_process.id().to_string())
+            .status()
+            .expect("Failed to send SIGTERM");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let new_request = reqwest::Client::builder()
+            .timeout(Duration::from_millis(300))
+            .build()
+            .unwrap()
+            .get("http://127.0.0.1:3074/internal-backstage/health")
+            .send()
+            .await;
+        assert!(
+            new_request.is_err(),
+            "New requests should not be served while draining"
+        );
+
+        stream.write_all(second_half.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 202"),
+            "Unexpected response: {response}"
+        );
+
+        let status = app_process
+            .wait()
+            .expect("Failed to wait for the app process");
+        assert!(status.success());
+    }
+}
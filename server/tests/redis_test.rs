@@ -1,4 +1,4 @@
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 use redis::Client;
 use testcontainers_modules::redis::RedisStack;
@@ -66,3 +66,47 @@ async fn redis_saves_and_restores_edge_tokens_correctly() {
     let saved_tokens = redis_persister.load_tokens().await.unwrap();
     assert_eq!(saved_tokens.len(), 2);
 }
+
+#[tokio::test]
+async fn redis_pool_handles_concurrent_saves_and_loads() {
+    let (_client, url, _node) = setup_redis().await;
+    let redis_persister = Arc::new(
+        RedisPersister::new(&url, TEST_TIMEOUT, TEST_TIMEOUT)
+            .unwrap()
+            .with_pool_size(4),
+    );
+    assert_eq!(redis_persister.pool_size(), 4);
+    let features = ClientFeatures {
+        features: vec![ClientFeature {
+            name: "test".to_string(),
+            ..ClientFeature::default()
+        }],
+        query: None,
+        segments: None,
+        version: 2,
+        meta: None,
+    };
+    redis_persister
+        .save_features(vec![("development".into(), features.clone())])
+        .await
+        .unwrap();
+
+    let operations = (0..20).map(|i| {
+        let redis_persister = redis_persister.clone();
+        let features = features.clone();
+        tokio::spawn(async move {
+            if i % 2 == 0 {
+                redis_persister
+                    .save_features(vec![("development".into(), features)])
+                    .await
+                    .unwrap();
+            } else {
+                let loaded = redis_persister.load_features().await.unwrap();
+                assert_eq!(loaded.get("development").unwrap(), &features);
+            }
+        })
+    });
+    for result in futures::future::join_all(operations).await {
+        result.unwrap();
+    }
+}
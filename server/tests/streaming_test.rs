@@ -11,7 +11,10 @@ mod streaming_test {
         sync::Arc,
     };
     use unleash_edge::{
-        cli::{EdgeArgs, EdgeMode, TokenHeader},
+        cli::{
+            EdgeArgs, EdgeMode, EnvironmentMismatchBehavior, MetricsSinkType, TokenHeader,
+            TokenSimplificationLogLevel,
+        },
         feature_cache::FeatureCache,
         http::broadcaster::Broadcaster,
         tokens::cache_key,
@@ -193,14 +196,20 @@ mod streaming_test {
             let edge_mode = EdgeMode::Edge(EdgeArgs {
                 streaming: true,
                 upstream_url: "".into(),
+                secondary_upstream_url: None,
+                secondary_upstream_retry_primary_seconds: 300,
                 backup_folder: None,
                 metrics_interval_seconds: 60,
                 features_refresh_interval_seconds: 60,
                 token_revalidation_interval_seconds: 60,
                 tokens: vec!["".into()],
+                tokens_file: None,
+                features_snapshot: None,
+                strict_startup_tokens: false,
                 custom_client_headers: vec![],
                 skip_ssl_verification: false,
                 client_identity: None,
+                client_identity_reload_interval_seconds: 60,
                 upstream_certificate_file: None,
                 upstream_request_timeout: 5,
                 upstream_socket_timeout: 5,
@@ -213,6 +222,54 @@ mod streaming_test {
                 dynamic: false,
                 delta: false,
                 delta_diff:false,
+                delta_diff_output: None,
+                token_priority: vec![],
+                pinned_tokens: vec![],
+                max_metrics_buffer: None,
+                metrics_bin_granularity: Default::default(),
+                metrics_sampling_rate: None,
+                metrics_sink: MetricsSinkType::Upstream,
+                metrics_sink_file: None,
+                metrics_sink_file_max_bytes: 10_485_760,
+                max_feature_payload_bytes: None,
+                circuit_breaker_failure_threshold: None,
+                circuit_breaker_window_seconds: 60,
+                circuit_breaker_open_seconds: 30,
+                token_validation_timeout_ms: None,
+                inline_segment_constraints_tokens: vec![],
+                empty_on_missing_environment: false,
+                hide_stale_features: false,
+                max_strategies_per_feature: None,
+                required_environments: vec![],
+                feature_count_drop_threshold: None,
+                fail_on_compile_warnings: false,
+                upstream_pool_max_idle_per_host: None,
+                upstream_pool_idle_timeout_seconds: 90,
+                upstream_user_agent_suffix: None,
+                upstream_http2: false,
+                upstream_http2_prior_knowledge: false,
+                stale_threshold_seconds: None,
+                token_eviction_grace_seconds: None,
+                lenient_json_parsing: false,
+                normalize_feature_names: false,
+                token_scope_metrics: false,
+                token_simplification_log_level: TokenSimplificationLogLevel::Info,
+                compress_metrics: None,
+                frontend_jwks_file: None,
+                upstream_environment_mismatch: EnvironmentMismatchBehavior::Reject,
+                reject_revision_regressions: false,
+                ignore_empty_upstream: false,
+                max_streams_per_token: None,
+                scheduler_tick_ms: None,
+                skip_client_registration: false,
+                client_spec_version: unleash_yggdrasil::SUPPORTED_SPEC_VERSION.into(),
+                chaos_fail_rate: None,
+                chaos_latency_ms: None,
+                unsafe_enable_chaos: false,
+                upstream_cert_pin: vec![],
+                upstream_proxy_url: None,
+                upstream_proxy_username: None,
+                upstream_proxy_password: None,
                 prometheus_remote_write_url: None,
                 prometheus_push_interval: 60,
                 prometheus_username: None,
@@ -242,7 +299,10 @@ mod streaming_test {
                         web::scope("/api")
                             .configure(unleash_edge::client_api::configure_client_api)
                             .configure(|cfg| {
-                                unleash_edge::frontend_api::configure_frontend_api(cfg, false)
+                                unleash_edge::frontend_api::configure_frontend_api(
+                                    cfg,
+                                    unleash_edge::frontend_api::AllEndpointAccess::default(),
+                                )
                             }),
                     )
                     .service(